@@ -0,0 +1,42 @@
+//! Write the header of every simfile given on the command line, or of `testdata/Springtime.ssc` if none is
+//! given, to standard output as MSD, with a comment naming the file it came from.
+//!
+//! The parser is streaming, so reading stops at the first `#NOTEDATA` or `#NOTES` parameter and the charts after
+//! it are never read. A parameter is only yielded once it is complete, so in a `.sm` file the note data of the
+//! first chart is still read, though not written. Each file is read in its own dialect with
+//! [`MSDParserOptions::for_path`], with its keys normalized and its comments kept, and written with an
+//! [`MSDWriter`], which escapes values again and puts the comments back.
+//!
+//! ```sh
+//! cargo run --example header_scan -- testdata/Springtime.ssc
+//! ```
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Write};
+
+use msdparser::tags::KnownTag;
+use msdparser::writer::MSDWriter;
+use msdparser::{parse_msd_with_options, MSDParserOptions};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    let paths = if paths.is_empty() { vec!["testdata/Springtime.ssc".to_string()] } else { paths };
+    let mut writer = MSDWriter::new(io::stdout().lock());
+
+    for path in paths {
+        writer.write_comment(&path)?;
+        let options = MSDParserOptions::for_path(&path).normalize_keys(true).keep_comments(true);
+        for parameter in parse_msd_with_options(File::open(&path)?, options) {
+            let parameter = parameter?;
+            if matches!(parameter.known_key(), Some(KnownTag::NoteData | KnownTag::Notes | KnownTag::Notes2)) {
+                break;
+            }
+            writer.write_parameter(&parameter)?;
+        }
+    }
+
+    writer.finish()?.flush()?;
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! Rewrite the `#TITLE` of every simfile in a song pack, appending a suffix.
+//!
+//! The pack is found with [`pack::scan`], each title is read from an [`MSDDocument`], and the edits are staged in
+//! a [`Transaction`], which keeps every other byte of each file, and committed all at once: if any file can't be
+//! written, the ones already written are restored. Pass `--dry-run` to print the new titles without writing
+//! anything.
+//!
+//! ```sh
+//! cargo run --example retitle_pack -- path/to/pack " (Remix)" --dry-run
+//! ```
+
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::{env, process};
+
+use msdparser::batch::Transaction;
+use msdparser::pack;
+use msdparser::{MSDDocument, MSDParserOptions};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let positional: Vec<&String> = args.iter().filter(|a| *a != "--dry-run").collect();
+
+    let [pack, suffix] = positional[..] else {
+        eprintln!("usage: retitle_pack <pack dir> <suffix> [--dry-run]");
+        process::exit(2);
+    };
+
+    let mut transaction = Transaction::new();
+    for path in pack::scan(Path::new(pack))?.files {
+        let options = MSDParserOptions::for_path(&path).normalize_keys(true);
+        let document = MSDDocument::from_reader(File::open(&path)?, options)?;
+        let Some(title) = document.get("TITLE").and_then(|parameter| parameter.value()) else {
+            println!("{}: no #TITLE, skipped", path.display());
+            continue;
+        };
+        let new_title = format!("{}{}", title, suffix);
+        println!("{}: {} -> {}", path.display(), title, new_title);
+
+        transaction.stage(&path, |parameters| {
+            let title = parameters.iter_mut()
+                .find(|parameter| parameter.key().is_some_and(|key| key.trim().eq_ignore_ascii_case("TITLE")))
+                .ok_or_else(|| "no #TITLE".to_string())?;
+            title.components.truncate(1);
+            title.components.push(new_title);
+            Ok(())
+        })?;
+    }
+
+    if !dry_run {
+        println!("{}", transaction.commit()?);
+    }
+    Ok(())
+}
@@ -0,0 +1,28 @@
+//! Convert a simfile into a JSON array of parameters, with their components, comments and byte spans.
+//!
+//! The file is read into an [`MSDDocument`] in its own dialect with its comments kept, and the parameters are
+//! written with `serde_json` through the crate's `serde` feature.
+//!
+//! ```sh
+//! cargo run --example to_json --features serde -- testdata/Springtime.ssc > springtime.json
+//! ```
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use msdparser::{MSDDocument, MSDParserOptions};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args().nth(1).unwrap_or_else(|| "testdata/Springtime.ssc".to_string());
+    let options = MSDParserOptions::for_path(&path).keep_comments(true);
+    let document = MSDDocument::from_reader(File::open(&path)?, options)?;
+
+    let mut writer = BufWriter::new(io::stdout().lock());
+    serde_json::to_writer_pretty(&mut writer, document.parameters())?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! Render the note data of a chart as ASCII art, one measure at a time, with its timing events.
+//!
+//! The simfile is converted to a typed [`Simfile`], its note data compressed to the fewest rows per measure
+//! with [`compress_notes_for`], which also checks every row against the column count of the steps type, and
+//! every BPM change, stop, delay and warp is printed next to the row it lands on.
+//!
+//! ```sh
+//! cargo run --example viewer -- testdata/conformance/timing.sm 0
+//! ```
+
+use std::error::Error;
+use std::fs::File;
+use std::{env, process};
+
+use msdparser::notes::compress_notes_for;
+use msdparser::serialize::FloatFormat;
+use msdparser::simfile::Simfile;
+use msdparser::stepstype::StepsTypeRegistry;
+use msdparser::{parse_msd, MSDParameter};
+
+const BEATS_PER_MEASURE: f64 = 4.0;
+
+/// How each note type is drawn.
+fn glyph(note: char) -> char {
+    match note {
+        '0' => '.',
+        '1' => 'o',
+        '2' => 'H',
+        '3' => '_',
+        '4' => 'R',
+        'M' => '*',
+        'L' => 'L',
+        'F' => 'f',
+        _ => '?',
+    }
+}
+
+/// The timing events on beats in `start..end`, labelled and formatted with `format`.
+fn events(simfile: &Simfile, start: f64, end: f64, format: &FloatFormat) -> Vec<(f64, String)> {
+    let lists = [("bpm", &simfile.bpms), ("stop", &simfile.stops), ("delay", &simfile.delays), ("warp", &simfile.warps)];
+    let mut events: Vec<(f64, String)> = lists.iter()
+        .flat_map(|(label, pairs)| pairs.iter().map(move |&(beat, value)| (beat, *label, value)))
+        .filter(|(beat, _, _)| (start..end).contains(beat))
+        .map(|(beat, label, value)| (beat, format!("{} {}", label, format.format(value))))
+        .collect();
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+    events
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (path, index) = match &args[..] {
+        [] => ("testdata/conformance/timing.sm", 0),
+        [path] => (path.as_str(), 0),
+        [path, index] => (path.as_str(), index.parse()?),
+        _ => {
+            eprintln!("usage: viewer [simfile] [chart index]");
+            process::exit(2);
+        },
+    };
+
+    let parameters: Vec<MSDParameter> = parse_msd(File::open(path)?, true, false).collect::<Result<_, _>>()?;
+    let simfile = Simfile::try_from(&parameters)?;
+    let Some(chart) = simfile.charts.get(index) else {
+        eprintln!("{}: no chart {}, it has {}", path, index, simfile.charts.len());
+        process::exit(1);
+    };
+    let format = FloatFormat::new().decimals(Some(3));
+
+    println!("{} - {}", simfile.title, simfile.artist);
+    println!("{} {} {} (offset {}s)", chart.steps_type, chart.difficulty, chart.meter, format.format(simfile.offset));
+
+    let notes = compress_notes_for(chart.notes.first().map_or("", String::as_str), &chart.steps_type, &StepsTypeRegistry::new())?;
+    for (measure, rows) in notes.split("\n,\n").map(|measure| measure.lines().collect::<Vec<_>>()).enumerate() {
+        let measure_beat = measure as f64 * BEATS_PER_MEASURE;
+        let row_beats = BEATS_PER_MEASURE / rows.len() as f64;
+        println!("-- measure {} (beat {})", measure, format.format(measure_beat));
+
+        for (i, row) in rows.iter().enumerate() {
+            let beat = measure_beat + i as f64 * row_beats;
+            let labels: Vec<String> = events(&simfile, beat, beat + row_beats, &format)
+                .into_iter()
+                .map(|(beat, label)| format!("{} @{}", label, format.format(beat)))
+                .collect();
+            let drawn: String = row.chars().map(glyph).collect();
+            println!("{}", format!("   {}  {}", drawn, labels.join(", ")).trim_end());
+        }
+    }
+
+    Ok(())
+}
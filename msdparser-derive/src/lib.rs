@@ -0,0 +1,118 @@
+//! Derive macro for [`msdparser::FromMsd`](https://docs.rs/msdparser/latest/msdparser/from_msd/trait.FromMsd.html).
+//!
+//! Use it through the `derive` feature of `msdparser` rather than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitStr};
+
+/// Implement `FromMsd` for a struct with named fields.
+///
+/// Every field is read from the key named after it, uppercased with underscores removed, so `sample_start`
+/// is read from `#SAMPLESTART`. The field type must implement `FromMsdValue`. Field attributes:
+///
+/// - `#[msd(key = "TITLETRANSLIT")]` reads the field from another key
+/// - `#[msd(default)]` uses `Default::default()` when the key is missing instead of failing
+#[proc_macro_derive(FromMsd, attributes(msd))]
+pub fn derive_from_msd(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+struct FieldOptions {
+    key: String,
+    default: bool,
+}
+
+fn field_options(field: &syn::Field, name: &str) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions {
+        key: name.replace('_', "").to_ascii_uppercase(),
+        default: false,
+    };
+
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("msd")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("key") {
+                let key: LitStr = meta.value()?.parse()?;
+                options.key = key.value();
+                Ok(())
+            } else if meta.path.is_ident("default") {
+                options.default = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown msd attribute, expected `key = \"...\"` or `default`"))
+            }
+        })?;
+    }
+
+    Ok(options)
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(&input.ident, "FromMsd can only be derived for structs"));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(&input.ident, "FromMsd can only be derived for structs with named fields"));
+    };
+
+    let mut declarations = Vec::new();
+    let mut matches = Vec::new();
+    let mut initializers = Vec::new();
+
+    for (i, field) in fields.named.iter().enumerate() {
+        let ident = field.ident.as_ref().expect("named field");
+        let ty = &field.ty;
+        let name = ident.to_string();
+        let name = name.trim_start_matches("r#");
+        let options = field_options(field, name)?;
+        let key = &options.key;
+        let slot = format_ident!("__field{}", i);
+
+        declarations.push(quote! {
+            let mut #slot: ::core::option::Option<#ty> = ::core::option::Option::None;
+        });
+        // Later occurrences of a key overwrite earlier ones, like in StepMania
+        matches.push(quote! {
+            if key.eq_ignore_ascii_case(#key) {
+                #slot = ::core::option::Option::Some(::msdparser::from_msd::field_value::<#ty>(#name, &parameter)?);
+                continue;
+            }
+        });
+        let missing = if options.default {
+            quote! { ::core::default::Default::default() }
+        } else {
+            quote! { ::msdparser::from_msd::missing_value::<#ty>(#name, #key)? }
+        };
+        initializers.push(quote! {
+            #ident: match #slot {
+                ::core::option::Option::Some(value) => value,
+                ::core::option::Option::None => #missing,
+            }
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::msdparser::from_msd::FromMsd for #ident #ty_generics #where_clause {
+            fn from_msd<I>(parameters: I) -> ::core::result::Result<Self, ::msdparser::from_msd::FromMsdError>
+            where
+                I: ::core::iter::IntoIterator<
+                    Item = ::core::result::Result<::msdparser::MSDParameter, ::msdparser::MSDParserError>,
+                >,
+            {
+                #(#declarations)*
+                for parameter in parameters {
+                    let parameter = parameter.map_err(::msdparser::from_msd::FromMsdError::Parse)?;
+                    let ::core::option::Option::Some(key) = parameter.components.first() else { continue };
+                    let key = key.trim();
+                    #(#matches)*
+                }
+                ::core::result::Result::Ok(Self { #(#initializers,)* })
+            }
+        }
+    })
+}
@@ -0,0 +1,182 @@
+use std::{error, fmt};
+
+use percent_encoding::percent_decode_str;
+
+use crate::parameter::MSDParameter;
+
+/// Keys whose values are paths to files next to the simfile, compared case-insensitively.
+pub const ASSET_KEYS: [&str; 9] = [
+    "MUSIC",
+    "BANNER",
+    "BACKGROUND",
+    "CDTITLE",
+    "JACKET",
+    "CDIMAGE",
+    "DISCIMAGE",
+    "LYRICSPATH",
+    "PREVIEWVID",
+];
+
+/// Whether `key` holds an asset path.
+pub fn is_asset_key(key: &str) -> bool {
+    ASSET_KEYS.iter().any(|k| k.eq_ignore_ascii_case(key.trim()))
+}
+
+/// Why an asset path was rejected.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum AssetPathError {
+    /// Nothing is left after normalization.
+    Empty,
+    /// The path is absolute, or starts with a drive letter or UNC prefix.
+    Absolute,
+    /// The path contains a `..` segment.
+    Traversal,
+    /// The percent-decoded path is not valid UTF-8.
+    InvalidEncoding,
+    /// The path contains a control character, such as a NUL byte.
+    ControlCharacter,
+}
+
+impl fmt::Display for AssetPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetPathError::Empty => write!(f, "path is empty"),
+            AssetPathError::Absolute => write!(f, "path is absolute"),
+            AssetPathError::Traversal => write!(f, "path leaves the song directory"),
+            AssetPathError::InvalidEncoding => write!(f, "path is not valid UTF-8 after percent-decoding"),
+            AssetPathError::ControlCharacter => write!(f, "path contains a control character"),
+        }
+    }
+}
+
+impl error::Error for AssetPathError {}
+
+/// Turn an asset path from untrusted input into a relative path that stays inside the song directory.
+///
+/// The path is percent-decoded, backslashes become `/`, and empty and `.` segments are dropped,
+/// so `.\Song%20A\\song.ogg` becomes `Song A/song.ogg`.
+///
+/// # Errors
+///
+/// Returns an error if the path is absolute, contains `..`, decodes to invalid UTF-8 or control characters,
+/// or is empty.
+pub fn sanitize_asset_path(path: &str) -> Result<String, AssetPathError> {
+    let decoded = percent_decode_str(path.trim())
+        .decode_utf8()
+        .map_err(|_| AssetPathError::InvalidEncoding)?
+        .replace('\\', "/");
+
+    if decoded.chars().any(char::is_control) {
+        return Err(AssetPathError::ControlCharacter);
+    }
+    if decoded.starts_with('/') || has_drive_letter(&decoded) {
+        return Err(AssetPathError::Absolute);
+    }
+
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => return Err(AssetPathError::Traversal),
+            segment => segments.push(segment),
+        }
+    }
+
+    if segments.is_empty() {
+        return Err(AssetPathError::Empty);
+    }
+    Ok(segments.join("/"))
+}
+
+fn has_drive_letter(path: &str) -> bool {
+    let mut chars = path.chars();
+    matches!((chars.next(), chars.next()), (Some(letter), Some(':')) if letter.is_ascii_alphabetic())
+}
+
+/// An asset parameter whose path was rejected.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct AssetPathIssue {
+    /// Index of the parameter.
+    pub parameter: usize,
+    pub key: String,
+    pub path: String,
+    pub error: AssetPathError,
+}
+
+impl fmt::Display for AssetPathIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}:{}: {}", self.key, self.path, self.error)
+    }
+}
+
+/// Sanitize the value of every asset parameter in place, see [`sanitize_asset_path`].
+///
+/// Blank values mean "no asset" and are left alone. Rejected values are left unchanged and reported,
+/// so a caller accepting uploads should refuse the document if anything is returned.
+pub fn sanitize_asset_parameters(parameters: &mut [MSDParameter]) -> Vec<AssetPathIssue> {
+    let mut issues = Vec::new();
+
+    for (i, parameter) in parameters.iter_mut().enumerate() {
+        let Some(key) = parameter.components.first() else { continue };
+        if !is_asset_key(key) {
+            continue;
+        }
+        let Some(value) = parameter.components.get_mut(1) else { continue };
+        if value.trim().is_empty() {
+            continue;
+        }
+
+        match sanitize_asset_path(value) {
+            Ok(path) => *value = path,
+            Err(error) => issues.push(AssetPathIssue {
+                parameter: i,
+                key: parameter.components[0].clone(),
+                path: parameter.components[1].clone(),
+                error,
+            }),
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_asset_path() {
+        assert_eq!(Ok("song.ogg".to_string()), sanitize_asset_path(" song.ogg "));
+        assert_eq!(Ok("Song A/song.ogg".to_string()), sanitize_asset_path(".\\Song%20A\\\\song.ogg"));
+        assert_eq!(Ok("実例.png".to_string()), sanitize_asset_path("%E5%AE%9F%E4%BE%8B.png"));
+
+        assert_eq!(Err(AssetPathError::Absolute), sanitize_asset_path("/etc/passwd"));
+        assert_eq!(Err(AssetPathError::Absolute), sanitize_asset_path("C:\\Windows\\win.ini"));
+        assert_eq!(Err(AssetPathError::Absolute), sanitize_asset_path("\\\\server\\share"));
+        assert_eq!(Err(AssetPathError::Traversal), sanitize_asset_path("../other/song.ogg"));
+        assert_eq!(Err(AssetPathError::Traversal), sanitize_asset_path("a/%2E%2E/b"));
+        assert_eq!(Err(AssetPathError::InvalidEncoding), sanitize_asset_path("%FF.ogg"));
+        assert_eq!(Err(AssetPathError::ControlCharacter), sanitize_asset_path("song%00.ogg"));
+        assert_eq!(Err(AssetPathError::Empty), sanitize_asset_path("./"));
+    }
+
+    #[test]
+    fn test_sanitize_asset_parameters() {
+        let mut parameters = vec![
+            MSDParameter::new(vec!["TITLE".to_string(), "../not a path".to_string()]),
+            MSDParameter::new(vec!["music".to_string(), "audio\\song.ogg".to_string()]),
+            MSDParameter::new(vec!["BANNER".to_string(), "".to_string()]),
+            MSDParameter::new(vec!["BACKGROUND".to_string(), "../../bg.png".to_string()]),
+        ];
+        let issues = sanitize_asset_parameters(&mut parameters);
+
+        assert_eq!("../not a path", parameters[0].components[1]);
+        assert_eq!("audio/song.ogg", parameters[1].components[1]);
+        assert_eq!(vec![AssetPathIssue {
+            parameter: 3,
+            key: "BACKGROUND".to_string(),
+            path: "../../bg.png".to_string(),
+            error: AssetPathError::Traversal,
+        }], issues);
+    }
+}
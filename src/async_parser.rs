@@ -0,0 +1,273 @@
+use std::future::poll_fn;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::diagnostic::DiagnosticSink;
+use crate::extension::MsdExtension;
+use crate::lexer::BUFFER_SIZE;
+use crate::parameter::MSDParameter;
+use crate::parser::{MSDParser, MSDParserError, MSDParserOptions, MSDParserWarning};
+
+/// Bytes received from the async reader that the synchronous parser has not read yet.
+///
+/// Reading past them fails with [`io::ErrorKind::WouldBlock`] until the end of the stream has been seen,
+/// which pauses the parser instead of ending it.
+#[derive(Debug, Default)]
+struct Pipe {
+    bytes: Vec<u8>,
+    read: usize,
+    eof: bool,
+}
+
+impl Pipe {
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes.drain(..self.read);
+        self.read = 0;
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = &self.bytes[self.read..];
+        if remaining.is_empty() && !self.eof {
+            return Err(io::Error::from(io::ErrorKind::WouldBlock));
+        }
+        let length = remaining.len().min(buf.len());
+        buf[..length].copy_from_slice(&remaining[..length]);
+        self.read += length;
+        Ok(length)
+    }
+}
+
+/// Parser for MSD documents arriving through a tokio [`AsyncRead`].
+///
+/// Parameters are yielded as soon as they are complete, so a document streamed over the network
+/// never has to be buffered whole. Behaves exactly like [`MSDParser`] otherwise.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::async_parser::AsyncMSDParser;
+/// # use msdparser::MSDParserOptions;
+/// # async fn example() -> Result<(), msdparser::MSDParserError> {
+/// let input: &[u8] = b"#TITLE:Springtime;\n#ARTIST:Kommisar;";
+/// let mut parser = AsyncMSDParser::new(input, MSDParserOptions::new());
+///
+/// while let Some(parameter) = parser.next_parameter().await {
+///     println!("{:?}", parameter?.key());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AsyncMSDParser<R> {
+    reader: R,
+    read_buffer: Box<[u8]>,
+    parser: MSDParser<Pipe>,
+    /// Whether a read failed, after which nothing more is yielded
+    failed: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncMSDParser<R> {
+    /// Create a new parser from an async reader and [`MSDParserOptions`].
+    ///
+    /// `resumable_reads` is ignored, since waiting for input is handled by the async reader.
+    pub fn new(reader: R, options: MSDParserOptions) -> Self {
+        Self {
+            reader,
+            read_buffer: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            parser: MSDParser::with_options(Pipe::default(), options.resumable_reads(true)),
+            failed: false,
+        }
+    }
+
+    /// Register an [`MsdExtension`], see [`MSDParser::with_extension`].
+    pub fn with_extension<E: MsdExtension + 'static>(mut self, extension: E) -> Self {
+        self.parser = self.parser.with_extension(extension);
+        self
+    }
+
+    /// Switch to diagnostics mode, see [`MSDParser::with_diagnostics`].
+    pub fn with_diagnostics<S: DiagnosticSink + 'static>(mut self, sink: Arc<Mutex<S>>) -> Self {
+        self.parser = self.parser.with_diagnostics(sink);
+        self
+    }
+
+    /// The options this parser was created with.
+    pub fn options(&self) -> &MSDParserOptions {
+        self.parser.options()
+    }
+
+    /// Everything the parser recovered from so far, see [`MSDParser::warnings`].
+    pub fn warnings(&self) -> &[MSDParserWarning] {
+        self.parser.warnings()
+    }
+
+    /// Get the next [`MSDParameter`] from the stream, waiting for more input as needed.
+    ///
+    /// # Errors
+    ///
+    /// See [`MSDParser::next_parameter`].
+    pub async fn next_parameter(&mut self) -> Option<Result<MSDParameter, MSDParserError>> {
+        poll_fn(|cx| self.poll_next_parameter(cx)).await
+    }
+
+    /// Poll for the next [`MSDParameter`], for use in hand-written futures.
+    ///
+    /// Returns `Poll::Pending` when the reader has no more data yet, after registering `cx` to be woken.
+    pub fn poll_next_parameter(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<MSDParameter, MSDParserError>>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+        loop {
+            if let Some(parameter) = self.parser.next_parameter() {
+                return Poll::Ready(Some(parameter));
+            }
+            if self.parser.is_done() {
+                return Poll::Ready(None);
+            }
+
+            // The parser has consumed everything received so far
+            let mut buf = ReadBuf::new(&mut self.read_buffer);
+            let result = ready!(Pin::new(&mut self.reader).poll_read(cx, &mut buf));
+            let pipe = self.parser.get_mut();
+            match result {
+                Ok(()) if buf.filled().is_empty() => pipe.eof = true,
+                Ok(()) => pipe.push(buf.filled()),
+                Err(e) => {
+                    // Every complete parameter was yielded above; drop the one cut off, like the synchronous parser
+                    self.failed = true;
+                    return Poll::Ready(Some(Err(MSDParserError::Io(e))));
+                },
+            }
+        }
+    }
+}
+
+/// With the `futures` feature, the parser is a [`Stream`] of parameters,
+/// so it works with `StreamExt` combinators like `filter` and `try_collect`.
+/// 
+/// [`Stream`]: futures_core::Stream
+#[cfg(feature = "futures")]
+impl<R: AsyncRead + Unpin> futures_core::Stream for AsyncMSDParser<R> {
+    type Item = Result<MSDParameter, MSDParserError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_parameter(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::diagnostic::Severity;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+    }
+
+    async fn collect<R: AsyncRead + Unpin>(mut parser: AsyncMSDParser<R>) -> Vec<Result<MSDParameter, MSDParserError>> {
+        let mut parameters = Vec::new();
+        while let Some(parameter) = parser.next_parameter().await {
+            parameters.push(parameter);
+        }
+        parameters
+    }
+
+    #[test]
+    fn test_matches_sync_parser() {
+        let input = std::fs::read("testdata/Springtime.ssc").unwrap();
+        let parameters = block_on(collect(AsyncMSDParser::new(input.as_slice(), MSDParserOptions::new())));
+        let expected: Vec<_> = crate::parse_msd(input.as_slice(), true, false).collect();
+
+        assert_eq!(expected, parameters);
+    }
+
+    #[test]
+    fn test_streamed_input() {
+        block_on(async {
+            let (mut writer, reader) = tokio::io::duplex(4);
+            let parser = AsyncMSDParser::new(reader, MSDParserOptions::new());
+            let writing = tokio::spawn(async move {
+                for chunk in ["#TITLE:実", "例;\n#NO", "TES:\n0000\n", ";\n#A:B"] {
+                    writer.write_all(chunk.as_bytes()).await.unwrap();
+                }
+            });
+
+            let parameters = collect(parser).await;
+            writing.await.unwrap();
+            let keys: Vec<_> = parameters.into_iter().map(|p| p.unwrap().components).collect();
+            assert_eq!(vec![
+                vec!["TITLE".to_string(), "実例".to_string()],
+                vec!["NOTES".to_string(), "\n0000\n".to_string()],
+                vec!["A".to_string(), "B".to_string()],
+            ], keys);
+        });
+    }
+
+    struct FailingReader {
+        chunk: Option<&'static [u8]>,
+    }
+
+    impl AsyncRead for FailingReader {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            match self.chunk.take() {
+                Some(chunk) => {
+                    buf.put_slice(chunk);
+                    Poll::Ready(Ok(()))
+                },
+                None => Poll::Ready(Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))),
+            }
+        }
+    }
+
+    #[test]
+    fn test_io_error() {
+        let parameters = block_on(collect(AsyncMSDParser::new(FailingReader { chunk: Some(b"#A:B;\n#C:D") }, MSDParserOptions::new())));
+
+        assert_eq!(2, parameters.len());
+        assert_eq!(Ok(MSDParameter::new(vec!["A".to_string(), "B".to_string()])), parameters[0]);
+        assert!(matches!(&parameters[1], Err(MSDParserError::Io(e)) if e.kind() == io::ErrorKind::ConnectionReset));
+    }
+
+    #[test]
+    fn test_stray_text_error() {
+        let parameters = block_on(collect(AsyncMSDParser::new(b"#A:B;\nC".as_ref(), MSDParserOptions::new())));
+
+        assert!(matches!(parameters[1], Err(MSDParserError::StrayText { ch: 'C', .. })));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let parser = AsyncMSDParser::new(b"#A:B;\nC\n#D:E".as_ref(), MSDParserOptions::new()).with_diagnostics(sink.clone());
+        let parameters = block_on(collect(parser));
+
+        assert_eq!(2, parameters.into_iter().map(Result::unwrap).count());
+        let severities: Vec<Severity> = sink.lock().unwrap().iter().map(|d| d.severity).collect();
+        assert_eq!(vec![Severity::Error, Severity::Warning], severities);
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn test_stream() {
+        use futures_util::StreamExt;
+
+        let parser = AsyncMSDParser::new(b"#A:1;\n#NOTES:x;\n#B:2;\n#NOTES:y;".as_ref(), MSDParserOptions::new());
+        let notes: Vec<String> = block_on(parser
+            .filter_map(|p| async move { p.ok().filter(|p| p.key().as_deref() == Some("NOTES")) })
+            .map(|p| p.value().unwrap())
+            .collect());
+
+        assert_eq!(vec!["x".to_string(), "y".to_string()], notes);
+    }
+}
@@ -0,0 +1,418 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::{error, fmt};
+
+use crate::lossless::LosslessDocument;
+use crate::parameter::{MSDParameter, MSDParameterError};
+use crate::parser::{MSDParserError, MSDParserOptions};
+use crate::progress::{ProgressReader, ProgressSink};
+use crate::serialize::{MsdSerialize, MSDSerializeOptions};
+
+const TEMP_SUFFIX: &str = ".msdtmp";
+const BACKUP_SUFFIX: &str = ".msdbak";
+
+/// What went wrong with one file of a batch.
+#[derive(Debug)]
+pub enum BatchErrorKind {
+    Parse(MSDParserError),
+    Edit(String),
+    Serialize(MSDParameterError),
+    Io(io::Error),
+}
+
+/// Custom error type for batch edits, naming the file that failed.
+#[derive(Debug)]
+pub struct BatchError {
+    pub path: PathBuf,
+    pub kind: BatchErrorKind,
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            BatchErrorKind::Parse(e) => write!(f, "{}: {}", self.path.display(), e),
+            BatchErrorKind::Edit(e) => write!(f, "{}: Edit Error: {}", self.path.display(), e),
+            BatchErrorKind::Serialize(e) => write!(f, "{}: {}", self.path.display(), e),
+            BatchErrorKind::Io(e) => write!(f, "{}: IO Error: {}", self.path.display(), e),
+        }
+    }
+}
+
+impl error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.kind {
+            BatchErrorKind::Parse(e) => Some(e),
+            BatchErrorKind::Edit(_) => None,
+            BatchErrorKind::Serialize(e) => Some(e),
+            BatchErrorKind::Io(e) => Some(e),
+        }
+    }
+}
+
+impl BatchError {
+    fn new(path: &Path, kind: BatchErrorKind) -> Self {
+        Self { path: path.to_path_buf(), kind }
+    }
+}
+
+/// Summary of a committed batch.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BatchReport {
+    /// Files that were written, in staging order.
+    pub files: Vec<PathBuf>,
+    /// Total number of bytes written.
+    pub bytes_written: usize,
+}
+
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files committed ({} bytes)", self.files.len(), self.bytes_written)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StagedFile {
+    path: PathBuf,
+    contents: Vec<u8>,
+}
+
+/// A set of edits across many files that is applied all at once or not at all.
+///
+/// Edits are staged in memory first. [`Transaction::commit`] writes every file to a temporary sibling
+/// and then swaps them in with renames. If anything fails, files that were already swapped are restored
+/// from their backups, so a pack is never left half-edited.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use msdparser::batch::Transaction;
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut transaction = Transaction::new();
+/// for path in ["pack/a/a.ssc", "pack/b/b.ssc"] {
+///     transaction.stage(path, |parameters| {
+///         parameters.retain(|p| p.key().as_deref() != Some("PREVIEWVID"));
+///         Ok(())
+///     })?;
+/// }
+/// println!("{}", transaction.commit()?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Transaction {
+    staged: Vec<StagedFile>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of staged files.
+    pub fn len(&self) -> usize {
+        self.staged.len()
+    }
+
+    /// Whether nothing has been staged.
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+
+    /// Parse the file at `path`, apply `edit` to its parameters, and stage the result.
+    ///
+    /// The file is read in the dialect of its extension, see [`MSDParserOptions::for_path`], and written back as
+    /// it was apart from the parameters `edit` changes, adds or removes: comments, blank lines, a byte order mark
+    /// and the text after the last parameter are kept, see [`LosslessDocument::set_parameters`]. Changed
+    /// parameters are escaped the way the dialect reads them, so `.dwi` files keep their backslashes.
+    ///
+    /// If `path` is already staged, `edit` is applied to the staged
+    /// contents instead of the file, so edits of the same file add up and rollback restores the original.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed, if `edit` fails,
+    /// or if the edited parameters cannot be serialized. Nothing is staged in that case.
+    pub fn stage<P, F>(&mut self, path: P, edit: F) -> Result<(), BatchError>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&mut Vec<MSDParameter>) -> Result<(), String>,
+    {
+        self.stage_with_progress(path, &mut (), edit)
+    }
+
+    /// Like [`Transaction::stage`], reporting the file and the bytes read from it to `progress`.
+    ///
+    /// # Errors
+    ///
+    /// See [`Transaction::stage`].
+    pub fn stage_with_progress<P, F>(&mut self, path: P, progress: &mut dyn ProgressSink, edit: F) -> Result<(), BatchError>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&mut Vec<MSDParameter>) -> Result<(), String>,
+    {
+        let path = path.as_ref();
+        progress.on_file(path);
+        let options = MSDParserOptions::for_path(path).keep_comments(true);
+        let parsed = match self.position(path) {
+            Some(i) => LosslessDocument::from_reader(ProgressReader::new(self.staged[i].contents.as_slice(), progress), options),
+            None => {
+                let file = File::open(path).map_err(|e| BatchError::new(path, BatchErrorKind::Io(e)))?;
+                LosslessDocument::from_reader(ProgressReader::new(file, progress), options)
+            },
+        };
+        let mut document = parsed.map_err(|e| BatchError::new(path, BatchErrorKind::Parse(e)))?;
+
+        let mut parameters: Vec<MSDParameter> = document.parameters().iter().map(|p| p.parameter().clone()).collect();
+        edit(&mut parameters).map_err(|e| BatchError::new(path, BatchErrorKind::Edit(e)))?;
+        document.set_parameters(parameters);
+
+        let mut contents = Vec::new();
+        document.serialize_msd(&mut contents, &MSDSerializeOptions::new().escapes(options.escapes))
+            .map_err(|e| BatchError::new(path, BatchErrorKind::Serialize(e)))?;
+
+        self.stage_contents(path, contents);
+        Ok(())
+    }
+
+    /// Stage raw contents to be written to `path`, replacing the contents staged for it before, if any.
+    pub fn stage_contents<P: AsRef<Path>>(&mut self, path: P, contents: Vec<u8>) {
+        let path = path.as_ref();
+        match self.position(path) {
+            Some(i) => self.staged[i].contents = contents,
+            None => self.staged.push(StagedFile { path: path.to_path_buf(), contents }),
+        }
+    }
+
+    /// Index of the file staged for `path`, also when it was staged under another spelling of the same path.
+    fn position(&self, path: &Path) -> Option<usize> {
+        let canonical = fs::canonicalize(path).ok();
+        self.staged.iter().position(|staged| {
+            staged.path == path || (canonical.is_some() && fs::canonicalize(&staged.path).ok() == canonical)
+        })
+    }
+
+    /// Write every staged file, or none of them.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first IO error encountered, after restoring every file touched so far.
+    pub fn commit(self) -> Result<BatchReport, BatchError> {
+        // Write everything next to its destination first, so the renames below cannot run out of space
+        for (i, staged) in self.staged.iter().enumerate() {
+            if let Err(e) = Self::write_temp(staged) {
+                for written in &self.staged[..i] {
+                    let _ = fs::remove_file(sibling(&written.path, TEMP_SUFFIX));
+                }
+                let _ = fs::remove_file(sibling(&staged.path, TEMP_SUFFIX));
+                return Err(BatchError::new(&staged.path, BatchErrorKind::Io(e)));
+            }
+        }
+
+        let mut backed_up = Vec::with_capacity(self.staged.len());
+        for (i, staged) in self.staged.iter().enumerate() {
+            if let Err(e) = Self::swap_in(staged, &mut backed_up) {
+                self.rollback(i, &backed_up);
+                return Err(BatchError::new(&staged.path, BatchErrorKind::Io(e)));
+            }
+        }
+
+        for path in backed_up.iter().flatten() {
+            let _ = fs::remove_file(sibling(path, BACKUP_SUFFIX));
+        }
+
+        Ok(BatchReport {
+            files: self.staged.iter().map(|s| s.path.clone()).collect(),
+            bytes_written: self.staged.iter().map(|s| s.contents.len()).sum(),
+        })
+    }
+
+    fn write_temp(staged: &StagedFile) -> io::Result<()> {
+        let mut file = File::create(sibling(&staged.path, TEMP_SUFFIX))?;
+        file.write_all(&staged.contents)?;
+        file.sync_all()
+    }
+
+    /// Move the original out of the way and the temporary file into place.
+    ///
+    /// Pushes the path to `backed_up` if the original existed, or `None` if the file is new.
+    fn swap_in(staged: &StagedFile, backed_up: &mut Vec<Option<PathBuf>>) -> io::Result<()> {
+        if staged.path.exists() {
+            fs::rename(&staged.path, sibling(&staged.path, BACKUP_SUFFIX))?;
+            backed_up.push(Some(staged.path.clone()));
+        } else {
+            backed_up.push(None);
+        }
+        fs::rename(sibling(&staged.path, TEMP_SUFFIX), &staged.path)
+    }
+
+    /// Undo the swaps of the first `failed + 1` files and remove all temporary files.
+    fn rollback(&self, failed: usize, backed_up: &[Option<PathBuf>]) {
+        for (staged, backup) in self.staged[..=failed].iter().zip(backed_up) {
+            match backup {
+                Some(path) => { let _ = fs::rename(sibling(path, BACKUP_SUFFIX), path); },
+                None => { let _ = fs::remove_file(&staged.path); },
+            }
+        }
+        for staged in &self.staged {
+            let _ = fs::remove_file(sibling(&staged.path, TEMP_SUFFIX));
+        }
+    }
+}
+
+/// `path` with `suffix` appended to the file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_commit() {
+        let dir = TempDir::new("batch-commit");
+        let a = dir.join("a.sm");
+        let b = dir.join("b.sm");
+        fs::write(&a, "#TITLE:A;\n#ARTIST:X;\n").unwrap();
+        fs::write(&b, "#TITLE:B;\n").unwrap();
+
+        let mut transaction = Transaction::new();
+        for path in [&a, &b] {
+            transaction.stage(path, |parameters| {
+                parameters[0].components[1].push_str(" (Remix)");
+                Ok(())
+            }).unwrap();
+        }
+        let report = transaction.commit().unwrap();
+
+        assert_eq!(vec![a.clone(), b.clone()], report.files);
+        assert_eq!("#TITLE:A (Remix);\n#ARTIST:X;\n", fs::read_to_string(&a).unwrap());
+        assert_eq!("#TITLE:B (Remix);\n", fs::read_to_string(&b).unwrap());
+        assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn test_commit_keeps_source_text() {
+        let dir = TempDir::new("batch-lossless");
+        let sm = dir.join("a.sm");
+        let dwi = dir.join("b.dwi");
+        let sm_input = "\u{feff}// header\r\n#TITLE:A; // title\r\n\r\n#ARTIST:X;\r\n// footer\r\n";
+        let dwi_input = "#TITLE:B;\n#FILE:C:\\Songs\\b.mp3;\n";
+        fs::write(&sm, sm_input).unwrap();
+        fs::write(&dwi, dwi_input).unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.stage(&sm, |_| Ok(())).unwrap();
+        transaction.stage(&dwi, |_| Ok(())).unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(sm_input, fs::read_to_string(&sm).unwrap());
+        assert_eq!(dwi_input, fs::read_to_string(&dwi).unwrap());
+
+        let mut transaction = Transaction::new();
+        transaction.stage(&sm, |parameters| {
+            parameters[1].components[1] = "Y".to_string();
+            Ok(())
+        }).unwrap();
+        transaction.stage(&dwi, |parameters| {
+            parameters[0].components[1] = "B\\C".to_string();
+            Ok(())
+        }).unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(sm_input.replace("#ARTIST:X;", "#ARTIST:Y;"), fs::read_to_string(&sm).unwrap());
+        assert_eq!("#TITLE:B\\C;\n#FILE:C:\\Songs\\b.mp3;\n", fs::read_to_string(&dwi).unwrap());
+    }
+
+    #[test]
+    fn test_failed_stage() {
+        let dir = TempDir::new("batch-stage");
+        let a = dir.join("a.sm");
+        fs::write(&a, "#TITLE:A;\n").unwrap();
+
+        let mut transaction = Transaction::new();
+        let error = transaction.stage(&a, |_| Err("nope".to_string())).unwrap_err();
+
+        assert_eq!(a, error.path);
+        assert!(matches!(error.kind, BatchErrorKind::Edit(_)));
+        assert!(transaction.is_empty());
+    }
+
+    #[test]
+    fn test_stage_twice() {
+        let dir = TempDir::new("batch-twice");
+        let a = dir.join("a.sm");
+        fs::write(&a, "#TITLE:A;\n").unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.stage(&a, |parameters| {
+            parameters[0].components[1].push('1');
+            Ok(())
+        }).unwrap();
+        transaction.stage(dir.join(".").join("a.sm"), |parameters| {
+            parameters[0].components[1].push('2');
+            Ok(())
+        }).unwrap();
+        assert_eq!(1, transaction.len());
+
+        transaction.stage_contents(dir.join("missing").join("b.sm"), b"#TITLE:B;\n".to_vec());
+        assert!(transaction.commit().is_err());
+        assert_eq!("#TITLE:A;\n", fs::read_to_string(&a).unwrap());
+
+        let mut transaction = Transaction::new();
+        transaction.stage(&a, |parameters| {
+            parameters[0].components[1].push('1');
+            Ok(())
+        }).unwrap();
+        transaction.stage(&a, |parameters| {
+            parameters[0].components[1].push('2');
+            Ok(())
+        }).unwrap();
+        transaction.commit().unwrap();
+        assert_eq!("#TITLE:A12;\n", fs::read_to_string(&a).unwrap());
+    }
+
+    #[test]
+    fn test_rollback() {
+        let dir = TempDir::new("batch-rollback");
+        let a = dir.join("a.sm");
+        fs::write(&a, "#TITLE:A;\n").unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.stage_contents(&a, b"#TITLE:changed;\n".to_vec());
+        transaction.stage_contents(dir.join("missing").join("b.sm"), b"#TITLE:B;\n".to_vec());
+        let error = transaction.commit().unwrap_err();
+
+        assert_eq!(dir.join("missing").join("b.sm"), error.path);
+        assert_eq!("#TITLE:A;\n", fs::read_to_string(&a).unwrap());
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn test_rollback_after_swap() {
+        let dir = TempDir::new("batch-swap");
+        let a = dir.join("a.sm");
+        let b = dir.join("b.sm");
+        fs::write(&a, "#TITLE:A;\n").unwrap();
+        fs::write(&b, "#TITLE:B;\n").unwrap();
+        // A file can't be renamed over a non-empty directory, so backing up b fails after a was swapped in
+        fs::create_dir_all(sibling(&b, BACKUP_SUFFIX).join("inner")).unwrap();
+
+        let mut transaction = Transaction::new();
+        transaction.stage_contents(&a, b"#TITLE:changed;\n".to_vec());
+        transaction.stage_contents(&b, b"#TITLE:changed;\n".to_vec());
+        let error = transaction.commit().unwrap_err();
+
+        assert_eq!(b, error.path);
+        assert!(matches!(error.kind, BatchErrorKind::Io(_)));
+        assert!(error::Error::source(&error).is_some());
+        assert_eq!("#TITLE:A;\n", fs::read_to_string(&a).unwrap());
+        assert_eq!("#TITLE:B;\n", fs::read_to_string(&b).unwrap());
+        let mut names: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(vec!["a.sm", "b.sm", "b.sm.msdbak"], names);
+    }
+}
@@ -0,0 +1,655 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use crate::diagnostic::{report, DiagnosticSink, Severity, SharedSink};
+use crate::lexer::{stuck_error, Dialect, EscapePolicy, LexerCore, MSDLexerError, MSDToken, Position};
+use crate::parameter::{Comment, CommentPlacement, MSDParameter, ValuePresence};
+use crate::tags::KnownTag;
+use crate::parser::{comment_text, is_ksf_step, ksf_step_cut, recovered_len, reserve_for, stray_text_diagnostic, stray_text_error, stray_text_range, MSDParserError, MSDParserOptions, MSDParserWarning};
+
+/// An MSD parameter whose components borrow from the input where possible.
+///
+/// A component is only allocated when it is not a contiguous slice of the input,
+/// i.e. when it contains an escape sequence or is interrupted by a comment.
+/// 
+/// Like [`MSDParameter`], parameters compare by their components only, and the struct is `#[non_exhaustive]`
+/// for the same reason: construct one with [`MSDParameterRef::new`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct MSDParameterRef<'a> {
+    pub components: Vec<Cow<'a, str>>,
+    /// Byte range of the `#...;` block in the input, if the parameter was parsed.
+    /// 
+    /// See [`MSDParameter::span`](../parameter/struct.MSDParameter.html#structfield.span).
+    pub span: Option<Range<usize>>,
+    /// The `//` comments before the parameter, inside it and after it.
+    /// 
+    /// See [`MSDParameter::comments`].
+    pub comments: Vec<Comment<&'a str>>,
+    /// Stray text between the previous parameter and this one.
+    /// 
+    /// See [`MSDParameter::stray_text`].
+    pub stray_text: Option<&'a str>,
+}
+
+impl PartialEq for MSDParameterRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.components == other.components
+    }
+}
+
+impl Hash for MSDParameterRef<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.components.hash(state);
+    }
+}
+
+impl PartialOrd for MSDParameterRef<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.components.partial_cmp(&other.components)
+    }
+}
+
+impl<'a> MSDParameterRef<'a> {
+    pub fn new(components: Vec<Cow<'a, str>>) -> Self {
+        Self { components, span: None, comments: Vec::new(), stray_text: None }
+    }
+
+    /// The first MSD component, the part immediately after the `#` sign.
+    ///
+    /// See [`MSDParameter::key`](../parameter/struct.MSDParameter.html#method.key).
+    pub fn key(&self) -> Option<&str> {
+        self.components.first().map(|c| c.as_ref())
+    }
+
+    /// The key as a [`KnownTag`], or `None` if StepMania doesn't know it.
+    ///
+    /// See [`MSDParameter::known_key`].
+    pub fn known_key(&self) -> Option<KnownTag> {
+        self.key()?.parse().ok()
+    }
+
+    /// The second MSD component, seperated from the key by a `:`
+    ///
+    /// See [`MSDParameter::value`](../parameter/struct.MSDParameter.html#method.value).
+    pub fn value(&self) -> Option<&str> {
+        self.components.get(1).map(|c| c.as_ref())
+    }
+
+    /// Byte offset of the `#` starting the parameter in the input, if it was parsed.
+    ///
+    /// See [`MSDParameter::offset`].
+    pub fn offset(&self) -> Option<usize> {
+        self.span.as_ref().map(|span| span.start)
+    }
+
+    /// Length in bytes of the parameter in the input, if it was parsed.
+    ///
+    /// See [`MSDParameter::byte_len`].
+    pub fn byte_len(&self) -> Option<usize> {
+        self.span.as_ref().map(|span| span.end - span.start)
+    }
+
+    /// Whether the value is there, empty, or missing along with its `:`.
+    ///
+    /// See [`MSDParameter::value_presence`].
+    pub fn value_presence(&self) -> ValuePresence {
+        match self.value() {
+            Some("") => ValuePresence::Empty,
+            Some(_) => ValuePresence::Present,
+            None => ValuePresence::Missing,
+        }
+    }
+
+    /// Copy every component into an owned [`MSDParameter`].
+    pub fn into_owned(self) -> MSDParameter {
+        let mut parameter = MSDParameter::new(self.components.into_iter().map(Cow::into_owned).collect());
+        parameter.span = self.span;
+        parameter.comments = self.comments.into_iter().map(|comment| Comment::new(comment.text.to_owned(), comment.placement)).collect();
+        parameter.stray_text = self.stray_text.map(str::to_owned);
+        parameter
+    }
+}
+
+impl From<MSDParameterRef<'_>> for MSDParameter {
+    fn from(parameter: MSDParameterRef<'_>) -> Self {
+        parameter.into_owned()
+    }
+}
+
+/// Zero-copy parser for MSD data held entirely in memory.
+///
+/// Behaves like [`MSDParser`], but yields [`MSDParameterRef`]s borrowing from the input
+/// instead of allocating a `String` per component. Extensions are not supported.
+///
+/// [`MSDParser`]: ../parser/struct.MSDParser.html
+#[derive(Debug, Clone)]
+pub struct MSDStrParser<'a> {
+    input: &'a str,
+    position: usize,
+    core: LexerCore,
+    ignore_stray_text: bool,
+    lenient_escapes: bool,
+    keep_comments: bool,
+    keep_stray_text: bool,
+    strict_semicolons: bool,
+    dialect: Dialect,
+    expected_value_size: usize,
+    warnings: Vec<MSDParserWarning>,
+
+    components: Vec<Cow<'a, str>>,
+    comments: Vec<Comment<&'a str>>,
+    /// With [`MSDParserOptions::keep_comments`], the last finished parameter, held back until the next one starts
+    /// or the input ends so that the comments after it can be attached
+    held: Option<MSDParameterRef<'a>>,
+    /// Whether a line break followed the held parameter, after which comments belong to the next one
+    held_line_ended: bool,
+    /// Error found while a parameter was held, returned after it
+    deferred_error: Option<MSDParserError>,
+    /// Range of the input from the first to the last stray text since the last parameter
+    stray: Option<Range<usize>>,
+    /// Stray text before the current parameter
+    stray_text: Option<&'a str>,
+    /// Range of the input borrowed by the last component, if it is borrowed and non-empty
+    borrowed: Option<Range<usize>>,
+    inside_parameter: bool,
+    /// Byte offset of the `#` starting the current parameter
+    parameter_start: usize,
+    /// Byte offset of the `#` starting the first parameter
+    first_start: Option<usize>,
+    /// Byte offset right after the last parameter
+    last_end: usize,
+    last_key: Option<Cow<'a, str>>,
+    diagnostics: Option<SharedSink>,
+}
+
+impl<'a> MSDStrParser<'a> {
+    /// Create a new parser over a string.
+    ///
+    /// `escapes` is the [`EscapePolicy`], or a `bool` for whether or not to escape special text.
+    /// `ignore_stray_text` indicates whether or not to ignore stray text.
+    pub fn new(input: &'a str, escapes: impl Into<EscapePolicy>, ignore_stray_text: bool) -> Self {
+        Self::with_options(input, MSDParserOptions::new().escapes(escapes).ignore_stray_text(ignore_stray_text))
+    }
+
+    /// Create a new parser over a string with the given [`MSDParserOptions`].
+    /// 
+    /// Resumable reads are irrelevant here, since the whole input is available up front.
+    pub fn with_options(input: &'a str, options: MSDParserOptions) -> Self {
+        let mut core = LexerCore::new(options.escapes);
+        core.set_recovery(options.recovery);
+        core.set_dialect(options.dialect);
+        Self {
+            input,
+            position: 0,
+            core,
+            ignore_stray_text: options.ignore_stray_text,
+            lenient_escapes: options.lenient_escapes,
+            keep_comments: options.keep_comments,
+            expected_value_size: options.expected_value_size,
+            keep_stray_text: options.keep_stray_text,
+            strict_semicolons: options.strict_semicolons,
+            dialect: options.dialect,
+            warnings: Vec::new(),
+
+            components: Vec::new(),
+            comments: Vec::new(),
+            held: None,
+            held_line_ended: false,
+            deferred_error: None,
+            stray: None,
+            stray_text: None,
+            borrowed: None,
+            inside_parameter: false,
+            parameter_start: 0,
+            first_start: None,
+            last_end: 0,
+            last_key: None,
+            diagnostics: None,
+        }
+    }
+
+    /// Switch to diagnostics mode, see [`MSDParser::with_diagnostics`](crate::parser::MSDParser::with_diagnostics).
+    pub fn with_diagnostics<S: DiagnosticSink + 'static>(mut self, sink: Arc<Mutex<S>>) -> Self {
+        self.diagnostics = Some(sink);
+        self
+    }
+
+    fn diagnose(&self, severity: Severity, position: Position, message: String) {
+        report(self.diagnostics.as_ref(), severity, position, message);
+    }
+
+    /// Text before the first parameter. See [`MSDParser::preamble`](crate::parser::MSDParser::preamble).
+    pub fn preamble(&self) -> &'a str {
+        &self.input[..self.first_start.unwrap_or(self.position)]
+    }
+
+    /// Text after the last parameter. See [`MSDParser::suffix`](crate::parser::MSDParser::suffix).
+    pub fn suffix(&self) -> &'a str {
+        if self.first_start.is_some() && !self.inside_parameter { &self.input[self.last_end..self.position] } else { "" }
+    }
+
+    /// Everything the parser recovered from so far. See [`MSDParser::warnings`](crate::parser::MSDParser::warnings).
+    pub fn warnings(&self) -> &[MSDParserWarning] {
+        &self.warnings
+    }
+
+    /// Append `input[range]` to the last component, borrowing if it continues the borrowed slice.
+    fn push_text(&mut self, range: Range<usize>) {
+        let Some(component) = self.components.last_mut() else { return };
+
+        match &self.borrowed {
+            Some(borrowed) if borrowed.end == range.start => {
+                let extended = borrowed.start..range.end;
+                *component = Cow::Borrowed(&self.input[extended.clone()]);
+                self.borrowed = Some(extended);
+            },
+            _ if component.is_empty() => {
+                *component = Cow::Borrowed(&self.input[range.clone()]);
+                self.borrowed = Some(range);
+            },
+            _ => {
+                let owned = component.to_mut();
+                reserve_for(owned, range.len(), self.expected_value_size);
+                owned.push_str(&self.input[range]);
+                self.borrowed = None;
+            },
+        }
+    }
+
+    fn start_component(&mut self) {
+        self.components.push(Cow::Borrowed(""));
+        self.borrowed = None;
+    }
+
+    /// Cut the last component as the dialect does when the next `#` ends the parameter instead of its `;`.
+    fn trim_recovered(&mut self) {
+        let Some(component) = self.components.last_mut() else { return };
+        let length = recovered_len(component, self.dialect);
+        match component {
+            Cow::Borrowed(text) => *text = &text[..length],
+            Cow::Owned(text) => text.truncate(length),
+        }
+    }
+
+    /// Build a parameter from the collected components, ending at byte offset `end`.
+    fn finish_parameter(&mut self, end: usize) -> MSDParameterRef<'a> {
+        let mut components: Vec<Cow<'a, str>> = self.components.drain(..).collect();
+        if is_ksf_step(components.first().map_or("", |key| key), self.dialect) {
+            if let Some((component, length)) = ksf_step_cut(&components) {
+                components.truncate(component + 1);
+                match &mut components[component] {
+                    Cow::Borrowed(data) => *data = &data[..length],
+                    Cow::Owned(data) => data.truncate(length),
+                }
+            }
+        }
+        let mut parameter = MSDParameterRef::new(components);
+        parameter.span = Some(self.parameter_start..end);
+        parameter.comments = std::mem::take(&mut self.comments);
+        parameter.stray_text = self.stray_text.take();
+        self.last_end = end;
+        self.borrowed = None;
+        self.last_key = parameter.components.first().cloned();
+        parameter
+    }
+
+    /// Recover `parameter`, which was ended at byte offset `end` by something other than its `;`,
+    /// unless [`MSDParserOptions::strict_semicolons`] is set outside of diagnostics mode.
+    fn unterminated(&self, parameter: MSDParameterRef<'a>, end: usize) -> Result<MSDParameterRef<'a>, MSDParserError> {
+        // KSF step data has no `;` and was already cut at its end by finish_parameter
+        if is_ksf_step(parameter.key().unwrap_or_default(), self.dialect) {
+            return Ok(parameter);
+        }
+        let key = parameter.key().unwrap_or_default();
+        if self.diagnostics.is_some() {
+            let severity = if self.strict_semicolons { Severity::Error } else { Severity::Warning };
+            self.diagnose(severity, Position::of(self.input, end), format!("missing ';' after '{}' parameter", key));
+        } else if self.strict_semicolons {
+            return Err(MSDParserError::MissingSemicolon { key: key.to_string(), position: Position::of(self.input, end) });
+        }
+        Ok(parameter)
+    }
+
+    /// Get the next [`MSDParameterRef`] from the input.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a stray text token is encountered and `ignore_stray_text` is `false`,
+    /// if the input ends in a backslash without [`MSDParserOptions::lenient_escapes`],
+    /// if a `;` is missing with [`MSDParserOptions::strict_semicolons`],
+    /// or if the lexer cannot make sense of the remaining input.
+    pub fn next_parameter(&mut self) -> Option<Result<MSDParameterRef<'a>, MSDParserError>> {
+        if let Some(e) = self.deferred_error.take() {
+            return Some(Err(e));
+        }
+        match self.read_parameter() {
+            Some(Err(e)) => match self.held.take() {
+                Some(held) => {
+                    self.deferred_error = Some(e);
+                    Some(Ok(held))
+                },
+                None => Some(Err(e)),
+            },
+            None => {
+                let mut held = self.held.take()?;
+                held.comments.extend(self.comments.drain(..).map(|comment| Comment::new(comment.text, CommentPlacement::After)));
+                Some(Ok(held))
+            },
+            result => result,
+        }
+    }
+
+    fn read_parameter(&mut self) -> Option<Result<MSDParameterRef<'a>, MSDParserError>> {
+        while self.position < self.input.len() {
+            let start = self.position;
+            let (token, length) = match self.core.match_token(&self.input[start..]) {
+                Some(matched) => matched,
+                None => match stuck_error(self.input[start..].to_string(), Position::of(self.input, start)) {
+                    MSDLexerError::UnterminatedEscape { position } if self.lenient_escapes || self.diagnostics.is_some() => {
+                        self.warnings.push(MSDParserWarning::UnterminatedEscape { position });
+                        self.diagnose(Severity::Warning, position, "unterminated escape at end of input, kept as a backslash".to_string());
+                        (MSDToken::Text, self.input.len() - start)
+                    },
+                    e => {
+                        self.position = self.input.len();
+                        return Some(Err(e.into()));
+                    },
+                },
+            };
+            self.position += length;
+            let text = &self.input[start..self.position];
+
+            match token {
+                MSDToken::Text | MSDToken::Escape => {
+                    if self.inside_parameter {
+                        let skip = if token == MSDToken::Escape { 1 } else { 0 };
+                        self.push_text(start + skip..self.position);
+                        continue;
+                    }
+                    self.held_line_ended |= text.contains(['\n', '\r']);
+                    if self.keep_stray_text {
+                        if let Some(range) = stray_text_range(text) {
+                            let stray_start = self.stray.as_ref().map_or(start + range.start, |stray| stray.start);
+                            self.stray = Some(stray_start..start + range.end);
+                        }
+                    } else if self.diagnostics.is_some() {
+                        if let Some((position, message)) = stray_text_diagnostic(text, self.last_key.as_deref(), Position::of(self.input, start)) {
+                            self.diagnose(Severity::Error, position, message);
+                        }
+                    } else if !self.ignore_stray_text {
+                        if let Some(e) = stray_text_error(text, self.last_key.as_deref(), Position::of(self.input, start)) {
+                            return Some(Err(e));
+                        }
+                    }
+                },
+                MSDToken::StartParameter => {
+                    if self.inside_parameter {
+                        self.trim_recovered();
+                    }
+                    let was_inside = self.inside_parameter;
+                    let parameter = was_inside.then(|| self.finish_parameter(start));
+                    self.stray_text = self.stray.take().map(|stray| &self.input[stray]);
+
+                    self.inside_parameter = true;
+                    self.parameter_start = start;
+                    self.first_start.get_or_insert(start);
+                    self.start_component();
+                    if let Some(parameter) = parameter {
+                        return Some(self.unterminated(parameter, start));
+                    }
+                    if let Some(held) = self.held.take() {
+                        return Some(Ok(held));
+                    }
+                },
+                MSDToken::EndParameter => if self.inside_parameter {
+                    self.inside_parameter = false;
+                    let parameter = self.finish_parameter(self.position);
+                    if !self.keep_comments {
+                        return Some(Ok(parameter));
+                    }
+                    self.held = Some(parameter);
+                    self.held_line_ended = false;
+                },
+                MSDToken::NextComponent => if self.inside_parameter {
+                    self.start_component();
+                },
+                MSDToken::Comment => if self.keep_comments {
+                    let comment = comment_text(text);
+                    match (&mut self.held, self.inside_parameter) {
+                        (_, true) => {
+                            let component = self.components.len().saturating_sub(1);
+                            let offset = self.components.last().map_or(0, |component| component.len());
+                            self.comments.push(Comment::new(comment, CommentPlacement::Inside { component, offset }));
+                        },
+                        (Some(held), false) if !self.held_line_ended => held.comments.push(Comment::new(comment, CommentPlacement::SameLine)),
+                        _ => self.comments.push(Comment::new(comment, CommentPlacement::Before)),
+                    }
+                    if !self.inside_parameter {
+                        self.held_line_ended |= text.ends_with('\r');
+                    }
+                },
+            }
+        }
+
+        // Handle missing `;` at the end of the input
+        if self.inside_parameter {
+            self.inside_parameter = false;
+            let parameter = self.finish_parameter(self.input.len());
+            return Some(self.unterminated(parameter, self.input.len()));
+        }
+
+        None
+    }
+}
+
+impl<'a> Iterator for MSDStrParser<'a> {
+    type Item = Result<MSDParameterRef<'a>, MSDParserError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_parameter()
+    }
+}
+
+/// Parse an MSD document held in a string without copying its components.
+///
+/// Returns an [`MSDStrParser`], which is an [`Iterator`] of type [`Result<MSDParameterRef, MSDParserError>`].
+/// This is the fastest way to analyze large files that are already in memory.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::borrow::Cow;
+/// # use msdparser::parse_msd_str;
+/// let input = "#TITLE:Springtime;\n#ARTIST:Kommisar\\;;";
+/// let parameters: Vec<_> = parse_msd_str(input, true, false).collect::<Result<_, _>>().unwrap();
+///
+/// assert_eq!(parameters[0].value(), Some("Springtime"));
+/// assert!(matches!(parameters[0].components[1], Cow::Borrowed(_)));
+/// assert_eq!(parameters[1].value(), Some("Kommisar;"));
+/// ```
+pub fn parse_msd_str(input: &str, escapes: impl Into<EscapePolicy>, ignore_stray_text: bool) -> MSDStrParser<'_> {
+    MSDStrParser::new(input, escapes, ignore_stray_text)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::lexer::RecoveryPolicy;
+    use crate::parser::{parse_msd, parse_msd_with_options};
+
+    fn assert_same_as_streaming(input: &str, escapes: bool, ignore_stray_text: bool) {
+        let borrowed: Vec<Result<MSDParameter, MSDParserError>> = parse_msd_str(input, escapes, ignore_stray_text)
+            .map(|p| p.map(MSDParameterRef::into_owned))
+            .collect();
+        let owned: Vec<Result<MSDParameter, MSDParserError>> = parse_msd(input.as_bytes(), escapes, ignore_stray_text)
+            .collect();
+        assert_eq!(owned, borrowed, "{:?}", input);
+
+        let spans = |parameters: &[Result<MSDParameter, MSDParserError>]| -> Vec<_> {
+            parameters.iter().map(|p| p.as_ref().ok().and_then(|p| p.span.clone())).collect()
+        };
+        assert_eq!(spans(&owned), spans(&borrowed), "{:?}", input);
+    }
+
+    #[test]
+    fn test_borrows_plain_components() {
+        let mut parser = parse_msd_str("#A:B\nC:;", true, false);
+        let parameter = parser.next().unwrap().unwrap();
+
+        assert_eq!(3, parameter.components.len());
+        assert!(parameter.components.iter().all(|c| matches!(c, Cow::Borrowed(_))));
+        assert_eq!("B\nC", parameter.components[1]);
+        assert_eq!(None, parser.next());
+    }
+
+    #[test]
+    fn test_allocates_only_when_needed() {
+        let mut parser = parse_msd_str("#A\\:B:C// comment\nD;", true, false);
+        let parameter = parser.next().unwrap().unwrap();
+
+        assert!(matches!(parameter.components[0], Cow::Owned(_)));
+        assert_eq!("A:B", parameter.components[0]);
+        assert!(matches!(parameter.components[1], Cow::Owned(_)));
+        assert_eq!("C\nD", parameter.components[1]);
+    }
+
+    #[test]
+    fn test_matches_streaming_parser() {
+        let inputs = [
+            "",
+            "#A1,./'\"[]{\\\\}|`~!@#$%^&*()-_=+ \r\n\t:A1,./'\"[]{\\\\}|`~!@#$%^&*()-_=+ \r\n\t:;",
+            "#A// comment //\r\nBC:D// ; \nEF;//#NO:PE;",
+            "#A:B\nCD;#E:FGH\n#IJKL// comment\n#M:NOP",
+            "#A:B;n#C:D;",
+            "TITLE:oops;",
+            "#A\\:B:C\\;D;#E\\#F:G\\\\H;#LF:\\\nLF;",
+            "#A:B\\",
+            "#A\\:B:C;D",
+            "#A\\:B:C;D",
+            "\u{feff}#TITLE:実例;\n#ARTIST:楽士;",
+        ];
+        for input in inputs {
+            for (escapes, ignore_stray_text) in [(true, false), (false, false), (true, true), (false, true)] {
+                assert_same_as_streaming(input, escapes, ignore_stray_text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lenient_escapes() {
+        let options = MSDParserOptions::new().lenient_escapes(true);
+        let mut parser = MSDStrParser::with_options("#A:B\\", options);
+        let mut streaming = crate::parser::parse_msd_with_options("#A:B\\".as_bytes(), options);
+
+        assert_eq!(streaming.next().unwrap().unwrap(), parser.next().unwrap().unwrap().into_owned());
+        assert_eq!(None, parser.next());
+        assert_eq!(streaming.warnings(), parser.warnings());
+        assert_eq!(1, parser.warnings().len());
+    }
+
+    #[test]
+    fn test_recovery_policies() {
+        let input = "#A:B\n#C:D #E:F;";
+        for recovery in [RecoveryPolicy::Off, RecoveryPolicy::LineStart, RecoveryPolicy::Aggressive] {
+            let options = MSDParserOptions::new().recovery(recovery);
+            let parameters: Vec<MSDParameter> = MSDStrParser::with_options(input, options).map(|p| p.unwrap().into_owned()).collect();
+            let streaming: Vec<MSDParameter> = crate::parser::parse_msd_with_options(input.as_bytes(), options).map(Result::unwrap).collect();
+            assert_eq!(streaming, parameters);
+        }
+
+        let count = |recovery| MSDStrParser::with_options(input, MSDParserOptions::new().recovery(recovery)).count();
+        assert_eq!((1, 2, 3), (count(RecoveryPolicy::Off), count(RecoveryPolicy::LineStart), count(RecoveryPolicy::Aggressive)));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let input = "x #A:B\n#C:D;\n;#E:F\\";
+        let options = MSDParserOptions::new().ignore_stray_text(true);
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let streaming_sink = Arc::new(Mutex::new(Vec::new()));
+
+        let values: Vec<String> = MSDStrParser::with_options(input, options).with_diagnostics(sink.clone())
+            .map(|p| p.unwrap().value().unwrap().to_string())
+            .collect();
+        let streaming: Vec<String> = parse_msd_with_options(input.as_bytes(), options).with_diagnostics(streaming_sink.clone())
+            .map(|p| p.unwrap().value().unwrap())
+            .collect();
+        assert_eq!(streaming, values);
+        assert_eq!(5, sink.lock().unwrap().len());
+        assert_eq!(*streaming_sink.lock().unwrap(), *sink.lock().unwrap());
+    }
+
+    #[test]
+    fn test_strict_semicolons() {
+        let input = "#A:B\n#C:D;#E:F";
+        let options = MSDParserOptions::new().strict_semicolons(true);
+        let parameters: Vec<_> = MSDStrParser::with_options(input, options).map(|p| p.map(MSDParameterRef::into_owned)).collect();
+        let streaming: Vec<_> = crate::parser::parse_msd_with_options(input.as_bytes(), options).collect();
+
+        assert_eq!(streaming, parameters);
+        assert_eq!(Some(Position { offset: 5, line: 2, column: 1 }), parameters[0].as_ref().unwrap_err().position());
+        assert_eq!(Some(Position { offset: 14, line: 2, column: 10 }), parameters[2].as_ref().unwrap_err().position());
+    }
+
+    #[test]
+    fn test_keep_stray_text() {
+        let input = "stray\n#A:B;\n\\; more // c\nstray;\n#C:D;#E:F;";
+        let options = MSDParserOptions::new().keep_stray_text(true);
+        let parameters: Vec<MSDParameterRef> = MSDStrParser::with_options(input, options).map(Result::unwrap).collect();
+        let streaming: Vec<MSDParameter> = crate::parser::parse_msd_with_options(input.as_bytes(), options).map(Result::unwrap).collect();
+
+        let stray_text: Vec<_> = parameters.iter().map(|p| p.stray_text).collect();
+        assert_eq!(vec![Some("stray"), Some("\\; more // c\nstray;"), None], stray_text);
+        let streaming_stray_text: Vec<_> = streaming.iter().map(|p| p.stray_text.as_deref()).collect();
+        assert_eq!(stray_text, streaming_stray_text);
+    }
+
+    #[test]
+    fn test_keep_comments() {
+        let input = "// a\n#A:B//b\n;#C:D; // c\n// d";
+        let options = MSDParserOptions::new().keep_comments(true);
+        let parameters: Vec<MSDParameterRef> = MSDStrParser::with_options(input, options).map(Result::unwrap).collect();
+        let streaming: Vec<MSDParameter> = crate::parser::parse_msd_with_options(input.as_bytes(), options).map(Result::unwrap).collect();
+
+        assert_eq!(vec![
+            Comment::new(" a", CommentPlacement::Before),
+            Comment::new("b", CommentPlacement::Inside { component: 1, offset: 1 }),
+        ], parameters[0].comments);
+        assert_eq!(vec![Comment::new(" c", CommentPlacement::SameLine), Comment::new(" d", CommentPlacement::After)], parameters[1].comments);
+        for (parameter, streaming) in parameters.iter().zip(&streaming) {
+            assert_eq!(streaming.comments, parameter.clone().into_owned().comments);
+        }
+
+        let mut parser = MSDStrParser::with_options("#A:B; // c\nstray", options);
+        assert_eq!(vec![Comment::new(" c", CommentPlacement::SameLine)], parser.next().unwrap().unwrap().comments);
+        assert!(matches!(parser.next(), Some(Err(MSDParserError::StrayText { .. }))));
+    }
+
+    #[test]
+    fn test_preamble_and_suffix() {
+        for input in ["  // a\n#A:B;\n// b\n", "stray #A:B;", "#A:B", "// only", ""] {
+            let options = MSDParserOptions::new().ignore_stray_text(true);
+            let mut parser = MSDStrParser::with_options(input, options);
+            let mut streaming = crate::parser::parse_msd_with_options(input.as_bytes(), options);
+            parser.by_ref().for_each(drop);
+            streaming.by_ref().for_each(drop);
+
+            assert_eq!((streaming.preamble(), streaming.suffix()), (parser.preamble(), parser.suffix()), "{:?}", input);
+        }
+        let mut parser = parse_msd_str("  // a\n#A:B;\n// b\n", true, false);
+        parser.by_ref().for_each(drop);
+        assert_eq!(("  // a\n", "\n// b\n"), (parser.preamble(), parser.suffix()));
+    }
+
+    #[test]
+    fn test_file() {
+        let input = fs::read_to_string("testdata/Springtime.ssc").unwrap();
+        assert_same_as_streaming(&input, true, false);
+    }
+}
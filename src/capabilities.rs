@@ -0,0 +1,100 @@
+use crate::lexer::EscapePolicy;
+
+/// Cargo features of this crate with whether each was compiled in.
+const FEATURES: [(&str, bool); 12] = [
+    ("io", cfg!(feature = "io")),
+    ("document", cfg!(feature = "document")),
+    ("simfile", cfg!(feature = "simfile")),
+    ("fs", cfg!(feature = "fs")),
+    ("serde", cfg!(feature = "serde")),
+    ("schema", cfg!(feature = "schema")),
+    ("script", cfg!(feature = "script")),
+    ("http", cfg!(feature = "http")),
+    ("tokio", cfg!(feature = "tokio")),
+    ("futures", cfg!(feature = "futures")),
+    ("derive", cfg!(feature = "derive")),
+    ("unicode", cfg!(feature = "unicode")),
+];
+
+/// What this build of the crate can do, see [`capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Version of the crate, e.g. `"0.3.0"`.
+    pub version: &'static str,
+    /// Text encodings the parser decodes, as WHATWG labels. Input is always decoded as `utf-8`; invalid
+    /// sequences are an error unless [`MSDParserOptions::lossy_utf8`] replaces them.
+    ///
+    /// [`MSDParserOptions::lossy_utf8`]: crate::parser::MSDParserOptions::lossy_utf8
+    pub encodings: Vec<&'static str>,
+    /// Every [`EscapePolicy`] the lexer and serializer support.
+    pub escape_policies: Vec<EscapePolicy>,
+    /// Names of the Cargo features that were enabled.
+    pub features: Vec<&'static str>,
+    /// Highest conformance level whose behavior is compiled in, see "Conformance" in the README.
+    /// Levels 1 and 2 are always available, level 3 needs the `simfile` feature.
+    pub conformance_level: u8,
+}
+
+impl Capabilities {
+    /// Whether the Cargo feature `name` was enabled.
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features.contains(&name)
+    }
+
+    /// Whether documents can be edited in memory, see the `document` feature.
+    pub fn document(&self) -> bool {
+        self.has_feature("document")
+    }
+
+    /// Whether the StepMania layer, such as `simfile::Simfile`, is available.
+    pub fn simfile(&self) -> bool {
+        self.has_feature("simfile")
+    }
+
+    /// Whether the asynchronous parser is available, see the `tokio` feature.
+    pub fn async_parser(&self) -> bool {
+        self.has_feature("tokio")
+    }
+}
+
+/// Report which features this build of the crate was compiled with.
+///
+/// Meant for hosts that load plugins or bind the crate over FFI, which can't probe Cargo features at compile time.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::EscapePolicy;
+/// let capabilities = msdparser::capabilities();
+///
+/// assert_eq!(env!("CARGO_PKG_VERSION"), capabilities.version);
+/// assert!(capabilities.escape_policies.contains(&EscapePolicy::StrictStepMania));
+/// assert_eq!(cfg!(feature = "simfile"), capabilities.simfile());
+/// ```
+pub fn capabilities() -> Capabilities {
+    let features: Vec<&'static str> = FEATURES.iter().filter(|(_, enabled)| *enabled).map(|(name, _)| *name).collect();
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        encodings: vec!["utf-8"],
+        escape_policies: vec![EscapePolicy::Escaped, EscapePolicy::Unescaped, EscapePolicy::StrictStepMania],
+        conformance_level: if cfg!(feature = "simfile") { 3 } else { 2 },
+        features,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = capabilities();
+
+        assert_eq!(cfg!(feature = "document"), capabilities.document());
+        assert_eq!(cfg!(feature = "tokio"), capabilities.async_parser());
+        assert!(!capabilities.has_feature("unknown"));
+        assert!(capabilities.conformance_level >= 2);
+        assert_eq!(FEATURES.iter().filter(|(_, enabled)| *enabled).count(), capabilities.features.len());
+    }
+}
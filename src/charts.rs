@@ -0,0 +1,596 @@
+use std::cmp::Ordering;
+use std::convert::Infallible;
+use std::io::Write;
+
+use crate::parameter::{MSDParameter, MSDParameterError};
+use crate::serialize::{MSDSerializeOptions, MsdSerialize};
+use crate::stepstype::BUILTIN_STEPS_TYPES;
+
+/// Where a [`Chart`] came from.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+pub enum ChartSource {
+    /// A `#NOTES` parameter of an SM file.
+    Notes,
+    /// A legacy `#NOTES2` parameter, which has the same layout as `#NOTES`.
+    Notes2,
+    /// A DWI `#SINGLE`, `#DOUBLE`, `#COUPLE` or `#SOLO` parameter.
+    ///
+    /// Its note data uses DWI's own encoding rather than SM rows.
+    Dwi,
+    /// A `#NOTEDATA` block of an SSC file, which has a parameter per field, see [`SscChart`].
+    Ssc,
+}
+
+/// A chart in the shape shared by every supported format.
+#[derive(Debug, PartialEq, Eq, Clone, Hash, PartialOrd, Ord)]
+pub struct Chart {
+    /// Steps type, e.g. `dance-single`. DWI modes are mapped to their SM equivalents.
+    pub steps_type: String,
+    pub description: String,
+    /// Difficulty, e.g. `Hard`. DWI names like `MANIAC` are mapped to their SM equivalents.
+    pub difficulty: String,
+    pub meter: String,
+    /// Empty for DWI charts, which carry no radar values.
+    pub radar_values: String,
+    /// Note data. DWI doubles and couples have one entry per side, every other chart has exactly one.
+    pub notes: Vec<String>,
+    pub source: ChartSource,
+}
+
+/// DWI mode keys and the steps types they correspond to.
+const DWI_MODES: [(&str, &str); 4] = [
+    ("SINGLE", "dance-single"),
+    ("DOUBLE", "dance-double"),
+    ("COUPLE", "dance-couple"),
+    ("SOLO", "dance-solo"),
+];
+
+/// DWI difficulty names and their SM equivalents.
+const DWI_DIFFICULTIES: [(&str, &str); 5] = [
+    ("BEGINNER", "Beginner"),
+    ("BASIC", "Easy"),
+    ("ANOTHER", "Medium"),
+    ("MANIAC", "Hard"),
+    ("SMANIAC", "Challenge"),
+];
+
+fn component(parameter: &MSDParameter, i: usize) -> String {
+    parameter.components.get(i).map(|c| c.trim().to_string()).unwrap_or_default()
+}
+
+impl Chart {
+    /// Interpret a chart parameter of an SM or DWI file, or return `None` if it is not one.
+    ///
+    /// Keys are compared ignoring ASCII case and fields are trimmed. A `#NOTES` or `#NOTES2` is only a chart with
+    /// all six fields, so the bare `#NOTES` of an SSC chart, which holds only the note data, is not one; build
+    /// SSC charts with [`Chart::from_ssc`] instead.
+    pub fn from_parameter(parameter: &MSDParameter) -> Option<Self> {
+        let key = parameter.components.first()?.trim();
+
+        let source = if key.eq_ignore_ascii_case("NOTES") {
+            ChartSource::Notes
+        } else if key.eq_ignore_ascii_case("NOTES2") {
+            ChartSource::Notes2
+        } else {
+            return Self::from_dwi_parameter(key, parameter);
+        };
+        if parameter.components.len() < 7 {
+            return None;
+        }
+
+        let chart = parse_sm_notes(parameter);
+        Some(Self {
+            steps_type: chart.steps_type,
+            description: chart.description,
+            difficulty: chart.difficulty,
+            meter: chart.meter,
+            radar_values: chart.radar_values,
+            notes: vec![chart.notes],
+            source,
+        })
+    }
+
+    /// Interpret the chart of an `.ssc` file from its `#STEPSTYPE`, `#DESCRIPTION`, `#DIFFICULTY`, `#METER`,
+    /// `#RADARVALUES` and `#NOTES` parameters. Missing fields are left empty.
+    pub fn from_ssc(chart: &SscChart) -> Self {
+        Self::from_ssc_parameters(&chart.parameters)
+    }
+
+    fn from_ssc_parameters(parameters: &[MSDParameter]) -> Self {
+        let field = |key: &str| ssc_value(parameters, key).map(|value| value.trim().to_string()).unwrap_or_default();
+        Self {
+            steps_type: field("STEPSTYPE"),
+            description: field("DESCRIPTION"),
+            difficulty: field("DIFFICULTY"),
+            meter: field("METER"),
+            radar_values: field("RADARVALUES"),
+            notes: vec![field("NOTES")],
+            source: ChartSource::Ssc,
+        }
+    }
+
+    /// The chart as a single parameter, the inverse of [`Chart::from_parameter`].
+    ///
+    /// DWI charts are written back with their DWI mode and difficulty names. A steps type or difficulty DWI has no
+    /// name for is written as is. SSC charts, which take several parameters, are written as an SM `#NOTES`; use
+    /// [`Chart::to_parameters`] to write them in their own format.
+    pub fn to_parameter(&self) -> MSDParameter {
+        let key = match self.source {
+            ChartSource::Notes | ChartSource::Ssc => "NOTES",
+            ChartSource::Notes2 => "NOTES2",
+            ChartSource::Dwi => return self.to_dwi_parameter(),
+        };
+        let notes = self.notes.first().cloned().unwrap_or_default();
+        let fields = [&self.steps_type, &self.description, &self.difficulty, &self.meter, &self.radar_values];
+        let mut components = vec![key.to_string()];
+        components.extend(fields.into_iter().cloned());
+        components.push(notes);
+        MSDParameter::new(components)
+    }
+
+    /// The chart as the parameters of the format it came from: a `#NOTEDATA` block for SSC charts, the inverse of
+    /// [`Chart::from_ssc`], and the one parameter of [`Chart::to_parameter`] otherwise.
+    ///
+    /// Only the fields of a [`Chart`] are written, so other parameters of an SSC chart, like `#CHARTNAME` or
+    /// timing of its own, are not; keep the [`SscChart`] to write those back.
+    pub fn to_parameters(&self) -> Vec<MSDParameter> {
+        if self.source != ChartSource::Ssc {
+            return vec![self.to_parameter()];
+        }
+        let parameter = |key: &str, value: &str| MSDParameter::new(vec![key.to_string(), value.to_string()]);
+        vec![
+            parameter("NOTEDATA", ""),
+            parameter("STEPSTYPE", &self.steps_type),
+            parameter("DESCRIPTION", &self.description),
+            parameter("DIFFICULTY", &self.difficulty),
+            parameter("METER", &self.meter),
+            parameter("RADARVALUES", &self.radar_values),
+            parameter("NOTES", self.notes.first().map_or("", String::as_str)),
+        ]
+    }
+
+    fn to_dwi_parameter(&self) -> MSDParameter {
+        let mode = DWI_MODES.iter().find(|(_, steps_type)| *steps_type == self.steps_type).map_or(self.steps_type.as_str(), |(mode, _)| mode);
+        let difficulty = DWI_DIFFICULTIES.iter().find(|(_, sm)| *sm == self.difficulty).map_or(self.difficulty.as_str(), |(dwi, _)| dwi);
+        let mut components = vec![mode.to_string(), difficulty.to_string(), self.meter.clone()];
+        components.extend(self.notes.iter().cloned());
+        MSDParameter::new(components)
+    }
+
+    /// `#SINGLE:DIFFICULTY:METER:NOTES;`, with a second note data component for doubles and couples.
+    fn from_dwi_parameter(key: &str, parameter: &MSDParameter) -> Option<Self> {
+        let (_, steps_type) = DWI_MODES.iter().find(|(mode, _)| mode.eq_ignore_ascii_case(key))?;
+
+        let difficulty = component(parameter, 1);
+        let difficulty = DWI_DIFFICULTIES.iter()
+            .find(|(dwi, _)| dwi.eq_ignore_ascii_case(&difficulty))
+            .map_or(difficulty, |(_, sm)| sm.to_string());
+
+        let mut notes: Vec<String> = (3..parameter.components.len()).map(|i| component(parameter, i)).collect();
+        if notes.is_empty() {
+            notes.push(String::new());
+        }
+
+        Some(Self {
+            steps_type: steps_type.to_string(),
+            description: String::new(),
+            difficulty,
+            meter: component(parameter, 2),
+            radar_values: String::new(),
+            notes,
+            source: ChartSource::Dwi,
+        })
+    }
+}
+
+/// The parameters [`Chart::to_parameters`] gives, without a trailing line ending.
+impl MsdSerialize for Chart {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        self.to_parameters().serialize_msd(writer, &options.clone().trailing_line_ending(false))
+    }
+}
+
+/// The fields of an `.sm` `#NOTES` parameter, as returned by [`parse_sm_notes`].
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Default)]
+pub struct SmChart {
+    /// Steps type, e.g. `dance-single`.
+    pub steps_type: String,
+    /// Description, which older files use for the chart author.
+    pub description: String,
+    /// Difficulty, e.g. `Hard`.
+    pub difficulty: String,
+    pub meter: String,
+    /// Comma-separated radar values.
+    pub radar_values: String,
+    /// Note data, with measures separated by `,`.
+    pub notes: String,
+}
+
+/// Split an `.sm` `#NOTES` (or `#NOTES2`) parameter into its six fields.
+///
+/// Every field is trimmed, removing the indentation and line breaks StepMania writes around them.
+/// Missing fields are left empty and extra fields are ignored. The key is not checked.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::parse_msd;
+/// # use msdparser::charts::parse_sm_notes;
+/// let input = "#NOTES:\n     dance-single:\n     K:\n     Hard:\n     9:\n     0.5,0.5,0.5,0.5,0.5:\n0000\n;";
+/// let parameter = parse_msd(input.as_bytes(), true, false).next().unwrap().unwrap();
+/// let chart = parse_sm_notes(&parameter);
+///
+/// assert_eq!(("dance-single", "K", "Hard", "9"), (&*chart.steps_type, &*chart.description, &*chart.difficulty, &*chart.meter));
+/// assert_eq!("0000", chart.notes);
+/// ```
+pub fn parse_sm_notes(parameter: &MSDParameter) -> SmChart {
+    SmChart {
+        steps_type: component(parameter, 1),
+        description: component(parameter, 2),
+        difficulty: component(parameter, 3),
+        meter: component(parameter, 4),
+        radar_values: component(parameter, 5),
+        notes: component(parameter, 6),
+    }
+}
+
+/// A chart of an `.ssc` file: the parameters from a `#NOTEDATA` up to the next one, as grouped by [`group_ssc`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SscChart {
+    /// The `#NOTEDATA` and the parameters after it, e.g. `#STEPSTYPE`, `#METER` and `#NOTES`, in document
+    /// order, so writing them out gives back the chart as it was.
+    pub parameters: Vec<MSDParameter>,
+}
+
+fn is_key(parameter: &MSDParameter, key: &str) -> bool {
+    parameter.components.first().is_some_and(|k| k.trim().eq_ignore_ascii_case(key))
+}
+
+fn ssc_value<'a>(parameters: &'a [MSDParameter], key: &str) -> Option<&'a str> {
+    parameters.iter()
+        .find(|parameter| is_key(parameter, key))
+        .and_then(|parameter| parameter.components.get(1))
+        .map(String::as_str)
+}
+
+impl SscChart {
+    /// The value of the first parameter with `key`, compared ignoring ASCII case and surrounding whitespace.
+    ///
+    /// Charts may repeat song keys such as `#BPMS` to override them, so a missing key means the song's value.
+    pub fn value(&self, key: &str) -> Option<&str> {
+        ssc_value(&self.parameters, key)
+    }
+}
+
+/// A header parameter or a whole chart of an `.ssc` file, see [`group_ssc`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum SscItem {
+    /// A parameter before the first `#NOTEDATA`, which applies to the whole song.
+    Header(MSDParameter),
+    Chart(SscChart),
+}
+
+/// Iterator returned by [`group_ssc`].
+#[derive(Debug)]
+pub struct SscGroups<I> {
+    parameters: I,
+    chart: Option<SscChart>,
+}
+
+impl<I, E> Iterator for SscGroups<I>
+where
+    I: Iterator<Item = Result<MSDParameter, E>>,
+{
+    type Item = Result<SscItem, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(parameter) = self.parameters.next() else {
+                return self.chart.take().map(|chart| Ok(SscItem::Chart(chart)));
+            };
+            let parameter = match parameter {
+                Ok(parameter) => parameter,
+                Err(e) => return Some(Err(e)),
+            };
+
+            match (&mut self.chart, is_key(&parameter, "NOTEDATA")) {
+                (chart, true) => {
+                    if let Some(finished) = chart.replace(SscChart { parameters: vec![parameter] }) {
+                        return Some(Ok(SscItem::Chart(finished)));
+                    }
+                },
+                (Some(chart), false) => chart.parameters.push(parameter),
+                (None, false) => return Some(Ok(SscItem::Header(parameter))),
+            }
+        }
+    }
+}
+
+/// Group a stream of `.ssc` parameters, e.g. from [`parse_msd`], into header parameters and charts.
+///
+/// Every parameter before the first `#NOTEDATA` is yielded on its own as a header parameter. From there on,
+/// each `#NOTEDATA` starts a chart that collects every parameter up to the next one, and a chart is yielded
+/// once it is complete, so only one chart is held in memory at a time. Errors are passed through.
+///
+/// [`parse_msd`]: crate::parser::parse_msd
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::parse_msd;
+/// # use msdparser::charts::{group_ssc, SscItem};
+/// let input = "#TITLE:A;\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n#METER:3;\n#NOTEDATA:;\n#METER:9;";
+/// let items: Vec<SscItem> = group_ssc(parse_msd(input.as_bytes(), true, false)).map(Result::unwrap).collect();
+///
+/// assert!(matches!(&items[0], SscItem::Header(parameter) if parameter.key().unwrap() == "TITLE"));
+/// let meters: Vec<_> = items[1..].iter().map(|item| match item {
+///     SscItem::Chart(chart) => chart.value("METER"),
+///     SscItem::Header(_) => None,
+/// }).collect();
+/// assert_eq!(vec![Some("3"), Some("9")], meters);
+/// ```
+pub fn group_ssc<I, E>(parameters: I) -> SscGroups<I::IntoIter>
+where
+    I: IntoIterator<Item = Result<MSDParameter, E>>,
+{
+    SscGroups { parameters: parameters.into_iter(), chart: None }
+}
+
+/// Every chart of a document, in document order, regardless of the format it was written in.
+///
+/// Each `#NOTEDATA` block of an `.ssc` file is one chart, see [`Chart::from_ssc`]; every other chart is a single
+/// parameter, see [`Chart::from_parameter`].
+pub fn charts(parameters: &[MSDParameter]) -> Vec<Chart> {
+    let first_notedata = parameters.iter().position(|parameter| is_key(parameter, "NOTEDATA")).unwrap_or(parameters.len());
+    let (header, blocks) = parameters.split_at(first_notedata);
+
+    let mut charts: Vec<Chart> = header.iter().filter_map(Chart::from_parameter).collect();
+    let mut starts: Vec<usize> = (0..blocks.len()).filter(|&i| is_key(&blocks[i], "NOTEDATA")).collect();
+    starts.push(blocks.len());
+    charts.extend(starts.windows(2).map(|range| Chart::from_ssc_parameters(&blocks[range[0]..range[1]])));
+    charts
+}
+
+/// Difficulties in the order StepMania lists them.
+const DIFFICULTIES: [&str; 6] = ["Beginner", "Easy", "Medium", "Hard", "Challenge", "Edit"];
+
+/// Order of charts like StepMania's song select: by steps type, then difficulty, then meter, then description.
+///
+/// Sorting with it is stable, so charts that compare equal keep the order they were written in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct CanonicalOrder {
+    /// Steps types in order, compared ignoring ASCII case. Others come after them, alphabetically.
+    /// Defaults to the order of [`BUILTIN_STEPS_TYPES`], which is the game's.
+    pub steps_types: Vec<String>,
+    /// Difficulties in order, compared ignoring ASCII case. Others come after them, alphabetically.
+    /// Defaults to `Beginner`, `Easy`, `Medium`, `Hard`, `Challenge` and `Edit`.
+    pub difficulties: Vec<String>,
+}
+
+impl Default for CanonicalOrder {
+    fn default() -> Self {
+        Self {
+            steps_types: BUILTIN_STEPS_TYPES.iter().map(|(steps_type, _)| steps_type.to_string()).collect(),
+            difficulties: DIFFICULTIES.iter().map(|difficulty| difficulty.to_string()).collect(),
+        }
+    }
+}
+
+impl CanonicalOrder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn steps_types<I: IntoIterator<Item = S>, S: Into<String>>(mut self, steps_types: I) -> Self {
+        self.steps_types = steps_types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn difficulties<I: IntoIterator<Item = S>, S: Into<String>>(mut self, difficulties: I) -> Self {
+        self.difficulties = difficulties.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Compare two charts. Numeric meters come before other meters, in numeric order.
+    pub fn compare(&self, a: &Chart, b: &Chart) -> Ordering {
+        rank(&self.steps_types, &a.steps_type).cmp(&rank(&self.steps_types, &b.steps_type))
+            .then_with(|| rank(&self.difficulties, &a.difficulty).cmp(&rank(&self.difficulties, &b.difficulty)))
+            .then_with(|| meter_rank(&a.meter).cmp(&meter_rank(&b.meter)))
+            .then_with(|| a.description.cmp(&b.description))
+    }
+}
+
+/// Position of `value` in `order`, then its text for values not in it.
+fn rank(order: &[String], value: &str) -> (usize, String) {
+    let position = order.iter().position(|known| known.eq_ignore_ascii_case(value));
+    (position.unwrap_or(order.len()), value.to_ascii_lowercase())
+}
+
+fn meter_rank(meter: &str) -> (bool, i64, &str) {
+    let number = meter.parse().ok();
+    (number.is_none(), number.unwrap_or(0), meter)
+}
+
+/// Sort the chart parameters among `parameters` in `order`, leaving every other parameter where it is.
+///
+/// The charts trade places with each other, so the header stays in front of them even if it is interleaved.
+/// The charts of an `.ssc` file move as whole `#NOTEDATA` blocks, grouped like [`group_ssc`] does, so every
+/// chart keeps its own parameters.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::parse_msd;
+/// # use msdparser::charts::{sort_chart_parameters, CanonicalOrder};
+/// let input = "#NOTES:dance-single::Hard:9::;#TITLE:A;#NOTES:dance-single::Easy:3::;";
+/// let mut parameters: Vec<_> = parse_msd(input.as_bytes(), true, false).map(Result::unwrap).collect();
+/// sort_chart_parameters(&mut parameters, &CanonicalOrder::new());
+///
+/// let keys: Vec<_> = parameters.iter().map(|p| p.components.get(3).cloned().unwrap_or_default()).collect();
+/// assert_eq!(vec!["Easy", "", "Hard"], keys);
+/// ```
+pub fn sort_chart_parameters(parameters: &mut [MSDParameter], order: &CanonicalOrder) {
+    let first_notedata = parameters.iter().position(|parameter| is_key(parameter, "NOTEDATA")).unwrap_or(parameters.len());
+    let (header, blocks) = parameters.split_at_mut(first_notedata);
+
+    let mut charts: Vec<(usize, Chart)> = header.iter()
+        .enumerate()
+        .filter_map(|(i, parameter)| Some((i, Chart::from_parameter(parameter)?)))
+        .collect();
+    let slots: Vec<usize> = charts.iter().map(|(i, _)| *i).collect();
+    charts.sort_by(|(_, a), (_, b)| order.compare(a, b));
+
+    let sorted: Vec<MSDParameter> = charts.iter()
+        .map(|(i, _)| std::mem::replace(&mut header[*i], MSDParameter::new(Vec::new())))
+        .collect();
+    for (slot, parameter) in slots.into_iter().zip(sorted) {
+        header[slot] = parameter;
+    }
+
+    // Every parameter from the first #NOTEDATA on belongs to a chart, so there are no header items here
+    let taken = blocks.iter_mut().map(|parameter| Ok::<_, Infallible>(std::mem::replace(parameter, MSDParameter::new(Vec::new()))));
+    let mut ssc_charts: Vec<(Chart, SscChart)> = group_ssc(taken)
+        .filter_map(|item| match item {
+            Ok(SscItem::Chart(chart)) => Some((Chart::from_ssc(&chart), chart)),
+            Ok(SscItem::Header(_)) => None,
+            Err(never) => match never {},
+        })
+        .collect();
+    ssc_charts.sort_by(|(a, _), (b, _)| order.compare(a, b));
+    for (slot, parameter) in blocks.iter_mut().zip(ssc_charts.into_iter().flat_map(|(_, chart)| chart.parameters)) {
+        *slot = parameter;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_msd;
+
+    fn parse(input: &str) -> Vec<MSDParameter> {
+        parse_msd(input.as_bytes(), true, false).map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_sm_charts() {
+        let parameters = parse("#TITLE:A;\n#NOTES:\n     dance-single:\n     K:\n     Hard:\n     9:\n     0.5,0.5,0.5,0.5,0.5:\n0000\n;\n#NOTES2:dance-couple:::3::;#NOTES:0000;");
+        let charts = charts(&parameters);
+
+        assert_eq!(2, charts.len());
+        assert_eq!(Chart {
+            steps_type: "dance-single".to_string(),
+            description: "K".to_string(),
+            difficulty: "Hard".to_string(),
+            meter: "9".to_string(),
+            radar_values: "0.5,0.5,0.5,0.5,0.5".to_string(),
+            notes: vec!["0000".to_string()],
+            source: ChartSource::Notes,
+        }, charts[0]);
+        assert_eq!(("dance-couple", "3", ChartSource::Notes2), (charts[1].steps_type.as_str(), charts[1].meter.as_str(), charts[1].source));
+        assert_eq!(vec![String::new()], charts[1].notes);
+    }
+
+    #[test]
+    fn test_parse_sm_notes() {
+        let parameters = parse("#NOTES:\r\n     dance-single:\r\n     :\r\n     Easy:\r\n     3:\r\n     0,0:\r\n1000\r\n,\r\n0000\r\n;#NOTES:pump-single;");
+
+        assert_eq!(SmChart {
+            steps_type: "dance-single".to_string(),
+            difficulty: "Easy".to_string(),
+            meter: "3".to_string(),
+            radar_values: "0,0".to_string(),
+            notes: "1000\r\n,\r\n0000".to_string(),
+            ..SmChart::default()
+        }, parse_sm_notes(&parameters[0]));
+        assert_eq!(SmChart { steps_type: "pump-single".to_string(), ..SmChart::default() }, parse_sm_notes(&parameters[1]));
+    }
+
+    #[test]
+    fn test_group_ssc() {
+        let input = std::fs::read("testdata/Springtime.ssc").unwrap();
+        let items: Vec<SscItem> = group_ssc(parse_msd(input.as_slice(), true, false)).map(Result::unwrap).collect();
+        let charts: Vec<&SscChart> = items.iter().filter_map(|item| match item {
+            SscItem::Chart(chart) => Some(chart),
+            SscItem::Header(_) => None,
+        }).collect();
+
+        assert_eq!(Some(""), charts[0].value("NOTEDATA"));
+        assert_eq!(Some("dance-single"), charts[0].value("stepstype"));
+        assert_eq!(Some("Challenge"), charts[0].value("DIFFICULTY"));
+        assert!(charts.iter().all(|chart| chart.value("NOTES").is_some()));
+        assert!(items.iter().position(|item| matches!(item, SscItem::Chart(_))).unwrap() > 1);
+        assert!(items.iter().skip_while(|item| matches!(item, SscItem::Header(_))).all(|item| matches!(item, SscItem::Chart(_))));
+    }
+
+    #[test]
+    fn test_ssc_charts() {
+        let parameters = parse(&std::fs::read_to_string("testdata/Springtime.ssc").unwrap());
+        let charts = charts(&parameters);
+        let blocks: Vec<SscChart> = group_ssc(parameters.iter().cloned().map(Ok::<_, ()>)).filter_map(|item| match item {
+            Ok(SscItem::Chart(chart)) => Some(chart),
+            _ => None,
+        }).collect();
+
+        assert_eq!(blocks.len(), charts.len());
+        assert!(charts.iter().all(|chart| chart.source == ChartSource::Ssc));
+        assert!(charts.iter().all(|chart| ["dance-single", "pump-single"].contains(&chart.steps_type.as_str())));
+        assert_eq!(("Challenge", "12"), (charts[0].difficulty.as_str(), charts[0].meter.as_str()));
+        assert!(charts[0].notes[0].starts_with("0000"));
+        assert_eq!(charts[0], Chart::from_ssc(&blocks[0]));
+
+        let written = charts[0].to_parameters();
+        assert_eq!(Some("NOTEDATA".to_string()), written[0].key());
+        assert_eq!(charts[0], Chart::from_ssc(&SscChart { parameters: written }));
+        assert_eq!(charts[0].notes[0], parse_sm_notes(&charts[0].to_parameter()).notes);
+    }
+
+    #[test]
+    fn test_dwi_charts() {
+        let parameters = parse("#TITLE:A;\n#SINGLE:MANIAC:9:2468;\n#double:basic:4:2020:8080;\n#SOLO:WILD:1:7;");
+        let charts = charts(&parameters);
+
+        assert_eq!(3, charts.len());
+        assert_eq!(("dance-single", "Hard", "9"), (charts[0].steps_type.as_str(), charts[0].difficulty.as_str(), charts[0].meter.as_str()));
+        assert_eq!(vec!["2020".to_string(), "8080".to_string()], charts[1].notes);
+        assert_eq!(("dance-double", "Easy"), (charts[1].steps_type.as_str(), charts[1].difficulty.as_str()));
+        assert_eq!(("dance-solo", "WILD"), (charts[2].steps_type.as_str(), charts[2].difficulty.as_str()));
+        assert!(charts.iter().all(|c| c.source == ChartSource::Dwi));
+    }
+
+    #[test]
+    fn test_sort_ssc_blocks() {
+        let input = concat!(
+            "#TITLE:A;#NOTEDATA:;#STEPSTYPE:dance-single;#DIFFICULTY:Hard;#METER:9;#NOTES:1111;",
+            "#NOTEDATA:;#CHARTNAME:B;#STEPSTYPE:dance-single;#DIFFICULTY:Easy;#NOTES:1000;",
+        );
+        let mut parameters = parse(input);
+        sort_chart_parameters(&mut parameters, &CanonicalOrder::new());
+
+        let keys: Vec<String> = parameters.iter().map(|p| p.key().unwrap()).collect();
+        assert_eq!(vec![
+            "TITLE", "NOTEDATA", "CHARTNAME", "STEPSTYPE", "DIFFICULTY", "NOTES", "NOTEDATA", "STEPSTYPE", "DIFFICULTY", "METER", "NOTES",
+        ], keys);
+        assert_eq!(vec!["1000", "1111"], charts(&parameters).iter().map(|chart| chart.notes[0].as_str()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        let parameters = parse(concat!(
+            "#NOTES:pump-single::Easy:2::;#NOTES:dance-double::Hard:9::;#NOTES:dance-single:B:edit:12::;",
+            "#NOTES:dance-single:A:Edit:12::;#NOTES:dance-single::Hard:10::;#NOTES:dance-single::Hard:9::;",
+            "#NOTES:custom-type::Easy:1::;#NOTES:dance-single::Hard:?::;",
+        ));
+        let mut charts = charts(&parameters);
+        charts.sort_by(|a, b| CanonicalOrder::new().compare(a, b));
+
+        let order: Vec<_> = charts.iter()
+            .map(|chart| format!("{} {} {} {}", chart.steps_type, chart.difficulty, chart.meter, chart.description))
+            .collect();
+        assert_eq!(vec![
+            "dance-single Hard 9 ", "dance-single Hard 10 ", "dance-single Hard ? ", "dance-single Edit 12 A",
+            "dance-single edit 12 B", "dance-double Hard 9 ", "pump-single Easy 2 ", "custom-type Easy 1 ",
+        ], order);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::parameter::MSDParameter;
+
+/// Bidirectional cursor over the parameters of a document.
+/// 
+/// The cursor points at one parameter at a time, or past the end of the document.
+/// It supports stepping in both directions, seeking to a key, and replacing the parameter in place,
+/// which is what interactive inspection tools and scripted navigation need.
+/// 
+/// # Examples
+/// 
+/// ```rust
+/// # use msdparser::{parse_msd, MSDParameter};
+/// # use msdparser::cursor::DocumentCursor;
+/// let input = b"#TITLE:Springtime;#ARTIST:Kommisar;#BPMS:0=181.685;";
+/// let mut parameters: Vec<MSDParameter> = parse_msd(input.as_ref(), true, false)
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// 
+/// let mut cursor = DocumentCursor::new(&mut parameters);
+/// assert_eq!(cursor.seek_key("BPMS").unwrap().value().unwrap(), "0=181.685");
+/// assert_eq!(cursor.prev_parameter().unwrap().key().unwrap(), "ARTIST");
+/// 
+/// cursor.replace_value("kommisar");
+/// assert_eq!(parameters[1].value().unwrap(), "kommisar");
+/// ```
+#[derive(Debug)]
+pub struct DocumentCursor<'a> {
+    parameters: &'a mut Vec<MSDParameter>,
+    position: usize,
+}
+
+impl<'a> DocumentCursor<'a> {
+    /// Create a cursor pointing at the first parameter.
+    pub fn new(parameters: &'a mut Vec<MSDParameter>) -> Self {
+        Self { parameters, position: 0 }
+    }
+
+    /// Index of the current parameter.
+    /// 
+    /// Equal to the number of parameters when the cursor is past the end.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The parameter under the cursor, or `None` if the cursor is past the end.
+    pub fn current(&self) -> Option<&MSDParameter> {
+        self.parameters.get(self.position)
+    }
+
+    /// Move to the next parameter and return it.
+    /// 
+    /// Returns `None` once the cursor moves past the end; further calls leave it there.
+    pub fn next_parameter(&mut self) -> Option<&MSDParameter> {
+        self.position = (self.position + 1).min(self.parameters.len());
+        self.current()
+    }
+
+    /// Move to the previous parameter and return it.
+    /// 
+    /// Returns `None` without moving if the cursor is already at the first parameter.
+    pub fn prev_parameter(&mut self) -> Option<&MSDParameter> {
+        if self.position == 0 {
+            return None;
+        }
+        self.position -= 1;
+        self.current()
+    }
+
+    /// Move to the given index, clamped to the past-the-end position.
+    pub fn seek(&mut self, position: usize) -> Option<&MSDParameter> {
+        self.position = position.min(self.parameters.len());
+        self.current()
+    }
+
+    /// Move to the next parameter with the given key, searching forward from the current one (inclusive).
+    /// 
+    /// Returns `None` and leaves the cursor where it was if no such parameter exists.
+    pub fn seek_key(&mut self, key: &str) -> Option<&MSDParameter> {
+        let offset = self.parameters[self.position..].iter()
+            .position(|p| p.components.first().map(String::as_str) == Some(key))?;
+        self.position += offset;
+        self.current()
+    }
+
+    /// Move to the previous parameter with the given key, searching backward from the one before the cursor.
+    /// 
+    /// Returns `None` and leaves the cursor where it was if no such parameter exists.
+    pub fn seek_key_back(&mut self, key: &str) -> Option<&MSDParameter> {
+        self.position = self.parameters[..self.position].iter()
+            .rposition(|p| p.components.first().map(String::as_str) == Some(key))?;
+        self.current()
+    }
+
+    /// Replace the parameter under the cursor, returning the old one.
+    /// 
+    /// Returns `None` and does nothing if the cursor is past the end.
+    pub fn replace(&mut self, parameter: MSDParameter) -> Option<MSDParameter> {
+        let current = self.parameters.get_mut(self.position)?;
+        Some(std::mem::replace(current, parameter))
+    }
+
+    /// Replace the value (second component) of the parameter under the cursor, returning the old value.
+    /// 
+    /// A parameter without a value gains one. Returns `None` if the cursor is past the end or there was no value.
+    pub fn replace_value(&mut self, value: &str) -> Option<String> {
+        let current = self.parameters.get_mut(self.position)?;
+        match current.components.len() {
+            0 => {
+                current.components = vec![String::new(), value.to_string()];
+                None
+            },
+            1 => {
+                current.components.push(value.to_string());
+                None
+            },
+            _ => Some(std::mem::replace(&mut current.components[1], value.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters() -> Vec<MSDParameter> {
+        vec![
+            MSDParameter::new(vec!["TITLE".to_string(), "A".to_string()]),
+            MSDParameter::new(vec!["NOTES".to_string(), "1".to_string()]),
+            MSDParameter::new(vec!["NOTES".to_string(), "2".to_string()]),
+        ]
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut parameters = parameters();
+        let mut cursor = DocumentCursor::new(&mut parameters);
+
+        assert_eq!(Some("TITLE".to_string()), cursor.current().unwrap().key());
+        assert_eq!(None, cursor.prev_parameter());
+        assert_eq!(Some("1".to_string()), cursor.next_parameter().unwrap().value());
+        assert_eq!(Some("2".to_string()), cursor.next_parameter().unwrap().value());
+        assert_eq!(None, cursor.next_parameter());
+        assert_eq!(None, cursor.next_parameter());
+        assert_eq!(3, cursor.position());
+        assert_eq!(Some("2".to_string()), cursor.prev_parameter().unwrap().value());
+    }
+
+    #[test]
+    fn test_seek_key() {
+        let mut parameters = parameters();
+        let mut cursor = DocumentCursor::new(&mut parameters);
+
+        assert_eq!(Some("1".to_string()), cursor.seek_key("NOTES").unwrap().value());
+        cursor.next_parameter();
+        assert_eq!(Some("2".to_string()), cursor.seek_key("NOTES").unwrap().value());
+        assert_eq!(None, cursor.seek_key("TITLE"));
+        assert_eq!(2, cursor.position());
+        assert_eq!(Some("A".to_string()), cursor.seek_key_back("TITLE").unwrap().value());
+        assert_eq!(None, cursor.seek_key_back("NOTES"));
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut parameters = parameters();
+        let mut cursor = DocumentCursor::new(&mut parameters);
+
+        let old = cursor.replace(MSDParameter::new(vec!["SUBTITLE".to_string()]));
+        assert_eq!(Some(MSDParameter::new(vec!["TITLE".to_string(), "A".to_string()])), old);
+        assert_eq!(None, cursor.replace_value("B"));
+        cursor.next_parameter();
+        assert_eq!(Some("1".to_string()), cursor.replace_value("3"));
+        cursor.seek(10);
+        assert_eq!(None, cursor.replace_value("4"));
+
+        assert_eq!(MSDParameter::new(vec!["SUBTITLE".to_string(), "B".to_string()]), parameters[0]);
+        assert_eq!(MSDParameter::new(vec!["NOTES".to_string(), "3".to_string()]), parameters[1]);
+    }
+}
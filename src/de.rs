@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::{error, fmt};
+
+use serde::de::value::StrDeserializer;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::forward_to_deserialize_any;
+
+use crate::parameter::MSDParameter;
+use crate::parser::{parse_msd, MSDParserError};
+use crate::repeat::{RepeatPolicies, RepeatPolicy, RepeatedKeyError};
+
+/// Custom error type for deserializing MSD into user types.
+#[derive(Debug)]
+pub enum DeserializeError {
+    /// The input is not valid MSD.
+    Parse(MSDParserError),
+    /// A repeated key could not be resolved, e.g. because its policy is [`RepeatPolicy::Error`].
+    Repeated(RepeatedKeyError),
+    /// A value does not fit the type it is deserialized into, e.g. `#OFFSET:abc;` into an `f64`.
+    Message(String),
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeserializeError::Parse(e) => write!(f, "{}", e),
+            DeserializeError::Repeated(e) => write!(f, "DeserializeError: {}", e),
+            DeserializeError::Message(message) => write!(f, "DeserializeError: {}", message),
+        }
+    }
+}
+
+impl error::Error for DeserializeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DeserializeError::Parse(e) => Some(e),
+            DeserializeError::Repeated(e) => Some(e),
+            DeserializeError::Message(_) => None,
+        }
+    }
+}
+
+impl de::Error for DeserializeError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        DeserializeError::Message(message.to_string())
+    }
+}
+
+impl From<MSDParserError> for DeserializeError {
+    fn from(e: MSDParserError) -> Self {
+        DeserializeError::Parse(e)
+    }
+}
+
+impl From<RepeatedKeyError> for DeserializeError {
+    fn from(e: RepeatedKeyError) -> Self {
+        DeserializeError::Repeated(e)
+    }
+}
+
+/// Deserialize a type whose fields correspond to MSD keys from a reader.
+///
+/// Struct fields match keys ignoring ASCII case and underscores, so `sample_start` is read from `#SAMPLESTART`;
+/// unknown keys are ignored unless the type denies them. Values are converted according to the field type:
+///
+/// - numbers are parsed after trimming whitespace
+/// - `bool` accepts `YES`/`NO`, `1`/`0` and `true`/`false`, ignoring case
+/// - `Option`s are `None` for blank values
+/// - sequences are the components after the key if there are several, like `#NOTES:a:b:c;`,
+///   otherwise the comma-separated entries of the value, like `#BPMS:0=120,4=240;`
+///
+/// A key that appears more than once is reported as a duplicate field; see [`from_reader_with_policies`]
+/// to choose which occurrence to keep instead.
+///
+/// # Errors
+///
+/// Returns an error if the input cannot be parsed or a value does not fit its field.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde::Deserialize;
+/// #[derive(Deserialize)]
+/// struct Header {
+///     title: String,
+///     artist: String,
+///     offset: f64,
+///     bpms: Vec<String>,
+/// }
+///
+/// let input = b"#TITLE:Springtime;\n#ARTIST:Kommisar;\n#OFFSET:-0.028;\n#BPMS:0=181.685;";
+/// let header: Header = msdparser::from_reader(input.as_ref()).unwrap();
+///
+/// assert_eq!("Springtime", header.title);
+/// assert_eq!(-0.028, header.offset);
+/// assert_eq!(vec!["0=181.685".to_string()], header.bpms);
+/// ```
+pub fn from_reader<R: Read, T: DeserializeOwned>(reader: R) -> Result<T, DeserializeError> {
+    let parameters = parse_msd(reader, true, false).collect::<Result<Vec<_>, _>>()?;
+    from_parameters(&parameters)
+}
+
+/// Deserialize a type from an MSD string. See [`from_reader`].
+///
+/// # Errors
+///
+/// Returns an error if the input cannot be parsed or a value does not fit its field.
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, DeserializeError> {
+    from_reader(input.as_bytes())
+}
+
+/// Deserialize a type from already-parsed parameters. See [`from_reader`].
+///
+/// # Errors
+///
+/// Returns an error if a value does not fit its field.
+pub fn from_parameters<T: DeserializeOwned>(parameters: &[MSDParameter]) -> Result<T, DeserializeError> {
+    let entries = parameters.iter()
+        .filter_map(|p| p.components.split_first())
+        .map(|(key, value)| (key.as_str(), Entry::One(value)))
+        .collect();
+    T::deserialize(DocumentDeserializer { entries })
+}
+
+/// Deserialize a type from a reader, resolving repeated keys with `policies` first. See [`from_reader`].
+///
+/// Keys whose policy is [`RepeatPolicy::KeepAll`] are collected: the field receives a sequence with one element
+/// per occurrence, so it should be a `Vec` or similar even if the key appears only once.
+///
+/// # Errors
+///
+/// Returns an error if the input cannot be parsed, a key is repeated under [`RepeatPolicy::Error`],
+/// or a value does not fit its field.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde::Deserialize;
+/// # use msdparser::de::from_reader_with_policies;
+/// # use msdparser::repeat::{RepeatPolicies, RepeatPolicy};
+/// #[derive(Deserialize)]
+/// struct Song {
+///     bpms: String,
+///     notes: Vec<Vec<String>>,
+/// }
+///
+/// let input = b"#BPMS:0=120;\n#NOTES:dance-single:Hard;\n#BPMS:0=240;\n#NOTES:dance-double:Easy;";
+/// let policies = RepeatPolicies::new()
+///     .with_default(RepeatPolicy::KeepLast)
+///     .with_key("NOTES", RepeatPolicy::KeepAll);
+/// let song: Song = from_reader_with_policies(input.as_ref(), &policies).unwrap();
+///
+/// assert_eq!("0=240", song.bpms);
+/// assert_eq!(vec!["dance-double".to_string(), "Easy".to_string()], song.notes[1]);
+/// ```
+pub fn from_reader_with_policies<R: Read, T: DeserializeOwned>(reader: R, policies: &RepeatPolicies) -> Result<T, DeserializeError> {
+    let parameters = parse_msd(reader, true, false).collect::<Result<Vec<_>, _>>()?;
+    from_parameters_with_policies(&parameters, policies)
+}
+
+/// Deserialize a type from already-parsed parameters, resolving repeated keys with `policies` first.
+/// See [`from_reader_with_policies`].
+///
+/// # Errors
+///
+/// Returns an error if a key is repeated under [`RepeatPolicy::Error`] or a value does not fit its field.
+pub fn from_parameters_with_policies<T: DeserializeOwned>(
+    parameters: &[MSDParameter],
+    policies: &RepeatPolicies,
+) -> Result<T, DeserializeError> {
+    let parameters = policies.apply(parameters.to_vec())?;
+
+    let mut entries: Vec<(&str, Entry)> = Vec::new();
+    // Index in `entries` of each collected key
+    let mut collected: HashMap<String, usize> = HashMap::new();
+    for (key, value) in parameters.iter().filter_map(|p| p.components.split_first()) {
+        if policies.policy_for(key) != RepeatPolicy::KeepAll {
+            entries.push((key, Entry::One(value)));
+            continue;
+        }
+        match collected.get(&key.trim().to_ascii_uppercase()) {
+            Some(&i) => if let Entry::All(values) = &mut entries[i].1 { values.push(value) },
+            None => {
+                collected.insert(key.trim().to_ascii_uppercase(), entries.len());
+                entries.push((key, Entry::All(vec![value])));
+            },
+        }
+    }
+
+    T::deserialize(DocumentDeserializer { entries })
+}
+
+/// Whether struct field `field` is spelled like MSD key `key`, ignoring ASCII case and underscores.
+fn field_matches(field: &str, key: &str) -> bool {
+    field.bytes().filter(|&b| b != b'_').map(|b| b.to_ascii_uppercase())
+        .eq(key.bytes().map(|b| b.to_ascii_uppercase()))
+}
+
+/// Deserializes a whole document as a map from keys to values.
+struct DocumentDeserializer<'a> {
+    entries: Vec<(&'a str, Entry<'a>)>,
+}
+
+/// The components after a key: those of its only occurrence, or of every occurrence when collected.
+enum Entry<'a> {
+    One(&'a [String]),
+    All(Vec<&'a [String]>),
+}
+
+impl<'de> de::Deserializer<'de> for DocumentDeserializer<'_> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(Parameters { entries: self.entries.into_iter(), fields: &[], value: None })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(Parameters { entries: self.entries.into_iter(), fields, value: None })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct enum identifier ignored_any
+    }
+}
+
+struct Parameters<'a> {
+    entries: std::vec::IntoIter<(&'a str, Entry<'a>)>,
+    /// Field names of the struct being deserialized, empty for maps
+    fields: &'static [&'static str],
+    value: Option<Entry<'a>>,
+}
+
+impl<'de> MapAccess<'de> for Parameters<'_> {
+    type Error = DeserializeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.value = Some(value);
+
+        let key = key.trim();
+        match self.fields.iter().find(|field| field_matches(field, key)) {
+            Some(field) => seed.deserialize(StrDeserializer::<DeserializeError>::new(field)).map(Some),
+            None => seed.deserialize(key.into_deserializer()).map(Some),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        match self.value.take() {
+            Some(Entry::All(occurrences)) => seed.deserialize(OccurrencesDeserializer { occurrences }),
+            Some(Entry::One(components)) => seed.deserialize(ValueDeserializer { components }),
+            None => seed.deserialize(ValueDeserializer { components: &[] }),
+        }
+    }
+}
+
+/// Deserializes every occurrence of a collected key as a sequence of values.
+struct OccurrencesDeserializer<'a> {
+    occurrences: Vec<&'a [String]>,
+}
+
+impl<'de> de::Deserializer<'de> for OccurrencesDeserializer<'_> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(Occurrences { occurrences: self.occurrences.into_iter() })
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct Occurrences<'a> {
+    occurrences: std::vec::IntoIter<&'a [String]>,
+}
+
+impl<'de> SeqAccess<'de> for Occurrences<'_> {
+    type Error = DeserializeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        let Some(components) = self.occurrences.next() else { return Ok(None) };
+        seed.deserialize(ValueDeserializer { components }).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.occurrences.len())
+    }
+}
+
+/// Deserializes the components after a key.
+struct ValueDeserializer<'a> {
+    components: &'a [String],
+}
+
+impl ValueDeserializer<'_> {
+    /// The value, i.e. the second component of the parameter, or empty if there is none.
+    fn value(&self) -> &str {
+        self.components.first().map(String::as_str).unwrap_or_default()
+    }
+
+    fn parse<T: std::str::FromStr>(&self, expected: &str) -> Result<T, DeserializeError> {
+        self.value().trim().parse()
+            .map_err(|_| DeserializeError::Message(format!("invalid {}: {:?}", expected, self.value())))
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                visitor.$visit(self.parse(stringify!($method).trim_start_matches("deserialize_"))?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'_> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_string(self.value().to_string())
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let value = self.value().trim();
+        if ["YES", "1", "TRUE"].iter().any(|v| v.eq_ignore_ascii_case(value)) {
+            visitor.visit_bool(true)
+        } else if ["NO", "0", "FALSE"].iter().any(|v| v.eq_ignore_ascii_case(value)) {
+            visitor.visit_bool(false)
+        } else {
+            Err(DeserializeError::Message(format!("invalid bool: {:?}", self.value())))
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        if self.components.iter().all(|c| c.trim().is_empty()) {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let items: Vec<&str> = if self.components.len() > 1 {
+            self.components.iter().map(String::as_str).collect()
+        } else {
+            self.value().split(',').map(str::trim).filter(|item| !item.is_empty()).collect()
+        };
+        visitor.visit_seq(Items { items: items.into_iter() })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, _len: usize, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_enum(self.value().trim().into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct map struct identifier ignored_any
+    }
+}
+
+/// A sequence of strings within a value; each item deserializes like a value of its own.
+struct Items<'a> {
+    items: std::vec::IntoIter<&'a str>,
+}
+
+impl<'de> SeqAccess<'de> for Items<'_> {
+    type Error = DeserializeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        let Some(item) = self.items.next() else { return Ok(None) };
+        let components = [item.to_string()];
+        seed.deserialize(ValueDeserializer { components: &components }).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Header {
+        title: String,
+        #[serde(default)]
+        title_translit: String,
+        offset: f64,
+        #[serde(default)]
+        selectable: Option<bool>,
+        #[serde(default)]
+        sample_start: Option<f64>,
+        sample_length: u32,
+        bpms: Vec<String>,
+        notes: (String, String, String),
+    }
+
+    #[test]
+    fn test_struct() {
+        let header: Header = from_str("#TITLE:A;\n#titletranslit:B;\n#OFFSET: -0.5 ;\n#SELECTABLE:YES;\n#SAMPLESTART:;\n#SAMPLELENGTH:12;\n#BPMS:0=120,\n4=240,;\n#NOTES:dance-single:Hard:9;\n#UNKNOWN:x;").unwrap();
+
+        assert_eq!(Header {
+            title: "A".to_string(),
+            title_translit: "B".to_string(),
+            offset: -0.5,
+            selectable: Some(true),
+            sample_start: None,
+            sample_length: 12,
+            bpms: vec!["0=120".to_string(), "4=240".to_string()],
+            notes: ("dance-single".to_string(), "Hard".to_string(), "9".to_string()),
+        }, header);
+    }
+
+    #[test]
+    fn test_map_and_errors() {
+        let map: HashMap<String, String> = from_str("#A:1;#B:2;").unwrap();
+        assert_eq!(Some(&"2".to_string()), map.get("B"));
+
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Offset {
+            offset: f64,
+        }
+        let error = from_str::<Offset>("#OFFSET:abc;").unwrap_err();
+        assert_eq!("DeserializeError: invalid f64: \"abc\"", error.to_string());
+        assert!(matches!(from_str::<Offset>("#OFFSET:1;\nstray"), Err(DeserializeError::Parse(_))));
+    }
+
+    #[test]
+    fn test_repeat_policies() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Song {
+            title: String,
+            bpms: Vec<String>,
+            #[serde(default)]
+            notes: Vec<(String, String)>,
+        }
+        let input = "#TITLE:A;#BPMS:0=120;#NOTES:dance-single:Hard;#title:B;#BPMS:0=60,4=90;#NOTES:dance-double:Easy;";
+
+        assert!(from_str::<Song>(input).is_err());
+
+        let policies = RepeatPolicies::new()
+            .with_key("TITLE", RepeatPolicy::KeepFirst)
+            .with_key("BPMS", RepeatPolicy::KeepLast);
+        let song: Song = from_reader_with_policies(input.as_bytes(), &policies).unwrap();
+        assert_eq!(Song {
+            title: "A".to_string(),
+            bpms: vec!["0=60".to_string(), "4=90".to_string()],
+            notes: vec![
+                ("dance-single".to_string(), "Hard".to_string()),
+                ("dance-double".to_string(), "Easy".to_string()),
+            ],
+        }, song);
+
+        let strict = RepeatPolicies::new().with_default(RepeatPolicy::Error);
+        assert!(matches!(
+            from_reader_with_policies::<_, Song>(input.as_bytes(), &strict),
+            Err(DeserializeError::Repeated(RepeatedKeyError { first: 0, repeat: 3, .. }))
+        ));
+    }
+}
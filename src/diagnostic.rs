@@ -0,0 +1,95 @@
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::lexer::Position;
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum Severity {
+    /// Recovered from input that StepMania accepts too, like a missing `;` at the end of a line.
+    Warning,
+    /// Recovered from input that would otherwise be an error, like stray text.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem the parser recovered from in diagnostics mode, see [`MSDParser::with_diagnostics`].
+///
+/// [`MSDParser::with_diagnostics`]: crate::parser::MSDParser::with_diagnostics
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Where the problem starts in the input.
+    pub position: Position,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}: {}", self.severity, self.position, self.message)
+    }
+}
+
+/// Receiver of the [`Diagnostic`]s of a parser in diagnostics mode.
+///
+/// Implemented for `Vec<Diagnostic>`, and for channel senders so that parsers on several threads can report
+/// to one place.
+pub trait DiagnosticSink: fmt::Debug + Send {
+    fn push(&mut self, diagnostic: Diagnostic);
+}
+
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn push(&mut self, diagnostic: Diagnostic) {
+        Vec::push(self, diagnostic);
+    }
+}
+
+impl DiagnosticSink for Sender<Diagnostic> {
+    /// Send the diagnostic, dropping it if the receiver is gone.
+    fn push(&mut self, diagnostic: Diagnostic) {
+        let _ = self.send(diagnostic);
+    }
+}
+
+/// The sink of a parser in diagnostics mode, shared with the caller.
+pub(crate) type SharedSink = Arc<Mutex<dyn DiagnosticSink>>;
+
+/// Push a diagnostic to `sink`, if the parser is in diagnostics mode, even if another thread panicked while
+/// holding the sink.
+pub(crate) fn report(sink: Option<&SharedSink>, severity: Severity, position: Position, message: String) {
+    if let Some(sink) = sink {
+        sink.lock().unwrap_or_else(PoisonError::into_inner).push(Diagnostic { severity, position, message });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let diagnostic = Diagnostic { severity: Severity::Error, position: Position::start(), message: "stray 'x'".to_string() };
+
+        assert_eq!("error at line 1, column 1: stray 'x'", diagnostic.to_string());
+    }
+
+    #[test]
+    fn test_sender() {
+        let (mut sender, receiver) = std::sync::mpsc::channel();
+        let diagnostic = Diagnostic { severity: Severity::Warning, position: Position::start(), message: String::new() };
+        DiagnosticSink::push(&mut sender, diagnostic.clone());
+
+        assert_eq!(Ok(diagnostic), receiver.try_recv());
+    }
+}
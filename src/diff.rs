@@ -0,0 +1,210 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::document::MSDDocument;
+use crate::parameter::MSDParameter;
+
+/// Settings for [`diff_with_options`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct DiffOptions {
+    /// Whether components that differ only in the amount or kind of whitespace, like a reindented `#NOTES`
+    /// or a value with trailing spaces, count as equal.
+    /// Defaults to `false`.
+    pub ignore_whitespace: bool,
+}
+
+impl DiffOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ignore_whitespace(mut self, ignore_whitespace: bool) -> Self {
+        self.ignore_whitespace = ignore_whitespace;
+        self
+    }
+}
+
+/// A component that differs between two occurrences of a key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ComponentChange {
+    /// Index of the component, where `0` is the key and `1` the value.
+    pub index: usize,
+    /// The component in the old document, or `None` if the old parameter has fewer components.
+    pub old: Option<String>,
+    /// The component in the new document, or `None` if the new parameter has fewer components.
+    pub new: Option<String>,
+}
+
+/// A difference between two documents, see [`diff`].
+///
+/// Repeated keys are told apart by `occurrence`, counting from `0`, so the second `#NOTES` of one document
+/// is compared with the second `#NOTES` of the other.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocumentChange {
+    /// The parameter is only in the new document.
+    Added { key: String, occurrence: usize, parameter: MSDParameter },
+    /// The parameter is only in the old document.
+    Removed { key: String, occurrence: usize, parameter: MSDParameter },
+    /// The parameter is in both documents, with different components.
+    Modified { key: String, occurrence: usize, changes: Vec<ComponentChange> },
+}
+
+impl DocumentChange {
+    /// The key of the parameter, as spelled in the old document unless the parameter was added.
+    pub fn key(&self) -> &str {
+        match self {
+            DocumentChange::Added { key, .. } | DocumentChange::Removed { key, .. } | DocumentChange::Modified { key, .. } => key,
+        }
+    }
+}
+
+impl fmt::Display for DocumentChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentChange::Added { key, occurrence, .. } => write!(f, "+ #{} [{}]", key, occurrence),
+            DocumentChange::Removed { key, occurrence, .. } => write!(f, "- #{} [{}]", key, occurrence),
+            DocumentChange::Modified { key, occurrence, changes } => {
+                write!(f, "~ #{} [{}]", key, occurrence)?;
+                for change in changes {
+                    write!(f, "\n    component {}: {:?} -> {:?}", change.index, change.old, change.new)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Compare two documents parameter by parameter. See [`diff_with_options`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::{MSDDocument, MSDParserOptions};
+/// # use msdparser::diff::{diff, DocumentChange};
+/// let old = MSDDocument::from_reader("#TITLE:A;\n#BPMS:0=120;\n".as_bytes(), MSDParserOptions::new()).unwrap();
+/// let new = MSDDocument::from_reader("#title:A;\n#BPMS:0=128;\n#CREDIT:me;".as_bytes(), MSDParserOptions::new()).unwrap();
+///
+/// let changes: Vec<String> = diff(&old, &new).iter().map(ToString::to_string).collect();
+/// assert_eq!(vec![
+///     "~ #TITLE [0]\n    component 0: Some(\"TITLE\") -> Some(\"title\")",
+///     "~ #BPMS [0]\n    component 1: Some(\"0=120\") -> Some(\"0=128\")",
+///     "+ #CREDIT [0]",
+/// ], changes);
+/// ```
+pub fn diff(old: &MSDDocument, new: &MSDDocument) -> Vec<DocumentChange> {
+    diff_with_options(old, new, &DiffOptions::new())
+}
+
+/// Compare two documents parameter by parameter.
+///
+/// Parameters are matched by key, ignoring ASCII case and surrounding whitespace, and by occurrence,
+/// so moving a parameter is not a change. Comments are never part of a parsed document, so they never show up.
+///
+/// Removed and modified parameters come first, in the order of the old document, followed by added parameters
+/// in the order of the new one.
+pub fn diff_with_options(old: &MSDDocument, new: &MSDDocument, options: &DiffOptions) -> Vec<DocumentChange> {
+    let new_occurrences = occurrences(new);
+    let mut matched = HashSet::new();
+    let mut changes = Vec::new();
+
+    for (id, parameter) in occurrences(old) {
+        let (key, occurrence) = (display_key(parameter), id.1);
+        match new_occurrences.iter().find(|(new_id, _)| *new_id == id) {
+            Some((_, new_parameter)) => {
+                matched.insert(id);
+                let component_changes = compare(parameter, new_parameter, options);
+                if !component_changes.is_empty() {
+                    changes.push(DocumentChange::Modified { key, occurrence, changes: component_changes });
+                }
+            },
+            None => changes.push(DocumentChange::Removed { key, occurrence, parameter: parameter.clone() }),
+        }
+    }
+
+    for (id, parameter) in new_occurrences {
+        if !matched.contains(&id) {
+            changes.push(DocumentChange::Added { key: display_key(parameter), occurrence: id.1, parameter: parameter.clone() });
+        }
+    }
+
+    changes
+}
+
+/// Every parameter with its normalized key and how many times that key came before it.
+fn occurrences(document: &MSDDocument) -> Vec<((String, usize), &MSDParameter)> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    document.iter()
+        .map(|(key, parameter)| {
+            let key = key.trim().to_ascii_uppercase();
+            let count = counts.entry(key.clone()).or_default();
+            *count += 1;
+            ((key, *count - 1), parameter)
+        })
+        .collect()
+}
+
+fn display_key(parameter: &MSDParameter) -> String {
+    parameter.components.first().map_or("", |key| key.trim()).to_string()
+}
+
+fn compare(old: &MSDParameter, new: &MSDParameter, options: &DiffOptions) -> Vec<ComponentChange> {
+    // Ignoring whitespace still tells `0000\n0000` apart from `00000000`, since rows of notes are separated by it
+    let equal = |a: &str, b: &str| if options.ignore_whitespace {
+        a.split_whitespace().eq(b.split_whitespace())
+    } else {
+        a == b
+    };
+
+    (0..old.components.len().max(new.components.len()))
+        .filter_map(|index| {
+            let (a, b) = (old.components.get(index), new.components.get(index));
+            match (a, b) {
+                (Some(a), Some(b)) if equal(a, b) => None,
+                _ => Some(ComponentChange { index, old: a.cloned(), new: b.cloned() }),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::MSDParserOptions;
+
+    fn document(input: &str) -> MSDDocument {
+        MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap()
+    }
+
+    #[test]
+    fn test_repeated_keys() {
+        let old = document("#NOTES:a:1;#TITLE:x;#NOTES:b:2;#NOTES:c:3;");
+        let new = document("#TITLE:x;#NOTES:a:1;#NOTES:b:2:extra;");
+
+        assert_eq!(vec![
+            DocumentChange::Modified {
+                key: "NOTES".to_string(),
+                occurrence: 1,
+                changes: vec![ComponentChange { index: 3, old: None, new: Some("extra".to_string()) }],
+            },
+            DocumentChange::Removed {
+                key: "NOTES".to_string(),
+                occurrence: 2,
+                parameter: MSDParameter::new(vec!["NOTES".to_string(), "c".to_string(), "3".to_string()]),
+            },
+        ], diff(&old, &new));
+        assert!(diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_ignore_whitespace() {
+        let old = document("#NOTES:\n     dance-single:\n0000\n0000\n;#TITLE: A ;");
+        let new = document("#NOTES:dance-single:0000\r\n0000;#TITLE:A;");
+
+        assert_eq!(2, diff(&old, &new).len());
+        assert!(diff_with_options(&old, &new, &DiffOptions::new().ignore_whitespace(true)).is_empty());
+        let joined_rows = document("#NOTES:dance-single:00000000;#TITLE:A;");
+        assert_eq!(1, diff_with_options(&old, &joined_rows, &DiffOptions::new().ignore_whitespace(true)).len());
+    }
+}
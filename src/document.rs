@@ -0,0 +1,541 @@
+use std::io::{Read, Write};
+use std::ops::{Index, IndexMut};
+
+use crate::merge::{MergeConflictError, MergePolicy, MergeStrategy};
+use crate::normalize::Pipeline;
+use crate::parameter::{CommentPlacement, MSDParameter, MSDParameterError};
+use crate::parser::{parse_msd_with_options, MSDParserError, MSDParserOptions};
+use crate::repeat::{RepeatPolicies, RepeatedKeyError};
+use crate::serialize::{MSDSerializeOptions, MsdSerialize};
+
+/// The parameters of a document, in order, with lookup by key.
+///
+/// Repeated keys are kept as they appear, since a simfile has one `#NOTES` per chart;
+/// [`MSDDocument::get`] returns the first occurrence and [`MSDDocument::get_all`] every one of them.
+/// Use [`MSDDocument::resolve_repeats`] to keep only the first or last occurrence of some keys, or reject them.
+/// Keys are compared exactly, like in [`crate::cursor::DocumentCursor`], unless
+/// [`MSDDocument::with_case_insensitive_keys`] is set. Either way the parameters keep their keys as written,
+/// so serialization reproduces the original casing; to rewrite keys in uppercase instead, parse with
+/// [`MSDParserOptions::normalize_keys`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::{MSDDocument, MSDParserOptions};
+/// # use msdparser::serialize::{MsdSerialize, MSDSerializeOptions};
+/// let input = "#TITLE:Springtime;\n#NOTES:a;\n#NOTES:b;\n";
+/// let document = MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap();
+///
+/// assert_eq!(Some("Springtime".to_string()), document.get("TITLE").unwrap().value());
+/// assert_eq!(2, document.get_all("NOTES").count());
+/// assert_eq!(input, document.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MSDDocument {
+    parameters: Vec<MSDParameter>,
+    case_insensitive_keys: bool,
+    preamble: String,
+    suffix: Option<String>,
+}
+
+impl MSDDocument {
+    /// Create an empty document.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether lookups match keys ignoring ASCII case and surrounding whitespace, so `#Title:` is found
+    /// by `get("TITLE")`. Defaults to `false`.
+    pub fn with_case_insensitive_keys(mut self, case_insensitive_keys: bool) -> Self {
+        self.case_insensitive_keys = case_insensitive_keys;
+        self
+    }
+
+    pub fn case_insensitive_keys(&self) -> bool {
+        self.case_insensitive_keys
+    }
+
+    /// Parse a whole document from `reader`.
+    ///
+    /// The text around the parameters is kept as the [preamble](MSDDocument::preamble) and
+    /// [suffix](MSDDocument::suffix). With [`MSDParserOptions::keep_comments`], the comments in that text stay
+    /// there instead of also being attached to the first or last parameter, so they are written once.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error the parser yields.
+    pub fn from_reader<R: Read>(reader: R, options: MSDParserOptions) -> Result<Self, MSDParserError> {
+        let mut parser = parse_msd_with_options(reader, options);
+        let mut document: Self = parser.by_ref().collect::<Result<_, _>>()?;
+        document.preamble = parser.preamble().to_string();
+        document.suffix = match parser.suffix() {
+            "" | "\n" | "\r\n" => None,
+            suffix => Some(suffix.to_string()),
+        };
+        if let Some(first) = document.parameters.first_mut() {
+            first.comments.retain(|comment| comment.placement != CommentPlacement::Before);
+        }
+        if let Some(last) = document.parameters.last_mut() {
+            last.comments.retain(|comment| !matches!(comment.placement, CommentPlacement::SameLine | CommentPlacement::After));
+        }
+        Ok(document)
+    }
+
+    /// Text before the first parameter, see [`MSDParser::preamble`](crate::parser::MSDParser::preamble).
+    /// Empty unless the document was parsed.
+    pub fn preamble(&self) -> &str {
+        &self.preamble
+    }
+
+    pub fn set_preamble(&mut self, preamble: &str) {
+        self.preamble = preamble.to_string();
+    }
+
+    /// Text after the last parameter, see [`MSDParser::suffix`](crate::parser::MSDParser::suffix).
+    /// `None` unless the document was parsed and the text is more than the final line break, which is then
+    /// written like [`MSDSerializeOptions::trailing_line_ending`] says.
+    pub fn suffix(&self) -> Option<&str> {
+        self.suffix.as_deref()
+    }
+
+    /// Set the text after the last parameter. `None` ends the document with a line ending like
+    /// [`MSDSerializeOptions::trailing_line_ending`] says instead.
+    pub fn set_suffix(&mut self, suffix: Option<&str>) {
+        self.suffix = suffix.map(str::to_string);
+    }
+
+    /// Resolve repeated keys according to `policies`, keeping the order of the parameters.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first repeat of a key whose policy is [`crate::repeat::RepeatPolicy::Error`].
+    pub fn resolve_repeats(self, policies: &RepeatPolicies) -> Result<Self, RepeatedKeyError> {
+        Ok(Self { parameters: policies.apply(self.parameters)?, ..self })
+    }
+
+    /// Combine `other` into this document, e.g. translated titles from one file with the timing of another.
+    ///
+    /// The n-th occurrence of a key in `other` is paired with the n-th occurrence here, comparing keys like
+    /// [`MSDDocument::get`]. Parameters without a counterpart are appended in the order of `other`, and those
+    /// that differ from their counterpart are resolved by the [`MergeStrategy`] of their key.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first conflict on a key whose strategy is [`MergeStrategy::Error`].
+    pub fn merge(mut self, other: MSDDocument, policy: &MergePolicy) -> Result<Self, MergeConflictError> {
+        // Parameters appended during the merge are never paired, so repeated keys keep lining up
+        let original_len = self.parameters.len();
+        let mut seen: Vec<String> = Vec::new();
+
+        for parameter in other.parameters {
+            let Some(key) = parameter.components.first().cloned() else {
+                self.parameters.push(parameter);
+                continue;
+            };
+            let occurrence = seen.iter().filter(|k| self.keys_match(k, &key)).count();
+            seen.push(key.clone());
+            let ours = self.parameters[..original_len].iter()
+                .enumerate()
+                .filter(|(_, p)| self.has_key(p, &key))
+                .nth(occurrence)
+                .map(|(i, _)| i);
+
+            match ours {
+                None => self.parameters.push(parameter),
+                Some(i) if self.parameters[i] == parameter => {},
+                Some(i) => match policy.strategy_for(&key) {
+                    MergeStrategy::KeepOurs => {},
+                    MergeStrategy::TakeTheirs => self.parameters[i] = parameter,
+                    MergeStrategy::KeepBoth => self.parameters.push(parameter),
+                    MergeStrategy::Error => return Err(MergeConflictError { key, occurrence, span: parameter.span }),
+                },
+            }
+        }
+
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.parameters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parameters.is_empty()
+    }
+
+    /// Every parameter, in document order.
+    pub fn parameters(&self) -> &[MSDParameter] {
+        &self.parameters
+    }
+
+    /// Every parameter, in document order, open to changes.
+    pub fn parameters_mut(&mut self) -> &mut [MSDParameter] {
+        &mut self.parameters
+    }
+
+    pub fn into_parameters(self) -> Vec<MSDParameter> {
+        self.parameters
+    }
+
+    /// Run `pipeline` on every parameter, see [`Pipeline`].
+    pub fn normalize(&mut self, pipeline: &Pipeline) {
+        pipeline.apply_all(&mut self.parameters);
+    }
+
+    /// Append a parameter to the end of the document.
+    pub fn push(&mut self, parameter: MSDParameter) {
+        self.parameters.push(parameter);
+    }
+
+    /// Every parameter with its key, in document order, without copying the keys.
+    ///
+    /// Parameters without components have an empty key.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&str, &MSDParameter)> + ExactSizeIterator {
+        self.parameters.iter().map(|p| (p.components.first().map_or("", String::as_str), p))
+    }
+
+    /// Every key with the components after it, in document order, with the components open to changes.
+    ///
+    /// The key is borrowed from the parameter, so it can't change while its values do.
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = (&str, &mut [String])> + ExactSizeIterator {
+        self.parameters.iter_mut().map(|p| match p.components.split_first_mut() {
+            Some((key, values)) => (key.as_str(), values),
+            None => ("", &mut [][..]),
+        })
+    }
+
+    /// The first parameter with the given key.
+    pub fn get(&self, key: &str) -> Option<&MSDParameter> {
+        self.parameters.iter().find(|p| self.has_key(p, key))
+    }
+
+    /// The first parameter with the given key, open to changes.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut MSDParameter> {
+        let index = self.position(key)?;
+        Some(&mut self.parameters[index])
+    }
+
+    /// Every parameter with the given key, in document order.
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a MSDParameter> + 'a {
+        self.parameters.iter().filter(move |p| self.has_key(p, key))
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn has_key(&self, parameter: &MSDParameter, key: &str) -> bool {
+        parameter.components.first().is_some_and(|k| self.keys_match(k, key))
+    }
+
+    fn keys_match(&self, a: &str, b: &str) -> bool {
+        if self.case_insensitive_keys {
+            a.trim().eq_ignore_ascii_case(b.trim())
+        } else {
+            a == b
+        }
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.parameters.iter().position(|p| self.has_key(p, key))
+    }
+
+    /// Insert a parameter right after the first parameter with the given key.
+    ///
+    /// Returns `false` and does nothing if no parameter has the key.
+    pub fn insert_after(&mut self, key: &str, parameter: MSDParameter) -> bool {
+        let Some(index) = self.position(key) else { return false };
+        self.parameters.insert(index + 1, parameter);
+        true
+    }
+
+    /// Remove and return the first parameter with the given key.
+    pub fn remove(&mut self, key: &str) -> Option<MSDParameter> {
+        let index = self.position(key)?;
+        Some(self.parameters.remove(index))
+    }
+
+    /// Remove every parameter with the given key, returning them in document order.
+    pub fn remove_all(&mut self, key: &str) -> Vec<MSDParameter> {
+        let (removed, kept) = std::mem::take(&mut self.parameters).into_iter().partition(|p| self.has_key(p, key));
+        self.parameters = kept;
+        removed
+    }
+
+    /// Replace the value (second component) of the first parameter with the given key, returning the old value.
+    ///
+    /// Other components are left alone, and a parameter without a value gains one.
+    /// Returns `None` if no parameter has the key or it had no value.
+    pub fn replace_value(&mut self, key: &str, value: &str) -> Option<String> {
+        let index = self.position(key)?;
+        let components = &mut self.parameters[index].components;
+        match components.get_mut(1) {
+            Some(existing) => Some(std::mem::replace(existing, value.to_string())),
+            None => {
+                components.push(value.to_string());
+                None
+            },
+        }
+    }
+}
+
+/// The first parameter with the given key.
+///
+/// # Panics
+///
+/// Panics if no parameter has the key, like indexing a `HashMap`. Use [`MSDDocument::get`] otherwise.
+impl Index<&str> for MSDDocument {
+    type Output = MSDParameter;
+
+    fn index(&self, key: &str) -> &MSDParameter {
+        self.get(key).unwrap_or_else(|| panic!("no parameter with key {:?}", key))
+    }
+}
+
+/// The first parameter with the given key, open to changes.
+///
+/// # Panics
+///
+/// Panics if no parameter has the key. Use [`MSDDocument::get_mut`] otherwise.
+impl IndexMut<&str> for MSDDocument {
+    fn index_mut(&mut self, key: &str) -> &mut MSDParameter {
+        let index = self.position(key).unwrap_or_else(|| panic!("no parameter with key {:?}", key));
+        &mut self.parameters[index]
+    }
+}
+
+impl From<Vec<MSDParameter>> for MSDDocument {
+    fn from(parameters: Vec<MSDParameter>) -> Self {
+        Self { parameters, ..Self::default() }
+    }
+}
+
+impl From<MSDDocument> for Vec<MSDParameter> {
+    fn from(document: MSDDocument) -> Self {
+        document.parameters
+    }
+}
+
+impl FromIterator<MSDParameter> for MSDDocument {
+    fn from_iter<I: IntoIterator<Item = MSDParameter>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl Extend<MSDParameter> for MSDDocument {
+    fn extend<I: IntoIterator<Item = MSDParameter>>(&mut self, iter: I) {
+        self.parameters.extend(iter);
+    }
+}
+
+impl IntoIterator for MSDDocument {
+    type Item = MSDParameter;
+    type IntoIter = std::vec::IntoIter<MSDParameter>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.parameters.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a MSDDocument {
+    type Item = &'a MSDParameter;
+    type IntoIter = std::slice::Iter<'a, MSDParameter>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.parameters.iter()
+    }
+}
+
+/// Like a slice of parameters: separated by the [`crate::serialize::RecordSeparator`], with an optional trailing line ending.
+///
+/// The [preamble](MSDDocument::preamble) comes first, and the [suffix](MSDDocument::suffix) takes the place of the
+/// trailing line ending, so a parsed document is written back with the text around its parameters.
+impl MsdSerialize for MSDDocument {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        writer.write_all(self.preamble.as_bytes())?;
+        match &self.suffix {
+            Some(suffix) => {
+                self.parameters.serialize_msd(writer, &options.clone().trailing_line_ending(false))?;
+                writer.write_all(suffix.as_bytes())?;
+            },
+            None => self.parameters.serialize_msd(writer, options)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameter(key: &str, value: &str) -> MSDParameter {
+        MSDParameter::new(vec![key.to_string(), value.to_string()])
+    }
+
+    #[test]
+    fn test_lookup() {
+        let document = MSDDocument::from_reader(
+            b"#TITLE:A;#NOTES:1;#title:B;#NOTES:2;".as_ref(),
+            MSDParserOptions::new(),
+        ).unwrap();
+
+        assert_eq!(4, document.len());
+        assert_eq!(Some("A".to_string()), document.get("TITLE").and_then(MSDParameter::value));
+        assert_eq!(vec!["1", "2"], document.get_all("NOTES").map(|p| p.components[1].as_str()).collect::<Vec<_>>());
+        assert!(!document.contains_key("ARTIST"));
+        assert_eq!(
+            vec!["TITLE", "NOTES", "title", "NOTES"],
+            (&document).into_iter().map(|p| p.components[0].as_str()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut document = MSDDocument::from(vec![parameter("title", "A"), parameter("Notes", "1")]);
+        document.normalize(&Pipeline::new().stage(crate::normalize::UppercaseKeys));
+
+        assert_eq!(vec![parameter("TITLE", "A"), parameter("NOTES", "1")], document.parameters());
+        assert_eq!(Some("Notes"), document.parameters()[1].original_key());
+    }
+
+    #[test]
+    fn test_build_and_serialize() {
+        let mut document: MSDDocument = vec![parameter("TITLE", "A")].into_iter().collect();
+        document.push(parameter("ARTIST", "B"));
+        document.extend([parameter("NOTES", "x:y")]);
+
+        assert_eq!("#TITLE:A;\n#ARTIST:B;\n#NOTES:x\\:y;\n", document.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+        assert!(MSDDocument::from_reader(b"#A:B;C".as_ref(), MSDParserOptions::new()).is_err());
+    }
+
+    #[test]
+    fn test_mutation() {
+        let mut document = MSDDocument::from(vec![
+            parameter("TITLE", "A"),
+            parameter("NOTES", "1"),
+            parameter("ARTIST", "B"),
+            parameter("NOTES", "2"),
+        ]);
+
+        assert!(document.insert_after("TITLE", parameter("SUBTITLE", "S")));
+        assert!(!document.insert_after("GENRE", parameter("CREDIT", "C")));
+        assert_eq!(Some("B".to_string()), document.replace_value("ARTIST", "C"));
+        assert_eq!(None, document.replace_value("GENRE", "Pop"));
+        assert_eq!(Some(parameter("TITLE", "A")), document.remove("TITLE"));
+        assert_eq!(vec![parameter("NOTES", "1"), parameter("NOTES", "2")], document.remove_all("NOTES"));
+
+        assert_eq!(vec![parameter("SUBTITLE", "S"), parameter("ARTIST", "C")], document.into_parameters());
+
+        let mut keyless = MSDDocument::from(vec![MSDParameter::new(vec!["OFFSET".to_string()])]);
+        assert_eq!(None, keyless.replace_value("OFFSET", "0"));
+        assert_eq!(Some("0".to_string()), keyless.get("OFFSET").and_then(MSDParameter::value));
+    }
+
+    #[test]
+    fn test_case_insensitive_keys() {
+        let input = b"#Title:A;\n# artist :B;\n#TITLE:C;\n";
+        let mut document = MSDDocument::from_reader(input.as_ref(), MSDParserOptions::new()).unwrap();
+        assert_eq!(Some("C".to_string()), document.get("TITLE").and_then(MSDParameter::value));
+
+        document = document.with_case_insensitive_keys(true);
+        assert_eq!(Some("A".to_string()), document.get("TITLE").and_then(MSDParameter::value));
+        assert_eq!(2, document.get_all("title").count());
+        assert_eq!(Some("B".to_string()), document.replace_value("ARTIST", "D"));
+
+        assert_eq!("#Title:A;\n# artist :D;\n#TITLE:C;\n", document.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut document = MSDDocument::from(vec![
+            parameter("TITLE", "A"),
+            MSDParameter::new(Vec::new()),
+            MSDParameter::new(vec!["NOTES".to_string(), "dance-single".to_string(), "1000".to_string()]),
+        ]);
+
+        let index: std::collections::HashMap<&str, &MSDParameter> = document.iter().collect();
+        assert_eq!(Some(&&parameter("TITLE", "A")), index.get("TITLE"));
+        assert_eq!(3, document.iter().len());
+
+        for (key, values) in document.iter_mut() {
+            if key == "NOTES" {
+                values[1] = "0001".to_string();
+            }
+            values.iter_mut().for_each(|value| value.make_ascii_lowercase());
+        }
+        assert_eq!(
+            vec![("TITLE", vec!["a"]), ("", vec![]), ("NOTES", vec!["dance-single", "0001"])],
+            document.iter().map(|(key, p)| (key, p.components.iter().skip(1).map(String::as_str).collect::<Vec<_>>())).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_resolve_repeats() {
+        let document = MSDDocument::from(vec![parameter("BPMS", "0=120"), parameter("NOTES", "1"), parameter("BPMS", "0=60"), parameter("NOTES", "2")]);
+        let policies = RepeatPolicies::new().with_key("BPMS", crate::repeat::RepeatPolicy::KeepLast);
+
+        let resolved = document.clone().resolve_repeats(&policies).unwrap();
+        assert_eq!(vec![parameter("BPMS", "0=60"), parameter("NOTES", "1"), parameter("NOTES", "2")], resolved.parameters());
+        assert!(document.resolve_repeats(&policies.with_default(crate::repeat::RepeatPolicy::Error)).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let document = |input: &str| MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap();
+        let ours = document("#TITLE:A;#NOTES:1;#NOTES:2;#ARTIST:B;");
+        let theirs = document("#NOTES:1;#NOTES:3;#NOTES:4;#TITLE:C;#ARTIST:B;");
+
+        let merged = ours.clone().merge(theirs.clone(), &MergePolicy::new().with_key("NOTES", MergeStrategy::KeepBoth)).unwrap();
+        assert_eq!("#TITLE:C;\n#NOTES:1;\n#NOTES:2;\n#ARTIST:B;\n#NOTES:3;\n#NOTES:4;\n", merged.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+
+        let conflict = ours.merge(theirs, &MergePolicy::new().with_default(MergeStrategy::Error)).unwrap_err();
+        assert_eq!(("NOTES", 1, Some(9..18)), (conflict.key.as_str(), conflict.occurrence, conflict.span));
+    }
+
+    #[test]
+    fn test_preamble_and_suffix() {
+        let input = "\u{feff}// header\r\n#TITLE:A;\r\n#ARTIST:B; \r\n// footer";
+        let mut document = MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap();
+
+        assert_eq!(("\u{feff}// header\r\n", Some(" \r\n// footer")), (document.preamble(), document.suffix()));
+        let options = MSDSerializeOptions::new().line_ending(crate::serialize::LineEnding::CrLf);
+        assert_eq!(input, document.to_msd_string(&options).unwrap());
+
+        document.set_preamble("");
+        document.set_suffix(None);
+        assert_eq!("#TITLE:A;\r\n#ARTIST:B;\r\n", document.to_msd_string(&options).unwrap());
+
+        let document = MSDDocument::from_reader(b"#TITLE:A;\r\n".as_ref(), MSDParserOptions::new()).unwrap();
+        assert_eq!(None, document.suffix());
+    }
+
+    #[test]
+    fn test_comments_around_parameters() {
+        let input = "// header\n#TITLE:A; // title\n// artist\n#ARTIST:B; // last\n// footer";
+        let document = MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new().keep_comments(true)).unwrap();
+
+        assert_eq!(vec![" title"], document["TITLE"].comments.iter().map(|c| c.text.as_str()).collect::<Vec<_>>());
+        assert_eq!(vec![" artist"], document["ARTIST"].comments.iter().map(|c| c.text.as_str()).collect::<Vec<_>>());
+        assert_eq!(input, document.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+    }
+
+    #[test]
+    fn test_index() {
+        let mut document = MSDDocument::from(vec![parameter("TITLE", "A"), parameter("TITLE", "B")]);
+
+        assert_eq!(Some("A".to_string()), document["TITLE"].value());
+        document["TITLE"].components[1] = "C".to_string();
+        if let Some(title) = document.get_mut("TITLE") {
+            title.components.push("D".to_string());
+        }
+        assert_eq!(vec!["TITLE", "C", "D"], document["TITLE"].components);
+        assert!(document.get_mut("ARTIST").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "no parameter with key \"ARTIST\"")]
+    fn test_index_missing_key() {
+        let document = MSDDocument::from(vec![parameter("TITLE", "A")]);
+        let _ = &document["ARTIST"];
+    }
+}
@@ -0,0 +1,271 @@
+use std::ops::Range;
+use std::{error, fmt, io};
+#[cfg(feature = "io")]
+use std::{fs, path::Path};
+
+use crate::lexer::{lex_msd, EscapePolicy, MSDToken};
+use crate::parameter::{MSDParameter, MSDParameterError};
+#[cfg(feature = "io")]
+use crate::parser::MSDParserOptions;
+
+/// Custom error type for in-place edits.
+#[derive(Debug)]
+pub enum RewriteError {
+    /// No parameter has the key.
+    MissingKey(String),
+    /// The input is not valid UTF-8, so it can't be edited without changing other bytes.
+    InvalidUtf8(std::str::Utf8Error),
+    /// The new value can't be written as MSD.
+    Serialize(MSDParameterError),
+    Io(io::Error),
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewriteError::MissingKey(key) => write!(f, "RewriteError: no parameter with key {:?}", key),
+            RewriteError::InvalidUtf8(e) => write!(f, "RewriteError: {}", e),
+            RewriteError::Serialize(e) => write!(f, "{}", e),
+            RewriteError::Io(e) => write!(f, "IO Error: {}", e),
+        }
+    }
+}
+
+impl error::Error for RewriteError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            RewriteError::MissingKey(_) => None,
+            RewriteError::InvalidUtf8(e) => Some(e),
+            RewriteError::Serialize(e) => Some(e),
+            RewriteError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<MSDParameterError> for RewriteError {
+    fn from(e: MSDParameterError) -> Self {
+        RewriteError::Serialize(e)
+    }
+}
+
+impl From<io::Error> for RewriteError {
+    fn from(e: io::Error) -> Self {
+        RewriteError::Io(e)
+    }
+}
+
+/// Where the value of a parameter is in the source.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct ValueLocation {
+    /// Bytes of the value, without the `:` before it. Empty if the parameter has no value.
+    range: Range<usize>,
+    /// Whether the parameter has no value, so a `:` must be written before the new one.
+    needs_colon: bool,
+}
+
+/// Offset of the end of `text` at `start`, without trailing whitespace, or `None` if it's all whitespace.
+fn content_end(text: &str, start: usize) -> Option<usize> {
+    let trimmed = text.trim_end();
+    (!trimmed.is_empty()).then_some(start + trimmed.len())
+}
+
+/// Find the value of the first parameter whose key matches `key`, ignoring ASCII case and surrounding whitespace.
+fn locate_value(input: &str, key: &str, escapes: EscapePolicy) -> Option<ValueLocation> {
+    let key = key.trim();
+    let mut inside = false;
+    let mut current_key = String::new();
+    // Where the key or value ends if the parameter is cut short by a missing `;`
+    let mut content_end_offset = 0;
+    let mut value_start = None;
+
+    let is_match = |current_key: &str| current_key.trim().eq_ignore_ascii_case(key);
+
+    for token_match in lex_msd(input.as_bytes(), escapes).filter_map(Result::ok) {
+        let start = token_match.span.start;
+        match token_match.token {
+            MSDToken::StartParameter => {
+                if inside {
+                    // The previous parameter is missing its `;`
+                    if let Some(value_start) = value_start {
+                        return Some(ValueLocation { range: value_start..content_end_offset.max(value_start), needs_colon: false });
+                    }
+                    if is_match(&current_key) {
+                        return Some(ValueLocation { range: content_end_offset..content_end_offset, needs_colon: true });
+                    }
+                }
+                inside = true;
+                current_key.clear();
+                content_end_offset = token_match.span.end;
+            },
+            MSDToken::NextComponent if inside => match value_start {
+                Some(value_start) => return Some(ValueLocation { range: value_start..start, needs_colon: false }),
+                None if is_match(&current_key) => {
+                    value_start = Some(token_match.span.end);
+                    content_end_offset = token_match.span.end;
+                },
+                None => inside = false,
+            },
+            MSDToken::EndParameter if inside => {
+                if let Some(value_start) = value_start {
+                    return Some(ValueLocation { range: value_start..start, needs_colon: false });
+                }
+                if is_match(&current_key) {
+                    return Some(ValueLocation { range: start..start, needs_colon: true });
+                }
+                inside = false;
+            },
+            MSDToken::Text | MSDToken::Escape if inside => {
+                if value_start.is_none() {
+                    match token_match.token {
+                        MSDToken::Escape => current_key.push_str(&token_match.text[1..]),
+                        _ => current_key.push_str(&token_match.text),
+                    }
+                }
+                if let Some(end) = content_end(&token_match.text, start) {
+                    content_end_offset = end;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // The last parameter is missing its `;`
+    match value_start {
+        Some(value_start) => Some(ValueLocation { range: value_start..content_end_offset.max(value_start), needs_colon: false }),
+        None if inside && is_match(&current_key) => {
+            Some(ValueLocation { range: content_end_offset..content_end_offset, needs_colon: true })
+        },
+        None => None,
+    }
+}
+
+/// Replace the value of the first parameter with the given key, leaving every other byte of `input` as it was.
+///
+/// Keys are compared ignoring ASCII case and surrounding whitespace. The new value is escaped, and anything else
+/// in the parameter, like the spelling of its key or components after the value, is kept.
+/// A parameter without a value gains one.
+///
+/// # Errors
+///
+/// Returns [`RewriteError::MissingKey`] if no parameter has the key.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::edit::rewrite_parameter;
+/// let input = "// header\r\n#Title:Old;  // note\r\n#NOTES:a:b;\r\n";
+///
+/// assert_eq!(
+///     "// header\r\n#Title:New\\: Version;  // note\r\n#NOTES:a:b;\r\n",
+///     rewrite_parameter(input, "TITLE", "New: Version").unwrap()
+/// );
+/// ```
+pub fn rewrite_parameter(input: &str, key: &str, value: &str) -> Result<String, RewriteError> {
+    rewrite_parameter_with(input, key, value, EscapePolicy::Escaped)
+}
+
+/// Like [`rewrite_parameter`], reading `input` and writing the new value with the given [`EscapePolicy`].
+///
+/// With [`EscapePolicy::Unescaped`], as `.dwi` files need, backslashes in `input` are ordinary text and the new
+/// value is written as it is.
+///
+/// # Errors
+///
+/// Returns [`RewriteError::MissingKey`] if no parameter has the key, or [`RewriteError::Serialize`] if the value
+/// can't be written without escapes under [`EscapePolicy::Unescaped`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::edit::rewrite_parameter_with;
+/// # use msdparser::lexer::EscapePolicy;
+/// let input = "#TITLE:A\\B;\n#FILE:C:\\Songs\\b.mp3;\n";
+///
+/// assert_eq!(
+///     "#TITLE:A\\C;\n#FILE:C:\\Songs\\b.mp3;\n",
+///     rewrite_parameter_with(input, "TITLE", "A\\C", EscapePolicy::Unescaped).unwrap()
+/// );
+/// ```
+pub fn rewrite_parameter_with(input: &str, key: &str, value: &str, escapes: impl Into<EscapePolicy>) -> Result<String, RewriteError> {
+    let escapes = escapes.into();
+    let location = locate_value(input, key, escapes).ok_or_else(|| RewriteError::MissingKey(key.to_string()))?;
+    let mut replacement = MSDParameter::serialize_component(value, escapes)?;
+    if location.needs_colon {
+        replacement.insert(0, ':');
+    }
+
+    let mut output = String::with_capacity(input.len() + replacement.len());
+    output.push_str(&input[..location.range.start]);
+    output.push_str(&replacement);
+    output.push_str(&input[location.range.end..]);
+    Ok(output)
+}
+
+/// Replace the value of a parameter in a file, leaving every other byte untouched. See [`rewrite_parameter`].
+///
+/// Backslashes are read and written the way the file's dialect has them, see [`MSDParserOptions::for_path`], so
+/// `.dwi` and `.ksf` files get no escapes. The file is written to a temporary sibling first and then renamed over the original.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or written, isn't valid UTF-8, or has no parameter with the key.
+#[cfg(feature = "io")]
+pub fn rewrite_parameter_file<P: AsRef<Path>>(path: P, key: &str, value: &str) -> Result<(), RewriteError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    let input = std::str::from_utf8(&bytes).map_err(RewriteError::InvalidUtf8)?;
+    let output = rewrite_parameter_with(input, key, value, MSDParserOptions::for_path(path).escapes)?;
+
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".msdtmp");
+    fs::write(&temp, output)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_parameter() {
+        let input = "#TITLE:A;\n#artist :B:extra// c\n;\n#OFFSET;\n#BPMS:0=120\n#SUBTITLE\n#CREDIT";
+        let rewrite = |key, value| rewrite_parameter(input, key, value).unwrap();
+
+        assert_eq!(input.replacen("#TITLE:A;", "#TITLE:x\\;y;", 1), rewrite("title", "x;y"));
+        assert_eq!(input.replacen(":B:extra", ":C:extra", 1), rewrite("ARTIST", "C"));
+        assert_eq!(input.replacen("#OFFSET;", "#OFFSET:0.5;", 1), rewrite("OFFSET", "0.5"));
+        assert_eq!(input.replacen("0=120\n", "0=90\n", 1), rewrite("BPMS", "0=90"));
+        assert_eq!(input.replacen("#SUBTITLE\n", "#SUBTITLE:S\n", 1), rewrite("SUBTITLE", "S"));
+        assert_eq!(format!("{}:C", input), rewrite("CREDIT", "C"));
+        assert!(matches!(rewrite_parameter(input, "GENRE", "x"), Err(RewriteError::MissingKey(_))));
+    }
+
+    #[test]
+    fn test_rewrite_value_with_comment() {
+        let input = "#NOTES:dance-single:\n// measure 1\n0000\n;\n";
+        assert_eq!("#NOTES:dance-double:\n// measure 1\n0000\n;\n", rewrite_parameter(input, "NOTES", "dance-double").unwrap());
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_rewrite_parameter_file() {
+        let dir = crate::test_util::TempDir::new("edit");
+        let path = dir.join("song.sm");
+        fs::write(&path, "\u{feff}#TITLE:A;\r\n#ARTIST:B;\r\n").unwrap();
+
+        rewrite_parameter_file(&path, "ARTIST", "C").unwrap();
+        assert_eq!("\u{feff}#TITLE:A;\r\n#ARTIST:C;\r\n", fs::read_to_string(&path).unwrap());
+
+        fs::write(&path, b"#TITLE:\xff;").unwrap();
+        assert!(matches!(rewrite_parameter_file(&path, "TITLE", "x"), Err(RewriteError::InvalidUtf8(_))));
+
+        let dwi = dir.join("song.dwi");
+        fs::write(&dwi, "#TITLE:A\\;\n#FILE:C:\\Songs\\b.mp3;\n#ARTIST:B;\n").unwrap();
+        rewrite_parameter_file(&dwi, "ARTIST", "C\\D").unwrap();
+        assert_eq!("#TITLE:A\\;\n#FILE:C:\\Songs\\b.mp3;\n#ARTIST:C\\D;\n", fs::read_to_string(&dwi).unwrap());
+        assert!(matches!(rewrite_parameter_file(&dwi, "ARTIST", "C:D"), Err(RewriteError::Serialize(_))));
+    }
+}
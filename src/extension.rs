@@ -0,0 +1,127 @@
+use std::fmt;
+
+use crate::lexer::EscapePolicy;
+use crate::parameter::MSDParameter;
+use crate::parser::MSDParserError;
+
+/// Extension point for MSD format variants.
+/// 
+/// Companion crates implement this trait to plug a dialect, a key schema, and post-processing
+/// into [`MSDParser`] while reusing the core lexing and parsing machinery.
+/// Register an extension with [`MSDParser::with_extension`]; extensions run in registration order.
+/// 
+/// Every method has a default implementation that leaves the parser's behavior unchanged.
+/// 
+/// [`MSDParser`]: ../parser/struct.MSDParser.html
+/// [`MSDParser::with_extension`]: ../parser/struct.MSDParser.html#method.with_extension
+pub trait MsdExtension: fmt::Debug + Send + Sync {
+    /// Name of the extension, used in error messages.
+    fn name(&self) -> &str;
+
+    /// Dialect override for escape handling.
+    /// 
+    /// Returning `Some` replaces the [`EscapePolicy`] the parser was created with.
+    fn escapes(&self) -> Option<EscapePolicy> {
+        None
+    }
+
+    /// Key schema check, called with the key of every parsed parameter.
+    /// 
+    /// # Errors
+    /// 
+    /// Returns a description of the problem if the key is not allowed by the schema.
+    fn validate_key(&self, _key: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Post-process a parsed parameter.
+    /// 
+    /// Returning `Ok(None)` drops the parameter from the output.
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the parameter is invalid for this format variant.
+    fn process(&self, parameter: MSDParameter) -> Result<Option<MSDParameter>, MSDParserError> {
+        Ok(Some(parameter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_msd;
+
+    #[derive(Debug)]
+    struct UppercaseKeys;
+
+    impl MsdExtension for UppercaseKeys {
+        fn name(&self) -> &str {
+            "uppercase-keys"
+        }
+
+        fn validate_key(&self, key: &str) -> Result<(), String> {
+            if key.chars().any(|c| c.is_lowercase()) {
+                Err(format!("key '{}' is not uppercase", key))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct DropEmpty;
+
+    impl MsdExtension for DropEmpty {
+        fn name(&self) -> &str {
+            "drop-empty"
+        }
+
+        fn escapes(&self) -> Option<EscapePolicy> {
+            Some(EscapePolicy::Unescaped)
+        }
+
+        fn process(&self, parameter: MSDParameter) -> Result<Option<MSDParameter>, MSDParserError> {
+            if parameter.value().unwrap_or_default().is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(parameter))
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_key() {
+        let input = b"#TITLE:A;#artist:B;";
+        let mut parser = parse_msd(input.as_ref(), true, false).with_extension(UppercaseKeys);
+
+        assert_eq!(Some(Ok(MSDParameter::new(vec!["TITLE".to_string(), "A".to_string()]))), parser.next());
+        assert_eq!(
+            Some(Err(MSDParserError::Extension { name: "uppercase-keys".to_string(), message: "key 'artist' is not uppercase".to_string() })),
+            parser.next()
+        );
+    }
+
+    #[test]
+    fn test_validate_key_diagnostics() {
+        let sink = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let input = b"#TITLE:A;\n#artist:B;\n#GENRE:C;";
+        let parser = parse_msd(input.as_ref(), true, false).with_extension(UppercaseKeys).with_diagnostics(sink.clone());
+
+        let keys: Vec<String> = parser.map(|p| p.unwrap().key().unwrap()).collect();
+        assert_eq!(vec!["TITLE", "GENRE"], keys);
+        let messages: Vec<String> = sink.lock().unwrap().iter().map(crate::diagnostic::Diagnostic::to_string).collect();
+        assert_eq!(vec!["error at line 2, column 1: uppercase-keys: key 'artist' is not uppercase"], messages);
+    }
+
+    #[test]
+    fn test_process_and_dialect() {
+        let input = b"#TITLE:A\\:B;#SUBTITLE:;#ARTIST:C;";
+        let parser = parse_msd(input.as_ref(), true, false).with_extension(DropEmpty);
+        let params: Vec<MSDParameter> = parser.map(Result::unwrap).collect();
+
+        assert_eq!(vec![
+            MSDParameter::new(vec!["TITLE".to_string(), "A\\".to_string(), "B".to_string()]),
+            MSDParameter::new(vec!["ARTIST".to_string(), "C".to_string()]),
+        ], params);
+    }
+}
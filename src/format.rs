@@ -0,0 +1,464 @@
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::{error, fmt};
+
+#[cfg(feature = "simfile")]
+use crate::charts::{sort_chart_parameters, CanonicalOrder};
+use crate::document::MSDDocument;
+use crate::lossless::LosslessDocument;
+use crate::parameter::{CommentPlacement, MSDParameter, MSDParameterError};
+use crate::parser::{parse_msd_with_options, MSDParserError, MSDParserOptions};
+use crate::serialize::{LineEnding, MSDSerializeOptions, MsdSerialize};
+
+/// Custom error type for [`format_reader`].
+#[derive(Debug)]
+pub enum FormatError {
+    Parse(MSDParserError),
+    Serialize(MSDParameterError),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Parse(e) => write!(f, "{}", e),
+            FormatError::Serialize(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for FormatError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FormatError::Parse(e) => Some(e),
+            FormatError::Serialize(e) => Some(e),
+        }
+    }
+}
+
+impl From<MSDParserError> for FormatError {
+    fn from(e: MSDParserError) -> Self {
+        FormatError::Parse(e)
+    }
+}
+
+impl From<MSDParameterError> for FormatError {
+    fn from(e: MSDParameterError) -> Self {
+        FormatError::Serialize(e)
+    }
+}
+
+/// Settings for the canonical style written by [`format_document`], [`format_lossless`] and [`format_reader`].
+///
+/// Every parameter is written on a line of its own with the fewest escapes that read back as the same value, and
+/// every line break, also inside values, as [`FormatOptions::line_ending`].
+///
+/// Comments are only kept if the input was parsed with [`MSDParserOptions::keep_comments`]; they are then written
+/// back where they were, see [`MSDParameter::comments`]. Otherwise they are lost, except with
+/// [`FormatOptions::changed_only`], which keeps all text between parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct FormatOptions {
+    /// Line ending after each parameter and blank line, and for the line breaks inside values.
+    /// Defaults to [`LineEnding::Lf`].
+    pub line_ending: LineEnding,
+    /// Number of blank lines before each section. Defaults to `1`.
+    pub blank_lines: usize,
+    /// Keys that start a new section, compared ignoring ASCII case and surrounding whitespace.
+    /// Defaults to `NOTES` and `NOTEDATA`, so every chart of a simfile is set apart from the header.
+    pub section_keys: Vec<String>,
+    /// Whether keys are trimmed and uppercased. Defaults to `true`.
+    pub normalize_keys: bool,
+    /// Most blank lines kept between parameters by [`format_lossless`], which keeps the author's grouping
+    /// of parameters. Defaults to `1`.
+    pub max_blank_lines: usize,
+    /// Whether [`format_lossless`] only reformats the parameters changed since parsing, writing every other
+    /// parameter and all text between parameters exactly as it was. Defaults to `false`.
+    ///
+    /// Meant for editors that save a file after an edit session: new and edited content gets the canonical style,
+    /// but the diff against the source shows only what the user changed.
+    pub changed_only: bool,
+    /// Order to sort the charts of a simfile in, or `None` to keep them as written. Defaults to `None`.
+    /// The charts of an `.ssc` file move as whole `#NOTEDATA` blocks, see [`sort_chart_parameters`].
+    ///
+    /// Ignored by [`format_reader`], which never holds more than one parameter, and with
+    /// [`FormatOptions::changed_only`], which never moves a parameter.
+    #[cfg(feature = "simfile")]
+    pub chart_order: Option<CanonicalOrder>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            line_ending: LineEnding::Lf,
+            blank_lines: 1,
+            section_keys: vec!["NOTES".to_string(), "NOTEDATA".to_string()],
+            normalize_keys: true,
+            max_blank_lines: 1,
+            changed_only: false,
+            #[cfg(feature = "simfile")]
+            chart_order: None,
+        }
+    }
+}
+
+impl FormatOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    pub fn blank_lines(mut self, blank_lines: usize) -> Self {
+        self.blank_lines = blank_lines;
+        self
+    }
+
+    pub fn section_keys<I: IntoIterator<Item = S>, S: Into<String>>(mut self, section_keys: I) -> Self {
+        self.section_keys = section_keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn normalize_keys(mut self, normalize_keys: bool) -> Self {
+        self.normalize_keys = normalize_keys;
+        self
+    }
+
+    pub fn max_blank_lines(mut self, max_blank_lines: usize) -> Self {
+        self.max_blank_lines = max_blank_lines;
+        self
+    }
+
+    pub fn changed_only(mut self, changed_only: bool) -> Self {
+        self.changed_only = changed_only;
+        self
+    }
+
+    #[cfg(feature = "simfile")]
+    pub fn chart_order(mut self, chart_order: Option<CanonicalOrder>) -> Self {
+        self.chart_order = chart_order;
+        self
+    }
+
+    fn starts_section(&self, parameter: &MSDParameter) -> bool {
+        let key = parameter.components.first().map_or("", |key| key.trim());
+        self.section_keys.iter().any(|section| section.trim().eq_ignore_ascii_case(key))
+    }
+}
+
+/// Writes parameters one by one, keeping track of where sections start.
+struct Formatter<'a> {
+    options: &'a FormatOptions,
+    serialize_options: MSDSerializeOptions,
+    first: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(options: &'a FormatOptions) -> Self {
+        Self {
+            options,
+            serialize_options: MSDSerializeOptions::new().line_ending(options.line_ending),
+            first: true,
+        }
+    }
+
+    /// Write `parameter`, after the blank lines that start a section or `source_blank_lines`, whichever is more.
+    fn write(&mut self, writer: &mut dyn Write, parameter: &MSDParameter, source_blank_lines: usize) -> Result<(), MSDParameterError> {
+        let line_ending = self.options.line_ending.as_str().as_bytes();
+        if !self.first {
+            let section_blank_lines = if self.options.starts_section(parameter) { self.options.blank_lines } else { 0 };
+            for _ in 0..section_blank_lines.max(source_blank_lines.min(self.options.max_blank_lines)) {
+                writer.write_all(line_ending)?;
+            }
+        }
+        self.first = false;
+        normalize_line_breaks(parameter, self.options.line_ending).serialize_msd(writer, &self.serialize_options)?;
+        writer.write_all(line_ending)?;
+        Ok(())
+    }
+}
+
+/// Normalize the keys of `document` if [`FormatOptions::normalize_keys`] is set, then write it in the canonical style.
+///
+/// Values keep their layout, so `#NOTES` keeps one measure per line, but their line breaks are written as
+/// [`FormatOptions::line_ending`]. With [`FormatOptions::chart_order`],
+/// the charts of `document` are sorted first.
+/// Since keys are normalized like [`MSDParserOptions::normalize_keys`] does, the spelling from the source
+/// is still available through [`MSDParameter::original_key`].
+///
+/// # Errors
+///
+/// See [`MsdSerialize::serialize_msd`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::{MSDDocument, MSDParserOptions};
+/// # use msdparser::format::{format_document, FormatOptions};
+/// let input = "#title:A\\b;#Artist :B;  // comment\n#NOTES:\n0000\n;#NOTES:x;";
+/// let mut document = MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap();
+///
+/// assert_eq!(
+///     "#TITLE:Ab;\n#ARTIST:B;\n\n#NOTES:\n0000\n;\n\n#NOTES:x;\n",
+///     format_document(&mut document, &FormatOptions::new()).unwrap()
+/// );
+/// assert_eq!(Some("TITLE".to_string()), document.parameters()[0].key());
+/// ```
+pub fn format_document(document: &mut MSDDocument, options: &FormatOptions) -> Result<String, MSDParameterError> {
+    #[cfg(feature = "simfile")]
+    if let Some(order) = &options.chart_order {
+        sort_chart_parameters(document.parameters_mut(), order);
+    }
+
+    let mut formatter = Formatter::new(options);
+    let mut output = Vec::new();
+    for parameter in document.parameters_mut() {
+        if options.normalize_keys {
+            normalize_key(parameter);
+        }
+        formatter.write(&mut output, parameter, 0)?;
+    }
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Write `document` in the canonical style like [`format_document`], but keep the blank lines the author put
+/// between parameters, up to [`FormatOptions::max_blank_lines`] in a row. The text between parameters is not kept,
+/// so comments are lost unless the document was parsed with [`MSDParserOptions::keep_comments`].
+///
+/// # Errors
+///
+/// See [`MsdSerialize::serialize_msd`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::MSDParserOptions;
+/// # use msdparser::format::{format_lossless, FormatOptions};
+/// # use msdparser::lossless::LosslessDocument;
+/// let input = "#TITLE:A;#ARTIST:B;\n\n\n\n#BPMS:0=120;\n#STOPS:;\n#NOTES:x;";
+/// let document = LosslessDocument::parse(input, MSDParserOptions::new()).unwrap();
+///
+/// assert_eq!(
+///     "#TITLE:A;\n#ARTIST:B;\n\n#BPMS:0=120;\n#STOPS:;\n\n#NOTES:x;\n",
+///     format_lossless(&document, &FormatOptions::new()).unwrap()
+/// );
+/// ```
+pub fn format_lossless(document: &LosslessDocument, options: &FormatOptions) -> Result<String, MSDParameterError> {
+    if options.changed_only {
+        return format_changed(document, options);
+    }
+
+    #[cfg_attr(not(feature = "simfile"), allow(unused_mut))]
+    let mut parameters: Vec<MSDParameter> = document.parameters().iter().map(|p| p.parameter().clone()).collect();
+    #[cfg(feature = "simfile")]
+    if let Some(order) = &options.chart_order {
+        sort_chart_parameters(&mut parameters, order);
+    }
+
+    let mut formatter = Formatter::new(options);
+    let mut output = Vec::new();
+    for (mut parameter, source) in parameters.into_iter().zip(document.parameters()) {
+        if options.normalize_keys {
+            normalize_key(&mut parameter);
+        }
+        formatter.write(&mut output, &parameter, source.blank_lines())?;
+    }
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Write `document` as it was parsed, except for the parameters changed since, which are written in the
+/// canonical style. See [`FormatOptions::changed_only`].
+fn format_changed(document: &LosslessDocument, options: &FormatOptions) -> Result<String, MSDParameterError> {
+    let serialize_options = MSDSerializeOptions::new().line_ending(options.line_ending);
+    let mut output = Vec::new();
+    for source in document.parameters() {
+        output.write_all(source.leading().as_bytes())?;
+        match source.raw() {
+            Some(raw) => output.write_all(raw.as_bytes())?,
+            None => {
+                let mut parameter = source.parameter().clone();
+                // The comments around a changed parameter are still part of the text between parameters
+                parameter.comments.retain(|comment| matches!(comment.placement, CommentPlacement::Inside { .. }));
+                if options.normalize_keys {
+                    normalize_key(&mut parameter);
+                }
+                normalize_line_breaks(&parameter, options.line_ending).serialize_msd(&mut output, &serialize_options)?;
+            },
+        }
+    }
+    output.write_all(document.trailing().as_bytes())?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Parse `reader` and write it to `writer` in the canonical style, one parameter at a time.
+///
+/// Unlike [`format_document`], the input is never held in memory as a whole.
+///
+/// # Errors
+///
+/// Returns the first error the parser yields, or the first error writing.
+/// Parameters before the error have already been written.
+pub fn format_reader<R: Read, W: Write>(
+    reader: R,
+    mut writer: W,
+    parser_options: MSDParserOptions,
+    options: &FormatOptions,
+) -> Result<(), FormatError> {
+    let mut formatter = Formatter::new(options);
+    for parameter in parse_msd_with_options(reader, parser_options) {
+        let mut parameter = parameter?;
+        if options.normalize_keys {
+            normalize_key(&mut parameter);
+        }
+        formatter.write(&mut writer, &parameter, 0)?;
+    }
+    writer.flush().map_err(MSDParameterError::from)?;
+    Ok(())
+}
+
+/// `parameter` with every `\r\n`, `\r` and `\n` in its components written as `line_ending`.
+fn normalize_line_breaks(parameter: &MSDParameter, line_ending: LineEnding) -> Cow<'_, MSDParameter> {
+    let needs_change = |component: &String| match line_ending {
+        LineEnding::Lf => component.contains('\r'),
+        LineEnding::CrLf => component.replace("\r\n", "").contains(['\r', '\n']),
+    };
+    if !parameter.components.iter().any(needs_change) {
+        return Cow::Borrowed(parameter);
+    }
+
+    let normalize = |text: &str| text.replace("\r\n", "\n").replace('\r', "\n").replace('\n', line_ending.as_str());
+    let mut parameter = parameter.clone();
+    for comment in &mut parameter.comments {
+        if let CommentPlacement::Inside { component, offset } = &mut comment.placement {
+            if let Some(before) = parameter.components.get(*component).and_then(|text| text.get(..*offset)) {
+                *offset = normalize(before).len();
+            }
+        }
+    }
+    for component in &mut parameter.components {
+        *component = normalize(component);
+    }
+    Cow::Owned(parameter)
+}
+
+fn normalize_key(parameter: &mut MSDParameter) {
+    if let Some(key) = parameter.components.first() {
+        parameter.normalize_key(key.trim().to_ascii_uppercase());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "simfile")]
+    use crate::charts::{charts, group_ssc, Chart, SscChart, SscItem};
+    #[cfg(feature = "simfile")]
+    use crate::parser::parse_msd;
+
+    const INPUT: &str = "// header\r\n#TITLE:a\\:b\\c;\r\n\r\n\r\n#notes:\r\n0000\r\n;  #NOTEDATA:;#STEPSTYPE:dance-single;";
+
+    #[test]
+    fn test_format_document() {
+        let mut document = MSDDocument::from_reader(INPUT.as_bytes(), MSDParserOptions::new()).unwrap();
+        let options = FormatOptions::new().line_ending(LineEnding::CrLf).blank_lines(2);
+
+        assert_eq!(
+            "#TITLE:a\\:bc;\r\n\r\n\r\n#NOTES:\r\n0000\r\n;\r\n\r\n\r\n#NOTEDATA:;\r\n#STEPSTYPE:dance-single;\r\n",
+            format_document(&mut document, &options).unwrap()
+        );
+        assert_eq!(Some("notes"), document.parameters()[1].original_key());
+    }
+
+    #[cfg(feature = "simfile")]
+    #[test]
+    fn test_chart_order() {
+        let input = "#TITLE:A;\n\n#NOTES:dance-single::Hard:9::;\n#NOTES:dance-single::Easy:3::;";
+        let options = FormatOptions::new().chart_order(Some(CanonicalOrder::new()));
+        let expected = "#TITLE:A;\n\n#NOTES:dance-single::Easy:3::;\n\n#NOTES:dance-single::Hard:9::;\n";
+
+        let mut document = MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap();
+        assert_eq!(expected, format_document(&mut document, &options).unwrap());
+        let lossless = LosslessDocument::parse(input, MSDParserOptions::new()).unwrap();
+        assert_eq!(expected, format_lossless(&lossless, &options).unwrap());
+    }
+
+    #[cfg(feature = "simfile")]
+    #[test]
+    fn test_chart_order_ssc() {
+        let input = std::fs::read_to_string("testdata/Springtime.ssc").unwrap();
+        let options = FormatOptions::new().chart_order(Some(CanonicalOrder::new()));
+        let mut document = MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap();
+        let formatted = format_document(&mut document, &options).unwrap();
+
+        let blocks = |input: &str| -> Vec<SscChart> {
+            group_ssc(parse_msd(input.as_bytes(), true, false))
+                .filter_map(|item| match item.unwrap() {
+                    SscItem::Chart(chart) => Some(chart),
+                    SscItem::Header(_) => None,
+                })
+                .collect()
+        };
+        let (original, reparsed) = (blocks(&input), blocks(&formatted));
+        assert_eq!(original.len(), reparsed.len());
+        assert!(original.iter().all(|chart| reparsed.contains(chart)));
+
+        let sorted: Vec<Chart> = reparsed.iter().map(Chart::from_ssc).collect();
+        assert!(sorted.windows(2).all(|pair| CanonicalOrder::new().compare(&pair[0], &pair[1]).is_le()));
+        assert_eq!(sorted, charts(document.parameters()));
+        assert_eq!(formatted, format_document(&mut document, &options).unwrap());
+    }
+
+    #[test]
+    fn test_changed_only() {
+        let mut document = LosslessDocument::parse(INPUT, MSDParserOptions::new()).unwrap();
+        let options = FormatOptions::new().changed_only(true);
+        assert_eq!(INPUT, format_lossless(&document, &options).unwrap());
+
+        document.get_mut("notes").unwrap().parameter_mut().components[1] = "1000".to_string();
+        document.push(MSDParameter::new(vec!["meter ".to_string(), "a:b".to_string()]));
+        assert_eq!(
+            INPUT.replace("#notes:\r\n0000\r\n;", "#NOTES:1000;") + "\r\n#METER:a\\:b;",
+            format_lossless(&document, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_line_breaks_in_values() {
+        let input = "#NOTES:\n0000\r1000\r\n;\n#TITLE:A;";
+        for (line_ending, expected) in [
+            (LineEnding::Lf, "#NOTES:\n0000\n1000\n;\n\n#TITLE:A;\n"),
+            (LineEnding::CrLf, "#NOTES:\r\n0000\r\n1000\r\n;\r\n\r\n#TITLE:A;\r\n"),
+        ] {
+            let options = FormatOptions::new().line_ending(line_ending).section_keys(["TITLE"]);
+            let mut document = MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap();
+            assert_eq!(expected, format_document(&mut document, &options).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_keep_comments() {
+        let mut output = Vec::new();
+        format_reader(INPUT.as_bytes(), &mut output, MSDParserOptions::new().keep_comments(true), &FormatOptions::new()).unwrap();
+
+        assert!(String::from_utf8(output).unwrap().starts_with("// header\n#TITLE:a\\:bc;\n"));
+    }
+
+    #[test]
+    fn test_format_reader() {
+        let mut output = Vec::new();
+        let options = FormatOptions::new().blank_lines(0).section_keys(["TITLE"]).normalize_keys(false);
+        format_reader(INPUT.as_bytes(), &mut output, MSDParserOptions::new(), &options).unwrap();
+
+        assert_eq!(
+            "#TITLE:a\\:bc;\n#notes:\n0000\n;\n#NOTEDATA:;\n#STEPSTYPE:dance-single;\n",
+            String::from_utf8(output).unwrap()
+        );
+        assert!(matches!(
+            format_reader("#A:B; stray".as_bytes(), Vec::new(), MSDParserOptions::new(), &options),
+            Err(FormatError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,255 @@
+use std::ops::Range;
+use std::{error, fmt};
+
+use crate::parameter::MSDParameter;
+use crate::parser::MSDParserError;
+
+/// Custom error type for building a [`FromMsd`] type from parameters.
+#[derive(Debug)]
+pub enum FromMsdError {
+    /// The input is not valid MSD.
+    Parse(MSDParserError),
+    /// A required key never appeared.
+    MissingKey {
+        /// The struct field the key is read into.
+        field: &'static str,
+        key: &'static str,
+    },
+    /// A value could not be converted to the type of its field.
+    InvalidValue {
+        /// The struct field the value is read into.
+        field: &'static str,
+        /// The key as written in the document.
+        key: String,
+        value: String,
+        message: String,
+        /// Byte range of the parameter in the source, if it was parsed.
+        span: Option<Range<usize>>,
+    },
+}
+
+impl fmt::Display for FromMsdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromMsdError::Parse(e) => write!(f, "{}", e),
+            FromMsdError::MissingKey { field, key } => write!(f, "FromMsdError: missing #{} for field '{}'", key, field),
+            FromMsdError::InvalidValue { field, key, value, message, span } => {
+                write!(f, "FromMsdError: #{}:{} for field '{}': {}", key, value, field, message)?;
+                if let Some(span) = span {
+                    write!(f, " (bytes {}..{})", span.start, span.end)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl error::Error for FromMsdError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FromMsdError::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MSDParserError> for FromMsdError {
+    fn from(e: MSDParserError) -> Self {
+        FromMsdError::Parse(e)
+    }
+}
+
+/// A type that can be built from the parameters of a document, usually through `#[derive(FromMsd)]`
+/// with the `derive` feature.
+///
+/// Keys are compared ignoring ASCII case and surrounding whitespace, unknown keys are ignored
+/// and when a key is repeated the last occurrence wins.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[cfg(feature = "derive")] {
+/// use msdparser::{parse_msd, FromMsd};
+///
+/// #[derive(FromMsd)]
+/// struct Song {
+///     title: String,
+///     #[msd(key = "TITLETRANSLIT", default)]
+///     transliterated: String,
+///     offset: f64,
+///     sample_start: Option<f64>,
+/// }
+///
+/// let input = b"#TITLE:Springtime;\n#OFFSET:-0.012;\n#SAMPLESTART:;";
+/// let song = Song::from_msd(parse_msd(&input[..], true, false)).unwrap();
+///
+/// assert_eq!(("Springtime", "", -0.012, None), (song.title.as_str(), song.transliterated.as_str(), song.offset, song.sample_start));
+/// # }
+/// ```
+pub trait FromMsd: Sized {
+    /// Build the type from a stream of parsed parameters, like the iterator returned by [`crate::parse_msd`].
+    ///
+    /// # Errors
+    ///
+    /// Stops at the first parse error, or the first value that fails conversion.
+    /// Required keys are checked once the stream is exhausted.
+    fn from_msd<I>(parameters: I) -> Result<Self, FromMsdError>
+    where
+        I: IntoIterator<Item = Result<MSDParameter, MSDParserError>>;
+
+    /// Build the type from parameters that were already parsed.
+    fn from_parameters(parameters: &[MSDParameter]) -> Result<Self, FromMsdError> {
+        Self::from_msd(parameters.iter().cloned().map(Ok))
+    }
+}
+
+/// A field type of a [`FromMsd`] struct, converted from the value of its parameter.
+pub trait FromMsdValue: Sized {
+    /// Convert a value, or describe why it is invalid.
+    fn from_msd_value(value: &str) -> Result<Self, String>;
+
+    /// The value of a field whose key never appeared, or `None` if the key is required.
+    fn missing() -> Option<Self> {
+        None
+    }
+}
+
+impl FromMsdValue for String {
+    fn from_msd_value(value: &str) -> Result<Self, String> {
+        Ok(value.to_string())
+    }
+}
+
+/// Accepts `YES`/`NO`, `1`/`0` and `true`/`false`, ignoring case.
+impl FromMsdValue for bool {
+    fn from_msd_value(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "YES" | "1" | "TRUE" => Ok(true),
+            "NO" | "0" | "FALSE" => Ok(false),
+            _ => Err("expected YES or NO".to_string()),
+        }
+    }
+}
+
+macro_rules! impl_from_msd_value_parse {
+    ($($ty:ty),*) => {
+        $(
+            impl FromMsdValue for $ty {
+                fn from_msd_value(value: &str) -> Result<Self, String> {
+                    value.trim().parse().map_err(|e| format!("{}", e))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_msd_value_parse!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+/// Optional: `None` when the key is missing or its value is blank.
+impl<T: FromMsdValue> FromMsdValue for Option<T> {
+    fn from_msd_value(value: &str) -> Result<Self, String> {
+        if value.trim().is_empty() {
+            Ok(None)
+        } else {
+            T::from_msd_value(value).map(Some)
+        }
+    }
+
+    fn missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+/// The comma-separated entries of the value, like `#BPMS:0=120,4=240;`. Blank entries are skipped.
+impl<T: FromMsdValue> FromMsdValue for Vec<T> {
+    fn from_msd_value(value: &str) -> Result<Self, String> {
+        value.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(T::from_msd_value)
+            .collect()
+    }
+}
+
+/// Convert the value of `parameter` for the field `field`. Used by the derive macro.
+#[doc(hidden)]
+pub fn field_value<T: FromMsdValue>(field: &'static str, parameter: &MSDParameter) -> Result<T, FromMsdError> {
+    let value = parameter.components.get(1).map(String::as_str).unwrap_or_default();
+    T::from_msd_value(value).map_err(|message| FromMsdError::InvalidValue {
+        field,
+        key: parameter.components[0].clone(),
+        value: value.to_string(),
+        message,
+        span: parameter.span.clone(),
+    })
+}
+
+/// The value of the field `field` whose key never appeared. Used by the derive macro.
+#[doc(hidden)]
+pub fn missing_value<T: FromMsdValue>(field: &'static str, key: &'static str) -> Result<T, FromMsdError> {
+    T::missing().ok_or(FromMsdError::MissingKey { field, key })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        assert_eq!(Ok(true), bool::from_msd_value(" yes "));
+        assert_eq!(Ok(-0.5), f64::from_msd_value(" -0.5\n"));
+        assert_eq!(Ok(None), Option::<u32>::from_msd_value("  "));
+        assert_eq!(Ok(vec![1, 2]), Vec::<u8>::from_msd_value("1, 2,"));
+        assert!(u8::from_msd_value("300").is_err());
+        assert_eq!(Some(None), Option::<String>::missing());
+        assert_eq!(None, String::missing());
+    }
+
+    #[cfg(feature = "derive")]
+    mod derive {
+        use crate::from_msd::{FromMsd, FromMsdError};
+        use crate::parser::parse_msd;
+
+        #[derive(Debug, PartialEq, crate::FromMsd)]
+        struct Song {
+            title: String,
+            #[msd(key = "TITLETRANSLIT", default)]
+            transliterated: String,
+            offset: f64,
+            sample_start: Option<f64>,
+            #[msd(default)]
+            selectable: Option<bool>,
+            #[msd(key = "DISPLAYBPM")]
+            display_bpm: Vec<f64>,
+        }
+
+        fn song(input: &str) -> Result<Song, FromMsdError> {
+            Song::from_msd(parse_msd(input.as_bytes(), true, false))
+        }
+
+        #[test]
+        fn test_derive() {
+            assert_eq!(Song {
+                title: "B".to_string(),
+                transliterated: "Bee".to_string(),
+                offset: 0.25,
+                sample_start: None,
+                selectable: Some(false),
+                display_bpm: vec![120.0, 180.0],
+            }, song("#title:A;#TITLETRANSLIT:Bee;#OFFSET:0.25;#SELECTABLE:NO;#DisplayBPM:120,180;#TITLE:B;#NOTES:x;").unwrap());
+        }
+
+        #[test]
+        fn test_derive_errors() {
+            match song("#TITLE:A;\n#OFFSET:abc;") {
+                Err(FromMsdError::InvalidValue { field, key, value, span, .. }) => {
+                    assert_eq!(("offset", "OFFSET", "abc", Some(10..22)), (field, key.as_str(), value.as_str(), span));
+                },
+                other => panic!("unexpected result {:?}", other),
+            }
+            match song("#TITLE:A;#OFFSET:0;") {
+                Err(FromMsdError::MissingKey { field, key }) => assert_eq!(("display_bpm", "DISPLAYBPM"), (field, key)),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+}
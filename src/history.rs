@@ -0,0 +1,208 @@
+use crate::parameter::MSDParameter;
+
+/// A reversible change to the parameters of a document.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    /// Insert a parameter before `index`; an index equal to the length appends.
+    Insert { index: usize, parameter: MSDParameter },
+    /// Remove the parameter at `index`.
+    Remove { index: usize },
+    /// Replace the parameter at `index`.
+    Replace { index: usize, parameter: MSDParameter },
+}
+
+impl Edit {
+    fn is_valid_for(&self, parameters: &[MSDParameter]) -> bool {
+        match self {
+            Edit::Insert { index, .. } => *index <= parameters.len(),
+            Edit::Remove { index } | Edit::Replace { index, .. } => *index < parameters.len(),
+        }
+    }
+
+    /// Apply the edit and return the edit that reverts it.
+    fn apply(self, parameters: &mut Vec<MSDParameter>) -> Edit {
+        match self {
+            Edit::Insert { index, parameter } => {
+                parameters.insert(index, parameter);
+                Edit::Remove { index }
+            },
+            Edit::Remove { index } => {
+                let parameter = parameters.remove(index);
+                Edit::Insert { index, parameter }
+            },
+            Edit::Replace { index, parameter } => {
+                let parameter = std::mem::replace(&mut parameters[index], parameter);
+                Edit::Replace { index, parameter }
+            },
+        }
+    }
+}
+
+/// A document whose edits are recorded, so they can be undone and redone.
+///
+/// Every mutation goes through [`EditHistory::apply`] and pushes the [`Edit`] that reverts it onto the undo stack.
+/// Making a new edit after undoing discards the redo stack, like in any editor.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::MSDParameter;
+/// # use msdparser::history::EditHistory;
+/// let mut history = EditHistory::new(vec![MSDParameter::new(vec!["TITLE".to_string(), "A".to_string()])]);
+///
+/// history.replace_value(0, "B");
+/// assert_eq!(Some("B".to_string()), history.document()[0].value());
+///
+/// assert!(history.undo());
+/// assert_eq!(Some("A".to_string()), history.document()[0].value());
+///
+/// assert!(history.redo());
+/// assert_eq!(Some("B".to_string()), history.document()[0].value());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EditHistory {
+    parameters: Vec<MSDParameter>,
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl EditHistory {
+    /// Start recording edits to `parameters`, with empty history.
+    pub fn new(parameters: Vec<MSDParameter>) -> Self {
+        Self { parameters, undo: Vec::new(), redo: Vec::new() }
+    }
+
+    /// The document in its current state.
+    pub fn document(&self) -> &[MSDParameter] {
+        &self.parameters
+    }
+
+    /// Stop recording and return the document.
+    pub fn into_document(self) -> Vec<MSDParameter> {
+        self.parameters
+    }
+
+    /// Apply an edit and record it.
+    ///
+    /// Returns `false` and does nothing if the edit's index is out of bounds.
+    pub fn apply(&mut self, edit: Edit) -> bool {
+        if !edit.is_valid_for(&self.parameters) {
+            return false;
+        }
+        let inverse = edit.apply(&mut self.parameters);
+        self.undo.push(inverse);
+        self.redo.clear();
+        true
+    }
+
+    /// Insert a parameter before `index`. See [`Edit::Insert`].
+    pub fn insert(&mut self, index: usize, parameter: MSDParameter) -> bool {
+        self.apply(Edit::Insert { index, parameter })
+    }
+
+    /// Remove and return the parameter at `index`, or `None` if it does not exist.
+    pub fn remove(&mut self, index: usize) -> Option<MSDParameter> {
+        let removed = self.parameters.get(index)?.clone();
+        self.apply(Edit::Remove { index });
+        Some(removed)
+    }
+
+    /// Replace the parameter at `index`, returning the old one, or `None` if it does not exist.
+    pub fn replace(&mut self, index: usize, parameter: MSDParameter) -> Option<MSDParameter> {
+        let old = self.parameters.get(index)?.clone();
+        self.apply(Edit::Replace { index, parameter });
+        Some(old)
+    }
+
+    /// Replace the value (second component) of the parameter at `index`, returning the old value.
+    ///
+    /// A parameter without a value gains one. Returns `None` if the parameter does not exist or had no value.
+    pub fn replace_value(&mut self, index: usize, value: &str) -> Option<String> {
+        let mut parameter = self.parameters.get(index)?.clone();
+        if parameter.components.is_empty() {
+            parameter.components.push(String::new());
+        }
+        let old = if parameter.components.len() == 1 {
+            parameter.components.push(value.to_string());
+            None
+        } else {
+            Some(std::mem::replace(&mut parameter.components[1], value.to_string()))
+        };
+        self.apply(Edit::Replace { index, parameter });
+        old
+    }
+
+    /// Revert the last edit. Returns `false` if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let Some(edit) = self.undo.pop() else { return false };
+        let inverse = edit.apply(&mut self.parameters);
+        self.redo.push(inverse);
+        true
+    }
+
+    /// Reapply the last undone edit. Returns `false` if there is nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        let Some(edit) = self.redo.pop() else { return false };
+        let inverse = edit.apply(&mut self.parameters);
+        self.undo.push(inverse);
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Forget every recorded edit, keeping the document as it is.
+    pub fn clear_history(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameter(key: &str, value: &str) -> MSDParameter {
+        MSDParameter::new(vec![key.to_string(), value.to_string()])
+    }
+
+    #[test]
+    fn test_undo_redo_sequence() {
+        let original = vec![parameter("TITLE", "A"), parameter("ARTIST", "B")];
+        let mut history = EditHistory::new(original.clone());
+
+        assert!(history.insert(2, parameter("BPMS", "0=120")));
+        assert_eq!(Some(parameter("ARTIST", "B")), history.remove(1));
+        assert_eq!(Some("A".to_string()), history.replace_value(0, "C"));
+        assert_eq!(vec![parameter("TITLE", "C"), parameter("BPMS", "0=120")], history.document());
+
+        while history.undo() {}
+        assert_eq!(original, history.document());
+        assert!(!history.can_undo());
+
+        while history.redo() {}
+        assert_eq!(vec![parameter("TITLE", "C"), parameter("BPMS", "0=120")], history.document());
+    }
+
+    #[test]
+    fn test_new_edit_discards_redo() {
+        let mut history = EditHistory::new(vec![parameter("TITLE", "A")]);
+
+        history.replace_value(0, "B");
+        history.undo();
+        assert!(history.can_redo());
+
+        history.replace_value(0, "C");
+        assert!(!history.can_redo());
+        assert!(!history.insert(5, parameter("X", "Y")));
+        assert_eq!(None, history.remove(5));
+
+        history.undo();
+        assert_eq!(vec![parameter("TITLE", "A")], history.document());
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{self, Read};
+use std::{error, fmt};
+
+use reqwest::blocking::{Client, Response};
+use reqwest::StatusCode;
+
+use crate::parser::{parse_msd_with_options, MSDParser, MSDParserOptions};
+
+/// Default limit on the size of a response body, far above any real simfile.
+pub const DEFAULT_MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Custom error type for fetching MSD documents over HTTP.
+#[derive(Debug)]
+pub enum HttpError {
+    /// The request could not be sent or the response headers could not be read.
+    Request(reqwest::Error),
+    /// The server answered with a status other than success.
+    Status(StatusCode),
+    /// The server announced a body larger than the limit.
+    TooLarge { length: u64, limit: u64 },
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Request(e) => write!(f, "HTTP Error: {}", e),
+            HttpError::Status(status) => write!(f, "HTTP Error: unexpected status {}", status),
+            HttpError::TooLarge { length, limit } => {
+                write!(f, "HTTP Error: response of {} bytes exceeds the limit of {} bytes", length, limit)
+            },
+        }
+    }
+}
+
+impl error::Error for HttpError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            HttpError::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(e: reqwest::Error) -> Self {
+        HttpError::Request(e)
+    }
+}
+
+/// Reader that fails with [`io::ErrorKind::InvalidData`] once more than `limit` bytes have been read.
+///
+/// Guards against servers that send more than they announce, or announce nothing at all.
+#[derive(Debug)]
+pub struct LimitedReader<R> {
+    reader: R,
+    limit: u64,
+    remaining: u64,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(reader: R, limit: u64) -> Self {
+        Self { reader, limit, remaining: limit }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Ask for one byte more than allowed, so that hitting the limit exactly is not an error
+        let allowed = usize::try_from(self.remaining).unwrap_or(usize::MAX).saturating_add(1);
+        let length = buf.len().min(allowed);
+        let read = self.reader.read(&mut buf[..length])?;
+
+        if read as u64 > self.remaining {
+            self.remaining = 0;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("response body exceeds {} bytes", self.limit),
+            ));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Fetch an MSD document and parse it as it streams in, with the default options and size limit.
+///
+/// See [`parse_msd_url_with_options`].
+pub fn parse_msd_url(url: &str) -> Result<MSDParser<LimitedReader<Response>>, HttpError> {
+    parse_msd_url_with_options(url, MSDParserOptions::new(), DEFAULT_MAX_BYTES)
+}
+
+/// Fetch an MSD document with a blocking GET request and parse it as it streams in.
+///
+/// The body is never read past `max_bytes`. A larger announced `Content-Length` fails up front;
+/// a body that turns out larger than announced surfaces as an [`MSDParserError::Io`]
+/// of kind [`io::ErrorKind::InvalidData`] from the parser.
+///
+/// # Errors
+///
+/// Returns an error if the request fails, if the status is not a success, or if the body is announced as too large.
+///
+/// [`MSDParserError::Io`]: crate::parser::MSDParserError::Io
+pub fn parse_msd_url_with_options(
+    url: &str,
+    options: MSDParserOptions,
+    max_bytes: u64,
+) -> Result<MSDParser<LimitedReader<Response>>, HttpError> {
+    let response = Client::new().get(url).send()?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(HttpError::Status(status));
+    }
+    if let Some(length) = response.content_length().filter(|&length| length > max_bytes) {
+        return Err(HttpError::TooLarge { length, limit: max_bytes });
+    }
+
+    Ok(parse_msd_with_options(LimitedReader::new(response, max_bytes), options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parameter::MSDParameter;
+    use crate::parser::{parse_msd, MSDParserError};
+
+    #[test]
+    fn test_limited_reader_within_limit() {
+        let reader = LimitedReader::new(b"#A:B;".as_ref(), 5);
+        let parameters: Vec<_> = parse_msd(reader, true, false).collect();
+
+        assert_eq!(vec![Ok(MSDParameter::new(vec!["A".to_string(), "B".to_string()]))], parameters);
+    }
+
+    #[test]
+    fn test_limited_reader_over_limit() {
+        let reader = LimitedReader::new(b"#A:B;#C:D;".as_ref(), 5);
+        let error = parse_msd(reader, true, false).find_map(Result::err).unwrap();
+
+        assert!(matches!(error, MSDParserError::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// A map from parameter keys to settings, with keys compared ignoring ASCII case and surrounding whitespace.
+///
+/// Every per-key setting of the crate, such as [`RepeatPolicies`], [`MergePolicy`] and the escape overrides of
+/// [`MSDSerializeOptions`], is stored in one. Keys are normalized on the way in, by [`KeyMap::insert`] and when
+/// deserializing with the `serde` feature alike, so a lookup of `bpms` finds an entry written as `"Bpms"` in a
+/// config file. The entries are shared behind an [`Arc`], so cloning options that hold a map is cheap.
+///
+/// [`RepeatPolicies`]: crate::repeat::RepeatPolicies
+/// [`MergePolicy`]: crate::merge::MergePolicy
+/// [`MSDSerializeOptions`]: crate::serialize::MSDSerializeOptions
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::key_map::KeyMap;
+/// let mut map = KeyMap::new();
+/// map.insert(" Notes ", 1);
+///
+/// assert_eq!(Some(&1), map.get("NOTES"));
+/// assert_eq!(vec![("NOTES", &1)], map.iter().collect::<Vec<_>>());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyMap<V> {
+    entries: Arc<BTreeMap<String, V>>,
+}
+
+impl<V> Default for KeyMap<V> {
+    fn default() -> Self {
+        Self { entries: Arc::new(BTreeMap::new()) }
+    }
+}
+
+/// The form keys are stored in.
+fn normalize(key: &str) -> String {
+    key.trim().to_ascii_uppercase()
+}
+
+impl<V> KeyMap<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The value stored for `key`.
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.entries.get(&normalize(key))
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(&normalize(key))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries in order of their keys, which are in uppercase.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &V)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value))
+    }
+}
+
+impl<V: Clone> KeyMap<V> {
+    /// Store `value` for `key`, returning the value it replaces.
+    pub fn insert(&mut self, key: &str, value: V) -> Option<V> {
+        Arc::make_mut(&mut self.entries).insert(normalize(key), value)
+    }
+
+    /// Remove the value stored for `key` and return it.
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        if !self.contains_key(key) {
+            return None;
+        }
+        Arc::make_mut(&mut self.entries).remove(&normalize(key))
+    }
+}
+
+impl<K: AsRef<str>, V: Clone> FromIterator<(K, V)> for KeyMap<V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        for (key, value) in iter {
+            map.insert(key.as_ref(), value);
+        }
+        map
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, V: Clone + serde::Deserialize<'de>> serde::Deserialize<'de> for KeyMap<V> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = BTreeMap::<String, V>::deserialize(deserializer)?;
+        Ok(entries.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_ignore_case() {
+        let mut map: KeyMap<u8> = [("bpms", 1), ("NOTES", 2)].into_iter().collect();
+        let shared = map.clone();
+
+        assert_eq!(Some(1), map.insert(" Bpms", 3));
+        assert_eq!((Some(&3), Some(&1)), (map.get("BPMS"), shared.get("bpms")));
+        assert_eq!(Some(2), map.remove("notes"));
+        assert_eq!(None, map.remove("notes"));
+        assert_eq!(vec![("BPMS", &3)], map.iter().collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize() {
+        let map: KeyMap<u8> = serde_json::from_str(r#"{"Bpms": 1, " notes ": 2}"#).unwrap();
+        assert_eq!((Some(&1), Some(&2)), (map.get("BPMS"), map.get("NOTES")));
+    }
+}
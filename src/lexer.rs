@@ -1,8 +1,19 @@
-use std::{fmt, io::Read};
+use std::collections::VecDeque;
+use std::{error, fmt};
+use std::io::{self, Read};
+#[cfg(feature = "io")]
+use std::io::BufRead;
+use std::ops::Range;
 
-use regex::Regex;
+use memchr::{memchr, memchr2, memchr3, memrchr, memrchr2};
 
+/// Kind of an [`MSDTokenMatch`].
+/// 
+/// A `Comment` never owns the line break that ends it: the text token after it starts with the `\n`, `\r\n` or
+/// `\r`. The one exception is the `\r` of a `\r\n` in the dialects of StepMania, which the comment swallows just
+/// like StepMania does, see [`Dialect::StepMania`]. Either way, the tokens put together are exactly the input.
 #[derive(Debug, PartialEq, Clone, Copy, Hash, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MSDToken {
     Text,
     StartParameter,
@@ -18,172 +29,1008 @@ impl fmt::Display for MSDToken {
     }
 }
 
-#[derive(Debug, Clone)]
-struct LexerPattern {
-    regex: Regex,
-    token_outside_param: MSDToken,
-    token_inside_param: MSDToken,
-    escapes: Option<bool>,
+/// The shapes of text the lexer recognizes, before taking into account whether it is inside a parameter.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Lexeme {
+    /// A run of characters without special meaning.
+    Text,
+    Pound,
+    Colon,
+    Semicolon,
+    /// A backslash and the character after it, only when escapes are enabled.
+    Escape,
+    /// `//` up to the end of the line, or up to the next `\n` in the dialects of StepMania.
+    Comment,
+    /// A lone `/`.
+    Slash,
 }
 
-impl LexerPattern {
-    fn new(pattern: &str, token_outside: MSDToken, token_inside: MSDToken, escapes: Option<bool>) -> Self {
-        Self {
-            regex: Regex::new(pattern).unwrap(),
-            token_outside_param: token_outside,
-            token_inside_param: token_inside,
-            escapes,
+impl Lexeme {
+    /// The token this lexeme stands for outside and inside of a parameter.
+    fn tokens(self) -> (MSDToken, MSDToken) {
+        match self {
+            Lexeme::Text | Lexeme::Slash => (MSDToken::Text, MSDToken::Text),
+            Lexeme::Pound => (MSDToken::StartParameter, MSDToken::Text),
+            Lexeme::Colon => (MSDToken::Text, MSDToken::NextComponent),
+            Lexeme::Semicolon => (MSDToken::Text, MSDToken::EndParameter),
+            Lexeme::Escape => (MSDToken::Text, MSDToken::Escape),
+            Lexeme::Comment => (MSDToken::Comment, MSDToken::Comment),
         }
     }
 }
 
-const ESCAPED_TEXT: &str = r"^[^\\\/:;#]+";
-const UNESCAPED_TEXT: &str = r"^[^\/:;#]+";
-const POUND: &str = r"^#";
-const COLON: &str = r"^:";
-const SEMICOLON: &str = r"^;";
-const ESCAPE: &str = r"^(?s)\\.";
-const COMMENT: &str = r"^//[^\r\n]*";
-const SLASH: &str = r"^/";
+/// Buffer size for reading
+pub(crate) const BUFFER_SIZE: usize = 4096;
 
-lazy_static::lazy_static! {
-    static ref LEXER_PATTERNS: Vec<LexerPattern> = vec![
-        LexerPattern::new(ESCAPED_TEXT, MSDToken::Text, MSDToken::Text, Some(true)),
-        LexerPattern::new(UNESCAPED_TEXT, MSDToken::Text, MSDToken::Text, Some(false)),
-        LexerPattern::new(POUND, MSDToken::StartParameter, MSDToken::Text, None),
-        LexerPattern::new(COLON, MSDToken::Text, MSDToken::NextComponent, None),
-        LexerPattern::new(SEMICOLON, MSDToken::Text, MSDToken::EndParameter, None),
-        LexerPattern::new(ESCAPE, MSDToken::Text, MSDToken::Escape, Some(true)),
-        LexerPattern::new(COMMENT, MSDToken::Comment, MSDToken::Comment, None),
-        LexerPattern::new(SLASH, MSDToken::Text, MSDToken::Text, None),
-    ];
+/// A location in the input.
+/// 
+/// Lines and columns start at 1 and columns count characters, so they match what text editors show.
+/// `\n`, `\r\n` and a lone `\r` each start a new line, so classic Mac files are numbered like any other.
+/// The byte offset counts the bytes of the input as read, including invalid UTF-8.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    /// Byte offset from the start of the input.
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
-/// Buffer size for reading
-const BUFFER_SIZE: usize = 4096;
+impl Default for Position {
+    fn default() -> Self {
+        Self { offset: 0, line: 1, column: 1 }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+impl Position {
+    /// The position of the first character of the input.
+    pub fn start() -> Self {
+        Self::default()
+    }
+
+    /// Move the position past `text`.
+    ///
+    /// A `\r` at the end of `text` counts as a line break on its own; see [`MSDLexer::position`]
+    /// for input that is split between the `\r` and `\n` of a `\r\n`.
+    pub fn advance(&mut self, text: &str) {
+        self.offset += text.len();
+        let bytes = text.as_bytes();
+        match memrchr2(b'\n', b'\r', bytes) {
+            Some(i) => {
+                self.line += bytes.iter()
+                    .enumerate()
+                    .filter(|&(j, &b)| b == b'\n' || (b == b'\r' && bytes.get(j + 1) != Some(&b'\n')))
+                    .count();
+                self.column = text[i + 1..].chars().count() + 1;
+            },
+            None => self.column += text.chars().count(),
+        }
+    }
+
+    /// The position `offset` bytes into `input`.
+    pub fn of(input: &str, offset: usize) -> Self {
+        let mut position = Self::start();
+        position.advance(&input[..offset]);
+        position
+    }
+}
+
+/// Custom error type for the MSD lexer.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MSDLexerError {
+    /// No pattern matched the start of the buffer.
+    /// 
+    /// Contains the unmatched text, which is discarded so that the lexer always makes progress, and where it started.
+    LexerStuck {
+        text: String,
+        position: Position,
+    },
+    /// The input ends in a backslash, which has nothing left to escape.
+    /// 
+    /// Contains the position of the backslash, which is discarded.
+    UnterminatedEscape {
+        position: Position,
+    },
+    /// The input is not valid UTF-8.
+    /// 
+    /// Returned before the token holding the invalid sequence, which reads it as U+FFFD, so lexing can go on
+    /// where it left off. Contains the position of the invalid sequence.
+    InvalidUtf8 {
+        position: Position,
+    },
+    /// Reading from the underlying reader failed.
+    /// 
+    /// The lexer treats this as the end of the stream, so lexing continues with the buffered input and then stops.
+    Io(#[cfg_attr(feature = "serde", serde(with = "crate::serde_io"))] io::Error),
+}
+
+impl fmt::Display for MSDLexerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MSDLexerError::LexerStuck { text, position } => write!(f, "lexer stuck on {:?} at {}", text, position),
+            MSDLexerError::UnterminatedEscape { position } => write!(f, "unterminated escape at end of input at {}", position),
+            MSDLexerError::InvalidUtf8 { position } => write!(f, "invalid UTF-8 at {}", position),
+            MSDLexerError::Io(e) => write!(f, "IO Error: {}", e),
+        }
+    }
+}
+
+/// IO errors are equal if they are of the same kind and have the same message.
+impl PartialEq for MSDLexerError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                MSDLexerError::LexerStuck { text: a, position: a_position },
+                MSDLexerError::LexerStuck { text: b, position: b_position },
+            ) => a == b && a_position == b_position,
+            (MSDLexerError::UnterminatedEscape { position: a }, MSDLexerError::UnterminatedEscape { position: b }) => a == b,
+            (MSDLexerError::InvalidUtf8 { position: a }, MSDLexerError::InvalidUtf8 { position: b }) => a == b,
+            (MSDLexerError::Io(a), MSDLexerError::Io(b)) => a.kind() == b.kind() && a.to_string() == b.to_string(),
+            _ => false,
+        }
+    }
+}
+
+impl error::Error for MSDLexerError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MSDLexerError::Io(e) => Some(e),
+            MSDLexerError::LexerStuck { .. } | MSDLexerError::UnterminatedEscape { .. } | MSDLexerError::InvalidUtf8 { .. } => None,
+        }
+    }
+}
 
 /// Match for a LexerPattern
-#[derive(Debug, PartialEq, Clone, Hash, PartialOrd)]
+#[derive(Debug, PartialEq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MSDTokenMatch {
     pub token: MSDToken,
     pub text: String,
+    /// Byte range of `text` in the input. It is longer or shorter than `text` when `text` holds a U+FFFD that
+    /// replaces invalid UTF-8, since the range covers the invalid bytes as they are in the input.
+    pub span: Range<usize>,
 }
 
 impl MSDTokenMatch {
-    fn new(token: MSDToken, text: String) -> Self {
+    fn new(token: MSDToken, text: String, span: Range<usize>) -> Self {
         Self {
             token,
-            text
+            text,
+            span,
+        }
+    }
+}
+
+/// Tokenization state shared by every lexer, independent of where the input comes from.
+#[derive(Debug, Clone)]
+pub(crate) struct LexerCore {
+    inside_parameter: bool,
+    last_text_ends_with_newline: bool,
+    /// Whether the current component has nothing but spaces and tabs since its start or the last line break
+    line_is_blank: bool,
+    escapes: EscapePolicy,
+    recovery: RecoveryPolicy,
+    dialect: Dialect,
+}
+
+impl LexerCore {
+    pub(crate) fn new(escapes: EscapePolicy) -> Self {
+        Self {
+            inside_parameter: false,
+            last_text_ends_with_newline: false,
+            line_is_blank: false,
+            escapes,
+            recovery: RecoveryPolicy::default(),
+            dialect: Dialect::default(),
+        }
+    }
+
+    pub(crate) fn is_inside_parameter(&self) -> bool {
+        self.inside_parameter
+    }
+
+    pub(crate) fn set_escapes(&mut self, escapes: EscapePolicy) {
+        self.escapes = escapes;
+    }
+
+    pub(crate) fn set_recovery(&mut self, recovery: RecoveryPolicy) {
+        self.recovery = recovery;
+    }
+
+    pub(crate) fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    /// Find the lexeme at the start of `input` and its length in bytes.
+    /// 
+    /// Every special character is ASCII, so scanning bytes never splits a UTF-8 sequence.
+    fn scan(&self, input: &str) -> Option<(Lexeme, usize)> {
+        let bytes = input.as_bytes();
+        match *bytes.first()? {
+            b'#' => Some((Lexeme::Pound, 1)),
+            b':' => Some((Lexeme::Colon, 1)),
+            b';' => Some((Lexeme::Semicolon, 1)),
+            b'/' if bytes.get(1) == Some(&b'/') => {
+                let end = if self.dialect.is_msd_file() { memchr(b'\n', bytes) } else { memchr2(b'\r', b'\n', bytes) };
+                let length = end.unwrap_or(bytes.len());
+                Some((Lexeme::Comment, length))
+            },
+            b'/' => Some((Lexeme::Slash, 1)),
+            // A trailing backslash has nothing to escape and matches nothing
+            b'\\' if self.escapes.is_escaped() => input[1..].chars().next().map(|c| (Lexeme::Escape, 1 + c.len_utf8())),
+            _ => Some((Lexeme::Text, self.text_length(bytes))),
+        }
+    }
+
+    /// Length of the run of text at the start of `bytes`, i.e. the offset of the first special character.
+    /// 
+    /// Text runs make up most of a file, so this is the hot path. `memchr3` finds the nearest delimiter,
+    /// and the search for `/` (and `\`) only needs to cover the bytes before it.
+    fn text_length(&self, bytes: &[u8]) -> usize {
+        let delimiter = memchr3(b'#', b':', b';', bytes);
+        let before_delimiter = &bytes[..delimiter.unwrap_or(bytes.len())];
+        let special = if self.escapes.is_escaped() {
+            memchr2(b'/', b'\\', before_delimiter)
+        } else {
+            memchr(b'/', before_delimiter)
+        };
+        special.or(delimiter).unwrap_or(bytes.len())
+    }
+
+    /// Match the lexeme at the start of `input` and turn it into a token.
+    /// 
+    /// Returns the token and the length of the matched text in bytes,
+    /// or None if `input` is empty or is a lone backslash with escapes enabled.
+    pub(crate) fn match_token(&mut self, input: &str) -> Option<(MSDToken, usize)> {
+        let (lexeme, length) = self.scan(input)?;
+        let (token_outside, token_inside) = lexeme.tokens();
+        let mut token = if self.inside_parameter { token_inside } else { token_outside };
+
+        // Recovery from missing `;`
+        if lexeme == Lexeme::Pound && token == MSDToken::Text {
+            let recover = match self.recovery {
+                RecoveryPolicy::Off => false,
+                RecoveryPolicy::LineStart => self.last_text_ends_with_newline,
+                RecoveryPolicy::FirstOnLine => self.line_is_blank,
+                RecoveryPolicy::Aggressive => true,
+            };
+            if recover {
+                token = MSDToken::StartParameter;
+            }
+        }
+
+        match token {
+            MSDToken::StartParameter => {
+                self.inside_parameter = true;
+                self.line_is_blank = true;
+            },
+            MSDToken::NextComponent => { self.line_is_blank = true; },
+            MSDToken::EndParameter => { self.inside_parameter = false; },
+            MSDToken::Text => {
+                let text = &input[..length];
+                self.last_text_ends_with_newline = text.ends_with('\n') || text.ends_with('\r');
+                let (line, continued) = match text.rfind(['\n', '\r']) {
+                    Some(i) => (&text[i + 1..], true),
+                    None => (text, self.line_is_blank),
+                };
+                self.line_is_blank = continued && line.bytes().all(|b| b == b' ' || b == b'\t');
+            },
+            // StepMania looks at the value as unescaped, so an escaped space or tab leaves the line blank
+            MSDToken::Escape => match &input[1..length] {
+                " " | "\t" => {},
+                "\n" | "\r" => { self.line_is_blank = true; },
+                _ => { self.line_is_blank = false; },
+            },
+            MSDToken::Comment => {}
+        }
+
+        Some((token, length))
+    }
+}
+
+/// Number of bytes at the end of `bytes` that start a UTF-8 sequence without finishing it.
+fn incomplete_suffix(bytes: &[u8]) -> usize {
+    for i in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - i];
+        // Skip continuation bytes until the byte that starts the sequence
+        if byte & 0xC0 != 0x80 {
+            let width = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            return if width > i { i } else { 0 };
+        }
+    }
+    0
+}
+
+/// Readers that can be duplicated along with their position,
+/// which is what makes a copy of an [`MSDLexer`] or [`MSDParser`] meaningful.
+/// 
+/// `File` and other OS handles are deliberately not implemented,
+/// because their duplicates share a single position and would steal each other's input.
+/// 
+/// [`MSDParser`]: ../parser/struct.MSDParser.html
+pub trait TryCloneRead: Read + Sized {
+    fn try_clone_read(&self) -> io::Result<Self>;
+}
+
+impl TryCloneRead for &[u8] {
+    fn try_clone_read(&self) -> io::Result<Self> {
+        Ok(*self)
+    }
+}
+
+impl<T: AsRef<[u8]> + Clone> TryCloneRead for io::Cursor<T> {
+    fn try_clone_read(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+}
+
+/// How an [`MSDLexer`] pulls bytes out of its reader.
+pub trait ReadMode<R> {
+    /// Read the next chunk of input and pass it to `sink`.
+    /// 
+    /// Returns the number of bytes read, which is 0 at the end of the stream.
+    fn read_chunk(&mut self, reader: &mut R, sink: &mut dyn FnMut(&[u8])) -> io::Result<usize>;
+}
+
+/// Reads every chunk into a buffer owned by the lexer. Works with any [`Read`]er and is the default.
+#[derive(Clone)]
+pub struct Unbuffered {
+    buffer: [u8; BUFFER_SIZE],
+}
+
+impl Default for Unbuffered {
+    fn default() -> Self {
+        Self { buffer: [0; BUFFER_SIZE] }
+    }
+}
+
+impl fmt::Debug for Unbuffered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Unbuffered")
+    }
+}
+
+impl<R: Read> ReadMode<R> for Unbuffered {
+    fn read_chunk(&mut self, reader: &mut R, sink: &mut dyn FnMut(&[u8])) -> io::Result<usize> {
+        let read = reader.read(&mut self.buffer)?;
+        sink(&self.buffer[..read]);
+        Ok(read)
+    }
+}
+
+/// Decodes straight out of a [`BufRead`]er's own buffer with `fill_buf` and `consume`,
+/// saving a copy of every byte compared to [`Unbuffered`].
+#[cfg(feature = "io")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Buffered;
+
+#[cfg(feature = "io")]
+impl<R: BufRead> ReadMode<R> for Buffered {
+    fn read_chunk(&mut self, reader: &mut R, sink: &mut dyn FnMut(&[u8])) -> io::Result<usize> {
+        let chunk = reader.fill_buf()?;
+        let read = chunk.len();
+        sink(chunk);
+        reader.consume(read);
+        Ok(read)
+    }
+}
+
+/// Decoded input waiting to be tokenized.
+#[derive(Debug, Clone, Default)]
+struct TextBuffer {
+    text: String,
+    /// Index of the first byte of `text` that has not been consumed yet
+    start: usize,
+    /// Index of the last `\n` or `\r` in `text`, if any, or of the last `\n` with `newline_only`
+    last_newline: Option<usize>,
+    /// Whether only `\n` ends a line, so that comments in the dialects of StepMania are never cut in half
+    newline_only: bool,
+    /// Bytes of a character that the last chunk cut in half
+    pending: Vec<u8>,
+    /// Index in `text` and byte length in the input of every unconsumed U+FFFD that replaces invalid UTF-8
+    replacements: VecDeque<(usize, usize)>,
+    /// How many of `replacements`, from the front, have been reported
+    reported: usize,
+}
+
+impl TextBuffer {
+    fn remaining(&self) -> &str {
+        &self.text[self.start..]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.start == self.text.len() && self.pending.is_empty()
+    }
+
+    /// Whether the unconsumed text contains a line break.
+    fn has_complete_line(&self) -> bool {
+        self.last_newline.is_some_and(|i| i >= self.start)
+    }
+
+    /// Mark `length` bytes as consumed and return how many bytes of the input they were decoded from.
+    /// Nothing is moved, so tokenizing a long line is linear in its length.
+    fn consume(&mut self, length: usize) -> usize {
+        let raw = self.raw_len(length);
+        self.start += length;
+        while self.replacements.front().is_some_and(|&(index, _)| index < self.start) {
+            self.replacements.pop_front();
+            self.reported = self.reported.saturating_sub(1);
+        }
+        raw
+    }
+
+    /// Consume the rest of the text and return it with the number of bytes of the input it was decoded from.
+    fn take_remaining(&mut self) -> (String, usize) {
+        let text = self.remaining().to_string();
+        let raw = self.consume(text.len());
+        (text, raw)
+    }
+
+    /// Number of bytes of the input the next `length` bytes of unconsumed text were decoded from,
+    /// which differs from `length` only around replaced invalid UTF-8.
+    fn raw_len(&self, length: usize) -> usize {
+        let end = self.start + length;
+        self.replacements.iter()
+            .take_while(|&&(index, _)| index < end)
+            .fold(length, |raw, &(_, invalid)| raw + invalid - char::REPLACEMENT_CHARACTER.len_utf8())
+    }
+
+    /// Drop the consumed text. Only called when no line break remains, so at most one partial line is moved.
+    fn compact(&mut self) {
+        self.text.drain(..self.start);
+        for (index, _) in &mut self.replacements {
+            *index -= self.start;
+        }
+        self.start = 0;
+        self.last_newline = None;
+    }
+
+    /// Offset into the unconsumed text of the first unreported replacement of invalid UTF-8, if it is before `end`,
+    /// and marks it as reported.
+    fn report_replacement_before(&mut self, end: usize) -> Option<usize> {
+        let offset = self.replacements.get(self.reported).map(|&(index, _)| index - self.start).filter(|&offset| offset < end)?;
+        self.reported += 1;
+        Some(offset)
+    }
+
+    /// Decode `bytes` and append them.
+    /// 
+    /// A character cut in half at the end of `bytes` is held back until the next call.
+    fn append(&mut self, mut bytes: &[u8]) {
+        // Complete the character held back from the last chunk first
+        while !self.pending.is_empty() && !bytes.is_empty() {
+            self.pending.push(bytes[0]);
+            bytes = &bytes[1..];
+            if incomplete_suffix(&self.pending) == 0 {
+                self.finish();
+            }
+        }
+
+        let complete = bytes.len() - incomplete_suffix(bytes);
+        self.decode(&bytes[..complete]);
+        self.pending.extend_from_slice(&bytes[complete..]);
+    }
+
+    /// Decode whatever is held back, at the end of the stream.
+    fn finish(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        self.decode(&pending);
+    }
+
+    /// Append `bytes`, replacing every invalid sequence with U+FFFD like [`String::from_utf8_lossy`] and
+    /// remembering where, so the lexer can report it.
+    fn decode(&mut self, bytes: &[u8]) {
+        for chunk in bytes.utf8_chunks() {
+            self.push_str(chunk.valid());
+            if !chunk.invalid().is_empty() {
+                self.replacements.push_back((self.text.len(), chunk.invalid().len()));
+                self.push_str(char::REPLACEMENT_CHARACTER.encode_utf8(&mut [0; 3]));
+            }
+        }
+    }
+
+    fn push_str(&mut self, text: &str) {
+        let newline = if self.newline_only { memrchr(b'\n', text.as_bytes()) } else { memrchr2(b'\n', b'\r', text.as_bytes()) };
+        if let Some(i) = newline {
+            self.last_newline = Some(self.text.len() + i);
+        }
+        self.text.push_str(text);
+    }
+}
+
+/// How backslashes are treated, by the lexer and by serialization.
+/// 
+/// Converts from `bool` for the functions that used to take `escapes: bool`: `true` is [`EscapePolicy::Escaped`]
+/// and `false` is [`EscapePolicy::Unescaped`]. With the `serde` feature it deserializes from either a `bool`
+/// or the kebab-case name of a variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum EscapePolicy {
+    /// A backslash escapes the character after it. Serialization escapes `\\`, `:`, `;` and `//`.
+    #[default]
+    Escaped,
+    /// Backslashes are ordinary text, as in old MSD files that never escape anything.
+    /// Serialization refuses components containing `:`, `;` or `//`, which can't be written without escapes.
+    Unescaped,
+    /// Parsed like [`EscapePolicy::Escaped`], but serialization also escapes `#`, so a value line starting with `#`
+    /// can't be mistaken for a new parameter by StepMania's recovery from missing semicolons.
+    StrictStepMania,
+}
+
+impl EscapePolicy {
+    /// Whether a backslash escapes the character after it.
+    pub fn is_escaped(&self) -> bool {
+        !matches!(self, EscapePolicy::Unescaped)
+    }
+}
+
+impl From<bool> for EscapePolicy {
+    fn from(escapes: bool) -> Self {
+        if escapes { EscapePolicy::Escaped } else { EscapePolicy::Unescaped }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for EscapePolicy {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = EscapePolicy;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a bool or one of \"escaped\", \"unescaped\", \"strict-step-mania\"")
+            }
+
+            fn visit_bool<E: serde::de::Error>(self, escapes: bool) -> Result<EscapePolicy, E> {
+                Ok(escapes.into())
+            }
+
+            fn visit_str<E: serde::de::Error>(self, name: &str) -> Result<EscapePolicy, E> {
+                match name {
+                    "escaped" => Ok(EscapePolicy::Escaped),
+                    "unescaped" => Ok(EscapePolicy::Unescaped),
+                    "strict-step-mania" => Ok(EscapePolicy::StrictStepMania),
+                    _ => Err(E::unknown_variant(name, &["escaped", "unescaped", "strict-step-mania"])),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// When a `#` inside a parameter is taken as the start of the next parameter, recovering from a missing `;`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum RecoveryPolicy {
+    /// Never: a `#` inside a parameter is part of its value, so only `;` or the end of the input end a parameter.
+    Off,
+    /// When the `#` directly follows a line break.
+    #[default]
+    LineStart,
+    /// When the `#` is the first character of its line or component other than spaces and tabs,
+    /// which is how StepMania recovers, see [`Dialect::StepMania`]. Escaped spaces and tabs count as spaces and tabs.
+    FirstOnLine,
+    /// Always, for files that never escape `#` and miss `;` in the middle of a line.
+    Aggressive,
+}
+
+/// Whose reading rules the lexer and parser follow where MSD readers disagree.
+/// 
+/// Set with [`MSDParserOptions::dialect`](crate::parser::MSDParserOptions::dialect), which also sets the
+/// options the dialect implies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum Dialect {
+    /// The rules of the original Python library: comments end at any line break, and values are kept as written.
+    #[default]
+    Msd,
+    /// The rules of StepMania's `MsdFile.cpp`, for tools that want to read a file the way the game does. This
+    /// follows the game's reader closely and is checked against the cases in `tests/stepmania_parity.rs`, but it
+    /// is an emulation, not a guarantee: files that reach parts of the game's reader the corpus doesn't cover may
+    /// still come out differently.
+    ///
+    /// It differs from [`Dialect::Msd`] in that:
+    ///
+    /// * escapes are on and stray text is ignored;
+    /// * a `//` comment runs up to the next `\n`, so in files with lone `\r` line breaks it swallows the lines after it;
+    /// * a `#` inside a parameter starts the next one when it is the first character of its line or component other
+    ///   than spaces and tabs ([`RecoveryPolicy::FirstOnLine`]);
+    /// * the value ended that way loses its trailing spaces, tabs and line breaks.
+    StepMania,
+    /// The rules StepMania reads `.dwi` files with: those of [`Dialect::StepMania`], except that backslashes are
+    /// ordinary text, so Windows paths like `#FILE:C:\Songs\a.mp3` and the note data of `#SINGLE` and `#DOUBLE`
+    /// charts come through as written.
+    Dwi,
+    /// The rules StepMania reads Pump It Up `.ksf` files with: those of [`Dialect::Dwi`], except that `#STEP` is not
+    /// terminated by `;` but runs up to its row of `2`s, which marks the end of the step data, or to the end of the
+    /// input. Anything after that row is dropped, later components included, and the missing `;` is not an error.
+    ///
+    /// `MsdFile.cpp` itself reads `#STEP` to the end of the input; the cut is where the game's KSF loader stops
+    /// reading step rows, so it is not covered by `tests/stepmania_parity.rs` but by `tests/ksf_dialect.rs`.
+    Ksf,
+}
+
+impl Dialect {
+    /// Whether the dialect is one of StepMania's, which all read through `MsdFile.cpp`.
+    pub(crate) fn is_msd_file(self) -> bool {
+        matches!(self, Dialect::StepMania | Dialect::Dwi | Dialect::Ksf)
+    }
+}
+
+/// How the lexer treats reads that return no data.
+/// 
+/// The defaults follow the [`Read`] contract: a read of 0 bytes is the end of the stream,
+/// and a read failing with [`io::ErrorKind::Interrupted`] is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct ReadPolicy {
+    /// Whether reads failing with [`io::ErrorKind::Interrupted`] are retried instead of returned as an error.
+    /// Defaults to `true`.
+    pub retry_interrupted: bool,
+    /// Number of consecutive 0-byte reads that mark the end of the stream. Defaults to 1.
+    /// 
+    /// Raise it for readers that return 0 bytes before they are actually exhausted.
+    pub empty_reads_until_eof: u32,
+}
+
+impl Default for ReadPolicy {
+    fn default() -> Self {
+        Self {
+            retry_interrupted: true,
+            empty_reads_until_eof: 1,
         }
     }
 }
 
+impl ReadPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn retry_interrupted(mut self, retry_interrupted: bool) -> Self {
+        self.retry_interrupted = retry_interrupted;
+        self
+    }
+
+    pub fn empty_reads_until_eof(mut self, empty_reads_until_eof: u32) -> Self {
+        self.empty_reads_until_eof = empty_reads_until_eof;
+        self
+    }
+}
+
 /// Lexer for MSD files.
 /// 
-/// Implements an [`Iterator`] that yields [`MSDTokenMatch`]s
-#[derive(Debug, Clone)]
-pub struct MSDLexer<R> {
+/// Implements an [`Iterator`] that yields [`Result<MSDTokenMatch, MSDLexerError>`]s.
+/// 
+/// `M` is the [`ReadMode`]: [`Unbuffered`] for any reader (see [`MSDLexer::new`]),
+/// or [`Buffered`] for readers that already buffer their input (see [`MSDLexer::from_buf_read`]).
+/// 
+/// # Memory
+/// 
+/// The lexer reads more input only once every complete line in its buffer has been tokenized, so the buffer holds
+/// at most the longest line plus one chunk of the reader, however long the input or its values are.
+/// Lexing tokens is the way to stream a value too large to hold in memory, such as a huge `#NOTES`.
+/// This is a guarantee, checked by `tests/streaming.rs`.
+#[derive(Debug)]
+pub struct MSDLexer<R, M = Unbuffered> {
     reader: R,
-    msd_buffer: String,
-    read_buffer: [u8; BUFFER_SIZE],
-    inside_parameter: bool,
+    mode: M,
+    buffer: TextBuffer,
     done_reading: bool,
-    last_text_token: Option<String>,
-    lexer_patterns: Vec<LexerPattern>
+    resumable: bool,
+    read_policy: ReadPolicy,
+    /// Consecutive 0-byte reads so far
+    empty_reads: u32,
+    /// Position of `buffer.remaining()`
+    position: Position,
+    /// Whether the text consumed so far ends with `\r`, so a `\n` up next completes its line break
+    after_carriage_return: bool,
+    /// Whether comments inside parameters are consumed without being yielded
+    skip_comments: bool,
+    core: LexerCore,
 }
 
 impl<R: Read> MSDLexer<R> {
-    /// Create a new MSDLexer from a Read instance and whether or not to escape special characters.
-    pub fn new(reader: R, escapes: bool) -> Self {
+    /// Create a new MSDLexer from a Read instance and the [`EscapePolicy`].
+    pub fn new(reader: R, escapes: impl Into<EscapePolicy>) -> Self {
+        Self::with_mode(reader, Unbuffered::default(), escapes.into())
+    }
+}
+
+#[cfg(feature = "io")]
+impl<R: BufRead> MSDLexer<R, Buffered> {
+    /// Create a new MSDLexer that decodes directly from the internal buffer of a [`BufRead`] instance,
+    /// such as a `BufReader<File>` or a byte slice.
+    pub fn from_buf_read(reader: R, escapes: impl Into<EscapePolicy>) -> Self {
+        Self::with_mode(reader, Buffered, escapes.into())
+    }
+}
+
+impl<R, M: ReadMode<R>> MSDLexer<R, M> {
+    fn with_mode(reader: R, mode: M, escapes: EscapePolicy) -> Self {
         Self {
             reader,
-            
-            msd_buffer: String::new(),
-            read_buffer: [0; BUFFER_SIZE],
+            mode,
+            buffer: TextBuffer::default(),
 
-            inside_parameter: false,
             done_reading: false,
-            last_text_token: None,
+            resumable: false,
+            read_policy: ReadPolicy::default(),
+            empty_reads: 0,
+            position: Position::start(),
+            after_carriage_return: false,
+            skip_comments: false,
             
-            lexer_patterns: {
-                LEXER_PATTERNS.iter()
-                    .filter(|x| x.escapes == Some(escapes) || x.escapes.is_none())
-                    .cloned()
-                    .collect()
-            },
+            core: LexerCore::new(escapes),
         }
     }
 
-    /// Read the next token from the input stream.
+    /// Change the [`EscapePolicy`] for the rest of the stream.
+    pub fn set_escapes(&mut self, escapes: impl Into<EscapePolicy>) {
+        self.core.set_escapes(escapes.into());
+    }
+
+    /// Set whether reads failing with [`io::ErrorKind::WouldBlock`] or [`io::ErrorKind::TimedOut`]
+    /// pause the lexer instead of returning an error.
     /// 
-    /// Returns None if the end of the stream has been reached or no patterns match.
-    pub fn next_token(&mut self) -> Option<MSDTokenMatch> {
-        // End until both stream and buffer are empty
-        while !(self.done_reading && self.msd_buffer.is_empty()) {
-            // Read the next chunk
-            let read = self.reader.read(&mut self.read_buffer).unwrap();
+    /// When paused, [`MSDLexer::next_token`] yields every token that is already complete and then returns `None`
+    /// without ending the stream; calling it again once the reader has more data resumes lexing.
+    /// Use [`MSDLexer::is_done`] to tell a pause apart from the end of the stream.
+    pub fn with_resumable_reads(mut self, resumable: bool) -> Self {
+        self.resumable = resumable;
+        self
+    }
 
-            // End of the stream
-            if read == 0 { self.done_reading = true; }
+    /// Set when a `#` inside a parameter starts the next one, see [`RecoveryPolicy`].
+    pub fn with_recovery(mut self, recovery: RecoveryPolicy) -> Self {
+        self.core.set_recovery(recovery);
+        self
+    }
+
+    /// Follow the comment and recovery rules of `dialect` for the rest of the stream, see [`Dialect`].
+    /// 
+    /// In the dialects of StepMania, only `\n` ends a line, so the buffer of a file with lone `\r` line breaks may
+    /// hold the whole file.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.core.set_dialect(dialect);
+        self.buffer.newline_only = dialect.is_msd_file();
+        self
+    }
+
+    /// Consume comments inside parameters without yielding them, for parsers that drop them anyway.
+    pub(crate) fn with_comments_skipped(mut self, skip_comments: bool) -> Self {
+        self.skip_comments = skip_comments;
+        self
+    }
+
+    /// Count byte offsets from `offset` instead of 0, for input that starts in the middle of a file.
+    #[cfg(feature = "io")]
+    pub(crate) fn with_start_offset(mut self, offset: usize) -> Self {
+        self.position.offset = offset;
+        self
+    }
+
+    /// Set how reads that return no data are handled, see [`ReadPolicy`].
+    pub fn with_read_policy(mut self, read_policy: ReadPolicy) -> Self {
+        self.read_policy = read_policy;
+        self
+    }
+
+    /// Position of the next token, i.e. of everything that has not been yielded yet.
+    ///
+    /// A `\r\n` split between two tokens, e.g. by an escaped `\r`, still counts as a single line break.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Mutable access to the underlying reader.
+    /// 
+    /// Reading from it directly skips that input, since the lexer never sees it.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
 
-            // Add the next chunk to the buffer
-            self.msd_buffer += String::from_utf8_lossy(&self.read_buffer[..read]).as_ref();
+    /// Whether the end of the stream has been reached and every token has been yielded.
+    pub fn is_done(&self) -> bool {
+        self.done_reading && self.buffer.is_empty()
+    }
 
+    /// Read the next token from the input stream.
+    /// 
+    /// Returns None if the end of the stream has been reached,
+    /// or if the reader would block and resumable reads are enabled.
+    /// 
+    /// # Errors
+    /// 
+    /// Returns [`MSDLexerError::LexerStuck`] if no pattern matches the start of the buffer,
+    /// or [`MSDLexerError::UnterminatedEscape`] if that is because the input ends in a backslash.
+    /// The unmatched text is discarded, so every call either consumes input or ends the stream.
+    /// 
+    /// Returns [`MSDLexerError::InvalidUtf8`] once for every invalid UTF-8 sequence, before the token that holds it
+    /// as U+FFFD.
+    /// 
+    /// Returns [`MSDLexerError::Io`] if reading fails, after which no more reads are attempted.
+    pub fn next_token(&mut self) -> Option<Result<MSDTokenMatch, MSDLexerError>> {
+        // End until both stream and buffer are empty
+        while !self.is_done() {
             // Enforcing that the MSD buffer always either contains a newline or the rest of the stream,
             // so that comments, escapes, etc. don't get split in half.
-            while self.msd_buffer.contains('\n') || self.msd_buffer.contains('\r') || (self.done_reading && self.msd_buffer.len() > 0) {
-                for pattern in &self.lexer_patterns {
-                    if let Some(m) = pattern.regex.find(&self.msd_buffer) {
-                        let matched_text = self.msd_buffer.get(..m.end()).unwrap().to_owned();
-                        // Remove the matched section from the buffer
-                        self.msd_buffer = self.msd_buffer.get(m.end()..).unwrap().to_string();
-
-                        let mut token = 
-                            if self.inside_parameter { pattern.token_inside_param } 
-                            else { pattern.token_outside_param };
-                        
-                        // Recovery from missing `;` at the end of a line
-                        if let Some(last_token) = self.last_text_token.clone() {
-                            if last_token.ends_with("\n") || last_token.ends_with("\r") {
-                                if pattern.regex.as_str() == POUND && token == MSDToken::Text {
-                                    token = MSDToken::StartParameter;
-                                }
-                            }
-                        }
-
-                        match token {
-                            MSDToken::StartParameter => { self.inside_parameter = true; },
-                            MSDToken::EndParameter => { self.inside_parameter = false; },
-                            MSDToken::Text => { self.last_text_token = Some(matched_text.to_string()); },
-                            _ => {}
-                        }
-                        
-                        return Some(MSDTokenMatch::new(token, matched_text));
-                    }
+            if self.buffer.has_complete_line() || self.done_reading {
+                if let Some(e) = self.invalid_utf8() {
+                    return Some(Err(e));
                 }
+                if self.skip_comment() {
+                    continue;
+                }
+                return Some(match self.match_pattern() {
+                    Some(token_match) => Ok(token_match),
+                    None => {
+                        let position = self.position;
+                        let (text, raw) = self.buffer.take_remaining();
+                        self.advance_position(&text, raw);
+                        Err(stuck_error(text, position))
+                    },
+                });
+            }
+
+            self.buffer.compact();
+
+            // Read the next chunk
+            let buffer = &mut self.buffer;
+            let read = match self.mode.read_chunk(&mut self.reader, &mut |chunk| buffer.append(chunk)) {
+                Ok(read) => read,
+                Err(e) if self.read_policy.retry_interrupted && e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if self.resumable && is_pause(&e) => return None,
+                Err(e) => {
+                    self.done_reading = true;
+                    self.buffer.finish();
+                    return Some(Err(MSDLexerError::Io(e)));
+                },
+            };
+
+            if read > 0 {
+                self.empty_reads = 0;
+                continue;
+            }
+
+            // End of the stream
+            self.empty_reads += 1;
+            if self.empty_reads >= self.read_policy.empty_reads_until_eof {
+                self.done_reading = true;
+                self.buffer.finish();
             }
         }
         None
     }
+
+    /// The error for the first invalid UTF-8 sequence in the next token, if it has one that was not reported yet.
+    fn invalid_utf8(&mut self) -> Option<MSDLexerError> {
+        let remaining = self.buffer.remaining();
+        let length = self.core.scan(remaining).map_or(remaining.len(), |(_, length)| length);
+        let offset = self.buffer.report_replacement_before(length)?;
+        let remaining = self.buffer.remaining();
+        let mut position = self.position;
+        match remaining[..offset].strip_prefix('\n') {
+            Some(rest) if self.after_carriage_return => position.advance(rest),
+            _ => position.advance(&remaining[..offset]),
+        }
+        position.offset = self.position.offset + self.buffer.raw_len(offset);
+        Some(MSDLexerError::InvalidUtf8 { position })
+    }
+
+    /// Consume a comment inside a parameter without copying it, if comments are skipped.
+    /// Returns whether a comment was skipped.
+    /// 
+    /// Generated files can hold millions of comments, which a parser dropping them shouldn't allocate for.
+    fn skip_comment(&mut self) -> bool {
+        if !self.skip_comments || !self.core.is_inside_parameter() || !self.buffer.remaining().starts_with("//") {
+            return false;
+        }
+        let Some((_, length)) = self.core.match_token(self.buffer.remaining()) else { return false };
+
+        // A comment never starts with the `\n` of a split `\r\n`, so it can be counted on its own
+        let comment = &self.buffer.remaining()[..length];
+        self.after_carriage_return = comment.ends_with('\r');
+        let offset = self.position.offset;
+        self.position.advance(comment);
+        self.position.offset = offset + self.buffer.consume(length);
+        true
+    }
+
+    /// Match the next token against the start of the unconsumed buffer and consume it.
+    fn match_pattern(&mut self) -> Option<MSDTokenMatch> {
+        let (token, length) = self.core.match_token(self.buffer.remaining())?;
+        let matched_text = self.buffer.remaining()[..length].to_owned();
+        let raw = self.buffer.consume(length);
+        let start = self.position.offset;
+        self.advance_position(&matched_text, raw);
+        Some(MSDTokenMatch::new(token, matched_text, start..start + raw))
+    }
+
+    /// Move the position past consumed text, decoded from `raw` bytes of the input,
+    /// counting a `\r\n` split across two tokens as one line break.
+    fn advance_position(&mut self, text: &str, raw: usize) {
+        let offset = self.position.offset;
+        match text.strip_prefix('\n') {
+            Some(rest) if self.after_carriage_return => self.position.advance(rest),
+            _ => self.position.advance(text),
+        }
+        self.position.offset = offset + raw;
+        if !text.is_empty() {
+            self.after_carriage_return = text.ends_with('\r');
+        }
+    }
+}
+
+/// The error for `text` at `position`, which no pattern matched.
+/// 
+/// Only a lone backslash at the end of the input can fail to match, since everything else is at least text.
+pub(crate) fn stuck_error(text: String, position: Position) -> MSDLexerError {
+    if text == "\\" {
+        MSDLexerError::UnterminatedEscape { position }
+    } else {
+        MSDLexerError::LexerStuck { text, position }
+    }
+}
+
+fn is_pause(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+impl<R: TryCloneRead, M: ReadMode<R> + Clone> MSDLexer<R, M> {
+    /// Duplicate the lexer, including its reader and everything buffered so far.
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the reader cannot be duplicated.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            reader: self.reader.try_clone_read()?,
+            mode: self.mode.clone(),
+            buffer: self.buffer.clone(),
+            done_reading: self.done_reading,
+            resumable: self.resumable,
+            read_policy: self.read_policy,
+            empty_reads: self.empty_reads,
+            position: self.position,
+            after_carriage_return: self.after_carriage_return,
+            skip_comments: self.skip_comments,
+            core: self.core.clone(),
+        })
+    }
 }
 
-impl <R: Read> Iterator for MSDLexer<R> {
-    type Item = MSDTokenMatch;
+impl<R, M: ReadMode<R>> Iterator for MSDLexer<R, M> {
+    type Item = Result<MSDTokenMatch, MSDLexerError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.next_token()
     }  
 }
 
-/// Create a new [`MSDLexer`] from a [`Read`] impl and whether or not to escape special characters.
+/// Create a new [`MSDLexer`] from a [`Read`] impl and the [`EscapePolicy`].
 /// 
 /// [`MSDLexer`] is an [`Iterator`] that yields [`MSDTokenMatch`]s, 
-/// which consists of a [`MSDToken`] and the matched text.
+/// which consists of a [`MSDToken`] and the matched text, or an [`MSDLexerError`].
 /// 
 /// In practice you don't have to call this function directly, as it is called during [`parse_msd`].
 /// 
 /// [`parse_msd`]: ../parser/fn.parse_msd.html
-pub fn lex_msd<R: Read>(reader: R, escapes: bool) -> MSDLexer<R> {
+pub fn lex_msd<R: Read>(reader: R, escapes: impl Into<EscapePolicy>) -> MSDLexer<R> {
     MSDLexer::new(reader, escapes)
 }
 
@@ -194,26 +1041,30 @@ mod tests {
 
     use super::*;
 
+    fn tokens_of<R: Read>(lexer: MSDLexer<R>) -> Vec<(MSDToken, String)> {
+        lexer.map(|t| t.map(|t| (t.token, t.text)).unwrap()).collect()
+    }
+
     #[test]
     fn test_tokens_with_escapes() {
         let input = "#ABC:DEF\\:GHI;\n#JKL:MNO\nPQR# STU".as_bytes();
         let mut cursor = Cursor::new(input);
-        let tokens: Vec<MSDTokenMatch> = lex_msd(&mut cursor, true).collect();
+        let tokens = tokens_of(lex_msd(&mut cursor, true));
         let expected_tokens = vec![
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "ABC".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "DEF".to_string()),
-            MSDTokenMatch::new(MSDToken::Escape, "\\:".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "GHI".to_string()),
-            MSDTokenMatch::new(MSDToken::EndParameter, ";".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "\n".to_string()),
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "JKL".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "MNO\nPQR".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, " STU".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "ABC".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "DEF".to_string()),
+            (MSDToken::Escape, "\\:".to_string()),
+            (MSDToken::Text, "GHI".to_string()),
+            (MSDToken::EndParameter, ";".to_string()),
+            (MSDToken::Text, "\n".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "JKL".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "MNO\nPQR".to_string()),
+            (MSDToken::Text, "#".to_string()),
+            (MSDToken::Text, " STU".to_string()),
         ];
 
         assert_eq!(tokens, expected_tokens);
@@ -223,22 +1074,22 @@ mod tests {
     fn test_tokens_without_escapes() {
         let input = "#ABC:DEF\\:GHI;\n#JKL:MNO\nPQR# STU".as_bytes();
         let mut reader = Cursor::new(input);
-        let tokens: Vec<MSDTokenMatch> = lex_msd(&mut reader, false).collect();
+        let tokens = tokens_of(lex_msd(&mut reader, false));
         let expected_tokens = vec![
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "ABC".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "DEF\\".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "GHI".to_string()),
-            MSDTokenMatch::new(MSDToken::EndParameter, ";".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "\n".to_string()),
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "JKL".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "MNO\nPQR".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, " STU".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "ABC".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "DEF\\".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "GHI".to_string()),
+            (MSDToken::EndParameter, ";".to_string()),
+            (MSDToken::Text, "\n".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "JKL".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "MNO\nPQR".to_string()),
+            (MSDToken::Text, "#".to_string()),
+            (MSDToken::Text, " STU".to_string()),
         ];
 
         assert_eq!(expected_tokens, tokens);
@@ -248,22 +1099,22 @@ mod tests {
     fn test_stray_metacharacters() {
         let input = ":;#A:B;;:#C:D;".as_bytes();
         let mut reader = Cursor::new(input);
-        let tokens: Vec<MSDTokenMatch> = lex_msd(&mut reader, true).collect();
+        let tokens = tokens_of(lex_msd(&mut reader, true));
         let expected_tokens = vec![
-            MSDTokenMatch::new(MSDToken::Text, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, ";".to_string()),
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "A".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "B".to_string()),
-            MSDTokenMatch::new(MSDToken::EndParameter, ";".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, ";".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "C".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "D".to_string()),
-            MSDTokenMatch::new(MSDToken::EndParameter, ";".to_string()),
+            (MSDToken::Text, ":".to_string()),
+            (MSDToken::Text, ";".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "A".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "B".to_string()),
+            (MSDToken::EndParameter, ";".to_string()),
+            (MSDToken::Text, ";".to_string()),
+            (MSDToken::Text, ":".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "C".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "D".to_string()),
+            (MSDToken::EndParameter, ";".to_string()),
         ];
 
         assert_eq!(expected_tokens, tokens);
@@ -273,25 +1124,25 @@ mod tests {
     fn test_missing_semicolon() {
         let input = "#A:B\nCD;#E:FGH\n#IJKL// comment\n#M:NOP".as_bytes();
         let mut reader = Cursor::new(input);
-        let tokens: Vec<MSDTokenMatch> = lex_msd(&mut reader, true).collect();
+        let tokens = tokens_of(lex_msd(&mut reader, true));
         let expected_tokens = vec![
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "A".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "B\nCD".to_string()),
-            MSDTokenMatch::new(MSDToken::EndParameter, ";".to_string()),
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "E".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "FGH\n".to_string()),
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "IJKL".to_string()),
-            MSDTokenMatch::new(MSDToken::Comment, "// comment".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "\n".to_string()),
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "M".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "NOP".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "A".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "B\nCD".to_string()),
+            (MSDToken::EndParameter, ";".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "E".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "FGH\n".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "IJKL".to_string()),
+            (MSDToken::Comment, "// comment".to_string()),
+            (MSDToken::Text, "\n".to_string()),
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "M".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "NOP".to_string()),
         ];
 
         assert_eq!(expected_tokens, tokens);
@@ -301,20 +1152,273 @@ mod tests {
     fn test_comments() {
         let input = "#A// comment //\r\nBC:D// ; \nEF;//#NO:PE;".as_bytes();
         let mut reader = Cursor::new(input);
-        let tokens: Vec<MSDTokenMatch> = lex_msd(&mut reader, true).collect();
+        let tokens = tokens_of(lex_msd(&mut reader, true));
+        let expected_tokens = vec![
+            (MSDToken::StartParameter, "#".to_string()),
+            (MSDToken::Text, "A".to_string()),
+            (MSDToken::Comment, "// comment //".to_string()),
+            (MSDToken::Text, "\r\nBC".to_string()),
+            (MSDToken::NextComponent, ":".to_string()),
+            (MSDToken::Text, "D".to_string()),
+            (MSDToken::Comment, "// ; ".to_string()),
+            (MSDToken::Text, "\nEF".to_string()),
+            (MSDToken::EndParameter, ";".to_string()),
+            (MSDToken::Comment, "//#NO:PE;".to_string()),
+        ];
+
+        assert_eq!(expected_tokens, tokens);
+    }
+
+    #[test]
+    fn test_trailing_backslash_does_not_hang() {
+        let input = "#A:B\\".as_bytes();
+        let mut reader = Cursor::new(input);
+        let tokens: Vec<Result<(MSDToken, String), MSDLexerError>> = lex_msd(&mut reader, true)
+            .map(|t| t.map(|t| (t.token, t.text)))
+            .collect();
         let expected_tokens = vec![
-            MSDTokenMatch::new(MSDToken::StartParameter, "#".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "A".to_string()),
-            MSDTokenMatch::new(MSDToken::Comment, "// comment //".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "\r\nBC".to_string()),
-            MSDTokenMatch::new(MSDToken::NextComponent, ":".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "D".to_string()),
-            MSDTokenMatch::new(MSDToken::Comment, "// ; ".to_string()),
-            MSDTokenMatch::new(MSDToken::Text, "\nEF".to_string()),
-            MSDTokenMatch::new(MSDToken::EndParameter, ";".to_string()),
-            MSDTokenMatch::new(MSDToken::Comment, "//#NO:PE;".to_string()),
+            Ok((MSDToken::StartParameter, "#".to_string())),
+            Ok((MSDToken::Text, "A".to_string())),
+            Ok((MSDToken::NextComponent, ":".to_string())),
+            Ok((MSDToken::Text, "B".to_string())),
+            Err(MSDLexerError::UnterminatedEscape { position: Position { offset: 4, line: 1, column: 5 } }),
         ];
 
         assert_eq!(expected_tokens, tokens);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_position_and_span() {
+        let mut lexer = lex_msd("#A:B;\r\n#TITLE:実例;\n".as_bytes(), true);
+        let mut positions = Vec::new();
+        while let Some(Ok(token_match)) = lexer.next_token() {
+            positions.push((token_match.text, token_match.span, lexer.position()));
+        }
+
+        assert_eq!(("\r\n".to_string(), 5..7, Position { offset: 7, line: 2, column: 1 }), positions[5]);
+        assert_eq!(("実例".to_string(), 14..20, Position { offset: 20, line: 2, column: 10 }), positions[9]);
+        assert_eq!(Position { offset: 22, line: 3, column: 1 }, lexer.position());
+        assert_eq!(Position { offset: 20, line: 2, column: 10 }, Position::of("#A:B;\r\n#TITLE:実例;\n", 20));
+    }
+
+    #[test]
+    fn test_carriage_return_line_endings() {
+        // Comment termination and missing `;` recovery must not depend on `\n`
+        let lf = "#A:B\n#C:D;// c ; \n#E// c\n:F;\n#G:\\\n;";
+        let cr = lf.replace('\n', "\r");
+        let expected: Vec<(MSDToken, String)> = lex_msd(lf.as_bytes(), true)
+            .map(|t| t.unwrap())
+            .map(|t| (t.token, t.text.replace('\n', "\r")))
+            .collect();
+
+        for chunk_size in [1, 2, 3, 4096] {
+            let reader = ChunkedReader { input: cr.as_bytes(), chunk_size };
+            let tokens: Vec<(MSDToken, String)> = lex_msd(reader, true).map(|t| t.unwrap()).map(|t| (t.token, t.text)).collect();
+            assert_eq!(expected, tokens);
+        }
+
+        let mut lexer = lex_msd(cr.as_bytes(), true);
+        while lexer.next_token().is_some() {}
+        assert_eq!(Position { offset: cr.len(), line: 6, column: 2 }, lexer.position());
+    }
+
+    #[test]
+    fn test_split_carriage_return_line_feed() {
+        let input = "#A:\\\r\n#B:\r\r\n;";
+        let mut lexer = lex_msd(input.as_bytes(), true);
+        let tokens: Vec<String> = lexer.by_ref().map(|t| t.unwrap().text).collect();
+
+        assert_eq!(vec!["#", "A", ":", "\\\r", "\n", "#", "B", ":", "\r\r\n", ";"], tokens);
+        assert_eq!(Position { offset: input.len(), line: 4, column: 2 }, lexer.position());
+        assert_eq!(lexer.position(), Position::of(input, input.len()));
+    }
+
+    /// Reader returning at most `chunk_size` bytes per read.
+    struct ChunkedReader<'a> {
+        input: &'a [u8],
+        chunk_size: usize,
+    }
+
+    impl Read for ChunkedReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let length = self.input.len().min(buf.len()).min(self.chunk_size);
+            buf[..length].copy_from_slice(&self.input[..length]);
+            self.input = &self.input[length..];
+            Ok(length)
+        }
+    }
+
+    /// Yields one byte per read, with an `Interrupted` error before each byte and a 0-byte read after every `gap` bytes.
+    struct StutteringReader<'a> {
+        input: &'a [u8],
+        gap: usize,
+        reads: usize,
+    }
+
+    impl Read for StutteringReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            match self.reads % 3 {
+                0 => Err(io::Error::from(io::ErrorKind::Interrupted)),
+                1 if self.reads % self.gap == 1 => Ok(0),
+                _ => {
+                    let length = self.input.len().min(buf.len()).min(1);
+                    buf[..length].copy_from_slice(&self.input[..length]);
+                    self.input = &self.input[length..];
+                    Ok(length)
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_reads() {
+        let input = "#TITLE:実例;\n#NOTES:\n0000\n;\n";
+        let reader = StutteringReader { input: input.as_bytes(), gap: 4, reads: 0 };
+        let policy = ReadPolicy::new().empty_reads_until_eof(3);
+        let tokens: Vec<MSDTokenMatch> = MSDLexer::new(reader, true).with_read_policy(policy).map(Result::unwrap).collect();
+
+        assert_eq!(input, tokens.iter().map(|t| t.text.as_str()).collect::<String>());
+    }
+
+    #[test]
+    fn test_interrupted_without_retry() {
+        let reader = StutteringReader { input: b"#A:B;\n", gap: 1000, reads: 1 };
+        let policy = ReadPolicy::new().retry_interrupted(false);
+        let mut lexer = MSDLexer::new(reader, true).with_read_policy(policy);
+
+        assert!(matches!(lexer.next(), Some(Err(MSDLexerError::Io(e))) if e.kind() == io::ErrorKind::Interrupted));
+    }
+
+    #[test]
+    fn test_chunked_reads() {
+        let input = "#A:B;\r\n#TITLE:実例// c\n#NOTES:\n0000\n1000\n,\n0100\n;\n".repeat(50);
+        for chunk_size in [1, 3, 7, 4096] {
+            let reader = ChunkedReader { input: input.as_bytes(), chunk_size };
+            let tokens: Vec<MSDTokenMatch> = lex_msd(reader, true).map(Result::unwrap).collect();
+
+            assert_eq!(input, tokens.iter().map(|t| t.text.as_str()).collect::<String>());
+            assert!(tokens.iter().all(|t| input[t.span.clone()] == t.text));
+        }
+    }
+
+    #[test]
+    fn test_stepmania_dialect() {
+        let input = "#A:1// c\r2\r\n  #B:3\t#C;";
+        for chunk_size in [1, 3, 4096] {
+            let reader = ChunkedReader { input: input.as_bytes(), chunk_size };
+            let tokens: Vec<(MSDToken, String)> = MSDLexer::new(reader, true)
+                .with_recovery(RecoveryPolicy::FirstOnLine)
+                .with_dialect(Dialect::StepMania)
+                .map(|t| { let t = t.unwrap(); (t.token, t.text) })
+                .collect();
+
+            assert!(tokens.contains(&(MSDToken::Comment, "// c\r2\r".to_string())));
+            assert_eq!(2, tokens.iter().filter(|(token, _)| *token == MSDToken::StartParameter).count());
+        }
+    }
+
+    #[test]
+    fn test_first_on_line_after_escapes() {
+        for (input, parameters) in [("#A:1\n\\ \\\t#B:2;", 2), ("#A:1\\\n#B:2;", 2), ("#A:1\n\\x#B:2;", 1)] {
+            let starts = MSDLexer::new(input.as_bytes(), true)
+                .with_recovery(RecoveryPolicy::FirstOnLine)
+                .filter(|t| t.as_ref().unwrap().token == MSDToken::StartParameter)
+                .count();
+            assert_eq!(parameters, starts, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_comments_skipped() {
+        let input = "#A:B// c\r\nC;\n// outside\n#E:F;";
+        for chunk_size in [1, 4096] {
+            let reader = ChunkedReader { input: input.as_bytes(), chunk_size };
+            let tokens: Vec<MSDTokenMatch> = MSDLexer::new(reader, true).with_comments_skipped(true).map(Result::unwrap).collect();
+
+            let comments: Vec<&str> = tokens.iter().filter(|t| t.token == MSDToken::Comment).map(|t| t.text.as_str()).collect();
+            assert_eq!(vec!["// outside"], comments);
+            assert!(tokens.iter().all(|t| input[t.span.clone()] == t.text));
+            assert_eq!(input.replace("// c", ""), tokens.iter().map(|t| t.text.as_str()).collect::<String>());
+        }
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_buffered() {
+        let input = "#A:B;\r\n#TITLE:実例// c\n#NOTES:\n0000\n;\n".repeat(20);
+        for capacity in [1, 2, 5, 4096] {
+            let reader = io::BufReader::with_capacity(capacity, input.as_bytes());
+            let tokens: Vec<MSDTokenMatch> = MSDLexer::from_buf_read(reader, true).map(Result::unwrap).collect();
+
+            assert_eq!(input, tokens.iter().map(|t| t.text.as_str()).collect::<String>());
+            assert!(tokens.iter().all(|t| input[t.span.clone()] == t.text));
+            assert!(!tokens.iter().any(|t| t.text.contains('\u{FFFD}')));
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8_spans() {
+        let input = b"#A:\xe5\xae// \xff\r\n\xf0;\n#B:\xe5\xae\x9f;".as_ref();
+        for chunk_size in [1, 2, 4096] {
+            let reader = ChunkedReader { input, chunk_size };
+            let (tokens, errors): (Vec<_>, Vec<_>) = MSDLexer::new(reader, true).partition(Result::is_ok);
+            let tokens: Vec<MSDTokenMatch> = tokens.into_iter().map(Result::unwrap).collect();
+
+            let offsets: Vec<usize> = errors.into_iter().map(|e| match e {
+                Err(MSDLexerError::InvalidUtf8 { position }) => position.offset,
+                e => panic!("unexpected {:?}", e),
+            }).collect();
+            assert_eq!(vec![3, 8, 11], offsets);
+            for token in &tokens {
+                assert_eq!(String::from_utf8_lossy(&input[token.span.clone()]), token.text);
+            }
+            assert_eq!(input.len(), tokens.last().unwrap().span.end);
+        }
+    }
+
+    #[test]
+    fn test_adversarial_inputs_terminate() {
+        let inputs = ["\\", "/", "//", "\\\\\\", "#\\", "\r\\", "#A:\\\n\\"];
+        for input in inputs {
+            for escapes in [true, false] {
+                let mut reader = Cursor::new(input.as_bytes());
+                let tokens: Vec<Result<MSDTokenMatch, MSDLexerError>> = lex_msd(&mut reader, escapes).take(100).collect();
+                assert!(tokens.len() < 100, "{:?} did not terminate", input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_clone() {
+        let input = "#A:B;\n#C:D;".as_bytes();
+        let mut lexer = lex_msd(input, true);
+        lexer.next();
+        let clone = lexer.try_clone().unwrap();
+
+        let rest: Vec<MSDTokenMatch> = lexer.map(Result::unwrap).collect();
+        let cloned_rest: Vec<MSDTokenMatch> = clone.map(Result::unwrap).collect();
+        assert_eq!(10, rest.len());
+        assert_eq!(rest, cloned_rest);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_tokens() {
+        let tokens: Vec<MSDTokenMatch> = lex_msd(b"#A:B;".as_ref(), true).map(Result::unwrap).collect();
+        let json = serde_json::to_string(&tokens[0]).unwrap();
+
+        assert_eq!(r##"{"token":"StartParameter","text":"#","span":{"start":0,"end":1}}"##, json);
+        let decoded: Vec<MSDTokenMatch> = serde_json::from_str(&serde_json::to_string(&tokens).unwrap()).unwrap();
+        assert_eq!(tokens, decoded);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_escape_policy() {
+        let policies: Vec<EscapePolicy> = serde_json::from_str(r#"[true, false, "unescaped", "strict-step-mania"]"#).unwrap();
+
+        assert_eq!(vec![EscapePolicy::Escaped, EscapePolicy::Unescaped, EscapePolicy::Unescaped, EscapePolicy::StrictStepMania], policies);
+        assert!(serde_json::from_str::<EscapePolicy>(r#""backslash""#).is_err());
+    }
+}
@@ -1,6 +1,90 @@
+// Lets the code generated by `#[derive(FromMsd)]` refer to `::msdparser` inside this crate too
+extern crate self as msdparser;
+
 pub mod parser;
 pub mod parameter;
 pub mod lexer;
+pub mod borrowed;
+pub mod extension;
+pub mod serialize;
+pub mod writer;
+pub mod repeat;
+pub mod from_msd;
+pub mod edit;
+pub mod redact;
+pub mod capabilities;
+pub mod diagnostic;
+pub mod tags;
+pub mod key_map;
+pub mod normalize;
+pub mod values;
+#[cfg(feature = "serde")]
+mod serde_io;
+#[cfg(feature = "serde")]
+pub mod de;
+#[cfg(feature = "serde")]
+pub mod ser;
+#[cfg(feature = "document")]
+pub mod document;
+#[cfg(feature = "document")]
+pub mod lossless;
+#[cfg(feature = "document")]
+pub mod format;
+#[cfg(feature = "document")]
+pub mod diff;
+#[cfg(feature = "document")]
+pub mod merge;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "document")]
+pub mod cursor;
+#[cfg(feature = "document")]
+pub mod history;
+#[cfg(feature = "fs")]
+pub mod batch;
+#[cfg(feature = "simfile")]
+pub mod lint;
+#[cfg(feature = "fs")]
+pub mod pack;
+#[cfg(feature = "fs")]
+pub mod progress;
+#[cfg(feature = "simfile")]
+pub mod stepstype;
+#[cfg(feature = "simfile")]
+pub mod charts;
+#[cfg(feature = "simfile")]
+pub mod notes;
+#[cfg(feature = "simfile")]
+pub mod assets;
+#[cfg(feature = "simfile")]
+pub mod simfile;
+#[cfg(feature = "simfile")]
+pub mod timing;
+#[cfg(feature = "script")]
+pub mod script;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "tokio")]
+pub mod async_parser;
+#[cfg(all(test, feature = "io"))]
+mod test_util;
 
-pub use parser::{parse_msd, MSDParserError};
-pub use parameter::MSDParameter;
\ No newline at end of file
+pub use parser::{parse_msd, parse_msd_pairs, parse_msd_with_options, MSDParserError, MSDParserOptions, MSDParserWarning};
+#[cfg(feature = "io")]
+pub use parser::{parse_msd_at, parse_msd_buf_read};
+pub use parameter::{Comment, CommentPlacement, MSDParameter};
+pub use lexer::{Dialect, EscapePolicy, Position, RecoveryPolicy};
+pub use borrowed::{parse_msd_str, MSDParameterRef};
+pub use extension::MsdExtension;
+pub use capabilities::{capabilities, Capabilities};
+#[cfg(feature = "document")]
+pub use document::MSDDocument;
+pub use from_msd::{FromMsd, FromMsdError, FromMsdValue};
+#[cfg(feature = "derive")]
+pub use msdparser_derive::FromMsd;
+#[cfg(feature = "http")]
+pub use http::parse_msd_url;
+#[cfg(feature = "serde")]
+pub use de::{from_reader, from_str};
+#[cfg(feature = "serde")]
+pub use ser::to_writer;
\ No newline at end of file
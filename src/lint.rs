@@ -0,0 +1,519 @@
+use std::fmt;
+
+use crate::assets::is_asset_key;
+use crate::lexer::{lex_msd, MSDToken, Position};
+use crate::parameter::MSDParameter;
+
+/// Kind of problem reported by a [`Lint`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum LintCode {
+    /// A field contains non-ASCII text but its transliteration is missing or empty.
+    MissingTranslit,
+    /// A transliteration field contains non-ASCII text.
+    NonAsciiTranslit,
+    /// The file escapes metacharacters in some places but writes them raw in others.
+    MixedEscapes,
+    /// An asset path is too long for Windows once the song is installed.
+    AssetPathTooLong,
+    /// An asset path contains a character or name that Windows or macOS refuses.
+    NonPortableAssetPath,
+}
+
+impl fmt::Display for LintCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A problem found in a document, attached to the key it concerns.
+#[derive(Debug, PartialEq, Clone, Hash, PartialOrd)]
+pub struct Lint {
+    pub code: LintCode,
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (#{}): {}", self.code, self.key, self.message)
+    }
+}
+
+/// A metadata field paired with a `TRANSLIT` field holding its romanization.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, PartialOrd, Ord)]
+pub enum TranslitField {
+    Title,
+    Subtitle,
+    Artist,
+}
+
+impl TranslitField {
+    pub const ALL: [TranslitField; 3] = [TranslitField::Title, TranslitField::Subtitle, TranslitField::Artist];
+
+    /// Key of the original field, e.g. `TITLE`.
+    pub fn key(&self) -> &'static str {
+        match self {
+            TranslitField::Title => "TITLE",
+            TranslitField::Subtitle => "SUBTITLE",
+            TranslitField::Artist => "ARTIST",
+        }
+    }
+
+    /// Key of the transliteration field, e.g. `TITLETRANSLIT`.
+    pub fn translit_key(&self) -> &'static str {
+        match self {
+            TranslitField::Title => "TITLETRANSLIT",
+            TranslitField::Subtitle => "SUBTITLETRANSLIT",
+            TranslitField::Artist => "ARTISTTRANSLIT",
+        }
+    }
+}
+
+fn position_of(parameters: &[MSDParameter], key: &str) -> Option<usize> {
+    parameters.iter().position(|p| p.components.first().map(String::as_str) == Some(key))
+}
+
+fn value_of<'a>(parameters: &'a [MSDParameter], key: &str) -> Option<&'a str> {
+    position_of(parameters, key)
+        .and_then(|i| parameters[i].components.get(1))
+        .map(String::as_str)
+}
+
+/// Check that every `TITLE`/`SUBTITLE`/`ARTIST` field agrees with its `TRANSLIT` counterpart.
+///
+/// Reports non-ASCII fields without a transliteration, and transliterations that are not ASCII-only.
+pub fn check_translit(parameters: &[MSDParameter]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+
+    for field in TranslitField::ALL {
+        let value = value_of(parameters, field.key()).unwrap_or_default();
+        let translit = value_of(parameters, field.translit_key()).unwrap_or_default();
+
+        if !value.is_ascii() && translit.trim().is_empty() {
+            lints.push(Lint {
+                code: LintCode::MissingTranslit,
+                key: field.key().to_string(),
+                message: format!("'{}' is not ASCII but #{} is empty", value, field.translit_key()),
+            });
+        }
+
+        if !translit.is_ascii() {
+            lints.push(Lint {
+                code: LintCode::NonAsciiTranslit,
+                key: field.translit_key().to_string(),
+                message: format!("'{}' contains non-ASCII characters", translit),
+            });
+        }
+    }
+
+    lints
+}
+
+/// Number of examples kept for each escape convention.
+const ESCAPE_EXAMPLES: usize = 3;
+
+/// Keys whose value is a single piece of text, so a `:` in them should have been escaped.
+const TEXT_KEYS: [&str; 8] = [
+    "TITLE", "SUBTITLE", "ARTIST", "TITLETRANSLIT", "SUBTITLETRANSLIT", "ARTISTTRANSLIT", "GENRE", "CREDIT",
+];
+
+/// A place where the source shows which escape convention it follows.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct EscapeSite {
+    /// Key of the parameter the site is in, empty outside of parameters.
+    pub key: String,
+    /// The source text, e.g. `\:` or `:`.
+    pub text: String,
+    pub position: Position,
+}
+
+impl fmt::Display for EscapeSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} in #{} at {}", self.text, self.key, self.position)
+    }
+}
+
+/// How a file writes metacharacters, see [`escape_usage`].
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Default)]
+pub struct EscapeUsage {
+    /// Number of metacharacters escaped with a backslash, like `\:` or `\\`.
+    pub escaped: usize,
+    /// Number of places written for a parser without escapes: a backslash before an ordinary character,
+    /// like in `C:\songs`, or a `:` inside a single-text field like `#TITLE`.
+    pub raw: usize,
+    /// The first few escaped sites.
+    pub escaped_examples: Vec<EscapeSite>,
+    /// The first few raw sites.
+    pub raw_examples: Vec<EscapeSite>,
+}
+
+impl EscapeUsage {
+    /// Whether the file follows both conventions.
+    pub fn is_mixed(&self) -> bool {
+        self.escaped > 0 && self.raw > 0
+    }
+}
+
+/// Count the escaped and raw metacharacters of a whole source file.
+///
+/// Files are written either for parsers with escapes, which need `\:`, `\;`, `\#`, `\/` and `\\`, or for parsers
+/// without them, where a backslash is an ordinary character. Both in one file means it was edited by tools with
+/// different settings, and engines will disagree on its contents.
+pub fn escape_usage(input: &str) -> EscapeUsage {
+    let mut usage = EscapeUsage::default();
+    let mut key = String::new();
+    let mut component = 0;
+
+    for token_match in lex_msd(input.as_bytes(), true).filter_map(Result::ok) {
+        let escaped = match token_match.token {
+            MSDToken::StartParameter => {
+                key.clear();
+                component = 0;
+                continue;
+            },
+            MSDToken::Text if component == 0 => {
+                key.push_str(&token_match.text);
+                continue;
+            },
+            MSDToken::NextComponent => {
+                component += 1;
+                if component < 2 || !TEXT_KEYS.contains(&key.trim().to_ascii_uppercase().as_str()) {
+                    continue;
+                }
+                false
+            },
+            MSDToken::Escape => matches!(&token_match.text[1..], ":" | ";" | "#" | "/" | "\\"),
+            _ => continue,
+        };
+
+        let (count, examples) = if escaped {
+            (&mut usage.escaped, &mut usage.escaped_examples)
+        } else {
+            (&mut usage.raw, &mut usage.raw_examples)
+        };
+        *count += 1;
+        if examples.len() < ESCAPE_EXAMPLES {
+            examples.push(EscapeSite {
+                key: key.trim().to_string(),
+                text: token_match.text,
+                position: Position::of(input, token_match.span.start),
+            });
+        }
+    }
+
+    usage
+}
+
+/// Report a file that mixes escaped and raw metacharacters, with counts and example locations.
+///
+/// See [`escape_usage`].
+pub fn check_mixed_escapes(input: &str) -> Vec<Lint> {
+    let usage = escape_usage(input);
+    if !usage.is_mixed() {
+        return Vec::new();
+    }
+
+    let examples = |sites: &[EscapeSite]| sites.iter().map(EscapeSite::to_string).collect::<Vec<_>>().join(", ");
+    vec![Lint {
+        code: LintCode::MixedEscapes,
+        key: usage.raw_examples[0].key.clone(),
+        message: format!(
+            "{} escaped metacharacter(s), e.g. {}; {} raw, e.g. {}",
+            usage.escaped, examples(&usage.escaped_examples), usage.raw, examples(&usage.raw_examples),
+        ),
+    }]
+}
+
+/// Settings for [`check_asset_portability`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct PortabilityOptions {
+    /// Directory the song folder is installed into, counted in front of every asset path.
+    /// Defaults to a typical Windows install, `C:\Program Files (x86)\StepMania 5\Songs\`.
+    pub install_prefix: String,
+    /// Pack and song folder the simfile lives in, e.g. `Pack\Song\`. Defaults to being empty.
+    pub song_directory: String,
+    /// Longest path Windows accepts, counting the terminating NUL. Defaults to `260`, the value of `MAX_PATH`.
+    pub max_path: usize,
+}
+
+impl Default for PortabilityOptions {
+    fn default() -> Self {
+        Self {
+            install_prefix: "C:\\Program Files (x86)\\StepMania 5\\Songs\\".to_string(),
+            song_directory: String::new(),
+            max_path: 260,
+        }
+    }
+}
+
+impl PortabilityOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn install_prefix(mut self, install_prefix: impl Into<String>) -> Self {
+        self.install_prefix = install_prefix.into();
+        self
+    }
+
+    pub fn song_directory(mut self, song_directory: impl Into<String>) -> Self {
+        self.song_directory = song_directory.into();
+        self
+    }
+
+    pub fn max_path(mut self, max_path: usize) -> Self {
+        self.max_path = max_path;
+        self
+    }
+}
+
+/// Characters Windows refuses in file names. `:` is also the path separator of classic macOS APIs.
+const RESERVED_CHARACTERS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// File names Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Why `segment` of a path cannot be created on Windows or macOS, if it can't.
+fn non_portable_segment(segment: &str) -> Option<String> {
+    if let Some(c) = segment.chars().find(|c| RESERVED_CHARACTERS.contains(c) || c.is_control()) {
+        return Some(format!("'{}' contains {:?}", segment, c));
+    }
+    let stem = segment.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES.iter().any(|name| name.eq_ignore_ascii_case(stem)) {
+        return Some(format!("'{}' is a reserved device name on Windows", segment));
+    }
+    if segment.ends_with(['.', ' ']) && segment != "." && segment != ".." {
+        return Some(format!("'{}' ends with a dot or space, which Windows strips", segment));
+    }
+    None
+}
+
+/// Report asset paths that work on the Linux machine a pack was authored on, but break on Windows or macOS.
+///
+/// A path is too long if the [`PortabilityOptions::install_prefix`], the [`PortabilityOptions::song_directory`]
+/// and the path together exceed [`PortabilityOptions::max_path`], counted in UTF-16 units like Windows does.
+/// Every segment of the path is also checked for characters and device names Windows refuses.
+/// Both `/` and `\` separate segments, and blank values are skipped.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::MSDParameter;
+/// # use msdparser::lint::{check_asset_portability, LintCode, PortabilityOptions};
+/// let parameters = vec![
+///     MSDParameter::new(vec!["MUSIC".to_string(), "song?.ogg".to_string()]),
+///     MSDParameter::new(vec!["BANNER".to_string(), "a".repeat(250)]),
+/// ];
+/// let lints = check_asset_portability(&parameters, &PortabilityOptions::new());
+///
+/// assert_eq!(vec![LintCode::NonPortableAssetPath, LintCode::AssetPathTooLong], lints.iter().map(|l| l.code).collect::<Vec<_>>());
+/// ```
+pub fn check_asset_portability(parameters: &[MSDParameter], options: &PortabilityOptions) -> Vec<Lint> {
+    let mut lints = Vec::new();
+
+    for parameter in parameters {
+        let (Some(key), Some(path)) = (parameter.components.first(), parameter.components.get(1)) else { continue };
+        let path = path.trim();
+        if !is_asset_key(key) || path.is_empty() {
+            continue;
+        }
+        let key = key.trim().to_string();
+
+        for reason in path.split(['/', '\\']).filter_map(non_portable_segment) {
+            lints.push(Lint { code: LintCode::NonPortableAssetPath, key: key.clone(), message: reason });
+        }
+
+        let length = [options.install_prefix.as_str(), &options.song_directory, path]
+            .iter()
+            .map(|part| part.encode_utf16().count())
+            .sum::<usize>() + 1;
+        if length > options.max_path {
+            lints.push(Lint {
+                code: LintCode::AssetPathTooLong,
+                key,
+                message: format!("'{}' is {} characters long once installed, more than {}", path, length, options.max_path),
+            });
+        }
+    }
+
+    lints
+}
+
+/// Settings for [`lint`]: which lints run, and the settings of those that have any.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct LintOptions {
+    /// Lints that are never reported. Defaults to none.
+    pub allow: Vec<LintCode>,
+    /// Settings for [`check_asset_portability`].
+    pub portability: PortabilityOptions,
+}
+
+impl LintOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop reporting `code`.
+    pub fn allow(mut self, code: LintCode) -> Self {
+        self.allow.push(code);
+        self
+    }
+
+    pub fn portability(mut self, portability: PortabilityOptions) -> Self {
+        self.portability = portability;
+        self
+    }
+}
+
+/// Run every lint on a document, given both as its source text and as the parameters parsed from it, leaving
+/// out the lints in [`LintOptions::allow`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::parse_msd;
+/// # use msdparser::lint::{lint, LintCode, LintOptions};
+/// let input = "#TITLE:実例;\n#MUSIC:song?.ogg;";
+/// let parameters: Vec<_> = parse_msd(input.as_bytes(), true, false).collect::<Result<_, _>>().unwrap();
+/// let options = LintOptions::new().allow(LintCode::MissingTranslit);
+///
+/// assert_eq!(vec![LintCode::NonPortableAssetPath], lint(input, &parameters, &options).iter().map(|l| l.code).collect::<Vec<_>>());
+/// ```
+pub fn lint(input: &str, parameters: &[MSDParameter], options: &LintOptions) -> Vec<Lint> {
+    let mut lints = check_translit(parameters);
+    lints.extend(check_mixed_escapes(input));
+    lints.extend(check_asset_portability(parameters, &options.portability));
+    lints.retain(|lint| !options.allow.contains(&lint.code));
+    lints
+}
+
+fn set_value(parameters: &mut Vec<MSDParameter>, key: &str, value: &str, insert_at: Option<usize>) -> usize {
+    match position_of(parameters, key) {
+        Some(i) => {
+            parameters[i].components.truncate(1);
+            parameters[i].components.push(value.to_string());
+            i
+        },
+        None => {
+            let i = insert_at.unwrap_or(parameters.len()).min(parameters.len());
+            parameters.insert(i, MSDParameter::new(vec![key.to_string(), value.to_string()]));
+            i
+        },
+    }
+}
+
+/// Set a field together with its transliteration so the pair never goes out of sync.
+///
+/// An ASCII value needs no transliteration, so `translit` defaults to an empty string.
+/// Missing parameters are inserted, the transliteration right after its field.
+pub fn set_with_translit(parameters: &mut Vec<MSDParameter>, field: TranslitField, value: &str, translit: Option<&str>) {
+    let i = set_value(parameters, field.key(), value, None);
+    set_value(parameters, field.translit_key(), translit.unwrap_or_default(), Some(i + 1));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(key: &str, value: &str) -> MSDParameter {
+        MSDParameter::new(vec![key.to_string(), value.to_string()])
+    }
+
+    #[test]
+    fn test_check_translit() {
+        let parameters = vec![
+            param("TITLE", "実例"),
+            param("TITLETRANSLIT", ""),
+            param("SUBTITLE", "ok"),
+            param("ARTIST", "楽士"),
+            param("ARTISTTRANSLIT", "Gakushi ♪"),
+        ];
+        let lints = check_translit(&parameters);
+
+        assert_eq!(2, lints.len());
+        assert_eq!(LintCode::MissingTranslit, lints[0].code);
+        assert_eq!("TITLE", lints[0].key);
+        assert_eq!(LintCode::NonAsciiTranslit, lints[1].code);
+        assert_eq!("ARTISTTRANSLIT", lints[1].key);
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let parameters = vec![param("TITLE", "実例"), param("TITLETRANSLIT", "Jitsurei"), param("ARTIST", "Kommisar")];
+        assert!(check_translit(&parameters).is_empty());
+    }
+
+    #[test]
+    fn test_set_with_translit() {
+        let mut parameters = vec![param("TITLE", "Old"), param("TITLETRANSLIT", "Stale"), param("ARTIST", "A")];
+
+        set_with_translit(&mut parameters, TranslitField::Title, "New", None);
+        set_with_translit(&mut parameters, TranslitField::Artist, "楽士", Some("Gakushi"));
+
+        assert_eq!(vec![
+            param("TITLE", "New"),
+            param("TITLETRANSLIT", ""),
+            param("ARTIST", "楽士"),
+            param("ARTISTTRANSLIT", "Gakushi"),
+        ], parameters);
+        assert!(check_translit(&parameters).is_empty());
+    }
+
+    #[test]
+    fn test_mixed_escapes() {
+        let input = "#TITLE:A\\:B;\n#ARTIST:C:D;\n#MUSIC:C:\\songs\\a.ogg;\n#NOTES:dance-single:x;";
+        let usage = escape_usage(input);
+
+        assert_eq!((1, 3), (usage.escaped, usage.raw));
+        assert_eq!(EscapeSite { key: "ARTIST".to_string(), text: ":".to_string(), position: Position { offset: 22, line: 2, column: 10 } }, usage.raw_examples[0]);
+        assert_eq!("\\s", usage.raw_examples[1].text);
+
+        let lints = check_mixed_escapes(input);
+        assert_eq!(LintCode::MixedEscapes, lints[0].code);
+        assert!(lints[0].message.starts_with("1 escaped metacharacter(s), e.g. \"\\\\:\" in #TITLE at line 1, column 9; 3 raw"));
+
+        assert!(check_mixed_escapes("#TITLE:A\\:B;#MUSIC:a\\\\b.ogg;").is_empty());
+        assert!(check_mixed_escapes("#TITLE:A:B;#MUSIC:C:\\a.ogg;").is_empty());
+    }
+
+    #[test]
+    fn test_asset_portability() {
+        let parameters = vec![
+            param("music", "Con.ogg"),
+            param("BANNER", "art\\bn |.png"),
+            param("BACKGROUND", "bg./bg.png"),
+            param("TITLE", "What?"),
+            param("CDTITLE", ""),
+            param("JACKET", "jacket.png"),
+        ];
+        let lints = check_asset_portability(&parameters, &PortabilityOptions::new());
+
+        assert_eq!(3, lints.len());
+        assert!(lints.iter().all(|lint| lint.code == LintCode::NonPortableAssetPath));
+        assert_eq!(vec!["music", "BANNER", "BACKGROUND"], lints.iter().map(|lint| lint.key.as_str()).collect::<Vec<_>>());
+
+        let options = PortabilityOptions::new().install_prefix("").song_directory("Pack\\Song\\").max_path(21);
+        assert!(check_asset_portability(&parameters[5..], &options).is_empty());
+        let lints = check_asset_portability(&parameters[5..], &options.max_path(20));
+        assert_eq!("AssetPathTooLong (#JACKET): 'jacket.png' is 21 characters long once installed, more than 20", lints[0].to_string());
+    }
+
+    #[test]
+    fn test_lint() {
+        let input = "#TITLE:実例;\n#ARTIST:C:D;\n#GENRE:E\\:F;\n#MUSIC:con.ogg;";
+        let parameters = vec![param("TITLE", "実例"), param("MUSIC", "con.ogg")];
+        let codes = |options: &LintOptions| lint(input, &parameters, options).iter().map(|l| l.code).collect::<Vec<_>>();
+
+        assert_eq!(vec![LintCode::MissingTranslit, LintCode::MixedEscapes, LintCode::NonPortableAssetPath], codes(&LintOptions::new()));
+        let options = LintOptions::new().allow(LintCode::MixedEscapes).portability(PortabilityOptions::new().max_path(10));
+        assert_eq!(vec![LintCode::MissingTranslit, LintCode::NonPortableAssetPath, LintCode::AssetPathTooLong], codes(&options));
+    }
+}
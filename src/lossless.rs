@@ -0,0 +1,414 @@
+use std::io::{Read, Write};
+
+use crate::document::MSDDocument;
+use crate::lexer::Position;
+use crate::parameter::{CommentPlacement, MSDParameter, MSDParameterError};
+use crate::parser::{parse_msd_with_options, MSDParserError, MSDParserOptions};
+use crate::serialize::{LineEnding, MSDSerializeOptions, MsdSerialize};
+
+/// A parameter of a [`LosslessDocument`], with the source text around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LosslessParameter {
+    leading: String,
+    raw: Option<String>,
+    parameter: MSDParameter,
+}
+
+impl LosslessParameter {
+    /// A parameter that was not parsed, written with `leading` before it.
+    pub fn new(leading: &str, parameter: MSDParameter) -> Self {
+        Self { leading: leading.to_string(), raw: None, parameter }
+    }
+
+    pub fn parameter(&self) -> &MSDParameter {
+        &self.parameter
+    }
+
+    /// The parameter, open to changes. From then on it is written out anew instead of as it was in the source.
+    pub fn parameter_mut(&mut self) -> &mut MSDParameter {
+        self.raw = None;
+        &mut self.parameter
+    }
+
+    /// Everything between the previous parameter and this one: whitespace, comments, a BOM or ignored stray text.
+    pub fn leading(&self) -> &str {
+        &self.leading
+    }
+
+    pub fn set_leading(&mut self, leading: &str) {
+        self.leading = leading.to_string();
+    }
+
+    /// Number of blank lines in the leading text, i.e. how far the author set this parameter apart from the last.
+    pub fn blank_lines(&self) -> usize {
+        inner_lines(&self.leading).filter(|line| line.trim().is_empty()).count()
+    }
+
+    /// Number of lines in the leading text that hold nothing but a comment.
+    pub fn comment_lines(&self) -> usize {
+        inner_lines(&self.leading).filter(|line| line.trim_start().starts_with("//")).count()
+    }
+
+    /// The source text of the parameter, from `#` to `;`, or `None` if it was changed or not parsed.
+    pub fn raw(&self) -> Option<&str> {
+        self.raw.as_deref()
+    }
+
+    pub fn into_parameter(self) -> MSDParameter {
+        self.parameter
+    }
+}
+
+/// Writes the leading text, then the source text of the parameter or the parameter itself if it was changed.
+///
+/// The comments around a changed parameter are already part of the text between parameters, so of its
+/// [`MSDParameter::comments`] only those inside it are written again.
+impl MsdSerialize for LosslessParameter {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        writer.write_all(self.leading.as_bytes())?;
+        match &self.raw {
+            Some(raw) => writer.write_all(raw.as_bytes())?,
+            None if self.parameter.comments.iter().any(|comment| !matches!(comment.placement, CommentPlacement::Inside { .. })) => {
+                let mut parameter = self.parameter.clone();
+                parameter.comments.retain(|comment| matches!(comment.placement, CommentPlacement::Inside { .. }));
+                parameter.serialize_msd(writer, options)?
+            },
+            None => self.parameter.serialize_msd(writer, options)?,
+        }
+        Ok(())
+    }
+}
+
+/// The lines of `text` that start and end within it, skipping the rest of the line before it and the
+/// indentation after it.
+fn inner_lines(text: &str) -> impl Iterator<Item = &str> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let inner = lines.len().saturating_sub(2);
+    lines.into_iter().skip(1).take(inner)
+}
+
+/// Drop blank lines from `text` beyond `max` in a row, keeping its line endings.
+fn limit_blank_lines(text: &str, max: usize) -> String {
+    let mut kept = Vec::new();
+    let mut run = 0;
+    let lines: Vec<&str> = text.split('\n').collect();
+    for (i, line) in lines.iter().enumerate() {
+        let inner = i != 0 && i != lines.len() - 1;
+        run = if inner && line.trim().is_empty() { run + 1 } else { 0 };
+        if run <= max {
+            kept.push(*line);
+        }
+    }
+    kept.join("\n")
+}
+
+/// A document that keeps everything between its parameters, so it can be written back byte for byte.
+///
+/// Comments, blank lines, a byte order mark and, with [`MSDParserOptions::ignore_stray_text`], stray text are kept
+/// as the leading text of the parameter after them, or as the trailing text of the document.
+/// Parameters are written as they were in the source until they are changed through
+/// [`LosslessParameter::parameter_mut`], so editing one tag leaves the rest of the file untouched.
+///
+/// [`LosslessDocument::from_reader`] rejects invalid UTF-8 unless [`MSDParserOptions::lossy_utf8`] is set, in
+/// which case it is replaced with U+FFFD and not restored when the document is written.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::lossless::LosslessDocument;
+/// # use msdparser::MSDParserOptions;
+/// # use msdparser::serialize::{MsdSerialize, MSDSerializeOptions};
+/// let input = "\u{feff}// Credits: me\r\n#TITLE:Springtime ;\r\n\r\n#ARTIST:Kommisar;  // trailing\r\n";
+/// let mut document = LosslessDocument::parse(input, MSDParserOptions::new()).unwrap();
+/// assert_eq!(input, document.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+///
+/// document.get_mut("ARTIST").unwrap().parameter_mut().components[1] = "kommisar".to_string();
+/// assert_eq!(
+///     "\u{feff}// Credits: me\r\n#TITLE:Springtime ;\r\n\r\n#ARTIST:kommisar;  // trailing\r\n",
+///     document.to_msd_string(&MSDSerializeOptions::new()).unwrap()
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LosslessDocument {
+    parameters: Vec<LosslessParameter>,
+    trailing: String,
+    line_ending: LineEnding,
+}
+
+impl LosslessDocument {
+    /// Parse `input`, keeping the text around every parameter.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error the parser yields.
+    pub fn parse(input: &str, options: MSDParserOptions) -> Result<Self, MSDParserError> {
+        let mut document = Self {
+            line_ending: if input.contains("\r\n") { LineEnding::CrLf } else { LineEnding::Lf },
+            ..Self::default()
+        };
+        let mut end = 0;
+
+        for parameter in parse_msd_with_options(input.as_bytes(), options) {
+            let parameter = parameter?;
+            let span = parameter.span.clone().expect("parsed parameters have a span");
+            document.parameters.push(LosslessParameter {
+                leading: input[end..span.start].to_string(),
+                raw: Some(input[span.clone()].to_string()),
+                parameter,
+            });
+            end = span.end;
+        }
+        document.trailing = input[end..].to_string();
+
+        Ok(document)
+    }
+
+    /// Read all of `reader` and parse it. See [`LosslessDocument::parse`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails, the input is not valid UTF-8 and [`MSDParserOptions::lossy_utf8`] is off,
+    /// or the input cannot be parsed.
+    pub fn from_reader<R: Read>(mut reader: R, options: MSDParserOptions) -> Result<Self, MSDParserError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(MSDParserError::Io)?;
+        if options.lossy_utf8 {
+            return Self::parse(&String::from_utf8_lossy(&bytes), options);
+        }
+        match std::str::from_utf8(&bytes) {
+            Ok(input) => Self::parse(input, options),
+            Err(e) => {
+                let valid = std::str::from_utf8(&bytes[..e.valid_up_to()]).expect("prefix is valid UTF-8");
+                Err(MSDParserError::InvalidUtf8 { position: Position::of(valid, valid.len()) })
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.parameters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parameters.is_empty()
+    }
+
+    pub fn parameters(&self) -> &[LosslessParameter] {
+        &self.parameters
+    }
+
+    pub fn parameters_mut(&mut self) -> &mut [LosslessParameter] {
+        &mut self.parameters
+    }
+
+    /// Text after the last parameter.
+    pub fn trailing(&self) -> &str {
+        &self.trailing
+    }
+
+    pub fn set_trailing(&mut self, trailing: &str) {
+        self.trailing = trailing.to_string();
+    }
+
+    /// The line ending of the source: [`LineEnding::CrLf`] if it contains any `\r\n`, otherwise [`LineEnding::Lf`].
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// The first parameter with the given key.
+    pub fn get(&self, key: &str) -> Option<&LosslessParameter> {
+        self.parameters.iter().find(|p| p.parameter.components.first().map(String::as_str) == Some(key))
+    }
+
+    /// The first parameter with the given key, open to changes.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut LosslessParameter> {
+        self.parameters.iter_mut().find(|p| p.parameter.components.first().map(String::as_str) == Some(key))
+    }
+
+    /// Append a parameter on a line of its own, using the line ending of the source.
+    pub fn push(&mut self, parameter: MSDParameter) {
+        let leading = if self.parameters.is_empty() && self.trailing.is_empty() { "" } else { self.line_ending.as_str() };
+        self.parameters.push(LosslessParameter::new(leading, parameter));
+    }
+
+    /// Remove and return the first parameter with the given key, along with its leading text.
+    pub fn remove(&mut self, key: &str) -> Option<LosslessParameter> {
+        let index = self.parameters.iter().position(|p| p.parameter.components.first().map(String::as_str) == Some(key))?;
+        Some(self.parameters.remove(index))
+    }
+
+    /// Replace the parameters by `parameters`, e.g. after editing a copy of them, keeping the source text of each
+    /// one that is unchanged.
+    ///
+    /// Each new parameter is matched to the next old one with equal components, and the old ones skipped on the
+    /// way are dropped along with their leading text. A new parameter without such a match replaces the next old
+    /// one with the same key, keeping its leading text, or else is added on a line of its own, after the rest of
+    /// the line of the parameter before it. The leading
+    /// text of the first parameter, which holds a byte order mark or header comments, is kept in any case.
+    pub fn set_parameters(&mut self, parameters: Vec<MSDParameter>) {
+        let mut old = std::mem::take(&mut self.parameters);
+        old.reverse();
+        let mut first_leading = old.last().map(|first| first.leading.clone());
+
+        for parameter in parameters {
+            let unchanged = old.iter().rposition(|entry| entry.parameter == parameter);
+            let same_key = || old.iter().rposition(|entry| entry.parameter.components.first() == parameter.components.first());
+            let mut entry = match (unchanged, same_key()) {
+                (Some(index), _) => {
+                    old.truncate(index + 1);
+                    old.pop().expect("index is in bounds")
+                },
+                (None, Some(index)) => {
+                    old.truncate(index + 1);
+                    let mut entry = old.pop().expect("index is in bounds");
+                    *entry.parameter_mut() = parameter;
+                    entry
+                },
+                (None, None) if self.parameters.is_empty() => LosslessParameter::new("", parameter),
+                (None, None) => {
+                    // The rest of the previous parameter's line, such as a comment, stays on that line
+                    let following = match old.last_mut() {
+                        Some(next) => &mut next.leading,
+                        None => &mut self.trailing,
+                    };
+                    let leading = match following.find('\n') {
+                        Some(end) => {
+                            let line = following[..=end].to_string();
+                            *following = format!("{}{}", self.line_ending.as_str(), &following[end + 1..]);
+                            line
+                        },
+                        None => self.line_ending.as_str().to_string(),
+                    };
+                    LosslessParameter::new(&leading, parameter)
+                },
+            };
+            if let Some(leading) = first_leading.take() {
+                entry.leading = leading;
+            }
+            self.parameters.push(entry);
+        }
+    }
+
+    /// Limit every run of blank lines between parameters, and after the last one, to `max` blank lines.
+    ///
+    /// Comments and the text of the parameters are left alone.
+    pub fn normalize_blank_lines(&mut self, max: usize) {
+        for parameter in &mut self.parameters {
+            parameter.leading = limit_blank_lines(&parameter.leading, max);
+        }
+        self.trailing = limit_blank_lines(&self.trailing, max);
+    }
+
+    /// The parameters without the text around them.
+    pub fn to_document(&self) -> MSDDocument {
+        self.parameters.iter().map(|p| p.parameter.clone()).collect()
+    }
+
+    /// Write the document, which reproduces the source exactly if nothing was changed.
+    ///
+    /// # Errors
+    ///
+    /// See [`MsdSerialize::serialize_msd`].
+    pub fn write<W: Write>(&self, mut writer: W, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        self.serialize_msd(&mut writer, options)
+    }
+}
+
+/// The leading text and parameter of every entry, then the trailing text. No line endings are added.
+impl MsdSerialize for LosslessDocument {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        for parameter in &self.parameters {
+            parameter.serialize_msd(writer, options)?;
+        }
+        writer.write_all(self.trailing.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn round_trip(input: &str, options: MSDParserOptions) -> String {
+        let document = LosslessDocument::parse(input, options).unwrap();
+        document.to_msd_string(&MSDSerializeOptions::new()).unwrap()
+    }
+
+    #[test]
+    fn test_byte_for_byte() {
+        let springtime = String::from_utf8(fs::read("testdata/Springtime.ssc").unwrap()).unwrap();
+        let inputs = [
+            springtime.as_str(),
+            "",
+            "   \n// only a comment",
+            "\u{feff}#A:B;//c\r\n#C:D\n#E:F\\;G;\n\n\n",
+            "#A:B\nCD;#E:FGH\n#IJKL// comment\n#M:NOP",
+        ];
+        for input in inputs {
+            assert_eq!(input, round_trip(input, MSDParserOptions::new()));
+        }
+
+        let stray = "#A:B;\n  stray text\n#C:D;";
+        assert_eq!(stray, round_trip(stray, MSDParserOptions::new().ignore_stray_text(true)));
+        assert!(LosslessDocument::parse(stray, MSDParserOptions::new()).is_err());
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let input = "#A:1;\r\n\r\n\r\n\r\n// group\r\n  \r\n  #B:2;\n#C:3;\n\n\n";
+        let mut document = LosslessDocument::parse(input, MSDParserOptions::new()).unwrap();
+        let counts: Vec<_> = document.parameters().iter().map(|p| (p.blank_lines(), p.comment_lines())).collect();
+        assert_eq!(vec![(0, 0), (4, 1), (0, 0)], counts);
+
+        document.normalize_blank_lines(1);
+        assert_eq!(
+            "#A:1;\r\n\r\n// group\r\n  \r\n  #B:2;\n#C:3;\n\n",
+            document.to_msd_string(&MSDSerializeOptions::new()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_edits() {
+        let mut document = LosslessDocument::parse("// header\r\n#TITLE:A;\r\n#NOTES:x:y;\r\n", MSDParserOptions::new()).unwrap();
+        assert_eq!(LineEnding::CrLf, document.line_ending());
+        assert_eq!(Some("#TITLE:A;"), document.get("TITLE").and_then(LosslessParameter::raw));
+
+        document.get_mut("NOTES").unwrap().parameter_mut().components[2] = "z;".to_string();
+        document.push(MSDParameter::new(vec!["ARTIST".to_string(), "B".to_string()]));
+        let title = document.remove("TITLE").unwrap();
+
+        assert_eq!("// header\r\n", title.leading());
+        assert_eq!("\r\n#NOTES:x:z\\;;\r\n#ARTIST:B;\r\n", document.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+        assert_eq!(2, document.to_document().len());
+    }
+
+    #[test]
+    fn test_set_parameters() {
+        let input = "\u{feff}// header\n#TITLE:A;\n\n#ARTIST:B; // artist\n#GENRE:C;\n// footer\n";
+        let mut document = LosslessDocument::parse(input, MSDParserOptions::new().keep_comments(true)).unwrap();
+        let parameters: Vec<_> = document.parameters().iter().map(|p| p.parameter().clone()).collect();
+        document.set_parameters(parameters.clone());
+        assert_eq!(input, document.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+
+        let mut edited = parameters;
+        edited[1].components[1] = "D".to_string();
+        edited.remove(0);
+        edited.insert(1, MSDParameter::new(vec!["CREDIT".to_string(), "E".to_string()]));
+        document.set_parameters(edited);
+        assert_eq!(
+            "\u{feff}// header\n#ARTIST:D; // artist\n#CREDIT:E;\n#GENRE:C;\n// footer\n",
+            document.to_msd_string(&MSDSerializeOptions::new()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let input = b"#TITLE:A;\n#ARTIST:\xff;".as_ref();
+        let position = Position { offset: 18, line: 2, column: 9 };
+        assert_eq!(Err(MSDParserError::InvalidUtf8 { position }), LosslessDocument::from_reader(input, MSDParserOptions::new()));
+
+        let document = LosslessDocument::from_reader(input, MSDParserOptions::new().lossy_utf8(true)).unwrap();
+        assert_eq!(Some("\u{fffd}".to_string()), document.get("ARTIST").and_then(|p| p.parameter().value()));
+    }
+}
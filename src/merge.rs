@@ -0,0 +1,115 @@
+use std::ops::Range;
+use std::{error, fmt};
+
+use crate::key_map::KeyMap;
+
+/// How [`MSDDocument::merge`](crate::MSDDocument::merge) resolves a key whose value differs between the documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum MergeStrategy {
+    /// Keep the parameter of the document being merged into.
+    KeepOurs,
+    /// Replace it in place with the parameter of the other document.
+    #[default]
+    TakeTheirs,
+    /// Keep it and append the parameter of the other document.
+    KeepBoth,
+    /// Reject the merge.
+    Error,
+}
+
+/// A conflicting key under [`MergeStrategy::Error`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MergeConflictError {
+    /// The key as written in the other document.
+    pub key: String,
+    /// Which occurrence of the key conflicts, counting from `0`.
+    pub occurrence: usize,
+    /// Byte range of the parameter in the source of the other document, if it was parsed.
+    pub span: Option<Range<usize>>,
+}
+
+impl fmt::Display for MergeConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "merge conflict on key '{}' (occurrence {})", self.key, self.occurrence)
+    }
+}
+
+impl error::Error for MergeConflictError {}
+
+/// Per-key [`MergeStrategy`]s, with a fallback for every other key.
+///
+/// Keys are compared ignoring ASCII case and surrounding whitespace.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::{MSDDocument, MSDParserOptions};
+/// # use msdparser::merge::{MergePolicy, MergeStrategy};
+/// let translated = MSDDocument::from_reader("#TITLE:春;#TITLETRANSLIT:Haru;#BPMS:0=100;".as_bytes(), MSDParserOptions::new()).unwrap();
+/// let timing = MSDDocument::from_reader("#TITLE:Haru;#BPMS:0=120;#OFFSET:-0.01;".as_bytes(), MSDParserOptions::new()).unwrap();
+///
+/// let policy = MergePolicy::new().with_default(MergeStrategy::KeepOurs).with_key("BPMS", MergeStrategy::TakeTheirs);
+/// let merged = translated.merge(timing, &policy).unwrap();
+///
+/// let values: Vec<_> = merged.iter().map(|(_, p)| p.value().unwrap()).collect();
+/// assert_eq!(vec!["春", "Haru", "0=120", "-0.01"], values);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct MergePolicy {
+    /// Strategy for keys without their own entry. Defaults to [`MergeStrategy::TakeTheirs`].
+    pub default: MergeStrategy,
+    /// Strategies by key. Set with [`MergePolicy::with_key`].
+    keys: KeyMap<MergeStrategy>,
+}
+
+impl MergePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_default(mut self, strategy: MergeStrategy) -> Self {
+        self.default = strategy;
+        self
+    }
+
+    pub fn with_key(mut self, key: &str, strategy: MergeStrategy) -> Self {
+        self.keys.insert(key, strategy);
+        self
+    }
+
+    /// The strategies set for single keys.
+    pub fn keys(&self) -> &KeyMap<MergeStrategy> {
+        &self.keys
+    }
+
+    /// The strategy that applies to `key`.
+    pub fn strategy_for(&self, key: &str) -> MergeStrategy {
+        self.keys.get(key).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strategy_for() {
+        let policy = MergePolicy::new().with_default(MergeStrategy::Error).with_key(" notes", MergeStrategy::KeepBoth);
+
+        assert_eq!(MergeStrategy::KeepBoth, policy.strategy_for("NOTES "));
+        assert_eq!(MergeStrategy::Error, policy.strategy_for("TITLE"));
+        assert_eq!(MergeStrategy::TakeTheirs, MergePolicy::new().strategy_for("TITLE"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_keys() {
+        let policy: MergePolicy = serde_json::from_str(r#"{"default": "error", "keys": {" Bpms": "keep-ours"}}"#).unwrap();
+        assert_eq!(MergeStrategy::KeepOurs, policy.strategy_for("BPMS"));
+        assert_eq!(MergeStrategy::Error, policy.strategy_for("TITLE"));
+        assert_eq!(Some(&MergeStrategy::KeepOurs), policy.keys().get("bpms"));
+    }
+}
@@ -0,0 +1,224 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::extension::MsdExtension;
+use crate::parameter::MSDParameter;
+use crate::parser::MSDParserError;
+use crate::serialize::LineEnding;
+
+/// One step of a normalization [`Pipeline`], rewriting a parameter in place.
+///
+/// The built-in stages cover the common cases; implement this trait for anything else.
+pub trait Stage: fmt::Debug + Send + Sync {
+    /// Rewrite `parameter`. Stages see the output of the stages before them and must not fail: leave a
+    /// parameter the stage doesn't apply to unchanged.
+    fn apply(&self, parameter: &mut MSDParameter);
+}
+
+/// Trim whitespace around the key and every value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Trim;
+
+impl Stage for Trim {
+    fn apply(&self, parameter: &mut MSDParameter) {
+        if let Some(key) = parameter.components.first() {
+            let key = key.trim().to_string();
+            parameter.normalize_key(key);
+        }
+        for value in parameter.components.iter_mut().skip(1) {
+            let trimmed = value.trim();
+            if trimmed.len() != value.len() {
+                *value = trimmed.to_string();
+            }
+        }
+    }
+}
+
+/// Write keys in uppercase. The key as written stays available as [`MSDParameter::original_key`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct UppercaseKeys;
+
+impl Stage for UppercaseKeys {
+    fn apply(&self, parameter: &mut MSDParameter) {
+        if let Some(key) = parameter.components.first() {
+            let key = key.to_ascii_uppercase();
+            parameter.normalize_key(key);
+        }
+    }
+}
+
+/// Convert every line break inside values, `\r\n`, `\r` or `\n`, to the given line ending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct LineEndings(pub LineEnding);
+
+impl Stage for LineEndings {
+    fn apply(&self, parameter: &mut MSDParameter) {
+        for value in parameter.components.iter_mut().skip(1).filter(|value| value.contains('\r') || value.contains('\n')) {
+            *value = value.replace("\r\n", "\n").replace('\r', "\n");
+            if self.0 != LineEnding::Lf {
+                *value = value.replace('\n', self.0.as_str());
+            }
+        }
+    }
+}
+
+/// Resolve the backslash escapes left in values parsed with [`EscapePolicy::Unescaped`], e.g. from a DWI file:
+/// each backslash is dropped and the character after it kept. A backslash at the end of a value is kept.
+///
+/// This is not the same as parsing with escapes. By the time this stage runs, the unescaped parse has already
+/// split components at `\:`, ended the parameter at `\;` and started a comment at `\//`, which can't be undone
+/// here; only values without such sequences come out as an escaping parse would give them.
+///
+/// [`EscapePolicy::Unescaped`]: crate::lexer::EscapePolicy::Unescaped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ResolveEscapes;
+
+impl Stage for ResolveEscapes {
+    fn apply(&self, parameter: &mut MSDParameter) {
+        for value in parameter.components.iter_mut().skip(1).filter(|value| value.contains('\\')) {
+            let mut resolved = String::with_capacity(value.len());
+            let mut chars = value.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => resolved.push(chars.next().unwrap_or('\\')),
+                    c => resolved.push(c),
+                }
+            }
+            *value = resolved;
+        }
+    }
+}
+
+/// Convert keys and values to Unicode Normalization Form C, so that e.g. `é` written as `e` and a combining
+/// accent equals a precomposed `é` in lookups and comparisons.
+#[cfg(feature = "unicode")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Nfc;
+
+#[cfg(feature = "unicode")]
+impl Stage for Nfc {
+    fn apply(&self, parameter: &mut MSDParameter) {
+        use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+        for component in parameter.components.iter_mut().filter(|component| !is_nfc(component)) {
+            *component = component.nfc().collect();
+        }
+    }
+}
+
+/// Normalization stages applied in order, the same way by every layer of the crate.
+///
+/// Register a pipeline on a parser with [`MSDParser::with_extension`], or apply it to a document with
+/// [`MSDDocument::normalize`] or to every file of a pack scan with [`PackScan::for_each_document_with`],
+/// [`PackScan::index_with`] or [`grep_with`].
+///
+/// [`MSDParser::with_extension`]: crate::parser::MSDParser::with_extension
+/// [`MSDDocument::normalize`]: crate::document::MSDDocument::normalize
+/// [`PackScan::for_each_document_with`]: crate::pack::PackScan::for_each_document_with
+/// [`PackScan::index_with`]: crate::pack::PackScan::index_with
+/// [`grep_with`]: crate::pack::grep_with
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::parse_msd;
+/// # use msdparser::normalize::{LineEndings, Pipeline, Trim, UppercaseKeys};
+/// # use msdparser::serialize::LineEnding;
+/// let pipeline = Pipeline::new().stage(Trim).stage(UppercaseKeys).stage(LineEndings(LineEnding::Lf));
+/// let parameter = parse_msd(b"# title : A\r\nB ;".as_ref(), true, false).with_extension(pipeline).next().unwrap().unwrap();
+///
+/// assert_eq!(vec!["TITLE", "A\nB"], parameter.components);
+/// assert_eq!(Some(" title "), parameter.original_key());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    stages: Vec<Arc<dyn Stage>>,
+}
+
+impl Pipeline {
+    /// An empty pipeline, which changes nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a stage, which runs after the stages already added.
+    pub fn stage<S: Stage + 'static>(mut self, stage: S) -> Self {
+        self.stages.push(Arc::new(stage));
+        self
+    }
+
+    /// Number of stages.
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Run every stage on `parameter`.
+    pub fn apply(&self, parameter: &mut MSDParameter) {
+        for stage in &self.stages {
+            stage.apply(parameter);
+        }
+    }
+
+    /// Run every stage on each of `parameters`.
+    pub fn apply_all(&self, parameters: &mut [MSDParameter]) {
+        for parameter in parameters {
+            self.apply(parameter);
+        }
+    }
+}
+
+impl MsdExtension for Pipeline {
+    fn name(&self) -> &str {
+        "normalize"
+    }
+
+    fn process(&self, mut parameter: MSDParameter) -> Result<Option<MSDParameter>, MSDParserError> {
+        self.apply(&mut parameter);
+        Ok(Some(parameter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_msd;
+
+    fn parameter(components: &[&str]) -> MSDParameter {
+        MSDParameter::new(components.iter().map(|c| c.to_string()).collect())
+    }
+
+    #[test]
+    fn test_stages() {
+        let mut crlf = parameter(&["notes", "0000\n1000\r0100\r\n"]);
+        Pipeline::new().stage(UppercaseKeys).stage(LineEndings(LineEnding::CrLf)).apply(&mut crlf);
+        assert_eq!(parameter(&["NOTES", "0000\r\n1000\r\n0100\r\n"]), crlf);
+
+        let mut escaped = parameter(&["TITLE", "A\\:B\\\\C\\"]);
+        ResolveEscapes.apply(&mut escaped);
+        assert_eq!(parameter(&["TITLE", "A:B\\C\\"]), escaped);
+    }
+
+    #[test]
+    fn test_parser_extension() {
+        let pipeline = Pipeline::new().stage(Trim);
+        let values: Vec<_> = parse_msd(b"#A: 1 ;\n#B:\t2\n;".as_ref(), true, false)
+            .with_extension(pipeline.clone())
+            .map(|parameter| parameter.unwrap().value().unwrap())
+            .collect();
+
+        assert_eq!(vec!["1", "2"], values);
+        assert_eq!(1, pipeline.len());
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_nfc() {
+        let mut decomposed = parameter(&["TITLE", "Pok\u{65}\u{301}mon"]);
+        Nfc.apply(&mut decomposed);
+        assert_eq!(parameter(&["TITLE", "Pok\u{e9}mon"]), decomposed);
+    }
+}
@@ -0,0 +1,423 @@
+use std::iter::{Enumerate, FilterMap};
+use std::str::{Lines, Split};
+use std::{error, fmt};
+
+use crate::stepstype::{blank_row, validate_row, StepsTypeRegistry};
+
+/// Fewest rows a measure is written with, like StepMania does for empty measures.
+pub const MIN_ROWS_PER_MEASURE: usize = 4;
+
+/// Why note data could not be compressed or expanded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NotesError {
+    /// A row is wider or narrower than its steps type requires or, if the steps type is not known, than the first
+    /// row of the note data.
+    InconsistentRowWidth { measure: usize, row: usize, expected: usize, found: usize },
+    /// A measure can't be expanded to the requested number of rows without moving notes,
+    /// since that number is not a multiple of its row count.
+    Unrepresentable { measure: usize, rows: usize, rows_per_measure: usize },
+    /// The result would place a note on a different beat than the input. Indicates a bug.
+    RoundTrip { measure: usize },
+}
+
+impl fmt::Display for NotesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotesError::InconsistentRowWidth { measure, row, expected, found } => write!(
+                f, "row {} of measure {} has {} columns, expected {}", row, measure, found, expected
+            ),
+            NotesError::Unrepresentable { measure, rows, rows_per_measure } => write!(
+                f, "measure {} has {} rows, which can't be expanded to {} rows", measure, rows, rows_per_measure
+            ),
+            NotesError::RoundTrip { measure } => write!(f, "notes of measure {} would move", measure),
+        }
+    }
+}
+
+impl error::Error for NotesError {}
+
+/// The rows of every measure, trimmed and without blank lines.
+fn split_measures(notes: &str) -> Vec<Vec<&str>> {
+    measures(notes).map(|measure| lines(measure.text).collect()).collect()
+}
+
+/// Beats in a measure of note data, which is always in 4/4.
+pub const BEATS_PER_MEASURE: f64 = 4.0;
+
+/// A measure of note data, as yielded by [`measures`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Measure<'a> {
+    /// Index of the measure, counting from 0.
+    pub index: usize,
+    /// The text of the measure, between the `,` separators.
+    pub text: &'a str,
+}
+
+impl<'a> Measure<'a> {
+    /// The beat the measure starts on.
+    pub fn beat(&self) -> f64 {
+        self.index as f64 * BEATS_PER_MEASURE
+    }
+
+    /// Number of rows, not counting blank lines.
+    pub fn row_count(&self) -> usize {
+        lines(self.text).count()
+    }
+
+    /// The rows of the measure, trimmed, without blank lines.
+    pub fn rows(&self) -> Rows<'a> {
+        Rows { measure: *self, lines: lines(self.text), count: self.row_count(), index: 0 }
+    }
+}
+
+/// A row of note data, as yielded by [`Measure::rows`] and [`rows`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Row<'a> {
+    /// Index of the measure the row is in.
+    pub measure: usize,
+    /// Index of the row within its measure.
+    pub index: usize,
+    /// The beat of the row, from the measure it is in and its position in it.
+    pub beat: f64,
+    /// The row, trimmed, with one character per column.
+    pub text: &'a str,
+}
+
+impl Row<'_> {
+    /// Whether the row holds no note of any kind.
+    pub fn is_empty(&self) -> bool {
+        is_empty_row(self.text)
+    }
+}
+
+type RowLines<'a> = FilterMap<Lines<'a>, fn(&'a str) -> Option<&'a str>>;
+
+fn row_text(line: &str) -> Option<&str> {
+    let row = line.trim();
+    (!row.is_empty()).then_some(row)
+}
+
+fn lines(text: &str) -> RowLines<'_> {
+    text.lines().filter_map(row_text)
+}
+
+/// Iterator over the measures of note data, returned by [`measures`].
+#[derive(Debug, Clone)]
+pub struct Measures<'a> {
+    measures: Enumerate<Split<'a, char>>,
+}
+
+impl<'a> Iterator for Measures<'a> {
+    type Item = Measure<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.measures.next().map(|(index, text)| Measure { index, text })
+    }
+}
+
+/// Iterator over the rows of a measure, returned by [`Measure::rows`].
+#[derive(Debug, Clone)]
+pub struct Rows<'a> {
+    measure: Measure<'a>,
+    lines: RowLines<'a>,
+    count: usize,
+    index: usize,
+}
+
+impl<'a> Iterator for Rows<'a> {
+    type Item = Row<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let text = self.lines.next()?;
+        let index = self.index;
+        self.index += 1;
+        Some(Row {
+            measure: self.measure.index,
+            index,
+            beat: self.measure.beat() + index as f64 * BEATS_PER_MEASURE / self.count as f64,
+            text,
+        })
+    }
+}
+
+/// The measures of note data, split on `,`, borrowing from `notes` instead of copying it.
+///
+/// Comments must already be stripped, as the parser does.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::notes::measures;
+/// let notes = "1000\n0100\n,\n0010\n0000\n0001\n0000\n";
+/// let beats: Vec<(f64, &str)> = measures(notes).flat_map(|m| m.rows()).map(|row| (row.beat, row.text)).collect();
+///
+/// assert_eq!(vec![(0.0, "1000"), (2.0, "0100"), (4.0, "0010"), (5.0, "0000"), (6.0, "0001"), (7.0, "0000")], beats);
+/// ```
+pub fn measures(notes: &str) -> Measures<'_> {
+    Measures { measures: notes.split(',').enumerate() }
+}
+
+/// Every row of note data, measure after measure, e.g. to count steps without copying the note data.
+pub fn rows(notes: &str) -> impl Iterator<Item = Row<'_>> {
+    measures(notes).flat_map(|measure| measure.rows())
+}
+
+fn is_empty_row(row: &str) -> bool {
+    row.chars().all(|c| c == '0')
+}
+
+/// Check that every row is `columns` wide, or as wide as the first one if `columns` is `None`, and return that width.
+fn row_width(measures: &[Vec<&str>], columns: Option<usize>) -> Result<usize, NotesError> {
+    let expected = columns.unwrap_or_else(|| measures.iter().flatten().next().map_or(0, |row| row.chars().count()));
+    for (measure, rows) in measures.iter().enumerate() {
+        for (row, text) in rows.iter().enumerate() {
+            if let Err(e) = validate_row(text, expected) {
+                return Err(NotesError::InconsistentRowWidth { measure, row, expected, found: e.found });
+            }
+        }
+    }
+    Ok(expected)
+}
+
+/// Check that every row of note data has as many columns as `steps_type` requires, looked up in `registry`.
+///
+/// Rows of a steps type the registry does not know must all be as wide as the first row.
+///
+/// # Errors
+///
+/// Returns the first row with the wrong number of columns.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::notes::{validate_notes, NotesError};
+/// # use msdparser::stepstype::StepsTypeRegistry;
+/// let registry = StepsTypeRegistry::new();
+///
+/// assert_eq!(Ok(()), validate_notes("10000\n00100\n,\n00001", "pump-single", &registry));
+/// assert_eq!(
+///     Err(NotesError::InconsistentRowWidth { measure: 0, row: 0, expected: 4, found: 5 }),
+///     validate_notes("10000\n00100\n,\n00001", "dance-single", &registry),
+/// );
+/// ```
+pub fn validate_notes(notes: &str, steps_type: &str, registry: &StepsTypeRegistry) -> Result<(), NotesError> {
+    let mut expected = registry.columns(steps_type);
+    for row in rows(notes) {
+        let columns = *expected.get_or_insert_with(|| row.text.chars().count());
+        if let Err(e) = validate_row(row.text, columns) {
+            return Err(NotesError::InconsistentRowWidth { measure: row.measure, row: row.index, expected: columns, found: e.found });
+        }
+    }
+    Ok(())
+}
+
+/// Every note as its measure, its position within the measure as a reduced fraction, and the row text.
+fn note_positions<'a>(measures: &[Vec<&'a str>]) -> Vec<(usize, (usize, usize), &'a str)> {
+    let mut positions = Vec::new();
+    for (measure, rows) in measures.iter().enumerate() {
+        for (row, text) in rows.iter().enumerate().filter(|(_, text)| !is_empty_row(text)) {
+            let divisor = gcd(row, rows.len());
+            positions.push((measure, (row / divisor, rows.len() / divisor), *text));
+        }
+    }
+    positions
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Check that `output` has the notes of `input` on the same beats.
+fn check_round_trip(input: &[Vec<&str>], output: &[Vec<&str>]) -> Result<(), NotesError> {
+    let (before, after) = (note_positions(input), note_positions(output));
+    match before.iter().zip(&after).find(|(a, b)| a != b) {
+        Some(((measure, _, _), _)) => Err(NotesError::RoundTrip { measure: *measure }),
+        None if before.len() != after.len() => {
+            let measure = before.get(after.len()).or(after.get(before.len())).map_or(0, |(measure, _, _)| *measure);
+            Err(NotesError::RoundTrip { measure })
+        },
+        None => Ok(()),
+    }
+}
+
+fn join(measures: &[Vec<&str>]) -> String {
+    measures.iter().map(|rows| rows.join("\n")).collect::<Vec<_>>().join("\n,\n")
+}
+
+/// Keep every `step`-th row of `rows`.
+fn every<'a>(rows: &[&'a str], step: usize) -> Vec<&'a str> {
+    rows.iter().step_by(step).copied().collect()
+}
+
+/// Shrink note data without moving any note, e.g. to store autogenerated charts or hash charts consistently.
+///
+/// Every measure is reduced to the fewest rows, but at least [`MIN_ROWS_PER_MEASURE`], that still hold all its
+/// notes on the same beats, so a 192nd measure of quarter notes becomes 4 rows. Empty measures at the end are
+/// dropped, keeping at least one. The output has one row per line and a `,` line between measures.
+///
+/// The width of the rows is taken from the first row; use [`compress_notes_for`] to check it against the steps type.
+///
+/// # Errors
+///
+/// Returns an error if the rows are not all the same width, or if the result would move a note.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::notes::compress_notes;
+/// let notes = "1000\n0000\n0100\n0000\n0010\n0000\n0001\n0000\n,\n0000\n0000\n0000\n0000\n";
+///
+/// assert_eq!("1000\n0100\n0010\n0001", compress_notes(notes).unwrap());
+/// ```
+pub fn compress_notes(notes: &str) -> Result<String, NotesError> {
+    compress(notes, None)
+}
+
+/// Like [`compress_notes`], checking every row against the column count of `steps_type` in `registry`, which
+/// also sets the width of the empty measures written. Unknown steps types are handled like [`compress_notes`] does.
+///
+/// # Errors
+///
+/// Returns an error if a row has the wrong number of columns, or if the result would move a note.
+pub fn compress_notes_for(notes: &str, steps_type: &str, registry: &StepsTypeRegistry) -> Result<String, NotesError> {
+    compress(notes, registry.columns(steps_type))
+}
+
+fn compress(notes: &str, columns: Option<usize>) -> Result<String, NotesError> {
+    let input = split_measures(notes);
+    let blank = blank_row(row_width(&input, columns)?);
+
+    let mut output: Vec<Vec<&str>> = input.iter()
+        .map(|rows| {
+            if rows.iter().all(|row| is_empty_row(row)) {
+                return vec![blank.as_str(); MIN_ROWS_PER_MEASURE];
+            }
+            // The largest step that only skips empty rows gives the fewest rows
+            let step = (1..=rows.len() / MIN_ROWS_PER_MEASURE.min(rows.len()))
+                .rev()
+                .filter(|&step| rows.len().is_multiple_of(step))
+                .find(|&step| rows.iter().enumerate().all(|(i, row)| i.is_multiple_of(step) || is_empty_row(row)))
+                .unwrap_or(1);
+            every(rows, step)
+        })
+        .collect();
+
+    while output.len() > 1 && output.last().is_some_and(|rows| rows.iter().all(|row| is_empty_row(row))) {
+        output.pop();
+    }
+
+    check_round_trip(&input, &output)?;
+    Ok(join(&output))
+}
+
+/// Write every measure with `rows_per_measure` rows, inserting empty rows, e.g. to line up charts for comparison.
+///
+/// The width of the rows is taken from the first row; use [`expand_notes_for`] to check it against the steps type.
+///
+/// # Errors
+///
+/// Returns an error if the rows are not all the same width, if `rows_per_measure` is not a multiple of the row
+/// count of some measure, or if the result would move a note.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::notes::expand_notes;
+/// assert_eq!("1000\n0000\n0001\n0000", expand_notes("1000\n0001", 4).unwrap());
+/// assert!(expand_notes("1000\n0000\n0001", 4).is_err());
+/// ```
+pub fn expand_notes(notes: &str, rows_per_measure: usize) -> Result<String, NotesError> {
+    expand(notes, rows_per_measure, None)
+}
+
+/// Like [`expand_notes`], checking every row against the column count of `steps_type` in `registry`, which also
+/// sets the width of the empty rows inserted. Unknown steps types are handled like [`expand_notes`] does.
+///
+/// # Errors
+///
+/// See [`expand_notes`]; rows must have as many columns as the steps type requires.
+pub fn expand_notes_for(notes: &str, rows_per_measure: usize, steps_type: &str, registry: &StepsTypeRegistry) -> Result<String, NotesError> {
+    expand(notes, rows_per_measure, registry.columns(steps_type))
+}
+
+fn expand(notes: &str, rows_per_measure: usize, columns: Option<usize>) -> Result<String, NotesError> {
+    let input = split_measures(notes);
+    let blank = blank_row(row_width(&input, columns)?);
+
+    let mut output = Vec::with_capacity(input.len());
+    for (measure, rows) in input.iter().enumerate() {
+        if rows.is_empty() {
+            output.push(vec![blank.as_str(); rows_per_measure]);
+            continue;
+        }
+        if rows_per_measure == 0 || !rows_per_measure.is_multiple_of(rows.len()) {
+            return Err(NotesError::Unrepresentable { measure, rows: rows.len(), rows_per_measure });
+        }
+        let step = rows_per_measure / rows.len();
+        output.push((0..rows_per_measure).map(|i| if i.is_multiple_of(step) { rows[i / step] } else { blank.as_str() }).collect());
+    }
+
+    check_round_trip(&input, &output)?;
+    Ok(join(&output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_notes() {
+        let sixteenths = "1000\n0000\n0000\n0100\n".repeat(4);
+        let eighths = "1000\n0000\n0000\n0000\n0000\n0000\n0100\n0000\n0000\n0000\n0000\n0000";
+        let notes = format!("  {}\n,\n{}\n,\n,\n0000\n0000", sixteenths, eighths);
+
+        let compressed = compress_notes(&notes).unwrap();
+        assert_eq!(format!("{}\n,\n1000\n0000\n0100\n0000", sixteenths.trim_end()), compressed);
+        assert_eq!(compressed, compress_notes(&compressed).unwrap());
+        assert_eq!("0000\n0000\n0000\n0000", compress_notes("0000\n0000\n,\n0000").unwrap());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Err(NotesError::InconsistentRowWidth { measure: 1, row: 0, expected: 4, found: 8 }),
+            compress_notes("1000\n,\n10000000")
+        );
+        assert_eq!(
+            Err(NotesError::Unrepresentable { measure: 0, rows: 3, rows_per_measure: 4 }),
+            expand_notes("1000\n0000\n0001", 4)
+        );
+        let expanded = expand_notes("1000\n0100\n,\n0010", 8).unwrap();
+        assert_eq!(16 + 1, expanded.lines().count());
+        assert_eq!("1000\n0000\n0100\n0000\n,\n0010\n0000\n0000\n0000", compress_notes(&expanded).unwrap());
+    }
+
+    #[test]
+    fn test_steps_type() {
+        let mut registry = StepsTypeRegistry::new();
+        registry.register("custom-three", 3);
+
+        assert_eq!("000\n000\n000\n000", compress_notes_for(",\n", "custom-three", &registry).unwrap());
+        assert_eq!("00000000\n00000000", expand_notes_for("", 2, "dance-double", &registry).unwrap());
+        assert_eq!(
+            Err(NotesError::InconsistentRowWidth { measure: 0, row: 0, expected: 3, found: 4 }),
+            expand_notes_for("1000", 2, "custom-three", &registry)
+        );
+        assert_eq!("1000\n0000", expand_notes_for("1000", 2, "unknown", &registry).unwrap());
+        assert_eq!(
+            Err(NotesError::InconsistentRowWidth { measure: 1, row: 1, expected: 2, found: 3 }),
+            validate_notes("10\n,\n01\n001", "unknown", &registry)
+        );
+    }
+
+    #[test]
+    fn test_rows() {
+        let notes = "\r\n1000\r\n\r\n0000\r\n0M00\r\n  \r\n,\r\n,0001\n";
+        let rows: Vec<Row> = rows(notes).collect();
+
+        assert_eq!(vec![(0, 0, 0.0), (0, 1, 4.0 / 3.0), (0, 2, 8.0 / 3.0), (2, 0, 8.0)], rows.iter().map(|r| (r.measure, r.index, r.beat)).collect::<Vec<_>>());
+        assert_eq!("0M00", rows[2].text);
+        assert!(rows[1].is_empty() && !rows[2].is_empty());
+        assert_eq!(vec![3, 0, 1], measures(notes).map(|m| m.row_count()).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{error, fmt};
+
+use regex::Regex;
+
+use crate::normalize::Pipeline;
+use crate::parameter::MSDParameter;
+use crate::parser::{parse_msd_with_options, MSDParserError, MSDParserOptions};
+use crate::progress::{ProgressReader, ProgressSink};
+
+/// File extensions recognized as MSD-family simfiles, compared case-insensitively.
+///
+/// Each is read in its own dialect, see [`MSDParserOptions::for_path`].
+pub const SIMFILE_EXTENSIONS: [&str; 4] = ["ssc", "sm", "dwi", "ksf"];
+
+/// Custom error type for pack operations, naming the file that failed.
+#[derive(Debug)]
+pub enum PackError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, MSDParserError),
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackError::Io(path, e) => write!(f, "{}: IO Error: {}", path.display(), e),
+            PackError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl error::Error for PackError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PackError::Io(_, e) => Some(e),
+            PackError::Parse(_, e) => Some(e),
+        }
+    }
+}
+
+/// The simfiles found under a pack (or collection) directory.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PackScan {
+    pub root: PathBuf,
+    /// Simfile paths in lexicographic order.
+    pub files: Vec<PathBuf>,
+}
+
+impl PackScan {
+    /// Parse every file of the scan, calling `f` with its path and parameters.
+    ///
+    /// Each file is read in the dialect of its extension, see [`MSDParserOptions::for_path`], so `.dwi` files
+    /// keep their backslashes.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first file that cannot be read or parsed.
+    pub fn for_each_document<F>(&self, f: F) -> Result<(), PackError>
+    where
+        F: FnMut(&Path, Vec<MSDParameter>),
+    {
+        self.for_each_document_with_progress(&mut (), f)
+    }
+
+    /// Like [`PackScan::for_each_document`], reporting each file and the bytes read from it to `progress`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first file that cannot be read or parsed.
+    pub fn for_each_document_with_progress<F>(&self, progress: &mut dyn ProgressSink, f: F) -> Result<(), PackError>
+    where
+        F: FnMut(&Path, Vec<MSDParameter>),
+    {
+        self.for_each_document_with(&Pipeline::new(), progress, f)
+    }
+
+    /// Like [`PackScan::for_each_document_with_progress`], running `pipeline` on the parameters of each file
+    /// before calling `f`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first file that cannot be read or parsed.
+    pub fn for_each_document_with<F>(&self, pipeline: &Pipeline, progress: &mut dyn ProgressSink, mut f: F) -> Result<(), PackError>
+    where
+        F: FnMut(&Path, Vec<MSDParameter>),
+    {
+        for path in &self.files {
+            progress.on_file(path);
+            let file = File::open(path).map_err(|e| PackError::Io(path.clone(), e))?;
+            let mut parameters = parse_msd_with_options(ProgressReader::new(file, progress), MSDParserOptions::for_path(path))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| PackError::Parse(path.clone(), e))?;
+            pipeline.apply_all(&mut parameters);
+            f(path, parameters);
+        }
+        Ok(())
+    }
+
+    /// Parse every file of the scan into an index whose strings are shared through `pool`.
+    ///
+    /// Keys, steps types, credits, asset names and the like repeat across a collection,
+    /// so each distinct string is stored once no matter how many files contain it.
+    /// Passing the same pool to several scans deduplicates across all of them.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first file that cannot be read or parsed.
+    pub fn index(&self, pool: &mut StringPool) -> Result<Vec<IndexedDocument>, PackError> {
+        self.index_with_progress(pool, &mut ())
+    }
+
+    /// Like [`PackScan::index`], reporting each file and the bytes read from it to `progress`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first file that cannot be read or parsed.
+    pub fn index_with_progress(&self, pool: &mut StringPool, progress: &mut dyn ProgressSink) -> Result<Vec<IndexedDocument>, PackError> {
+        self.index_with(pool, &Pipeline::new(), progress)
+    }
+
+    /// Like [`PackScan::index_with_progress`], running `pipeline` on the parameters of each file before they
+    /// are interned, so the index holds the normalized strings.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first file that cannot be read or parsed.
+    pub fn index_with(&self, pool: &mut StringPool, pipeline: &Pipeline, progress: &mut dyn ProgressSink) -> Result<Vec<IndexedDocument>, PackError> {
+        let mut documents = Vec::with_capacity(self.files.len());
+        self.for_each_document_with(pipeline, progress, |path, parameters| {
+            documents.push(IndexedDocument {
+                path: path.to_path_buf(),
+                parameters: parameters.iter().map(|parameter| InternedParameter::new(parameter, pool)).collect(),
+            });
+        })?;
+        Ok(documents)
+    }
+}
+
+/// Deduplicates strings that repeat across the documents of a scan.
+#[derive(Debug, Clone, Default)]
+pub struct StringPool {
+    strings: HashSet<Arc<str>>,
+}
+
+impl StringPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the pooled copy of `s`, adding it if it is not pooled yet.
+    pub fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(pooled) = self.strings.get(s) {
+            return Arc::clone(pooled);
+        }
+        let pooled: Arc<str> = Arc::from(s);
+        self.strings.insert(Arc::clone(&pooled));
+        pooled
+    }
+
+    /// Number of distinct strings in the pool.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+/// A parameter whose components are shared with a [`StringPool`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InternedParameter {
+    pub components: Vec<Arc<str>>,
+}
+
+impl InternedParameter {
+    pub fn new(parameter: &MSDParameter, pool: &mut StringPool) -> Self {
+        Self { components: parameter.components.iter().map(|c| pool.intern(c)).collect() }
+    }
+
+    pub fn key(&self) -> Option<&str> {
+        self.components.first().map(AsRef::as_ref)
+    }
+
+    pub fn value(&self) -> Option<&str> {
+        self.components.get(1).map(AsRef::as_ref)
+    }
+
+    /// Copy the components out of the pool into an owned [`MSDParameter`].
+    pub fn to_parameter(&self) -> MSDParameter {
+        MSDParameter::new(self.components.iter().map(|c| c.to_string()).collect())
+    }
+}
+
+/// One file of an index built by [`PackScan::index`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedDocument {
+    pub path: PathBuf,
+    pub parameters: Vec<InternedParameter>,
+}
+
+fn is_simfile(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| SIMFILE_EXTENSIONS.iter().any(|s| s.eq_ignore_ascii_case(e)))
+}
+
+fn visit(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // Not following symbolic links to directories, which packs sometimes have pointing back up the tree
+        if entry.file_type()?.is_dir() {
+            visit(&path, files)?;
+        } else if is_simfile(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Recursively find every simfile under `root`.
+///
+/// Symbolic links to files are followed, but not those to directories, so a link back to a parent directory
+/// can't make the scan go round in circles.
+///
+/// # Errors
+///
+/// Returns an error if a directory cannot be read.
+pub fn scan<P: AsRef<Path>>(root: P) -> Result<PackScan, PackError> {
+    let root = root.as_ref();
+    let mut files = Vec::new();
+    visit(root, &mut files).map_err(|e| PackError::Io(root.to_path_buf(), e))?;
+    files.sort();
+    Ok(PackScan { root: root.to_path_buf(), files })
+}
+
+/// A regex match inside a parameter value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GrepMatch {
+    pub file: PathBuf,
+    /// Index of the parameter within its document.
+    pub parameter: usize,
+    pub key: String,
+    /// Index of the component within the parameter; always at least 1, since keys are not searched.
+    pub component: usize,
+    /// Byte range of the match within the (unescaped) component.
+    pub span: Range<usize>,
+    /// The matched text.
+    pub text: String,
+}
+
+/// Search the values of already-parsed parameters.
+///
+/// Only parameters whose key equals `key_filter` (ignoring ASCII case) are searched, if given.
+/// Because values are searched after parsing, escapes are resolved and comments never match.
+pub fn grep_parameters(file: &Path, parameters: &[MSDParameter], key_filter: Option<&str>, pattern: &Regex) -> Vec<GrepMatch> {
+    let mut matches = Vec::new();
+
+    for (i, parameter) in parameters.iter().enumerate() {
+        let key = parameter.components.first().map(String::as_str).unwrap_or_default();
+        if key_filter.is_some_and(|filter| !filter.eq_ignore_ascii_case(key)) {
+            continue;
+        }
+
+        for (j, component) in parameter.components.iter().enumerate().skip(1) {
+            for m in pattern.find_iter(component) {
+                matches.push(GrepMatch {
+                    file: file.to_path_buf(),
+                    parameter: i,
+                    key: key.to_string(),
+                    component: j,
+                    span: m.range(),
+                    text: m.as_str().to_string(),
+                });
+            }
+        }
+    }
+
+    matches
+}
+
+/// Search the parameter values of every simfile in a scan.
+///
+/// See [`grep_parameters`] for the matching rules.
+///
+/// # Errors
+///
+/// Returns the first file that cannot be read or parsed.
+pub fn grep(scan: &PackScan, key_filter: Option<&str>, pattern: &Regex) -> Result<Vec<GrepMatch>, PackError> {
+    grep_with(scan, &Pipeline::new(), &mut (), key_filter, pattern)
+}
+
+/// Like [`grep`], running `pipeline` on the parameters of each file before searching them and reporting each
+/// file and the bytes read from it to `progress`.
+///
+/// # Errors
+///
+/// Returns the first file that cannot be read or parsed.
+pub fn grep_with(
+    scan: &PackScan,
+    pipeline: &Pipeline,
+    progress: &mut dyn ProgressSink,
+    key_filter: Option<&str>,
+    pattern: &Regex,
+) -> Result<Vec<GrepMatch>, PackError> {
+    let mut matches = Vec::new();
+    scan.for_each_document_with(pipeline, progress, |path, parameters| {
+        matches.extend(grep_parameters(path, &parameters, key_filter, pattern));
+    })?;
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::Trim;
+    use crate::test_util::{TempDir, Totals};
+
+    fn test_pack(name: &str) -> TempDir {
+        let dir = TempDir::new(&format!("pack-{}", name));
+        fs::create_dir_all(dir.join("Song A")).unwrap();
+        fs::create_dir_all(dir.join("Song B")).unwrap();
+        fs::write(dir.join("Song A").join("a.ssc"), "#TITLE:Spring\\:time;\n#CREDIT:spring // not a value\n;").unwrap();
+        fs::write(dir.join("Song B").join("b.SM"), "#TITLE:Autumn;\n#SUBTITLE:spring mix;").unwrap();
+        fs::write(dir.join("Song B").join("b.ogg"), "spring").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = test_pack("scan");
+        let scan = scan(dir.path()).unwrap();
+
+        assert_eq!(vec![dir.join("Song A").join("a.ssc"), dir.join("Song B").join("b.SM")], scan.files);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_symlinks() {
+        let dir = test_pack("symlinks");
+        fs::write(dir.join("Song B").join("b.ksf"), "#TITLE:Autumn;\n#STEP:\n2222222222222\n").unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.join("Song A").join("parent")).unwrap();
+        std::os::unix::fs::symlink(dir.join("Song A").join("a.ssc"), dir.join("Song B").join("linked.ssc")).unwrap();
+        let scan = scan(dir.path()).unwrap();
+
+        assert_eq!(vec![
+            dir.join("Song A").join("a.ssc"),
+            dir.join("Song B").join("b.SM"),
+            dir.join("Song B").join("b.ksf"),
+            dir.join("Song B").join("linked.ssc"),
+        ], scan.files);
+        let error = PackError::Io(dir.path().to_path_buf(), io::Error::other("x"));
+        assert!(error::Error::source(&error).is_some());
+    }
+
+    #[test]
+    fn test_index_shares_strings() {
+        let dir = test_pack("index");
+        fs::write(dir.join("Song B").join("b.SM"), "#TITLE:Autumn;\n#CREDIT:spring // not a value\n;").unwrap();
+        let mut pool = StringPool::new();
+        let documents = scan(dir.path()).unwrap().index(&mut pool).unwrap();
+
+        assert_eq!(2, documents.len());
+        assert_eq!(Some("Spring:time"), documents[0].parameters[0].value());
+        let credits: Vec<&InternedParameter> = documents.iter().map(|d| &d.parameters[1]).collect();
+        assert_eq!(credits[0], credits[1]);
+        assert!(Arc::ptr_eq(&credits[0].components[1], &credits[1].components[1]));
+        // TITLE, Spring:time, CREDIT, "spring \n", Autumn
+        assert_eq!(5, pool.len());
+    }
+
+    #[test]
+    fn test_index_progress() {
+        let dir = test_pack("progress");
+        let scan = scan(dir.path()).unwrap();
+        let mut totals = Totals::default();
+        scan.index_with_progress(&mut StringPool::new(), &mut totals).unwrap();
+
+        let expected: u64 = scan.files.iter().map(|path| fs::metadata(path).unwrap().len()).sum();
+        assert_eq!((scan.files.clone(), expected), (totals.files, totals.bytes));
+    }
+
+    #[test]
+    fn test_for_each_document_with_pipeline() {
+        let dir = test_pack("pipeline");
+        let mut values = Vec::new();
+        scan(dir.path()).unwrap().for_each_document_with(&Pipeline::new().stage(Trim), &mut (), |_, parameters| {
+            values.extend(parameters.into_iter().map(|parameter| parameter.value().unwrap()));
+        }).unwrap();
+
+        assert_eq!(vec!["Spring:time", "spring", "Autumn", "spring mix"], values);
+    }
+
+    #[test]
+    fn test_index_and_grep_with_pipeline() {
+        let dir = test_pack("pipeline-index");
+        let scan = scan(dir.path()).unwrap();
+        let pipeline = Pipeline::new().stage(Trim);
+        let documents = scan.index_with(&mut StringPool::new(), &pipeline, &mut ()).unwrap();
+        assert_eq!(Some("spring"), documents[0].parameters[1].value());
+
+        let pattern = Regex::new(r"spring\s").unwrap();
+        assert_eq!(1, grep(&scan, Some("credit"), &pattern).unwrap().len());
+        assert!(grep_with(&scan, &pipeline, &mut (), Some("credit"), &pattern).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dwi_dialect() {
+        let dir = test_pack("dwi");
+        fs::write(dir.join("Song B").join("b.dwi"), "#FILE:C:\\Songs\\b.mp3;").unwrap();
+        let mut values = Vec::new();
+        scan(dir.path()).unwrap().for_each_document(|path, parameters| {
+            if path.extension().unwrap() == "dwi" {
+                values.extend(parameters.into_iter().map(|parameter| parameter.components[1..].join(":")));
+            }
+        }).unwrap();
+
+        assert_eq!(vec!["C:\\Songs\\b.mp3"], values);
+    }
+
+    #[test]
+    fn test_grep() {
+        let dir = test_pack("grep");
+        let scan = scan(dir.path()).unwrap();
+        let matches = grep(&scan, None, &Regex::new("(?i)spring").unwrap()).unwrap();
+
+        assert_eq!(3, matches.len());
+        assert_eq!(("TITLE", 1, 0..6), (matches[0].key.as_str(), matches[0].component, matches[0].span.clone()));
+        assert_eq!(("CREDIT", 0..6), (matches[1].key.as_str(), matches[1].span.clone()));
+        assert_eq!(("SUBTITLE", 1), (matches[2].key.as_str(), matches[2].parameter));
+
+        let titles = grep(&scan, Some("title"), &Regex::new("Spring:").unwrap()).unwrap();
+        assert_eq!(1, titles.len());
+        assert_eq!("Spring:", titles[0].text);
+    }
+}
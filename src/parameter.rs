@@ -1,11 +1,18 @@
-use std::fmt;
+use std::{error, fmt};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 use std::io::{self, Write};
+use std::ops::Range;
 use std::vec::Vec;
 
+use crate::lexer::EscapePolicy;
+use crate::tags::KnownTag;
+
 /// Custom error type for MSD parameters.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MSDParameterError {
-    IoError(io::Error),
+    IoError(#[cfg_attr(feature = "serde", serde(with = "crate::serde_io"))] io::Error),
     SerializeError(String),
 }
 
@@ -18,6 +25,15 @@ impl fmt::Display for MSDParameterError {
     }
 }
 
+impl error::Error for MSDParameterError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MSDParameterError::IoError(e) => Some(e),
+            MSDParameterError::SerializeError(_) => None,
+        }
+    }
+}
+
 impl From<io::Error> for MSDParameterError {
     fn from(e: io::Error) -> Self {
         MSDParameterError::IoError(e)
@@ -30,20 +46,161 @@ impl From<String> for MSDParameterError {
     }
 }
 
+/// Whether a parameter has a value, see [`MSDParameter::value_presence`].
+/// 
+/// Serialization keeps the distinction, since an empty value is written with its `:` and a missing one without.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum ValuePresence {
+    /// `#KEY:value;`, including values that are only whitespace.
+    Present,
+    /// `#KEY:;`, a `:` followed by nothing.
+    Empty,
+    /// `#KEY;`, no `:` at all. Some engines treat this differently from an empty value.
+    Missing,
+}
+
+/// Where a comment kept with [`MSDParserOptions::keep_comments`] was found, relative to its parameter.
+/// 
+/// [`MSDParserOptions::keep_comments`]: crate::parser::MSDParserOptions::keep_comments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum CommentPlacement {
+    /// On a line of its own between the previous parameter and this one.
+    Before,
+    /// Inside the parameter, at byte `offset` of `components[component]`.
+    /// 
+    /// The line break ending the comment stays in the component, so it starts right at `offset`.
+    Inside { component: usize, offset: usize },
+    /// After the `;` of the parameter, on the same line.
+    SameLine,
+    /// On a line of its own after the parameter, which is the last of the input.
+    After,
+}
+
+/// A `//` comment kept with [`MSDParserOptions::keep_comments`], see [`MSDParameter::comments`].
+/// 
+/// `S` is the type of the text: `String` for [`MSDParameter`], `&str` for
+/// [`MSDParameterRef`](crate::borrowed::MSDParameterRef).
+/// 
+/// [`MSDParserOptions::keep_comments`]: crate::parser::MSDParserOptions::keep_comments
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Comment<S = String> {
+    /// Text of the comment without the `//` and without the line break ending it.
+    pub text: S,
+    pub placement: CommentPlacement,
+}
+
+impl<S> Comment<S> {
+    pub fn new(text: S, placement: CommentPlacement) -> Self {
+        Self { text, placement }
+    }
+}
+
 /// An MSD parameter, comprised of a key and some values (usually one).
 /// 
 /// Stringifying an `MSDParameter` converts it back into MSD, escaping
 /// any backslashes `\\` or special substrings.
-#[derive(Debug, Clone, PartialEq, Hash, PartialOrd)]
+/// 
+/// Parameters compare, hash and order by their components only, so a parsed parameter
+/// equals one built by hand regardless of where it was found.
+/// 
+/// The struct is `#[non_exhaustive]` because the parser keeps growing the source information it records,
+/// like the comments, stray text and original key: build one with [`MSDParameter::new`] and set the public
+/// fields afterwards instead of writing a struct literal.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub struct MSDParameter {
     pub components: Vec<String>,
+    /// Byte range of the `#...;` block in the original input, if the parameter was parsed.
+    /// 
+    /// Without a closing `;`, the span ends where the next parameter starts or at the end of the input.
+    /// It is not updated when the parameter is edited.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub span: Option<Range<usize>>,
+    /// The `//` comments before the parameter, inside it and after it, in source order, each with its
+    /// [`CommentPlacement`].
+    /// 
+    /// The line break ending a comment is not part of its text. Inside a value it stays in the value, so dropping
+    /// or keeping comments never changes the line breaks of a value.
+    /// 
+    /// Only filled with [`MSDParserOptions::keep_comments`], and written back where they were by
+    /// [`MsdSerialize`](crate::serialize::MsdSerialize) unless [`MSDSerializeOptions::comments`] is unset.
+    /// A comment inside a value that no longer has a line break at its offset, e.g. after an edit, is written in
+    /// front of the parameter instead.
+    /// 
+    /// [`MSDParserOptions::keep_comments`]: crate::parser::MSDParserOptions::keep_comments
+    /// [`MSDSerializeOptions::comments`]: crate::serialize::MSDSerializeOptions::comments
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub comments: Vec<Comment>,
+    /// Text outside of parameters between the previous parameter and this one, from the first stray character
+    /// to the last, including any comments in between.
+    /// 
+    /// Only filled with [`MSDParserOptions::keep_stray_text`]. It is not written back when serializing.
+    /// 
+    /// [`MSDParserOptions::keep_stray_text`]: crate::parser::MSDParserOptions::keep_stray_text
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub stray_text: Option<String>,
+    /// The key as written in the source, if the parser normalized it. See [`MSDParameter::original_key`].
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    original_key: Option<String>,
+}
+
+impl PartialEq for MSDParameter {
+    fn eq(&self, other: &Self) -> bool {
+        self.components == other.components
+    }
+}
+
+impl Hash for MSDParameter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.components.hash(state);
+    }
+}
+
+impl PartialOrd for MSDParameter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.components.partial_cmp(&other.components)
+    }
 }
 
 impl MSDParameter {
     const MUST_ESCAPE: [&'static str; 3] = ["//", ":", ";"];
 
     pub fn new(components: Vec<String>) -> Self {
-        Self { components }
+        Self { components, span: None, comments: Vec::new(), stray_text: None, original_key: None }
+    }
+
+    /// A parameter without components, with room for `capacity` of them, for code that pushes components
+    /// one by one, like a chart with its six fields.
+    pub fn with_component_capacity(capacity: usize) -> Self {
+        Self::new(Vec::with_capacity(capacity))
+    }
+
+    /// The key as it was written in the source, before key normalization.
+    /// 
+    /// Equal to the current key unless [`MSDParserOptions::normalize_keys`] changed its spelling.
+    /// If the key has since been edited to something else, the edited key is returned.
+    /// 
+    /// [`MSDParserOptions::normalize_keys`]: crate::parser::MSDParserOptions::normalize_keys
+    pub fn original_key(&self) -> Option<&str> {
+        let key = self.components.first()?;
+        match &self.original_key {
+            Some(original) if original.trim().eq_ignore_ascii_case(key) => Some(original),
+            _ => Some(key),
+        }
+    }
+
+    /// Replace the key with `key`, remembering the previous spelling as the original key.
+    pub(crate) fn normalize_key(&mut self, key: String) {
+        if let Some(first) = self.components.first_mut() {
+            if *first != key {
+                let previous = std::mem::replace(first, key);
+                self.original_key.get_or_insert(previous);
+            }
+        }
     }
 
     /// The first MSD component, the part immediately after the `#` sign.
@@ -53,33 +210,81 @@ impl MSDParameter {
     /// 
     /// [`parse_msd`]: ../parser/fn.parse_msd.html
     pub fn key(&self) -> Option<String> {
-        self.components.get(0).map(|s| s.clone())
+        self.components.first().cloned()
     }
     
+    /// The key as a [`KnownTag`], or `None` if StepMania doesn't know it.
+    pub fn known_key(&self) -> Option<KnownTag> {
+        self.components.first()?.parse().ok()
+    }
+
     /// The second MSD component, seperated from the key by a `:`
     /// 
     /// Returns `None` if the parameter ends after the key with no `:`.
     /// This rarely happens in practice and is typically treated the same as a blank value.
     pub fn value(&self) -> Option<String> {
-        self.components.get(1).map(|s| s.clone())
+        self.components.get(1).cloned()
+    }
+
+    /// Byte offset of the `#` starting the parameter in the input, if it was parsed. The start of [`MSDParameter::span`].
+    /// 
+    /// Together with [`MSDParameter::byte_len`], this lets an indexer remember where e.g. `#NOTES` is and later read
+    /// just that region. Offsets count the bytes of the input as read, so they stay file offsets even around
+    /// invalid UTF-8.
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// # use msdparser::parse_msd;
+    /// let input = "#TITLE:Springtime;\n#NOTES:dance-single::Hard:9::0000;\n";
+    /// let notes = parse_msd(input.as_bytes(), true, false).map(Result::unwrap).nth(1).unwrap();
+    /// let (offset, length) = (notes.offset().unwrap(), notes.byte_len().unwrap());
+    /// 
+    /// let region = &input.as_bytes()[offset..offset + length];
+    /// assert_eq!(Some(notes), parse_msd(region, true, false).map(Result::unwrap).next());
+    /// ```
+    pub fn offset(&self) -> Option<usize> {
+        self.span.as_ref().map(|span| span.start)
+    }
+
+    /// Length in bytes of the parameter in the input, from the `#` through the `;`, if it was parsed.
+    /// See [`MSDParameter::offset`].
+    pub fn byte_len(&self) -> Option<usize> {
+        self.span.as_ref().map(|span| span.end - span.start)
+    }
+
+    /// Whether the value is there, empty, or missing along with its `:`.
+    /// 
+    /// A parameter without any components has a missing value too.
+    pub fn value_presence(&self) -> ValuePresence {
+        match self.components.get(1) {
+            Some(value) if value.is_empty() => ValuePresence::Empty,
+            Some(_) => ValuePresence::Present,
+            None => ValuePresence::Missing,
+        }
     }
 
     /// Serialize an MSD component (key or value).
     /// 
     /// By default, backslashes (`\\`) and special substrings (`:`, `;`, and `//`) are escaped.
-    /// Setting `escapes` to `false` will return the component unchanged, unless it contains a special substring,
-    /// in which case an error is returned.
+    /// [`EscapePolicy::StrictStepMania`] escapes `#` as well.
+    /// [`EscapePolicy::Unescaped`] (or `false`) will return the component unchanged, unless it contains a special
+    /// substring, in which case an error is returned.
     /// 
     /// # Errors
     /// 
-    /// Returns an error if `component` contains a special substring and `escapes` is false.
-    pub fn serialize_component(component: &str, escapes: bool) -> Result<String, MSDParameterError> {
-        if escapes {
+    /// Returns an error if `component` contains a special substring and `escapes` is [`EscapePolicy::Unescaped`].
+    pub fn serialize_component(component: &str, escapes: impl Into<EscapePolicy>) -> Result<String, MSDParameterError> {
+        let escapes = escapes.into();
+        if escapes.is_escaped() {
             // Escape all special characters
             // Handle double backslashes first to avoid double escaping
             let mut result = component.to_string().replace("\\", "\\\\");
             for &esc in Self::MUST_ESCAPE.iter() {
-                result = result.replace(&esc, &format!("\\{}", esc));
+                result = result.replace(esc, &format!("\\{}", esc));
+            }
+            if escapes == EscapePolicy::StrictStepMania {
+                result = result.replace('#', "\\#");
             }
             Ok(result)
         } else if Self::MUST_ESCAPE.iter().any(|&esc| component.contains(esc)) {
@@ -91,14 +296,13 @@ impl MSDParameter {
 
     /// Serialize the key/value pair to MSD, including the surrounding `#:;` characters.
     /// 
-    /// By default, backslashes (`\\`) and special substrings (`:`, `;`, and `//`) are escaped.
-    /// Setting `escapes` to `false` will return the component unchanged, unless it contains a special substring,
-    /// in which case an error is returned.
+    /// Components are escaped according to `escapes`, see [`MSDParameter::serialize_component`].
     /// 
     /// # Errors
     /// 
-    /// Returns an error if `component` contains a special substring and `escapes` is false.
-    pub fn serialize<W: Write>(&self, writer: &mut W, escapes: bool) -> Result<(), MSDParameterError> {
+    /// Returns an error if `component` contains a special substring and `escapes` is [`EscapePolicy::Unescaped`].
+    pub fn serialize<W: Write>(&self, writer: &mut W, escapes: impl Into<EscapePolicy>) -> Result<(), MSDParameterError> {
+        let escapes = escapes.into();
         writer.write_all(b"#")?;
         for (i, component) in self.components.iter().enumerate() {
             writer.write_all(Self::serialize_component(component, escapes)?.as_bytes())?;
@@ -116,8 +320,8 @@ impl MSDParameter {
     /// 
     /// # Errors
     /// 
-    /// Returns an error if `component` contains a special substring and `escapes` is false.
-    pub fn to_string_with_escapes(&self, escapes: bool) -> Result<String, MSDParameterError> {
+    /// Returns an error if `component` contains a special substring and `escapes` is [`EscapePolicy::Unescaped`].
+    pub fn to_string_with_escapes(&self, escapes: impl Into<EscapePolicy>) -> Result<String, MSDParameterError> {
         let mut output = Vec::new();
         self.serialize(&mut output, escapes)?;
         Ok(String::from_utf8_lossy(&output).to_string())
@@ -146,6 +350,14 @@ mod tests {
         assert_eq!(param.components[1], "value");
     }
 
+    #[test]
+    fn test_with_component_capacity() {
+        let param = MSDParameter::with_component_capacity(7);
+
+        assert!(param.components.is_empty());
+        assert!(param.components.capacity() >= 7);
+    }
+
     #[test]
     fn test_key_without_value() {
         let param = MSDParameter::new(vec!["key".to_string()]);
@@ -154,6 +366,28 @@ mod tests {
         assert!(param.value().is_none());
     }
 
+    #[test]
+    fn test_value_presence() {
+        let input = "#A;#B:;#C: ;#D::x;";
+        let parameters: Vec<MSDParameter> = crate::parse_msd(input.as_bytes(), true, false).map(Result::unwrap).collect();
+        let presence: Vec<ValuePresence> = parameters.iter().map(MSDParameter::value_presence).collect();
+
+        assert_eq!(vec![ValuePresence::Missing, ValuePresence::Empty, ValuePresence::Present, ValuePresence::Empty], presence);
+        assert_eq!(input, parameters.iter().map(MSDParameter::to_string).collect::<String>());
+    }
+
+    #[test]
+    fn test_offset_and_byte_len() {
+        let input = "\u{feff}#TITLE:実例;\n#NOTES:\n0000\n#BPMS:0=120;";
+        let parameters: Vec<MSDParameter> = crate::parse_msd(input.as_bytes(), true, false).map(Result::unwrap).collect();
+        let regions: Vec<&str> = parameters.iter()
+            .map(|p| &input[p.offset().unwrap()..p.offset().unwrap() + p.byte_len().unwrap()])
+            .collect();
+
+        assert_eq!(vec!["#TITLE:実例;", "#NOTES:\n0000\n", "#BPMS:0=120;"], regions);
+        assert_eq!((None, None), (MSDParameter::new(Vec::new()).offset(), MSDParameter::new(Vec::new()).byte_len()));
+    }
+
     #[test]
     fn test_str_with_escapes() {
         let param = MSDParameter::new(vec!["key".to_string(), "value".to_string()]);
@@ -190,4 +424,29 @@ mod tests {
         Ok(())
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_strict_step_mania_escapes() {
+        let param = MSDParameter::new(vec!["NOTES".to_string(), "a:b\n#c".to_string()]);
+        let serialized = param.to_string_with_escapes(EscapePolicy::StrictStepMania).unwrap();
+
+        assert_eq!("#NOTES:a\\:b\n\\#c;", serialized);
+        let parsed = crate::parse_msd(serialized.as_bytes(), EscapePolicy::StrictStepMania, false).next().unwrap().unwrap();
+        assert_eq!(param.components, parsed.components);
+    }
+
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut parameter = MSDParameter::new(vec!["TITLE".to_string(), "A:B".to_string()]);
+        parameter.span = Some(0..12);
+        let json = serde_json::to_string(&parameter).unwrap();
+
+        assert_eq!(r#"{"components":["TITLE","A:B"],"span":{"start":0,"end":12}}"#, json);
+        let decoded: MSDParameter = serde_json::from_str(&json).unwrap();
+        assert_eq!((parameter.components, parameter.span), (decoded.components, decoded.span));
+
+        let without_span: MSDParameter = serde_json::from_str(r#"{"components":["A"]}"#).unwrap();
+        assert_eq!(None, without_span.span);
+    }
+}
@@ -1,40 +1,548 @@
 use std::{error, fmt};
-use std::io::Read;
+use std::io::{self, Read};
+#[cfg(feature = "io")]
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
-use crate::lexer::{lex_msd, MSDLexer, MSDToken, MSDTokenMatch};
-use crate::parameter::MSDParameter;
+use crate::diagnostic::{report, DiagnosticSink, Severity, SharedSink};
+use crate::extension::MsdExtension;
+use crate::lexer::{
+    Dialect, EscapePolicy, MSDLexer, MSDLexerError, MSDToken, MSDTokenMatch, Position, ReadMode, ReadPolicy, RecoveryPolicy,
+    TryCloneRead, Unbuffered,
+};
+#[cfg(feature = "io")]
+use crate::lexer::Buffered;
+use crate::parameter::{Comment, CommentPlacement, MSDParameter};
 
 /// Custom error type for MSD parsing.
-#[derive(Debug, PartialEq, Clone, Hash, PartialOrd)]
-pub struct MSDParserError(pub String);
+/// 
+/// Match on the variant to find out what went wrong; the [`Display`](fmt::Display) output is meant for humans.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum MSDParserError {
+    /// Text other than whitespace was found outside of a parameter.
+    StrayText {
+        /// First non-whitespace character of the stray text.
+        ch: char,
+        /// Key of the parameter preceding the stray text, or `None` at the start of the document.
+        after_key: Option<String>,
+        /// Position of `ch`.
+        position: Position,
+    },
+    /// The lexer could not make sense of the remaining input, e.g. a trailing backslash.
+    LexerStuck {
+        text: String,
+        position: Position,
+    },
+    /// The input ends in a backslash, which has nothing left to escape.
+    /// 
+    /// Only returned while [`MSDParserOptions::lenient_escapes`] is off.
+    UnterminatedEscape {
+        /// Position of the backslash; its `offset` is the byte offset into the input.
+        position: Position,
+    },
+    /// The input is not valid UTF-8.
+    /// 
+    /// Only returned while [`MSDParserOptions::lossy_utf8`] is off. Parsing can go on after it, reading the invalid
+    /// sequence as U+FFFD.
+    InvalidUtf8 {
+        /// Position of the invalid sequence.
+        position: Position,
+    },
+    /// A parameter ended without its `;`, at the `#` of the next parameter or at the end of the input.
+    /// 
+    /// Only returned with [`MSDParserOptions::strict_semicolons`].
+    MissingSemicolon {
+        /// Key of the unterminated parameter, as written.
+        key: String,
+        /// Where the `;` was expected.
+        position: Position,
+    },
+    /// A registered [`MsdExtension`](crate::extension::MsdExtension) rejected a parameter.
+    Extension {
+        /// Name of the extension.
+        name: String,
+        message: String,
+    },
+    /// Reading from the underlying reader failed.
+    Io(#[cfg_attr(feature = "serde", serde(with = "crate::serde_io"))] io::Error),
+}
 
 impl fmt::Display for MSDParserError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "MSDParserError: {}", self.0)
+        match self {
+            MSDParserError::StrayText { ch, after_key: Some(key), position } => {
+                write!(f, "MSDParserError: stray '{}' encountered after '{}' parameter at {}", ch, key, position)
+            },
+            MSDParserError::StrayText { ch, after_key: None, position } => {
+                write!(f, "MSDParserError: stray '{}' encountered at start of document at {}", ch, position)
+            },
+            MSDParserError::LexerStuck { text, position } => {
+                write!(f, "MSDParserError: lexer stuck on {:?} at {}", text, position)
+            },
+            MSDParserError::UnterminatedEscape { position } => {
+                write!(f, "MSDParserError: unterminated escape at end of input at {}", position)
+            },
+            MSDParserError::InvalidUtf8 { position } => write!(f, "MSDParserError: invalid UTF-8 at {}", position),
+            MSDParserError::MissingSemicolon { key, position } => {
+                write!(f, "MSDParserError: missing ';' after '{}' parameter at {}", key, position)
+            },
+            MSDParserError::Extension { name, message } => write!(f, "MSDParserError: {}: {}", name, message),
+            MSDParserError::Io(e) => write!(f, "MSDParserError: IO Error: {}", e),
+        }
+    }
+}
+
+/// IO errors are cloned with their kind and message, which is all they are compared by.
+impl Clone for MSDParserError {
+    fn clone(&self) -> Self {
+        match self {
+            MSDParserError::StrayText { ch, after_key, position } => {
+                MSDParserError::StrayText { ch: *ch, after_key: after_key.clone(), position: *position }
+            },
+            MSDParserError::LexerStuck { text, position } => MSDParserError::LexerStuck { text: text.clone(), position: *position },
+            MSDParserError::UnterminatedEscape { position } => MSDParserError::UnterminatedEscape { position: *position },
+            MSDParserError::InvalidUtf8 { position } => MSDParserError::InvalidUtf8 { position: *position },
+            MSDParserError::MissingSemicolon { key, position } => MSDParserError::MissingSemicolon { key: key.clone(), position: *position },
+            MSDParserError::Extension { name, message } => MSDParserError::Extension { name: name.clone(), message: message.clone() },
+            MSDParserError::Io(e) => MSDParserError::Io(io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+}
+
+/// IO errors are equal if they are of the same kind and have the same message.
+impl PartialEq for MSDParserError {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                MSDParserError::StrayText { ch: a, after_key: a_key, position: a_position },
+                MSDParserError::StrayText { ch: b, after_key: b_key, position: b_position },
+            ) => a == b && a_key == b_key && a_position == b_position,
+            (
+                MSDParserError::LexerStuck { text: a, position: a_position },
+                MSDParserError::LexerStuck { text: b, position: b_position },
+            ) => a == b && a_position == b_position,
+            (MSDParserError::UnterminatedEscape { position: a }, MSDParserError::UnterminatedEscape { position: b }) => a == b,
+            (MSDParserError::InvalidUtf8 { position: a }, MSDParserError::InvalidUtf8 { position: b }) => a == b,
+            (
+                MSDParserError::MissingSemicolon { key: a, position: a_position },
+                MSDParserError::MissingSemicolon { key: b, position: b_position },
+            ) => a == b && a_position == b_position,
+            (
+                MSDParserError::Extension { name: a, message: a_message },
+                MSDParserError::Extension { name: b, message: b_message },
+            ) => a == b && a_message == b_message,
+            (MSDParserError::Io(a), MSDParserError::Io(b)) => a.kind() == b.kind() && a.to_string() == b.to_string(),
+            _ => false,
+        }
+    }
+}
+
+impl MSDParserError {
+    /// Where in the input the error occurred, if it is tied to a location.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            MSDParserError::StrayText { position, .. }
+            | MSDParserError::LexerStuck { position, .. }
+            | MSDParserError::UnterminatedEscape { position }
+            | MSDParserError::InvalidUtf8 { position }
+            | MSDParserError::MissingSemicolon { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
+}
+
+impl error::Error for MSDParserError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MSDParserError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MSDParserError {
+    fn from(e: io::Error) -> Self {
+        MSDParserError::Io(e)
+    }
+}
+
+impl From<MSDLexerError> for MSDParserError {
+    fn from(e: MSDLexerError) -> Self {
+        match e {
+            MSDLexerError::LexerStuck { text, position } => MSDParserError::LexerStuck { text, position },
+            MSDLexerError::UnterminatedEscape { position } => MSDParserError::UnterminatedEscape { position },
+            MSDLexerError::InvalidUtf8 { position } => MSDParserError::InvalidUtf8 { position },
+            MSDLexerError::Io(e) => MSDParserError::Io(e),
+        }
+    }
+}
+
+/// Something questionable the parser recovered from instead of returning an error.
+/// 
+/// Collected by [`MSDParser::warnings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum MSDParserWarning {
+    /// The input ends in a backslash, which was kept as a literal backslash.
+    /// See [`MSDParserOptions::lenient_escapes`].
+    UnterminatedEscape {
+        position: Position,
+    },
+    /// The input is not valid UTF-8 there, and the invalid sequence was replaced with U+FFFD.
+    /// See [`MSDParserOptions::lossy_utf8`].
+    InvalidUtf8 {
+        position: Position,
+    },
+}
+
+impl fmt::Display for MSDParserWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MSDParserWarning::UnterminatedEscape { position } => {
+                write!(f, "MSDParserWarning: unterminated escape at end of input at {}, kept as a backslash", position)
+            },
+            MSDParserWarning::InvalidUtf8 { position } => {
+                write!(f, "MSDParserWarning: invalid UTF-8 at {}, replaced with U+FFFD", position)
+            },
+        }
     }
 }
 
-impl error::Error for MSDParserError {}
+/// Settings for [`MSDParser`].
+/// 
+/// Built with chained setters starting from the defaults, which match the original Python library:
+/// 
+/// ```rust
+/// # use msdparser::{parse_msd_with_options, MSDParserOptions};
+/// let options = MSDParserOptions::new().ignore_stray_text(true);
+/// let mut parser = parse_msd_with_options(b"stray #A:B;".as_ref(), options);
+/// 
+/// assert_eq!(parser.next().unwrap().unwrap().value().unwrap(), "B");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct MSDParserOptions {
+    /// How backslashes are treated. Defaults to [`EscapePolicy::Escaped`].
+    pub escapes: EscapePolicy,
+    /// Whether or not to ignore text outside of parameters instead of returning an error. Defaults to `false`.
+    pub ignore_stray_text: bool,
+    /// Whether reads that would block pause the parser instead of returning an error. Defaults to `false`.
+    /// 
+    /// See [`MSDParser::with_resumable_reads`].
+    pub resumable_reads: bool,
+    /// Whether keys are trimmed and uppercased, so that lookups need not care about spelling. Defaults to `false`.
+    /// 
+    /// The key as written is kept, see [`MSDParameter::original_key`].
+    pub normalize_keys: bool,
+    /// How reads that return no data are handled. See [`ReadPolicy`].
+    pub read_policy: ReadPolicy,
+    /// Whether a backslash at the very end of the input is kept as a literal backslash, with a
+    /// [`MSDParserWarning::UnterminatedEscape`], instead of returning [`MSDParserError::UnterminatedEscape`].
+    /// Defaults to `false`.
+    pub lenient_escapes: bool,
+    /// Whether invalid UTF-8 is replaced with U+FFFD, with an [`MSDParserWarning::InvalidUtf8`], instead of
+    /// returning [`MSDParserError::InvalidUtf8`]. Defaults to `false`.
+    /// 
+    /// Turn it on to read files in legacy encodings such as Shift JIS without failing; the replaced text is lost.
+    pub lossy_utf8: bool,
+    /// Whether `//` comments are kept in [`MSDParameter::comments`] of the parameter they precede or are inside of.
+    /// Comments after the last parameter are dropped. Defaults to `false`.
+    /// 
+    /// Without it, the lexer skips comments inside parameters without copying them, so files with huge numbers
+    /// of comments cost no allocations for them.
+    pub keep_comments: bool,
+    /// Whether stray text is kept in [`MSDParameter::stray_text`] of the parameter that follows it instead of
+    /// returning an error, e.g. to archive damaged files without losing any of their contents. Stray text after
+    /// the last parameter is only in [`MSDParser::suffix`]. Overrides `ignore_stray_text`. Defaults to `false`.
+    pub keep_stray_text: bool,
+    /// Whether a parameter ended by the next parameter or the end of the input instead of its `;` is an
+    /// [`MSDParserError::MissingSemicolon`] rather than recovered, e.g. to validate files instead of tolerating
+    /// them. Defaults to `false`.
+    pub strict_semicolons: bool,
+    /// When a `#` inside a parameter starts the next one. Defaults to [`RecoveryPolicy::LineStart`].
+    pub recovery: RecoveryPolicy,
+    /// Whose rules to follow where MSD readers disagree. Defaults to [`Dialect::Msd`].
+    /// 
+    /// Prefer the [`MSDParserOptions::dialect`] setter, which also sets the options the dialect implies.
+    pub dialect: Dialect,
+    /// Bytes to reserve at once for a component that outgrows its first run of text. Defaults to 0.
+    /// 
+    /// Set it to about the size of the largest value, e.g. a huge `#NOTES`, so that it is assembled without
+    /// repeated reallocation. Components that are a single run of text, which most short values are, never
+    /// reserve it.
+    pub expected_value_size: usize,
+}
+
+impl Default for MSDParserOptions {
+    fn default() -> Self {
+        Self {
+            escapes: EscapePolicy::Escaped,
+            ignore_stray_text: false,
+            resumable_reads: false,
+            normalize_keys: false,
+            read_policy: ReadPolicy::default(),
+            lenient_escapes: false,
+            lossy_utf8: false,
+            keep_comments: false,
+            keep_stray_text: false,
+            strict_semicolons: false,
+            recovery: RecoveryPolicy::LineStart,
+            dialect: Dialect::Msd,
+            expected_value_size: 0,
+        }
+    }
+}
+
+impl MSDParserOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The default options, in the [`Dialect`] StepMania reads the file at `path` with if it needs one of its own:
+    /// [`Dialect::Dwi`] for `.dwi` and [`Dialect::Ksf`] for `.ksf` files, compared ignoring ASCII case.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use msdparser::{Dialect, MSDParserOptions};
+    /// assert_eq!(Dialect::Dwi, MSDParserOptions::for_path("Songs/Pack/Song/song.DWI").dialect);
+    /// assert_eq!(Dialect::Msd, MSDParserOptions::for_path("song.sm").dialect);
+    /// ```
+    pub fn for_path<P: AsRef<Path>>(path: P) -> Self {
+        let extension = path.as_ref().extension().and_then(|e| e.to_str()).unwrap_or_default();
+        if extension.eq_ignore_ascii_case("dwi") {
+            Self::new().dialect(Dialect::Dwi)
+        } else if extension.eq_ignore_ascii_case("ksf") {
+            Self::new().dialect(Dialect::Ksf)
+        } else {
+            Self::new()
+        }
+    }
+
+    pub fn escapes(mut self, escapes: impl Into<EscapePolicy>) -> Self {
+        self.escapes = escapes.into();
+        self
+    }
+
+    pub fn ignore_stray_text(mut self, ignore_stray_text: bool) -> Self {
+        self.ignore_stray_text = ignore_stray_text;
+        self
+    }
+
+    pub fn resumable_reads(mut self, resumable_reads: bool) -> Self {
+        self.resumable_reads = resumable_reads;
+        self
+    }
+
+    pub fn normalize_keys(mut self, normalize_keys: bool) -> Self {
+        self.normalize_keys = normalize_keys;
+        self
+    }
+
+    pub fn read_policy(mut self, read_policy: ReadPolicy) -> Self {
+        self.read_policy = read_policy;
+        self
+    }
+
+    pub fn lenient_escapes(mut self, lenient_escapes: bool) -> Self {
+        self.lenient_escapes = lenient_escapes;
+        self
+    }
+
+    pub fn lossy_utf8(mut self, lossy_utf8: bool) -> Self {
+        self.lossy_utf8 = lossy_utf8;
+        self
+    }
+
+    pub fn keep_comments(mut self, keep_comments: bool) -> Self {
+        self.keep_comments = keep_comments;
+        self
+    }
+
+    pub fn keep_stray_text(mut self, keep_stray_text: bool) -> Self {
+        self.keep_stray_text = keep_stray_text;
+        self
+    }
+
+    pub fn strict_semicolons(mut self, strict_semicolons: bool) -> Self {
+        self.strict_semicolons = strict_semicolons;
+        self
+    }
+
+    pub fn recovery(mut self, recovery: RecoveryPolicy) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    pub fn expected_value_size(mut self, expected_value_size: usize) -> Self {
+        self.expected_value_size = expected_value_size;
+        self
+    }
+
+    /// Follow the rules of `dialect`, also setting the options it implies, see [`Dialect`].
+    /// 
+    /// Setters chained after this one can still override those options.
+    /// 
+    /// ```rust
+    /// # use msdparser::{parse_msd_with_options, Dialect, MSDParserOptions};
+    /// let options = MSDParserOptions::new().dialect(Dialect::StepMania);
+    /// let values: Vec<_> = parse_msd_with_options(b"#A:B  \n  #C:D;".as_ref(), options)
+    ///     .map(|parameter| parameter.unwrap().value().unwrap().to_string())
+    ///     .collect();
+    /// 
+    /// assert_eq!(vec!["B", "D"], values);
+    /// ```
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        if dialect.is_msd_file() {
+            self.escapes = if dialect == Dialect::StepMania { EscapePolicy::Escaped } else { EscapePolicy::Unescaped };
+            self.ignore_stray_text = true;
+            self.recovery = RecoveryPolicy::FirstOnLine;
+        }
+        self
+    }
+}
+
+/// Whether a parameter with `key` is the step data of a KSF file, which has no `;`, see [`Dialect::Ksf`].
+pub(crate) fn is_ksf_step(key: &str, dialect: Dialect) -> bool {
+    dialect == Dialect::Ksf && key.trim().eq_ignore_ascii_case("STEP")
+}
+
+/// Where KSF step data ends: the index of the component holding its terminating row of `2`s and that component's
+/// length up to the end of the row. Everything after it, later components included, is not step data.
+/// `None` if there is no such row, in which case all of it is.
+pub(crate) fn ksf_step_cut<S: AsRef<str>>(components: &[S]) -> Option<(usize, usize)> {
+    components.iter().enumerate().skip(1).find_map(|(i, component)| {
+        let mut start = 0;
+        for line in component.as_ref().split_inclusive(['\r', '\n']) {
+            let row = line.trim();
+            if !row.is_empty() && row.bytes().all(|b| b == b'2') {
+                return Some((i, start + line.trim_end().len()));
+            }
+            start += line.len();
+        }
+        None
+    })
+}
+
+/// Text of a comment token as kept in [`MSDParameter::comments`]: without the `//`, and without the `\r` that
+/// comments swallow in the dialects of StepMania, so writing it back with a line ending adds no line break.
+pub(crate) fn comment_text(token_text: &str) -> &str {
+    token_text[2..].strip_suffix('\r').unwrap_or(&token_text[2..])
+}
+
+/// Reserve `expected_value_size` bytes in `component` before `additional` more would make it reallocate,
+/// unless it is still empty.
+pub(crate) fn reserve_for(component: &mut String, additional: usize, expected_value_size: usize) {
+    if !component.is_empty() && component.capacity() - component.len() < additional {
+        component.reserve(expected_value_size.saturating_sub(component.len()).max(additional));
+    }
+}
+
+/// Length `dialect` cuts the last component of a parameter to when the next `#` ends it instead of its `;`.
+pub(crate) fn recovered_len(component: &str, dialect: Dialect) -> usize {
+    if dialect.is_msd_file() {
+        component.trim_end_matches([' ', '\t', '\r', '\n']).len()
+    } else {
+        component.len()
+    }
+}
+
+/// Error for text found outside of a parameter, or None if the text is only whitespace or a BOM.
+/// 
+/// `position` is where `text` starts.
+pub(crate) fn stray_text_error(text: &str, last_key: Option<&str>, position: Position) -> Option<MSDParserError> {
+    let start = stray_text_range(text)?.start;
+    let ch = text[start..].chars().next()?;
+    let mut position = position;
+    position.advance(&text[..start]);
+    Some(MSDParserError::StrayText { ch, after_key: last_key.map(str::to_string), position })
+}
+
+/// The message reporting the stray text in `text` in diagnostics mode and where it starts, if there is any.
+pub(crate) fn stray_text_diagnostic(text: &str, last_key: Option<&str>, position: Position) -> Option<(Position, String)> {
+    let Some(MSDParserError::StrayText { ch, position, .. }) = stray_text_error(text, None, position) else { return None };
+    let message = match last_key {
+        Some(key) => format!("stray '{}' encountered after '{}' parameter", ch, key),
+        None => format!("stray '{}' encountered at start of document", ch),
+    };
+    Some((position, message))
+}
+
+/// Range of `text` without surrounding whitespace, or None if the text is only whitespace or a BOM.
+pub(crate) fn stray_text_range(text: &str) -> Option<Range<usize>> {
+    if text.trim().is_empty() || text == "\u{feff}" {
+        return None;
+    }
+    Some(text.len() - text.trim_start().len()..text.trim_end().len())
+}
 
 /// Parser for MSD data.
 /// 
 /// Implements the [`Iterator`] trait of type [`Result<MSDParameter, MSDParserError>`].
-#[derive(Debug, Clone)]
-pub struct MSDParser<R> {
-    ignored_stray_text: bool,
+/// 
+/// Parsers over in-memory readers can be duplicated with [`MSDParser::try_clone`].
+/// 
+/// # Memory
+/// 
+/// Parameters are read from the stream as they are yielded, so parsing an input of any size holds at most the
+/// parameter being built and the lexer's buffer, see [`MSDLexer`]. Peak memory is proportional to the largest
+/// parameter, not to the input. This is a guarantee, checked by `tests/streaming.rs`.
+/// 
+/// `M` is the lexer's [`ReadMode`]; see [`MSDParser::from_buf_read`] for readers that already buffer their input.
+#[derive(Debug)]
+pub struct MSDParser<R, M = Unbuffered> {
+    options: MSDParserOptions,
 
     components: Vec<String>,
+    /// Comments kept for the next parameter to be finished
+    comments: Vec<Comment>,
+    /// With [`MSDParserOptions::keep_comments`], the last finished parameter, held back until the next one starts
+    /// or the input ends so that the comments after it can be attached
+    held: Option<MSDParameter>,
+    /// Whether a line break followed the held parameter, after which comments belong to the next one
+    held_line_ended: bool,
+    /// Error found while a parameter was held, returned after it
+    deferred_error: Option<MSDParserError>,
+    /// Text before the first parameter, once it has started
+    preamble: String,
+    /// Text outside of parameters since the last one ended, or since the start
+    outside: String,
+    /// Whether a parameter has started yet
+    started: bool,
+    /// Range of `outside` from the first to the last stray text, with [`MSDParserOptions::keep_stray_text`]
+    stray: Option<Range<usize>>,
+    /// Stray text before the current parameter
+    stray_text: Option<String>,
     inside_parameter: bool,
+    /// Byte offset of the `#` starting the current parameter
+    parameter_start: usize,
+    /// Position of the `#` starting the current parameter
+    parameter_position: Position,
+    /// Position of the `#` starting the last finished parameter, which is the next one to be yielded
+    finished_position: Position,
     last_key: Option<String>,
-    tokens: MSDLexer<R>,
+    parameters_emitted: usize,
+    /// Whether a read failed, after which nothing more is yielded
+    failed: bool,
+    warnings: Vec<MSDParserWarning>,
+    tokens: MSDLexer<R, M>,
+    extensions: Vec<Arc<dyn MsdExtension>>,
+    diagnostics: Option<SharedSink>,
 }
 
-impl <R: Read> fmt::Display for MSDParser<R> {
+impl<R, M> fmt::Display for MSDParser<R, M> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f, 
             "MSDParser: {{\n\tignored_stray_text: {},\n\tcomponents: {:?},\n\tinside_parameter: {},\n\tlast_key: {:?},\n}}", 
-            self.ignored_stray_text,
+            self.options.ignore_stray_text,
             self.components,
             self.inside_parameter,
             self.last_key
@@ -45,30 +553,294 @@ impl <R: Read> fmt::Display for MSDParser<R> {
 impl <R: Read> MSDParser<R> {
     /// Create a new parser from a reader.
     /// 
-    /// `escapes` indicates whether or not to escape special text.
+    /// `escapes` is the [`EscapePolicy`], or a `bool` for whether or not to escape special text.
     /// `ignore_stray_text` indicates whether or not to ignore stray text.
-    pub fn new(reader: R, escapes: bool, ignore_stray_text: bool) -> Self {
+    pub fn new(reader: R, escapes: impl Into<EscapePolicy>, ignore_stray_text: bool) -> Self {
+        Self::with_options(reader, MSDParserOptions::new().escapes(escapes).ignore_stray_text(ignore_stray_text))
+    }
+
+    /// Create a new parser from a reader and [`MSDParserOptions`].
+    pub fn with_options(reader: R, options: MSDParserOptions) -> Self {
+        Self::from_lexer(MSDLexer::new(reader, options.escapes), options)
+    }
+}
+
+#[cfg(feature = "io")]
+impl<R: BufRead> MSDParser<R, Buffered> {
+    /// Create a new parser that decodes directly from the internal buffer of a [`BufRead`] instance,
+    /// such as a `BufReader<File>`, instead of copying every chunk into a buffer of its own.
+    pub fn from_buf_read(reader: R, options: MSDParserOptions) -> Self {
+        Self::from_lexer(MSDLexer::from_buf_read(reader, options.escapes), options)
+    }
+}
+
+impl<R, M: ReadMode<R>> MSDParser<R, M> {
+    fn from_lexer(tokens: MSDLexer<R, M>, options: MSDParserOptions) -> Self {
         Self {
-            ignored_stray_text: ignore_stray_text,
+            options,
 
             components: Vec::new(),
+            comments: Vec::new(),
+            held: None,
+            held_line_ended: false,
+            deferred_error: None,
+            preamble: String::new(),
+            outside: String::new(),
+            started: false,
+            stray: None,
+            stray_text: None,
             inside_parameter: false,
+            parameter_start: 0,
+            parameter_position: Position::start(),
+            finished_position: Position::start(),
             last_key: None,
+            parameters_emitted: 0,
+            failed: false,
+            warnings: Vec::new(),
             
-            tokens: {lex_msd(reader, escapes)},
+            tokens: tokens
+                .with_resumable_reads(options.resumable_reads)
+                .with_read_policy(options.read_policy)
+                .with_recovery(options.recovery)
+                .with_dialect(options.dialect)
+                .with_comments_skipped(!options.keep_comments),
+            extensions: Vec::new(),
+            diagnostics: None,
         }
     }
 
+    /// Register an [`MsdExtension`] that validates keys and post-processes every parameter.
+    /// 
+    /// If the extension overrides the dialect's escape handling, it applies to the rest of the stream.
+    /// 
+    /// [`MsdExtension`]: ../extension/trait.MsdExtension.html
+    pub fn with_extension<E: MsdExtension + 'static>(mut self, extension: E) -> Self {
+        if let Some(escapes) = extension.escapes() {
+            self.options.escapes = escapes;
+            self.tokens.set_escapes(escapes);
+        }
+        self.extensions.push(Arc::new(extension));
+        self
+    }
+
+    /// Set whether reads failing with [`io::ErrorKind::WouldBlock`] or [`io::ErrorKind::TimedOut`]
+    /// pause the parser instead of returning an error.
+    /// 
+    /// When paused, [`MSDParser::next_parameter`] returns `None` after yielding every complete parameter,
+    /// and a later call resumes where it left off. This allows parsing from nonblocking sockets.
+    /// 
+    /// [`io::ErrorKind::WouldBlock`]: std::io::ErrorKind::WouldBlock
+    /// [`io::ErrorKind::TimedOut`]: std::io::ErrorKind::TimedOut
+    pub fn with_resumable_reads(mut self, resumable: bool) -> Self {
+        self.options.resumable_reads = resumable;
+        self.tokens = self.tokens.with_resumable_reads(resumable);
+        self
+    }
+
+    /// Switch to diagnostics mode, where the parser recovers from every problem it can and reports it to `sink`
+    /// instead of stopping, e.g. to validate thousands of simfiles in one pass.
+    /// 
+    /// Stray text is skipped and reported as an [`Severity::Error`], even with
+    /// [`MSDParserOptions::ignore_stray_text`], unless [`MSDParserOptions::keep_stray_text`] is set.
+    /// A backslash at the end of the input is kept as with [`MSDParserOptions::lenient_escapes`] and
+    /// a missing `;` is reported as a [`Severity::Warning`].
+    /// A parameter rejected by an [extension](MSDParser::with_extension) is reported as a [`Severity::Error`]
+    /// and left out. Errors that leave nothing to recover, like read failures, are still returned.
+    /// 
+    /// The sink is shared, so the same one can collect the diagnostics of many parsers.
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// # use std::sync::{Arc, Mutex};
+    /// # use msdparser::parse_msd;
+    /// # use msdparser::diagnostic::Severity;
+    /// let sink = Arc::new(Mutex::new(Vec::new()));
+    /// let parser = parse_msd(b"#A:B\n#C:D; stray\n#E:F;".as_ref(), true, false).with_diagnostics(sink.clone());
+    /// 
+    /// assert_eq!(3, parser.map(Result::unwrap).count());
+    /// let severities: Vec<_> = sink.lock().unwrap().iter().map(|d| d.severity).collect();
+    /// assert_eq!(vec![Severity::Warning, Severity::Error], severities);
+    /// ```
+    pub fn with_diagnostics<S: DiagnosticSink + 'static>(mut self, sink: Arc<Mutex<S>>) -> Self {
+        self.diagnostics = Some(sink);
+        self
+    }
+
+    fn diagnose(&self, severity: Severity, position: Position, message: String) {
+        report(self.diagnostics.as_ref(), severity, position, message);
+    }
+
+    /// Recover `parameter`, which was ended by something other than its `;` at `position`, unless
+    /// [`MSDParserOptions::strict_semicolons`] is set outside of diagnostics mode.
+    fn unterminated(&self, parameter: MSDParameter, position: Position) -> Result<MSDParameter, MSDParserError> {
+        // KSF step data has no `;` and was already cut at its end by finish_parameter
+        if is_ksf_step(parameter.original_key().unwrap_or_default(), self.options.dialect) {
+            return Ok(parameter);
+        }
+        let key = parameter.original_key().unwrap_or_default();
+        if self.diagnostics.is_some() {
+            let severity = if self.options.strict_semicolons { Severity::Error } else { Severity::Warning };
+            self.diagnose(severity, position, format!("missing ';' after '{}' parameter", key));
+        } else if self.options.strict_semicolons {
+            return Err(MSDParserError::MissingSemicolon { key: key.to_string(), position });
+        }
+        Ok(parameter)
+    }
+
+    /// Whether the whole stream has been parsed.
+    /// 
+    /// Only relevant with resumable reads, where `None` may also mean that the reader would block.
+    pub fn is_done(&self) -> bool {
+        self.failed || (self.tokens.is_done() && !self.inside_parameter && self.held.is_none() && self.deferred_error.is_none())
+    }
+
+    /// The options this parser was created with.
+    pub fn options(&self) -> &MSDParserOptions {
+        &self.options
+    }
+
+    /// Key of the last parameter that was completed, before extensions were applied.
+    /// 
+    /// Useful for error messages like "after #STEPSTYPE" in wrapping libraries.
+    pub fn last_key(&self) -> Option<&str> {
+        self.last_key.as_deref()
+    }
+
+    /// Number of parameters yielded so far. Parameters dropped by an extension are not counted.
+    pub fn parameters_emitted(&self) -> usize {
+        self.parameters_emitted
+    }
+
+    /// Everything the parser recovered from so far, in the order it was found.
+    pub fn warnings(&self) -> &[MSDParserWarning] {
+        &self.warnings
+    }
+
+    /// Text before the first parameter, such as a byte order mark, whitespace, comments and, with
+    /// [`MSDParserOptions::ignore_stray_text`], stray text. Until a parameter starts, everything read so far.
+    pub fn preamble(&self) -> &str {
+        if self.started { &self.preamble } else { &self.outside }
+    }
+
+    /// Text after the `;` of the last parameter, complete once the parser is done.
+    /// Empty if there are no parameters, since all the text is then the [`MSDParser::preamble`].
+    pub fn suffix(&self) -> &str {
+        if self.started && !self.inside_parameter { &self.outside } else { "" }
+    }
+
+    /// Whether the parser is in the middle of a parameter, i.e. has read a `#` but not its `;` yet.
+    pub fn inside_parameter(&self) -> bool {
+        self.inside_parameter
+    }
+
+    /// Mutable access to the underlying reader.
+    /// 
+    /// Reading from it directly skips that input, since the parser never sees it.
+    pub fn get_mut(&mut self) -> &mut R {
+        self.tokens.get_mut()
+    }
+
     /// Get the next [`MSDParameter`] from the stream. 
     /// 
     /// [`MSDParameter`]: ../parameter/struct.MSDParameter.html
     /// 
     /// # Errors
     /// 
-    /// Returns an error if a stray text token is encountered and `ignore_stray_text` is `false`.
+    /// Returns an error if a stray text token is encountered and `ignore_stray_text` is `false`,
+    /// if the input ends in a backslash without [`MSDParserOptions::lenient_escapes`],
+    /// if a `;` is missing with [`MSDParserOptions::strict_semicolons`],
+    /// if the lexer cannot make sense of the remaining input, if a registered extension rejects a parameter,
+    /// or if reading fails. After a read error the parser is done: the parameter cut off by the failed read is
+    /// dropped rather than yielded as if the input ended there.
     pub fn next_parameter(&mut self) -> Option<Result<MSDParameter, MSDParserError>> {
-        while let Some(MSDTokenMatch { token, text }) = self.tokens.next() {
-            // println!("{} {}", token, text);
+        if self.failed {
+            return None;
+        }
+        loop {
+            let parameter = match self.next_raw_parameter()? {
+                Ok(parameter) => parameter,
+                Err(e) => {
+                    self.failed = matches!(e, MSDParserError::Io(_));
+                    return Some(Err(e));
+                },
+            };
+
+            match self.apply_extensions(parameter) {
+                Ok(Some(parameter)) => {
+                    self.parameters_emitted += 1;
+                    return Some(Ok(parameter));
+                },
+                Ok(None) => continue,
+                Err(e) if self.diagnostics.is_some() => {
+                    let message = match e {
+                        MSDParserError::Extension { name, message } => format!("{}: {}", name, message),
+                        e => e.to_string(),
+                    };
+                    self.diagnose(Severity::Error, self.finished_position, message);
+                },
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    fn apply_extensions(&self, parameter: MSDParameter) -> Result<Option<MSDParameter>, MSDParserError> {
+        let mut parameter = parameter;
+        for extension in &self.extensions {
+            if let Some(key) = parameter.key() {
+                extension.validate_key(&key)
+                    .map_err(|message| MSDParserError::Extension { name: extension.name().to_string(), message })?;
+            }
+
+            parameter = match extension.process(parameter)? {
+                Some(parameter) => parameter,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(parameter))
+    }
+
+    /// The next parameter as parsed, once the comments after it are known.
+    fn next_raw_parameter(&mut self) -> Option<Result<MSDParameter, MSDParserError>> {
+        if let Some(e) = self.deferred_error.take() {
+            return Some(Err(e));
+        }
+        match self.read_parameter() {
+            Some(Err(e)) => match self.held.take() {
+                Some(held) => {
+                    self.deferred_error = Some(e);
+                    Some(Ok(held))
+                },
+                None => Some(Err(e)),
+            },
+            None if self.tokens.is_done() => {
+                let mut held = self.held.take()?;
+                held.comments.extend(self.comments.drain(..).map(|comment| Comment::new(comment.text, CommentPlacement::After)));
+                Some(Ok(held))
+            },
+            result => result,
+        }
+    }
+
+    fn read_parameter(&mut self) -> Option<Result<MSDParameter, MSDParserError>> {
+        loop {
+            let position = self.tokens.position();
+            let Some(token_match) = self.tokens.next_token() else { break };
+            let MSDTokenMatch { token, text, span } = match token_match {
+                Ok(token_match) => token_match,
+                Err(MSDLexerError::UnterminatedEscape { position }) if self.options.lenient_escapes || self.diagnostics.is_some() => {
+                    self.warnings.push(MSDParserWarning::UnterminatedEscape { position });
+                    self.diagnose(Severity::Warning, position, "unterminated escape at end of input, kept as a backslash".to_string());
+                    MSDTokenMatch { token: MSDToken::Text, text: "\\".to_string(), span: position.offset..position.offset + 1 }
+                },
+                Err(MSDLexerError::InvalidUtf8 { position }) if self.options.lossy_utf8 || self.diagnostics.is_some() => {
+                    self.warnings.push(MSDParserWarning::InvalidUtf8 { position });
+                    self.diagnose(Severity::Warning, position, "invalid UTF-8, replaced with U+FFFD".to_string());
+                    continue;
+                },
+                Err(e) => return Some(Err(e.into())),
+            };
+
             match token {
                 MSDToken::Text | MSDToken::Escape => {
                     let escaped_text = if token == MSDToken::Escape {
@@ -79,70 +851,159 @@ impl <R: Read> MSDParser<R> {
 
                     if self.inside_parameter {
                         if let Some(last_component) = self.components.last_mut() {
+                            reserve_for(last_component, escaped_text.len(), self.options.expected_value_size);
                             last_component.push_str(&escaped_text);
                         }
-                    } else if !self.ignored_stray_text {
-                        if !text.trim().is_empty() && text != "\u{feff}" {
-                            let at_location = if let Some(key) = &self.last_key {
-                                format!("after '{}' parameter", key)
-                            } else {
-                                "at start of document".to_string()
-                            };
-
-                            if let Some(first_char) = text.trim_start().chars().next() {
-                                return Some(
-                                    Err(MSDParserError(format!("stray '{}' encountered {}", first_char, at_location)))
-                                );
-                            } else {
-                                // Unreachable?
-                                return Some(Err(MSDParserError(format!("stray text {} encountered {}", text, at_location))));
+                    } else {
+                        if self.options.keep_stray_text {
+                            if let Some(range) = stray_text_range(&text) {
+                                let start = self.stray.as_ref().map_or(self.outside.len() + range.start, |stray| stray.start);
+                                self.stray = Some(start..self.outside.len() + range.end);
+                            }
+                        } else if self.diagnostics.is_some() {
+                            if let Some((position, message)) = stray_text_diagnostic(&text, self.last_key.as_deref(), position) {
+                                self.diagnose(Severity::Error, position, message);
+                            }
+                        } else if !self.options.ignore_stray_text {
+                            if let Some(e) = stray_text_error(&text, self.last_key.as_deref(), position) {
+                                return Some(Err(e));
                             }
                         }
+                        self.held_line_ended |= text.contains(['\n', '\r']);
+                        self.outside.push_str(&text);
                     }
                 },
                 MSDToken::StartParameter => {
-                    if self.inside_parameter {
-                        let parameter = MSDParameter::new(self.components.drain(..).collect());
-
-                        self.last_key = parameter.key();
-
-                        self.inside_parameter = true;
-                        self.components.push(String::new());
-                        return Some(Ok(parameter));
+                    if let (true, Some(last)) = (self.inside_parameter, self.components.last_mut()) {
+                        last.truncate(recovered_len(last, self.options.dialect));
+                    }
+                    let parameter = self.inside_parameter.then(|| self.finish_parameter(span.start));
+                    self.stray_text = self.stray.take().map(|stray| self.outside[stray].to_owned());
+                    if !self.started {
+                        self.preamble = std::mem::take(&mut self.outside);
+                        self.started = true;
                     }
+                    self.outside.clear();
 
                     self.inside_parameter = true;
+                    self.parameter_start = span.start;
+                    self.parameter_position = position;
                     self.components.push(String::new());
+                    if let Some(parameter) = parameter {
+                        return Some(self.unterminated(parameter, position));
+                    }
+                    if let Some(held) = self.held.take() {
+                        return Some(Ok(held));
+                    }
                 },
                 MSDToken::EndParameter => if self.inside_parameter {
-                    let parameter = MSDParameter::new(self.components.drain(..).collect());
-
-                    self.last_key = parameter.key();
                     self.inside_parameter = false;
-                    return Some(Ok(parameter));
+                    let parameter = self.finish_parameter(span.end);
+                    if !self.options.keep_comments {
+                        return Some(Ok(parameter));
+                    }
+                    self.held = Some(parameter);
+                    self.held_line_ended = false;
                 },
                 MSDToken::NextComponent => if self.inside_parameter {
                     self.inside_parameter = true;
                     self.components.push(String::new());
                 },
-                MSDToken::Comment => {},
-                // _ => Err(MSDParserError(format!("Unexpected token: {:?}", token)))?
+                MSDToken::Comment => {
+                    if self.options.keep_comments {
+                        let comment = comment_text(&text).to_owned();
+                        match (&mut self.held, self.inside_parameter) {
+                            (_, true) => {
+                                let component = self.components.len().saturating_sub(1);
+                                let offset = self.components.last().map_or(0, String::len);
+                                self.comments.push(Comment::new(comment, CommentPlacement::Inside { component, offset }));
+                            },
+                            (Some(held), false) if !self.held_line_ended => held.comments.push(Comment::new(comment, CommentPlacement::SameLine)),
+                            _ => self.comments.push(Comment::new(comment, CommentPlacement::Before)),
+                        }
+                    }
+                    if !self.inside_parameter {
+                        self.held_line_ended |= text.ends_with('\r');
+                        self.outside.push_str(&text);
+                    }
+                },
             }
-        };
+        }
+
+        // The reader would block, more input may follow
+        if !self.tokens.is_done() {
+            return None;
+        }
 
         // Handle missing `;` at the end of the input
         if self.inside_parameter {
-            let parameter = MSDParameter::new(self.components.drain(..).collect());
-            self.last_key = parameter.key();
             self.inside_parameter = false;
-            return Some(Ok(parameter));
+            let parameter = self.finish_parameter(self.tokens.position().offset);
+            return Some(self.unterminated(parameter, self.tokens.position()));
         }
 
         None
     }
+
+    /// Build a parameter from the collected components, ending at byte offset `end`.
+    fn finish_parameter(&mut self, end: usize) -> MSDParameter {
+        let mut components: Vec<String> = self.components.drain(..).collect();
+        if is_ksf_step(components.first().map_or("", String::as_str), self.options.dialect) {
+            if let Some((component, length)) = ksf_step_cut(&components) {
+                components.truncate(component + 1);
+                components[component].truncate(length);
+            }
+        }
+        let mut parameter = MSDParameter::new(components);
+        parameter.span = Some(self.parameter_start..end);
+        parameter.comments = std::mem::take(&mut self.comments);
+        parameter.stray_text = self.stray_text.take();
+        self.finished_position = self.parameter_position;
+        if self.options.normalize_keys {
+            if let Some(key) = parameter.components.first() {
+                parameter.normalize_key(key.trim().to_ascii_uppercase());
+            }
+        }
+        self.last_key = parameter.key();
+        parameter
+    }
+}
+
+impl<R: TryCloneRead, M: ReadMode<R> + Clone> MSDParser<R, M> {
+    /// Duplicate the parser at its current position, so that both copies yield the same remaining parameters.
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the reader cannot be duplicated.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            options: self.options,
+            components: self.components.clone(),
+            comments: self.comments.clone(),
+            held: self.held.clone(),
+            held_line_ended: self.held_line_ended,
+            deferred_error: self.deferred_error.clone(),
+            preamble: self.preamble.clone(),
+            outside: self.outside.clone(),
+            started: self.started,
+            stray: self.stray.clone(),
+            stray_text: self.stray_text.clone(),
+            inside_parameter: self.inside_parameter,
+            parameter_start: self.parameter_start,
+            parameter_position: self.parameter_position,
+            finished_position: self.finished_position,
+            last_key: self.last_key.clone(),
+            parameters_emitted: self.parameters_emitted,
+            failed: self.failed,
+            warnings: self.warnings.clone(),
+            tokens: self.tokens.try_clone()?,
+            extensions: self.extensions.clone(),
+            diagnostics: self.diagnostics.clone(),
+        })
+    }
 }
 
-impl <R: Read> Iterator for MSDParser<R> {
+impl<R, M: ReadMode<R>> Iterator for MSDParser<R, M> {
     type Item = Result<MSDParameter, MSDParserError>;
 
     /// Get the next parameter.
@@ -153,10 +1014,128 @@ impl <R: Read> Iterator for MSDParser<R> {
     }   
 }
 
+/// A [`Stream`] of the parameters of an [`MSDParser`], returned by [`MSDParser::into_stream`].
+/// 
+/// [`Stream`]: futures_core::Stream
+#[cfg(feature = "futures")]
+#[derive(Debug)]
+pub struct ParameterStream<R, M = Unbuffered> {
+    parser: MSDParser<R, M>,
+}
+
+#[cfg(feature = "futures")]
+impl<R, M> ParameterStream<R, M> {
+    /// The parser, e.g. for its [`MSDParser::warnings`].
+    pub fn get_ref(&self) -> &MSDParser<R, M> {
+        &self.parser
+    }
+
+    pub fn into_inner(self) -> MSDParser<R, M> {
+        self.parser
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<R, M: ReadMode<R>> futures_core::Stream for ParameterStream<R, M> where Self: Unpin {
+    type Item = Result<MSDParameter, MSDParserError>;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        std::task::Poll::Ready(self.get_mut().parser.next_parameter())
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<R, M: ReadMode<R>> MSDParser<R, M> {
+    /// Turn the parser into a [`Stream`] of parameters, so it can feed async pipelines built with `StreamExt`
+    /// combinators on any runtime, or none.
+    /// 
+    /// Every poll reads synchronously until a parameter is complete and is never pending, so use it with readers
+    /// that don't block for long, like files or data already in memory; for sockets, see `AsyncMSDParser`.
+    /// A parser with [`MSDParserOptions::resumable_reads`] ends the stream at its first pause.
+    /// 
+    /// [`Stream`]: futures_core::Stream
+    pub fn into_stream(self) -> ParameterStream<R, M> {
+        ParameterStream { parser: self }
+    }
+}
+
+/// Parse an MSD document from a reader with the given [`MSDParserOptions`].
+/// 
+/// Prefer this over [`parse_msd`] when the settings would otherwise be unreadable positional booleans.
+pub fn parse_msd_with_options<R: Read>(input: R, options: MSDParserOptions) -> MSDParser<R> {
+    MSDParser::with_options(input, options)
+}
+
+/// Parse an MSD document starting at byte `offset` of a seekable reader, e.g. to read only the `#NOTES` parameter
+/// whose [`MSDParameter::offset`] an index recorded earlier.
+/// 
+/// If `offset` is not the `#` of a parameter, parsing resynchronizes at the next `#` that starts a line, and the
+/// text in between is skipped without being reported as stray text. Spans and offsets of the parameters count
+/// from the start of the reader, while lines and columns of [`Position`]s count from where parsing started.
+/// 
+/// # Errors
+/// 
+/// Returns an error if seeking or reading while resynchronizing fails.
+/// 
+/// # Examples
+/// 
+/// ```rust
+/// # use std::io::Cursor;
+/// # use msdparser::{parse_msd, parse_msd_at, MSDParserOptions};
+/// let input = "#TITLE:Springtime;\n#ARTIST:Kommisar;\n#NOTES:dance-single::Hard:9::0000;\n";
+/// let offset = parse_msd(input.as_bytes(), true, false).map(Result::unwrap).nth(2).unwrap().offset().unwrap();
+/// 
+/// let mut parser = parse_msd_at(Cursor::new(input), offset as u64, MSDParserOptions::new()).unwrap();
+/// assert_eq!(Some("NOTES".to_string()), parser.next().unwrap().unwrap().key());
+/// 
+/// // Starting inside `#TITLE` skips ahead to `#ARTIST`
+/// let mut parser = parse_msd_at(Cursor::new(input), 3, MSDParserOptions::new()).unwrap();
+/// assert_eq!(Some("ARTIST".to_string()), parser.next().unwrap().unwrap().key());
+/// ```
+#[cfg(feature = "io")]
+pub fn parse_msd_at<R: Read + Seek>(reader: R, offset: u64, options: MSDParserOptions) -> io::Result<MSDParser<BufReader<R>, Buffered>> {
+    let mut reader = BufReader::new(reader);
+    reader.seek(SeekFrom::Start(offset))?;
+    let start = offset + skip_to_parameter(&mut reader)?;
+    let lexer = MSDLexer::from_buf_read(reader, options.escapes).with_start_offset(start as usize);
+    Ok(MSDParser::from_lexer(lexer, options))
+}
+
+/// Consume bytes up to the first `#` that is either the very next byte or starts a line, and return how many.
+#[cfg(feature = "io")]
+fn skip_to_parameter<R: BufRead>(reader: &mut R) -> io::Result<u64> {
+    let mut skipped = 0;
+    let mut at_line_start = true;
+    loop {
+        let buffer = reader.fill_buf()?;
+        let Some(&last) = buffer.last() else { return Ok(skipped) };
+        let found = buffer.iter().enumerate().position(|(i, &byte)| {
+            byte == b'#' && if i == 0 { at_line_start } else { matches!(buffer[i - 1], b'\n' | b'\r') }
+        });
+        let length = found.unwrap_or(buffer.len());
+        at_line_start = matches!(last, b'\n' | b'\r');
+        reader.consume(length);
+        skipped += length as u64;
+        if found.is_some() {
+            return Ok(skipped);
+        }
+    }
+}
+
+/// Parse an MSD document from a [`BufRead`] instance with the given [`MSDParserOptions`],
+/// decoding directly from the reader's own buffer.
+/// 
+/// See [`MSDParser::from_buf_read`].
+#[cfg(feature = "io")]
+pub fn parse_msd_buf_read<R: BufRead>(input: R, options: MSDParserOptions) -> MSDParser<R, Buffered> {
+    MSDParser::from_buf_read(input, options)
+}
+
 /// Parse an MSD document from a reader.
 /// 
-/// `escapes` indicates whether or not to escape special text.
+/// `escapes` is the [`EscapePolicy`], or a `bool` for whether or not to escape special text.
 /// `ignore_stray_text` indicates whether or not to ignore stray text.
+/// This is a shorthand for [`parse_msd_with_options`].
 /// 
 /// Returns an [`MSDParser`], which is an [`Iterator`] of type [`Result<MSDParameter, MSDParserError>`].
 /// 
@@ -213,22 +1192,71 @@ impl <R: Read> Iterator for MSDParser<R> {
 /// let mut parser = parse_msd(example_input.as_ref(), true, false);
 /// 
 /// assert_eq!(parser.next(), Some(Ok(MSDParameter::new(vec!["A".to_string(), "B".to_string()]))));
-/// assert_eq!(parser.next(), Some(Err(MSDParserError("stray 'C' encountered after 'A' parameter".to_string()))));
+/// let error = parser.next().unwrap().unwrap_err();
+/// assert!(matches!(&error, MSDParserError::StrayText { ch: 'C', .. }));
+/// assert_eq!(error.to_string(), "MSDParserError: stray 'C' encountered after 'A' parameter at line 2, column 1");
 /// #
 /// #   Ok(())
 /// # }
 /// ```
 /// 
-pub fn parse_msd<R: Read>(input: R, escapes: bool, ignore_stray_text: bool) -> MSDParser<R> {
-    MSDParser::new(input, escapes, ignore_stray_text)
+pub fn parse_msd<R: Read>(input: R, escapes: impl Into<EscapePolicy>, ignore_stray_text: bool) -> MSDParser<R> {
+    parse_msd_with_options(input, MSDParserOptions::new().escapes(escapes).ignore_stray_text(ignore_stray_text))
+}
+
+/// Parse an MSD document from a reader into `(key, value)` pairs, for one-off tools that don't need the full
+/// [`MSDParameter`] model. Uses the default [`MSDParserOptions`].
+/// 
+/// The value is every component after the key joined by `:`, i.e. the parameter as written without its `#KEY:`
+/// and `;` and with escapes resolved, and an empty string for a parameter without a `:`. An escaped `:` can't be
+/// told apart from a component separator this way; use [`parse_msd`] when that matters.
+/// 
+/// # Examples
+/// 
+/// ```rust
+/// # use msdparser::parse_msd_pairs;
+/// let input = b"#TITLE:Springtime;\n#NOTES:dance-single::Hard:9::0000;\n#EMPTY;";
+/// let pairs: Vec<(String, String)> = parse_msd_pairs(input.as_ref()).map(Result::unwrap).collect();
+/// 
+/// assert_eq!(("NOTES".to_string(), "dance-single::Hard:9::0000".to_string()), pairs[1]);
+/// assert_eq!(("EMPTY".to_string(), String::new()), pairs[2]);
+/// ```
+pub fn parse_msd_pairs<R: Read>(reader: R) -> impl Iterator<Item = Result<(String, String), MSDParserError>> {
+    parse_msd_with_options(reader, MSDParserOptions::new()).map(|parameter| {
+        let mut components = parameter?.components.into_iter();
+        let key = components.next().unwrap_or_default();
+        Ok((key, components.collect::<Vec<_>>().join(":")))
+    })
 }
 
 
 #[cfg(test)]
 mod tests {
+    use std::collections::VecDeque;
+    use std::ops::Range;
     use std::{fs, path::Path};
 
     use super::*;
+    use crate::diagnostic::Diagnostic;
+    use crate::serialize::{MSDSerializeOptions, MsdSerialize, RecordSeparator};
+
+    /// Reader yielding the given chunks, where `None` stands for a read that would block.
+    struct NonBlockingReader {
+        chunks: VecDeque<Option<&'static [u8]>>,
+    }
+
+    impl Read for NonBlockingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.chunks.pop_front() {
+                Some(Some(chunk)) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                },
+                Some(None) => Err(io::Error::from(io::ErrorKind::WouldBlock)),
+                None => Ok(0),
+            }
+        }
+    }
 
     fn get_next_parameter(parser: &mut MSDParser<&[u8]>) -> Option<MSDParameter> {
         parser.next().map(|p| p.unwrap_or(MSDParameter::new(Vec::new())))
@@ -320,6 +1348,18 @@ mod tests {
         assert_eq!(None, parser.next());
     }
 
+    #[test]
+    fn test_carriage_return_line_endings() {
+        let input = b"#A:B\rCD;#E:FGH\r#IJKL// comment\r#M:NOP;\r  oops";
+        let mut parser = parse_msd(input.as_ref(), true, false);
+
+        assert_eq!(MSDParameter::new(vec!["A".to_string(), "B\rCD".to_string()]), get_next_parameter(&mut parser).unwrap());
+        assert_eq!(MSDParameter::new(vec!["E".to_string(), "FGH\r".to_string()]), get_next_parameter(&mut parser).unwrap());
+        assert_eq!(MSDParameter::new(vec!["IJKL\r".to_string()]), get_next_parameter(&mut parser).unwrap());
+        assert_eq!(MSDParameter::new(vec!["M".to_string(), "NOP".to_string()]), get_next_parameter(&mut parser).unwrap());
+        assert_eq!(Some(Position { offset: 41, line: 5, column: 3 }), parser.next().unwrap().unwrap_err().position());
+    }
+
     #[test]
     fn test_missing_value_and_semicolon() {
         let input = b"#A\n#B\n#C\n";
@@ -334,7 +1374,7 @@ mod tests {
     #[test]
     fn test_unicode() {
         let input = "#TITLE:実例;\n#ARTIST:楽士;".as_bytes();
-        let mut parser = parse_msd(input.as_ref(), true, false);
+        let mut parser = parse_msd(input, true, false);
 
         assert_eq!(MSDParameter::new(vec!["TITLE".to_string(), "実例".to_string()]), get_next_parameter(&mut parser).unwrap());
         assert_eq!(MSDParameter::new(vec!["ARTIST".to_string(), "楽士".to_string()]), get_next_parameter(&mut parser).unwrap());
@@ -347,7 +1387,7 @@ mod tests {
         let mut parser = parse_msd(input.as_ref(), true, false);
 
         assert_eq!(MSDParameter::new(vec!["A".to_string(), "B".to_string()]), get_next_parameter(&mut parser).unwrap());
-        assert_eq!(MSDParserError("stray 'n' encountered after 'A' parameter".to_string()), parser.next().unwrap().unwrap_err());
+        assert_eq!(MSDParserError::StrayText { ch: 'n', after_key: Some("A".to_string()), position: Position { offset: 5, line: 1, column: 6 } }, parser.next().unwrap().unwrap_err());
     }
 
     #[test]
@@ -355,7 +1395,7 @@ mod tests {
         let input = b"TITLE:oops;";
         let mut parser = parse_msd(input.as_ref(), true, false);
 
-        assert_eq!(MSDParserError("stray 'T' encountered at start of document".to_string()), parser.next().unwrap().unwrap_err());
+        assert_eq!(MSDParserError::StrayText { ch: 'T', after_key: None, position: Position::start() }, parser.next().unwrap().unwrap_err());
     }
 
     #[test]
@@ -364,7 +1404,31 @@ mod tests {
         let mut parser = parse_msd(input.as_ref(), true, false);
 
         assert_eq!(MSDParameter::new(vec!["A".to_string(), "B".to_string()]), get_next_parameter(&mut parser).unwrap());
-        assert_eq!(MSDParserError("stray ';' encountered after 'A' parameter".to_string()), parser.next().unwrap().unwrap_err());
+        let error = parser.next().unwrap().unwrap_err();
+        assert_eq!(MSDParserError::StrayText { ch: ';', after_key: Some("A".to_string()), position: Position { offset: 5, line: 1, column: 6 } }, error);
+        assert_eq!("MSDParserError: stray ';' encountered after 'A' parameter at line 1, column 6", error.to_string());
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = "#A:B;\n#TITLE:実例;// c\n#C:D\n#E:F";
+        let spans: Vec<Option<Range<usize>>> = parse_msd(input.as_bytes(), true, false)
+            .map(|p| p.unwrap().span)
+            .collect();
+
+        assert_eq!(vec![Some(0..5), Some(6..20), Some(25..30), Some(30..34)], spans);
+        assert_eq!("#TITLE:実例;", &input[6..20]);
+    }
+
+    #[test]
+    fn test_stray_text_position() {
+        let input = "#TITLE:実例;\r\n  oops\n#A:B;".as_bytes();
+        let mut parser = parse_msd(input, true, false);
+        parser.next();
+
+        let error = parser.next().unwrap().unwrap_err();
+        assert_eq!(Some(Position { offset: 18, line: 2, column: 3 }), error.position());
+        assert_eq!("MSDParserError: stray 'o' encountered after 'TITLE' parameter at line 2, column 3", error.to_string());
     }
 
     #[test]
@@ -410,6 +1474,376 @@ mod tests {
         assert_eq!(MSDParameter::new(vec!["TITLE".to_string(), "Springtime".to_string()]), get_next_parameter(&mut parser).unwrap());
         assert_eq!(MSDParameter::new(vec!["SUBTITLE".to_string(), "".to_string()]), get_next_parameter(&mut parser).unwrap());
     }
-}
 
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_file_buf_read() {
+        let path = Path::new("testdata/Springtime.ssc");
+        let reader = io::BufReader::new(fs::File::open(path).unwrap());
+        let buffered: Vec<MSDParameter> = parse_msd_buf_read(reader, MSDParserOptions::new()).map(Result::unwrap).collect();
+        let input = fs::read(path).unwrap();
+        let unbuffered: Vec<MSDParameter> = parse_msd(input.as_slice(), true, false).map(Result::unwrap).collect();
+
+        assert_eq!(unbuffered, buffered);
+        assert_eq!(
+            unbuffered.iter().map(|p| p.span.clone()).collect::<Vec<_>>(),
+            buffered.iter().map(|p| p.span.clone()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_context_getters() {
+        let mut parser = parse_msd(b"#A:B;\n#NOTES:\n0000".as_ref(), true, false);
+        assert_eq!((None, 0, false), (parser.last_key(), parser.parameters_emitted(), parser.inside_parameter()));
+
+        parser.next().unwrap().unwrap();
+        assert_eq!((Some("A"), 1, false), (parser.last_key(), parser.parameters_emitted(), parser.inside_parameter()));
+
+        parser.next().unwrap().unwrap();
+        assert_eq!((Some("NOTES"), 2, false), (parser.last_key(), parser.parameters_emitted(), parser.inside_parameter()));
+        assert!(parser.next().is_none());
+    }
+
+    #[test]
+    fn test_inside_parameter_while_paused() {
+        let reader = NonBlockingReader { chunks: VecDeque::from([Some(b"#A:B;\n#C:\n".as_ref()), None, Some(b"D;".as_ref())]) };
+        let mut parser = parse_msd_with_options(reader, MSDParserOptions::new().resumable_reads(true));
+
+        assert_eq!(Some("A".to_string()), parser.next().unwrap().unwrap().key());
+        assert!(parser.next().is_none());
+        assert!(parser.inside_parameter());
+        assert_eq!(Some("C".to_string()), parser.next().unwrap().unwrap().key());
+        assert!(!parser.inside_parameter());
+    }
 
+    #[test]
+    fn test_normalize_keys() {
+        let options = MSDParserOptions::new().normalize_keys(true);
+        let parameters: Vec<MSDParameter> = parse_msd_with_options(b"#title:A;\n# Artist :B;\n#BPMS:0=120;".as_ref(), options)
+            .map(Result::unwrap)
+            .collect();
+
+        assert_eq!(vec![Some("TITLE"), Some("ARTIST"), Some("BPMS")], parameters.iter().map(|p| p.components.first().map(String::as_str)).collect::<Vec<_>>());
+        assert_eq!(vec![Some("title"), Some(" Artist "), Some("BPMS")], parameters.iter().map(MSDParameter::original_key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_trailing_backslash() {
+        let input = b"#A:B;#C:D\\";
+        let mut parser = parse_msd(input.as_ref(), true, false);
+
+        assert_eq!(MSDParameter::new(vec!["A".to_string(), "B".to_string()]), get_next_parameter(&mut parser).unwrap());
+        let error = parser.next().unwrap().unwrap_err();
+        assert_eq!(Some(Position { offset: 9, line: 1, column: 10 }), error.position());
+        assert_eq!("MSDParserError: unterminated escape at end of input at line 1, column 10", error.to_string());
+        assert_eq!(MSDParameter::new(vec!["C".to_string(), "D".to_string()]), get_next_parameter(&mut parser).unwrap());
+        assert_eq!(None, parser.next());
+    }
+
+    #[test]
+    fn test_resumable_reads() {
+        let reader = NonBlockingReader {
+            chunks: VecDeque::from(vec![Some(b"#A:B;\n#C:".as_ref()), None, Some(b"D;\n#E:F".as_ref()), None]),
+        };
+        let mut parser = MSDParser::new(reader, true, false).with_resumable_reads(true);
+
+        assert_eq!(Some(Ok(MSDParameter::new(vec!["A".to_string(), "B".to_string()]))), parser.next());
+        assert_eq!(None, parser.next());
+        assert!(!parser.is_done());
+        assert_eq!(Some(Ok(MSDParameter::new(vec!["C".to_string(), "D".to_string()]))), parser.next());
+        assert_eq!(None, parser.next());
+        assert!(!parser.is_done());
+        assert_eq!(Some(Ok(MSDParameter::new(vec!["E".to_string(), "F".to_string()]))), parser.next());
+        assert_eq!(None, parser.next());
+        assert!(parser.is_done());
+    }
+
+    #[test]
+    fn test_options() {
+        let input = b"#A\\:B;n#C:D;";
+        let options = MSDParserOptions::new().escapes(false).ignore_stray_text(true);
+        let mut parser = parse_msd_with_options(input.as_ref(), options);
+
+        assert_eq!(&options, parser.options());
+        assert_eq!(MSDParameter::new(vec!["A\\".to_string(), "B".to_string()]), get_next_parameter(&mut parser).unwrap());
+        assert_eq!(MSDParameter::new(vec!["C".to_string(), "D".to_string()]), get_next_parameter(&mut parser).unwrap());
+        assert_eq!(None, parser.next());
+    }
+
+    #[test]
+    fn test_unterminated_escape() {
+        let position = Position { offset: 8, line: 2, column: 3 };
+        let mut strict = parse_msd(b"#A:B;\n#C\\".as_ref(), true, false);
+        assert!(strict.next().unwrap().is_ok());
+        let error = strict.next().unwrap().unwrap_err();
+        assert_eq!(MSDParserError::UnterminatedEscape { position }, error);
+        assert_eq!(Some(8), error.position().map(|p| p.offset));
+
+        let options = MSDParserOptions::new().lenient_escapes(true);
+        let mut lenient = parse_msd_with_options(b"#A:B;\n#C\\".as_ref(), options);
+        assert_eq!(MSDParameter::new(vec!["A".to_string(), "B".to_string()]), get_next_parameter(&mut lenient).unwrap());
+        assert_eq!(MSDParameter::new(vec!["C\\".to_string()]), get_next_parameter(&mut lenient).unwrap());
+        assert_eq!(None, lenient.next());
+        assert_eq!(&[MSDParserWarning::UnterminatedEscape { position }], lenient.warnings());
+
+        let mut stray = parse_msd_with_options(b"#A:B; \\".as_ref(), options);
+        assert!(stray.next().unwrap().is_ok());
+        assert!(matches!(stray.next(), Some(Err(MSDParserError::StrayText { ch: '\\', .. }))));
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn test_stream() {
+        use futures_util::{FutureExt, StreamExt};
+
+        let stream = parse_msd(b"#A:1;\n#NOTES:x;\n#B:2;\n#NOTES:y;".as_ref(), true, false).into_stream();
+        let notes: Vec<String> = stream
+            .filter_map(|p| async move { p.ok().filter(|p| p.key().as_deref() == Some("NOTES")) })
+            .map(|p| p.value().unwrap())
+            .collect()
+            .now_or_never()
+            .unwrap();
+
+        assert_eq!(vec!["x".to_string(), "y".to_string()], notes);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let input = b"#A:B;\r\n#C:\xffD\xfe;".as_ref();
+        let first = Position { offset: 10, line: 2, column: 4 };
+        let mut strict = parse_msd(input, true, false);
+        assert!(strict.next().unwrap().is_ok());
+        assert_eq!(Some(Err(MSDParserError::InvalidUtf8 { position: first })), strict.next());
+        let second = Position { offset: 12, line: 2, column: 6 };
+        assert_eq!(Some(Err(MSDParserError::InvalidUtf8 { position: second })), strict.next());
+        let parameter = get_next_parameter(&mut strict).unwrap();
+        assert_eq!(vec!["C", "\u{fffd}D\u{fffd}"], parameter.components);
+        assert_eq!(Some(7..14), parameter.span);
+        assert_eq!(input.len(), parameter.span.unwrap().end);
+
+        let options = MSDParserOptions::new().lossy_utf8(true);
+        let mut lossy = parse_msd_with_options(input, options);
+        get_next_parameter(&mut lossy);
+        assert_eq!(vec!["C", "\u{fffd}D\u{fffd}"], get_next_parameter(&mut lossy).unwrap().components);
+        assert_eq!(None, lossy.next());
+        assert_eq!(&[MSDParserWarning::InvalidUtf8 { position: first }, MSDParserWarning::InvalidUtf8 { position: second }], lossy.warnings());
+    }
+
+    #[test]
+    fn test_keep_comments() {
+        let input = "// by me\n#TITLE:A; // trailing\n#NOTES:\n// easy\n0000\n;\n// end";
+        let options = MSDParserOptions::new().keep_comments(true);
+        let parameters: Vec<MSDParameter> = parse_msd_with_options(input.as_bytes(), options).map(Result::unwrap).collect();
+
+        assert_eq!(vec![
+            Comment::new(" by me".to_string(), CommentPlacement::Before),
+            Comment::new(" trailing".to_string(), CommentPlacement::SameLine),
+        ], parameters[0].comments);
+        assert_eq!(vec![
+            Comment::new(" easy".to_string(), CommentPlacement::Inside { component: 1, offset: 1 }),
+            Comment::new(" end".to_string(), CommentPlacement::After),
+        ], parameters[1].comments);
+        assert_eq!("\n\n0000\n", parameters[1].components[1]);
+        assert!(parse_msd(input.as_bytes(), true, false).all(|p| p.unwrap().comments.is_empty()));
+
+        let serialized = parameters.to_msd_string(&MSDSerializeOptions::new()).unwrap();
+        assert_eq!(format!("{}\n", input), serialized);
+        let reparsed: Vec<MSDParameter> = parse_msd_with_options(serialized.as_bytes(), options).map(Result::unwrap).collect();
+        assert_eq!(parameters[1].comments, reparsed[1].comments);
+
+        let separated = parameters.to_msd_string(&MSDSerializeOptions::new().separator(RecordSeparator::Nothing)).unwrap();
+        assert!(separated.contains("; // trailing\n#NOTES:"));
+    }
+
+    #[test]
+    fn test_edited_comment_value() {
+        let options = MSDParserOptions::new().keep_comments(true);
+        let mut parameter = parse_msd_with_options(b"#NOTES:0000// a\n1000;".as_ref(), options).next().unwrap().unwrap();
+        assert_eq!("#NOTES:0000// a\n1000;", parameter.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+
+        parameter.components[1] = "1111".to_string();
+        assert_eq!("// a\n#NOTES:1111;", parameter.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+    }
+
+    #[test]
+    fn test_held_parameter_before_error() {
+        let options = MSDParserOptions::new().keep_comments(true);
+        let mut parser = parse_msd_with_options(b"#A:B; // c\nstray".as_ref(), options);
+
+        assert_eq!(vec![Comment::new(" c".to_string(), CommentPlacement::SameLine)], parser.next().unwrap().unwrap().comments);
+        assert!(matches!(parser.next(), Some(Err(MSDParserError::StrayText { .. }))));
+        assert!(parser.next().is_none());
+    }
+
+    #[test]
+    fn test_expected_value_size() {
+        let input = format!("#TITLE:A;\n#NOTES:{};", "0000\n".repeat(2000));
+        let options = MSDParserOptions::new().expected_value_size(1 << 16);
+        let parameters: Vec<MSDParameter> = parse_msd_with_options(input.as_bytes(), options).map(Result::unwrap).collect();
+
+        assert!(parameters[0].components[1].capacity() < 1 << 16);
+        assert!(parameters[1].components[1].capacity() >= 1 << 16);
+    }
+
+    #[test]
+    fn test_comment_line_breaks() {
+        let input = "#NOTES:\r\n// easy\r\n0000// c\r1000\r\n;";
+        for dialect in [Dialect::Msd, Dialect::StepMania] {
+            for keep_comments in [false, true] {
+                let options = MSDParserOptions::new().dialect(dialect).keep_comments(keep_comments);
+                let parameter = parse_msd_with_options(input.as_bytes(), options).next().unwrap().unwrap();
+
+                let expected = if dialect == Dialect::Msd { "\r\n\r\n0000\r1000\r\n" } else { "\r\n\n0000\n" };
+                assert_eq!(expected, parameter.components[1]);
+                assert!(parameter.comments.iter().all(|comment| !comment.text.ends_with('\r')));
+            }
+        }
+    }
+
+    #[test]
+    fn test_keep_stray_text() {
+        let input = "\u{feff}#A:B;\n  damaged: text // note\n more\n#C:D;\n#E:F; trailing";
+        let options = MSDParserOptions::new().keep_stray_text(true);
+        let mut parser = parse_msd_with_options(input.as_bytes(), options);
+        let parameters: Vec<MSDParameter> = parser.by_ref().map(Result::unwrap).collect();
+
+        assert_eq!(None, parameters[0].stray_text);
+        assert_eq!(Some("damaged: text // note\n more"), parameters[1].stray_text.as_deref());
+        assert_eq!(None, parameters[2].stray_text);
+        assert_eq!(" trailing", parser.suffix());
+        assert!(parse_msd(input.as_bytes(), true, false).any(|p| p.is_err()));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let input = "x #A:B\n#C:D;\n;#E:F\\";
+        let parser = parse_msd_with_options(input.as_bytes(), MSDParserOptions::new().ignore_stray_text(true))
+            .with_diagnostics(sink.clone());
+
+        let values: Vec<String> = parser.map(|p| p.unwrap().value().unwrap().to_string()).collect();
+        assert_eq!(vec!["B\n", "D", "F\\"], values);
+        let messages: Vec<String> = sink.lock().unwrap().iter().map(Diagnostic::to_string).collect();
+        assert_eq!(vec![
+            "error at line 1, column 1: stray 'x' encountered at start of document",
+            "warning at line 2, column 1: missing ';' after 'A' parameter",
+            "error at line 3, column 1: stray ';' encountered after 'C' parameter",
+            "warning at line 3, column 6: unterminated escape at end of input, kept as a backslash",
+            "warning at line 3, column 7: missing ';' after 'E' parameter",
+        ], messages);
+    }
+
+    #[test]
+    fn test_strict_semicolons() {
+        let options = MSDParserOptions::new().strict_semicolons(true).normalize_keys(true);
+        let mut parser = parse_msd_with_options(b"#a:B\n#C:D;".as_ref(), options);
+
+        let error = parser.next().unwrap().unwrap_err();
+        assert_eq!(MSDParserError::MissingSemicolon { key: "a".to_string(), position: Position { offset: 5, line: 2, column: 1 } }, error);
+        assert_eq!("MSDParserError: missing ';' after 'a' parameter at line 2, column 1", error.to_string());
+        assert_eq!(Some("D".to_string()), parser.next().unwrap().unwrap().value());
+        assert!(parser.next().is_none());
+
+        let sink = Arc::new(Mutex::new(Vec::new()));
+        let parser = parse_msd_with_options(b"#A:B".as_ref(), options).with_diagnostics(sink.clone());
+        assert_eq!(1, parser.map(Result::unwrap).count());
+        assert_eq!(Severity::Error, sink.lock().unwrap()[0].severity);
+    }
+
+    #[test]
+    fn test_ksf_step() {
+        let options = MSDParserOptions::new().dialect(Dialect::Ksf).strict_semicolons(true);
+        let mut parser = parse_msd_with_options(b"#TICKCOUNT:4;\n#STEP:\n\\000\n2222\n0100\n".as_ref(), options);
+
+        assert_eq!(Some("4".to_string()), parser.next().unwrap().unwrap().value());
+        assert_eq!(Some("\n\\000\n2222".to_string()), parser.next().unwrap().unwrap().value());
+        assert!(parser.next().is_none());
+
+        for input in ["#STEP:\n0:0\n2222\n|T8|:x\n", "#STEP:\n0:0\n2222\n|T8|:x;\n#STEP:\n0:0\n2222;"] {
+            for parameter in parse_msd_with_options(input.as_bytes(), options) {
+                assert_eq!(vec!["STEP", "\n0", "0\n2222"], parameter.unwrap().components);
+            }
+        }
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_parse_msd_at() {
+        let input = "#A:B;\n#NOTES:\n0000\n;\n#C:D;";
+        let parameters: Vec<MSDParameter> = parse_msd(input.as_bytes(), true, false).map(Result::unwrap).collect();
+
+        for offset in 0..input.len() as u64 {
+            let parser = parse_msd_at(io::Cursor::new(input), offset, MSDParserOptions::new()).unwrap();
+            let tail: Vec<MSDParameter> = parser.map(Result::unwrap).collect();
+            let expected: Vec<&MSDParameter> = parameters.iter().filter(|p| p.offset().unwrap() as u64 >= offset).collect();
+
+            assert_eq!(expected, tail.iter().collect::<Vec<_>>(), "offset {}", offset);
+            assert!(tail.iter().zip(&expected).all(|(a, b)| a.span == b.span));
+        }
+        assert_eq!(0, parse_msd_at(io::Cursor::new(input), 100, MSDParserOptions::new()).unwrap().count());
+    }
+
+    #[test]
+    fn test_parse_msd_pairs() {
+        let mut pairs = parse_msd_pairs(b"#A:B\\:C:D;\nstray".as_ref());
+
+        assert_eq!(Some(Ok(("A".to_string(), "B:C:D".to_string()))), pairs.next());
+        assert!(matches!(pairs.next(), Some(Err(MSDParserError::StrayText { ch: 's', .. }))));
+    }
+
+    #[test]
+    fn test_try_clone() {
+        let input = b"#A:B;#C:D;#E:F;";
+        let mut parser = parse_msd(input.as_ref(), true, false);
+        parser.next();
+        let clone = parser.try_clone().unwrap();
+
+        let rest: Vec<Result<MSDParameter, MSDParserError>> = parser.collect();
+        let cloned_rest: Vec<Result<MSDParameter, MSDParserError>> = clone.collect();
+        assert_eq!(2, rest.len());
+        assert_eq!(rest, cloned_rest);
+    }
+
+    struct FailingReader {
+        chunk: Option<&'static [u8]>,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.chunk.take() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                },
+                None => Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_io_error() {
+        let reader = FailingReader { chunk: Some(b"#A:B;\n#C:D") };
+        let mut parser = MSDParser::new(reader, true, false);
+
+        assert_eq!(Some(Ok(MSDParameter::new(vec!["A".to_string(), "B".to_string()]))), parser.next());
+        let error = parser.next().unwrap().unwrap_err();
+        assert!(matches!(&error, MSDParserError::Io(e) if e.kind() == io::ErrorKind::ConnectionReset));
+        assert_eq!("MSDParserError: IO Error: reset", error.to_string());
+        assert!(parser.is_done());
+        assert_eq!(None, parser.next());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_errors() {
+        let error = parse_msd(b"#A:B;\nC".as_ref(), true, false).find_map(Result::err).unwrap();
+        let decoded: MSDParserError = serde_json::from_str(&serde_json::to_string(&error).unwrap()).unwrap();
+        assert_eq!(error, decoded);
+
+        let error = MSDParserError::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(r#"{"Io":"reset"}"#, json);
+        let decoded: MSDParserError = serde_json::from_str(&json).unwrap();
+        assert_eq!("MSDParserError: IO Error: reset", decoded.to_string());
+    }
+}
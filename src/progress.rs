@@ -0,0 +1,89 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Receives progress from long operations such as pack scans and batch edits.
+///
+/// Implement it to drive a progress bar, a log line or a metrics counter; the crate itself
+/// depends on no UI library. Both methods do nothing by default, and `()` ignores all progress.
+///
+/// # Examples
+///
+/// ```rust
+/// # use std::path::Path;
+/// # use msdparser::progress::ProgressSink;
+/// struct Log {
+///     bytes: u64,
+/// }
+///
+/// impl ProgressSink for Log {
+///     fn on_file(&mut self, path: &Path) {
+///         eprintln!("reading {} ({} bytes so far)", path.display(), self.bytes);
+///     }
+///
+///     fn on_bytes(&mut self, bytes: u64) {
+///         self.bytes += bytes;
+///     }
+/// }
+/// ```
+pub trait ProgressSink {
+    /// Called before the file at `path` is read.
+    fn on_file(&mut self, path: &Path) {
+        let _ = path;
+    }
+
+    /// Called with the number of bytes read since the last call, not a running total.
+    fn on_bytes(&mut self, bytes: u64) {
+        let _ = bytes;
+    }
+}
+
+impl ProgressSink for () {}
+
+impl<S: ProgressSink + ?Sized> ProgressSink for &mut S {
+    fn on_file(&mut self, path: &Path) {
+        (**self).on_file(path);
+    }
+
+    fn on_bytes(&mut self, bytes: u64) {
+        (**self).on_bytes(bytes);
+    }
+}
+
+/// Reader that reports every successful read to a [`ProgressSink`].
+pub(crate) struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a mut dyn ProgressSink,
+}
+
+impl<'a, R: Read> ProgressReader<'a, R> {
+    pub(crate) fn new(inner: R, progress: &'a mut dyn ProgressSink) -> Self {
+        Self { inner, progress }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.progress.on_bytes(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Totals;
+
+    #[test]
+    fn test_progress_reader() {
+        let mut totals = Totals::default();
+        let mut contents = String::new();
+        ProgressReader::new(b"#TITLE:a;".as_ref(), &mut totals).read_to_string(&mut contents).unwrap();
+
+        assert_eq!("#TITLE:a;", contents);
+        assert_eq!(9, totals.bytes);
+        assert!(totals.files.is_empty());
+    }
+}
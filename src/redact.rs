@@ -0,0 +1,184 @@
+use crate::lexer::{lex_msd, MSDLexerError, MSDToken};
+
+/// Settings for [`redact`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct RedactOptions {
+    /// Keys whose values are redacted, compared ignoring ASCII case and surrounding whitespace.
+    /// Defaults to `CREDIT`.
+    pub keys: Vec<String>,
+    /// Whether email addresses in comments are redacted. Defaults to `true`.
+    pub emails_in_comments: bool,
+    /// Character written in place of every redacted byte. Defaults to `x`.
+    ///
+    /// Should be an ASCII character other than `#`, `:`, `;`, `/` and `\`, or the output no longer has
+    /// the same lengths and structure as the input.
+    pub placeholder: char,
+}
+
+impl Default for RedactOptions {
+    fn default() -> Self {
+        Self {
+            keys: vec!["CREDIT".to_string()],
+            emails_in_comments: true,
+            placeholder: 'x',
+        }
+    }
+}
+
+impl RedactOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn keys<I: IntoIterator<Item = S>, S: Into<String>>(mut self, keys: I) -> Self {
+        self.keys = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn emails_in_comments(mut self, emails_in_comments: bool) -> Self {
+        self.emails_in_comments = emails_in_comments;
+        self
+    }
+
+    pub fn placeholder(mut self, placeholder: char) -> Self {
+        self.placeholder = placeholder;
+        self
+    }
+
+    fn redacts_key(&self, key: &str) -> bool {
+        self.keys.iter().any(|k| k.trim().eq_ignore_ascii_case(key.trim()))
+    }
+}
+
+/// Replace personal information in `document` with placeholders, so a problem file can be shared in a bug report.
+///
+/// Every byte of a redacted value is replaced except whitespace, so the output parses into the same keys with
+/// values of the same lengths, and errors and spans point at the same offsets as in the original.
+/// Keys, comments and everything else are kept as they are, apart from email addresses in comments.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::redact::{redact, RedactOptions};
+/// let input = "// ask jane.doe@example.com\n#TITLE:Song;\n#CREDIT:Jane Doe;\n";
+///
+/// assert_eq!(
+///     "// ask xxxxxxxx@xxxxxxxxxxx\n#TITLE:Song;\n#CREDIT:xxxx xxx;\n",
+///     redact(input, &RedactOptions::new())
+/// );
+/// ```
+pub fn redact(document: &str, options: &RedactOptions) -> String {
+    let mut output = String::with_capacity(document.len());
+    let mut key = String::new();
+    let mut component = 0;
+    let mut redacting = false;
+
+    for token_match in lex_msd(document.as_bytes(), true) {
+        let token_match = match token_match {
+            Ok(token_match) => token_match,
+            // Unmatched text is kept, so the output fails to parse in the same way
+            Err(MSDLexerError::UnterminatedEscape { .. }) => {
+                output.push('\\');
+                continue;
+            },
+            Err(MSDLexerError::LexerStuck { text, .. }) => {
+                output.push_str(&text);
+                continue;
+            },
+            Err(MSDLexerError::InvalidUtf8 { .. } | MSDLexerError::Io(_)) => continue,
+        };
+        let text = token_match.text.as_str();
+
+        match token_match.token {
+            MSDToken::StartParameter | MSDToken::EndParameter => {
+                key.clear();
+                component = 0;
+                redacting = false;
+                output.push_str(text);
+            },
+            MSDToken::NextComponent => {
+                component += 1;
+                redacting = options.redacts_key(&key);
+                output.push_str(text);
+            },
+            MSDToken::Text | MSDToken::Escape if component == 0 => {
+                key.push_str(text);
+                output.push_str(text);
+            },
+            MSDToken::Text | MSDToken::Escape if redacting => push_placeholders(&mut output, text, options.placeholder),
+            MSDToken::Comment if options.emails_in_comments => redact_emails(&mut output, text, options.placeholder),
+            _ => output.push_str(text),
+        }
+    }
+
+    output
+}
+
+/// Push `text` with every byte that is not whitespace replaced by `placeholder`.
+fn push_placeholders(output: &mut String, text: &str, placeholder: char) {
+    for c in text.chars() {
+        if c.is_whitespace() {
+            output.push(c);
+        } else {
+            output.extend(std::iter::repeat_n(placeholder, c.len_utf8()));
+        }
+    }
+}
+
+/// Push `text` with everything but the `@` of email addresses replaced by `placeholder`.
+fn redact_emails(output: &mut String, text: &str, placeholder: char) {
+    let is_local = |c: char| c.is_ascii_alphanumeric() || "._%+-".contains(c);
+    let is_domain = |c: char| c.is_ascii_alphanumeric() || ".-".contains(c);
+    let mut rest = 0;
+
+    for (at, _) in text.match_indices('@') {
+        if at < rest {
+            continue;
+        }
+        let start = text[..at].rfind(|c| !is_local(c)).map_or(0, |i| i + 1).max(rest);
+        let end = text[at + 1..].find(|c| !is_domain(c)).map_or(text.len(), |i| at + 1 + i);
+        let domain = text[at + 1..end].trim_end_matches('.');
+        if start == at || !domain.contains('.') {
+            continue;
+        }
+        let end = at + 1 + domain.len();
+
+        output.push_str(&text[rest..start]);
+        push_placeholders(output, &text[start..at], placeholder);
+        output.push('@');
+        push_placeholders(output, &text[at + 1..end], placeholder);
+        rest = end;
+    }
+
+    output.push_str(&text[rest..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_msd;
+
+    #[test]
+    fn test_redact_keys() {
+        let input = "#credit :Jäne\\;Doe:two;\n#ARTIST:A;#CREDIT\n#NOTES:x;\n#ARTIST:Me: Myself;";
+        let options = RedactOptions::new().keys(["CREDIT", "artist"]).placeholder('_');
+        let output = redact(input, &options);
+
+        assert_eq!("#credit :__________:___;\n#ARTIST:_;#CREDIT\n#NOTES:x;\n#ARTIST:__: ______;", output);
+        assert_eq!(input.len(), output.len());
+        let spans = |text: &str| parse_msd(text.as_bytes(), true, false).map(|p| p.unwrap().span).collect::<Vec<_>>();
+        assert_eq!(spans(input), spans(&output));
+    }
+
+    #[test]
+    fn test_redact_emails() {
+        let options = RedactOptions::new();
+        assert_eq!(
+            "#A:a@b.c;// x@xxxxxx, @handle, me@local, x@xxxx.",
+            redact("#A:a@b.c;// a@bbb.cc, @handle, me@local, z@q.io.", &options)
+        );
+        assert_eq!("// a@b.cd", redact("// a@b.cd", &options.emails_in_comments(false)));
+    }
+}
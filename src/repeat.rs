@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::{error, fmt};
+
+use crate::key_map::KeyMap;
+use crate::parameter::MSDParameter;
+
+/// What to do when a key appears more than once in a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum RepeatPolicy {
+    /// Reject the document.
+    Error,
+    /// Keep every occurrence as its own parameter, like the parser yields them.
+    ///
+    /// When deserializing with [`crate::de::from_parameters_with_policies`], every occurrence is collected
+    /// into a sequence instead.
+    #[default]
+    KeepAll,
+    /// Keep the first occurrence and drop the repeats.
+    KeepFirst,
+    /// Keep the last occurrence, in place of the first so that the order of the other keys is unaffected.
+    KeepLast,
+    /// Append the value of every repeat to the first occurrence, separated by a comma,
+    /// which is how engines read repeated `#BGCHANGES`.
+    ///
+    /// An occurrence with more than one value is rejected, since only its first value would fit in the list.
+    ConcatenateWithComma,
+}
+
+/// Why a repeated key was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RepeatErrorReason {
+    /// The policy of the key is [`RepeatPolicy::Error`].
+    Repeated,
+    /// The policy of the key is [`RepeatPolicy::ConcatenateWithComma`], but the first occurrence or the repeat has
+    /// more than one value, which a comma-separated list can't hold without dropping the rest.
+    MultipleValues,
+}
+
+/// A repeated key that could not be resolved.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RepeatedKeyError {
+    pub key: String,
+    /// Index of the first occurrence.
+    pub first: usize,
+    /// Index of the repeat.
+    pub repeat: usize,
+    /// Byte range of the repeat in the source, if it was parsed.
+    pub span: Option<Range<usize>>,
+    pub reason: RepeatErrorReason,
+}
+
+impl fmt::Display for RepeatedKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            RepeatErrorReason::Repeated => {
+                write!(f, "repeated key '{}' at parameter {} (first seen at parameter {})", self.key, self.repeat, self.first)
+            },
+            RepeatErrorReason::MultipleValues => write!(
+                f,
+                "can't concatenate repeated key '{}' at parameter {} with parameter {}: more than one value",
+                self.key, self.repeat, self.first
+            ),
+        }
+    }
+}
+
+impl error::Error for RepeatedKeyError {}
+
+/// Per-key [`RepeatPolicy`]s, with a fallback for every other key.
+///
+/// Keys are compared ignoring ASCII case and surrounding whitespace.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::parse_msd;
+/// # use msdparser::repeat::{RepeatPolicies, RepeatPolicy};
+/// let input = b"#BGCHANGES:0=a.png;\n#NOTES:1;\n#BGCHANGES:4=b.png;\n#NOTES:2;";
+/// let parameters = parse_msd(input.as_ref(), true, false).collect::<Result<Vec<_>, _>>().unwrap();
+///
+/// let policies = RepeatPolicies::new().with_key("BGCHANGES", RepeatPolicy::ConcatenateWithComma);
+/// let document = policies.apply(parameters).unwrap();
+///
+/// assert_eq!(3, document.len());
+/// assert_eq!(Some("0=a.png,4=b.png".to_string()), document[0].value());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct RepeatPolicies {
+    /// Policy for keys without their own entry. Defaults to [`RepeatPolicy::KeepAll`].
+    pub default: RepeatPolicy,
+    /// Policies by key. Set with [`RepeatPolicies::with_key`].
+    keys: KeyMap<RepeatPolicy>,
+}
+
+impl RepeatPolicies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_default(mut self, policy: RepeatPolicy) -> Self {
+        self.default = policy;
+        self
+    }
+
+    pub fn with_key(mut self, key: &str, policy: RepeatPolicy) -> Self {
+        self.keys.insert(key, policy);
+        self
+    }
+
+    /// The policies set for single keys.
+    pub fn keys(&self) -> &KeyMap<RepeatPolicy> {
+        &self.keys
+    }
+
+    /// The policy that applies to `key`.
+    pub fn policy_for(&self, key: &str) -> RepeatPolicy {
+        self.keys.get(key).copied().unwrap_or(self.default)
+    }
+
+    /// Build a document from parsed parameters, resolving repeated keys.
+    ///
+    /// Order is preserved; concatenated values end up on the first occurrence of their key.
+    /// Parameters without a key are always kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first repeat of a key whose policy is [`RepeatPolicy::Error`], or the first repeat that
+    /// [`RepeatPolicy::ConcatenateWithComma`] can't join without losing values.
+    pub fn apply(&self, parameters: Vec<MSDParameter>) -> Result<Vec<MSDParameter>, RepeatedKeyError> {
+        let mut document: Vec<MSDParameter> = Vec::with_capacity(parameters.len());
+        // Index in `parameters` and in `document` of the first occurrence of each key
+        let mut seen: HashMap<String, (usize, usize)> = HashMap::new();
+
+        for (i, parameter) in parameters.into_iter().enumerate() {
+            let Some(key) = parameter.components.first().map(|k| k.trim().to_ascii_uppercase()) else {
+                document.push(parameter);
+                continue;
+            };
+            let Some(&(first, target)) = seen.get(&key) else {
+                seen.insert(key, (i, document.len()));
+                document.push(parameter);
+                continue;
+            };
+
+            let error = |reason| RepeatedKeyError {
+                key: parameter.components[0].clone(),
+                first,
+                repeat: i,
+                span: parameter.span.clone(),
+                reason,
+            };
+            match self.policy_for(&key) {
+                RepeatPolicy::Error => return Err(error(RepeatErrorReason::Repeated)),
+                RepeatPolicy::KeepAll => document.push(parameter),
+                RepeatPolicy::KeepFirst => {},
+                RepeatPolicy::KeepLast => document[target] = parameter,
+                RepeatPolicy::ConcatenateWithComma => {
+                    if document[target].components.len() > 2 || parameter.components.len() > 2 {
+                        return Err(error(RepeatErrorReason::MultipleValues));
+                    }
+                    concatenate_value(&mut document[target], parameter);
+                },
+            }
+        }
+
+        Ok(document)
+    }
+}
+
+/// Append the value of `repeat` to the value of `target`. Blank values are skipped rather than leaving stray commas.
+///
+/// Both must have at most one value, which [`RepeatPolicies::apply`] checks.
+fn concatenate_value(target: &mut MSDParameter, repeat: MSDParameter) {
+    let Some(value) = repeat.components.into_iter().nth(1) else { return };
+    if value.trim().is_empty() {
+        return;
+    }
+
+    match target.components.get_mut(1) {
+        Some(existing) if !existing.trim().is_empty() => {
+            existing.push(',');
+            existing.push_str(&value);
+        },
+        Some(existing) => *existing = value,
+        None => target.components.push(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_msd;
+
+    fn parse(input: &str) -> Vec<MSDParameter> {
+        parse_msd(input.as_bytes(), true, false).map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_policies() {
+        let input = "#TITLE:A;#bgchanges:;#BGCHANGES:0=a;#BGChanges:4=b;#NOTES:1;#NOTES:2;";
+
+        let keep_all = RepeatPolicies::new().apply(parse(input)).unwrap();
+        assert_eq!(6, keep_all.len());
+
+        let concatenated = RepeatPolicies::new()
+            .with_key("BGCHANGES", RepeatPolicy::ConcatenateWithComma)
+            .apply(parse(input))
+            .unwrap();
+        assert_eq!(4, concatenated.len());
+        assert_eq!(vec!["bgchanges".to_string(), "0=a,4=b".to_string()], concatenated[1].components);
+        assert_eq!(Some("NOTES".to_string()), concatenated[3].key());
+    }
+
+    #[test]
+    fn test_keep_first_and_last() {
+        let input = "#BPMS:0=120;#TITLE:A;#bpms:0=60;#TITLE:B;#BPMS:0=90;";
+        let policies = RepeatPolicies::new()
+            .with_default(RepeatPolicy::KeepFirst)
+            .with_key("BPMS", RepeatPolicy::KeepLast);
+
+        let document = policies.apply(parse(input)).unwrap();
+        assert_eq!(vec![
+            vec!["BPMS".to_string(), "0=90".to_string()],
+            vec!["TITLE".to_string(), "A".to_string()],
+        ], document.into_iter().map(|p| p.components).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_error_policy() {
+        let policies = RepeatPolicies::new()
+            .with_default(RepeatPolicy::Error)
+            .with_key("notes", RepeatPolicy::KeepAll);
+
+        assert!(policies.apply(parse("#NOTES:1;#NOTES:2;#TITLE:A;")).is_ok());
+        assert_eq!(Err(RepeatedKeyError {
+            key: "title".to_string(),
+            first: 0,
+            repeat: 2,
+            span: Some(18..27),
+            reason: RepeatErrorReason::Repeated,
+        }), policies.apply(parse("#TITLE:A;#NOTES:1;#title:B;")));
+    }
+
+    #[test]
+    fn test_concatenate_multiple_values() {
+        let policies = RepeatPolicies::new().with_key("bgchanges", RepeatPolicy::ConcatenateWithComma);
+
+        let error = policies.apply(parse("#BGCHANGES:0=a;#BGCHANGES:4=b:extra;")).unwrap_err();
+        assert_eq!((RepeatErrorReason::MultipleValues, 0, 1), (error.reason, error.first, error.repeat));
+        assert_eq!(
+            "can't concatenate repeated key 'BGCHANGES' at parameter 1 with parameter 0: more than one value",
+            error.to_string()
+        );
+        assert!(policies.apply(parse("#BGCHANGES:0=a:b;#BGCHANGES:4=c;")).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_keys() {
+        let policies: RepeatPolicies = serde_json::from_str(r#"{"keys": {"BgChanges": "concatenate-with-comma"}}"#).unwrap();
+        assert_eq!(RepeatPolicy::ConcatenateWithComma, policies.policy_for("BGCHANGES"));
+        assert_eq!(Some(&RepeatPolicy::ConcatenateWithComma), policies.keys().get("bgchanges"));
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::{error, fmt};
+
+use regex::Regex;
+
+use crate::document::MSDDocument;
+use crate::tags::KnownTag;
+
+/// Rules for one key of a [`Schema`].
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct KeyRule {
+    /// Whether the key must appear. Defaults to `false`.
+    pub required: bool,
+    /// Whether the key may appear more than once, like `#NOTES`. Defaults to `false`.
+    pub allow_duplicates: bool,
+    /// Fewest components the parameter may have, counting the key. Defaults to no limit.
+    pub min_components: Option<usize>,
+    /// Most components the parameter may have, counting the key. Defaults to no limit.
+    pub max_components: Option<usize>,
+    /// Regular expression the whole value must match, e.g. `-?[0-9]+(\.[0-9]+)?` for a number.
+    /// A missing value is matched as an empty string. Defaults to no pattern.
+    pub pattern: Option<String>,
+}
+
+impl KeyRule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
+    pub fn allow_duplicates(mut self, allow_duplicates: bool) -> Self {
+        self.allow_duplicates = allow_duplicates;
+        self
+    }
+
+    pub fn min_components(mut self, min_components: Option<usize>) -> Self {
+        self.min_components = min_components;
+        self
+    }
+
+    pub fn max_components(mut self, max_components: Option<usize>) -> Self {
+        self.max_components = max_components;
+        self
+    }
+
+    pub fn pattern(mut self, pattern: Option<&str>) -> Self {
+        self.pattern = pattern.map(str::to_string);
+        self
+    }
+}
+
+/// Structural rules for a document, usually loaded from a TOML or JSON file so that rules like
+/// pack submission requirements can be changed without touching code.
+///
+/// Keys are compared ignoring ASCII case and surrounding whitespace.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::{MSDDocument, MSDParserOptions};
+/// # use msdparser::schema::{validate, Schema, SchemaViolation};
+/// let schema: Schema = serde_json::from_str(r#"{
+///     "deny_unknown_keys": true,
+///     "keys": {
+///         "TITLE": { "required": true, "pattern": ".+" },
+///         "OFFSET": { "pattern": "-?[0-9]+(\\.[0-9]+)?" },
+///         "NOTES": { "allow_duplicates": true, "min_components": 7, "max_components": 7 }
+///     }
+/// }"#).unwrap();
+///
+/// let input = "#TITLE:;\n#OFFSET:abc;\n#CDTITLE:cd.png;\n";
+/// let document = MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap();
+/// let violations: Vec<_> = validate(&document, &schema).unwrap()
+///     .into_iter()
+///     .map(|diagnostic| diagnostic.violation)
+///     .collect();
+///
+/// assert_eq!(3, violations.len());
+/// assert_eq!(SchemaViolation::UnknownKey, violations[2]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Schema {
+    /// Whether keys without a rule are reported. Defaults to `false`.
+    pub deny_unknown_keys: bool,
+    /// Rules by key.
+    pub keys: BTreeMap<String, KeyRule>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn deny_unknown_keys(mut self, deny_unknown_keys: bool) -> Self {
+        self.deny_unknown_keys = deny_unknown_keys;
+        self
+    }
+
+    pub fn with_key(mut self, key: &str, rule: KeyRule) -> Self {
+        self.keys.insert(key.trim().to_ascii_uppercase(), rule);
+        self
+    }
+
+    /// The rule for `key` and the key as the schema spells it.
+    fn rule_for(&self, key: &str) -> Option<(&str, &KeyRule)> {
+        let key = key.trim();
+        self.keys.iter()
+            .find(|(rule_key, _)| rule_key.trim().eq_ignore_ascii_case(key))
+            .map(|(rule_key, rule)| (rule_key.as_str(), rule))
+    }
+}
+
+/// Pattern for a decimal number like `-0.090`, or a blank value, which StepMania reads as the default.
+pub const NUMBER_PATTERN: &str = r"\s*(-?([0-9]+(\.[0-9]*)?|\.[0-9]+))?\s*";
+
+/// Pattern for a comma-separated list of `beat=value` entries with one or more numeric values each, like `#BPMS`
+/// or `#TIMESIGNATURES`. Line breaks and a trailing comma are allowed, and so is a blank value.
+pub const BEAT_VALUES_PATTERN: &str =
+    r"\s*(-?[0-9.]+(\s*=\s*-?[0-9.]+)+(\s*,\s*-?[0-9.]+(\s*=\s*-?[0-9.]+)+)*\s*,?)?\s*";
+
+/// Pattern for `#DISPLAYBPM`: a BPM, `*` for a randomly changing display, or blank. A range puts the upper BPM in
+/// a third component.
+pub const DISPLAY_BPM_PATTERN: &str = r"\s*(\*|-?[0-9]+(\.[0-9]*)?|-?\.[0-9]+)?\s*";
+
+/// Song keys of `.sm` and `.ssc` files with free-form values.
+const TEXT_KEYS: &[KnownTag] = &[
+    KnownTag::Title, KnownTag::Subtitle, KnownTag::Artist, KnownTag::TitleTranslit, KnownTag::SubtitleTranslit,
+    KnownTag::ArtistTranslit, KnownTag::Genre, KnownTag::Credit, KnownTag::Banner, KnownTag::Background,
+    KnownTag::LyricsPath, KnownTag::CdTitle, KnownTag::Music, KnownTag::Selectable, KnownTag::BgChanges,
+    KnownTag::FgChanges, KnownTag::KeySounds, KnownTag::Attacks, KnownTag::InstrumentTrack,
+];
+
+/// Song keys of `.sm` and `.ssc` files with a number as value.
+const NUMBER_KEYS: &[KnownTag] = &[KnownTag::Offset, KnownTag::SampleStart, KnownTag::SampleLength];
+
+/// Keys of `.ssc` files only with free-form values.
+const SSC_TEXT_KEYS: &[KnownTag] = &[
+    KnownTag::Origin, KnownTag::PreviewVid, KnownTag::Jacket, KnownTag::CdImage, KnownTag::DiscImage,
+    KnownTag::Preview, KnownTag::ChartName, KnownTag::StepsType, KnownTag::Description, KnownTag::ChartStyle,
+    KnownTag::Difficulty, KnownTag::RadarValues, KnownTag::Labels, KnownTag::NoteData,
+];
+
+/// Keys of `.ssc` files only with a number as value.
+const SSC_NUMBER_KEYS: &[KnownTag] = &[KnownTag::Version, KnownTag::Meter, KnownTag::MusicLength, KnownTag::LastSecondHint];
+
+/// Timing keys with a `beat=value,...` list, the last five only in `.ssc` files.
+const BEAT_VALUES_KEYS: &[KnownTag] = &[
+    KnownTag::Bpms, KnownTag::Stops, KnownTag::Freezes, KnownTag::Delays, KnownTag::TimeSignatures,
+    KnownTag::TickCounts, KnownTag::Warps, KnownTag::Combos, KnownTag::Speeds, KnownTag::Scrolls, KnownTag::Fakes,
+];
+
+/// Keys every chart of an `.ssc` file may set again after its `#NOTEDATA`.
+const SSC_CHART_KEYS: &[KnownTag] = &[
+    KnownTag::NoteData, KnownTag::ChartName, KnownTag::StepsType, KnownTag::Description, KnownTag::ChartStyle,
+    KnownTag::Difficulty, KnownTag::Meter, KnownTag::RadarValues, KnownTag::Credit, KnownTag::Notes,
+    KnownTag::Notes2, KnownTag::DisplayBpm, KnownTag::Offset, KnownTag::Bpms, KnownTag::Stops, KnownTag::Delays,
+    KnownTag::Warps, KnownTag::TimeSignatures, KnownTag::TickCounts, KnownTag::Combos, KnownTag::Speeds,
+    KnownTag::Scrolls, KnownTag::Fakes, KnownTag::Labels, KnownTag::Attacks,
+];
+
+impl Schema {
+    /// The keys StepMania reads from `.sm` files, with the type of their values.
+    ///
+    /// Only `#BPMS`, without which StepMania can't time a song, is required, and only `#NOTES`, which holds one
+    /// chart each, may be duplicated. Unknown keys are allowed; set [`Schema::deny_unknown_keys`] to report them.
+    pub fn sm() -> Self {
+        let mut schema = Self::song_keys(&BEAT_VALUES_KEYS[..6])
+            .with_tag(KnownTag::Notes, KeyRule::new().allow_duplicates(true).min_components(Some(7)).max_components(Some(7)));
+        schema.rule_mut(KnownTag::Bpms).required = true;
+        schema
+    }
+
+    /// The keys StepMania reads from `.ssc` files, with the type of their values.
+    ///
+    /// Only `#BPMS` is required. The keys a chart can override after its `#NOTEDATA`, like `#METER` or `#BPMS`,
+    /// may be duplicated. Unknown keys are allowed; set [`Schema::deny_unknown_keys`] to report them.
+    pub fn ssc() -> Self {
+        let mut schema = Self::song_keys(BEAT_VALUES_KEYS);
+        for &tag in SSC_TEXT_KEYS {
+            schema = schema.with_tag(tag, KeyRule::new());
+        }
+        for &tag in SSC_NUMBER_KEYS {
+            schema = schema.with_tag(tag, KeyRule::new().pattern(Some(NUMBER_PATTERN)));
+        }
+        schema = schema
+            .with_tag(KnownTag::Notes, KeyRule::new().max_components(Some(2)))
+            .with_tag(KnownTag::Notes2, KeyRule::new().max_components(Some(2)));
+        for &tag in SSC_CHART_KEYS {
+            schema.rule_mut(tag).allow_duplicates = true;
+        }
+        schema.rule_mut(KnownTag::Bpms).required = true;
+        schema
+    }
+
+    /// The song keys shared by `.sm` and `.ssc` files, with `timing_keys` taking `beat=value,...` lists.
+    fn song_keys(timing_keys: &[KnownTag]) -> Self {
+        let mut schema = Self::new();
+        for &tag in TEXT_KEYS {
+            schema = schema.with_tag(tag, KeyRule::new());
+        }
+        for &tag in NUMBER_KEYS {
+            schema = schema.with_tag(tag, KeyRule::new().pattern(Some(NUMBER_PATTERN)));
+        }
+        for &tag in timing_keys {
+            schema = schema.with_tag(tag, KeyRule::new().pattern(Some(BEAT_VALUES_PATTERN)));
+        }
+        schema.with_tag(KnownTag::DisplayBpm, KeyRule::new().max_components(Some(3)).pattern(Some(DISPLAY_BPM_PATTERN)))
+    }
+
+    fn with_tag(self, tag: KnownTag, rule: KeyRule) -> Self {
+        self.with_key(tag.as_str(), rule)
+    }
+
+    /// The rule for a key the built-in schemas have already added.
+    fn rule_mut(&mut self, tag: KnownTag) -> &mut KeyRule {
+        self.keys.get_mut(tag.as_str()).expect("built-in schemas add a rule for every key they change")
+    }
+}
+
+/// A schema that can't be applied.
+#[derive(Debug)]
+pub enum SchemaError {
+    /// The pattern of a key is not a valid regular expression.
+    InvalidPattern {
+        key: String,
+        source: regex::Error,
+    },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::InvalidPattern { key, source } => write!(f, "SchemaError: invalid pattern for #{}: {}", key, source),
+        }
+    }
+}
+
+impl error::Error for SchemaError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SchemaError::InvalidPattern { source, .. } => Some(source),
+        }
+    }
+}
+
+/// How a document breaks a [`Schema`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SchemaViolation {
+    /// A required key never appears.
+    MissingKey,
+    /// A key has no rule and the schema denies unknown keys.
+    UnknownKey,
+    /// A key appears again but may not be duplicated.
+    DuplicateKey,
+    /// The parameter has fewer components than the rule allows.
+    TooFewComponents { min: usize, found: usize },
+    /// The parameter has more components than the rule allows.
+    TooManyComponents { max: usize, found: usize },
+    /// The value doesn't match the pattern of the rule.
+    PatternMismatch { pattern: String, value: String },
+}
+
+/// A violation of a [`Schema`], attached to the key it concerns.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SchemaDiagnostic {
+    pub key: String,
+    pub violation: SchemaViolation,
+    /// Byte range of the parameter in the source, if it was parsed. `None` for missing keys.
+    pub span: Option<Range<usize>>,
+}
+
+impl fmt::Display for SchemaDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.violation {
+            SchemaViolation::MissingKey => write!(f, "#{}: required key is missing", self.key)?,
+            SchemaViolation::UnknownKey => write!(f, "#{}: key is not allowed", self.key)?,
+            SchemaViolation::DuplicateKey => write!(f, "#{}: key may appear only once", self.key)?,
+            SchemaViolation::TooFewComponents { min, found } => {
+                write!(f, "#{}: expected at least {} components, found {}", self.key, min, found)?
+            },
+            SchemaViolation::TooManyComponents { max, found } => {
+                write!(f, "#{}: expected at most {} components, found {}", self.key, max, found)?
+            },
+            SchemaViolation::PatternMismatch { pattern, value } => {
+                write!(f, "#{}: {:?} does not match /{}/", self.key, value, pattern)?
+            },
+        }
+        if let Some(span) = &self.span {
+            write!(f, " (bytes {}..{})", span.start, span.end)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check `document` against `schema`, such as one loaded from a file or the built-in [`Schema::sm`] and
+/// [`Schema::ssc`].
+///
+/// Diagnostics for parameters come in document order, followed by missing required keys in the order of the schema.
+///
+/// # Errors
+///
+/// Returns [`SchemaError::InvalidPattern`] if a pattern of the schema is not a valid regular expression.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::{MSDDocument, MSDParserOptions};
+/// # use msdparser::schema::{validate, Schema};
+/// let input = "#TITLE:A;\n#OFFSET:abc;\n#NOTES:dance-single:::1::0000;\n";
+/// let document = MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap();
+/// let diagnostics: Vec<String> = validate(&document, &Schema::sm()).unwrap().iter().map(ToString::to_string).collect();
+///
+/// assert_eq!(vec![
+///     r#"#OFFSET: "abc" does not match /\s*(-?([0-9]+(\.[0-9]*)?|\.[0-9]+))?\s*/ (bytes 10..22)"#,
+///     "#BPMS: required key is missing",
+/// ], diagnostics);
+/// ```
+pub fn validate(document: &MSDDocument, schema: &Schema) -> Result<Vec<SchemaDiagnostic>, SchemaError> {
+    let mut patterns = HashMap::new();
+    for (key, rule) in &schema.keys {
+        if let Some(pattern) = &rule.pattern {
+            let regex = Regex::new(&format!("^(?:{})$", pattern))
+                .map_err(|source| SchemaError::InvalidPattern { key: key.clone(), source })?;
+            patterns.insert(key.as_str(), regex);
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+
+    for (key, parameter) in document.iter() {
+        let mut report = |violation| diagnostics.push(SchemaDiagnostic {
+            key: key.to_string(),
+            violation,
+            span: parameter.span.clone(),
+        });
+
+        let Some((rule_key, rule)) = schema.rule_for(key) else {
+            if schema.deny_unknown_keys {
+                report(SchemaViolation::UnknownKey);
+            }
+            continue;
+        };
+
+        let count = seen.entry(rule_key).or_default();
+        *count += 1;
+        if *count > 1 && !rule.allow_duplicates {
+            report(SchemaViolation::DuplicateKey);
+        }
+
+        let found = parameter.components.len();
+        match (rule.min_components, rule.max_components) {
+            (Some(min), _) if found < min => report(SchemaViolation::TooFewComponents { min, found }),
+            (_, Some(max)) if found > max => report(SchemaViolation::TooManyComponents { max, found }),
+            _ => {},
+        }
+
+        if let Some(regex) = patterns.get(rule_key) {
+            let value = parameter.components.get(1).map_or("", String::as_str);
+            if !regex.is_match(value) {
+                report(SchemaViolation::PatternMismatch {
+                    pattern: rule.pattern.clone().unwrap_or_default(),
+                    value: value.to_string(),
+                });
+            }
+        }
+    }
+
+    for (key, rule) in &schema.keys {
+        if rule.required && !seen.contains_key(key.as_str()) {
+            diagnostics.push(SchemaDiagnostic { key: key.clone(), violation: SchemaViolation::MissingKey, span: None });
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::MSDParserOptions;
+
+    fn document(input: &str) -> MSDDocument {
+        MSDDocument::from_reader(input.as_bytes(), MSDParserOptions::new()).unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = Schema::new()
+            .with_key("title", KeyRule::new().required(true))
+            .with_key("ARTIST", KeyRule::new().required(true))
+            .with_key("BPMS", KeyRule::new().max_components(Some(2)).pattern(Some(r"[0-9.=,]*")))
+            .with_key("NOTES", KeyRule::new().allow_duplicates(true).min_components(Some(3)));
+        let input = "#Title:A;#TITLE:B;#BPMS:0=120:x;#NOTES:a;#NOTES:a:b:c;#BPMS:0=abc;#GENRE:x;";
+
+        let diagnostics = validate(&document(input), &schema).unwrap();
+        let violations: Vec<_> = diagnostics.iter().map(|d| (d.key.as_str(), d.violation.clone())).collect();
+        assert_eq!(vec![
+            ("TITLE", SchemaViolation::DuplicateKey),
+            ("BPMS", SchemaViolation::TooManyComponents { max: 2, found: 3 }),
+            ("NOTES", SchemaViolation::TooFewComponents { min: 3, found: 2 }),
+            ("BPMS", SchemaViolation::DuplicateKey),
+            ("BPMS", SchemaViolation::PatternMismatch { pattern: r"[0-9.=,]*".to_string(), value: "0=abc".to_string() }),
+            ("ARTIST", SchemaViolation::MissingKey),
+        ], violations);
+        assert_eq!(Some(9..18), diagnostics[0].span);
+        assert_eq!("#TITLE: key may appear only once (bytes 9..18)", diagnostics[0].to_string());
+        assert!(validate(&document(input), &schema.deny_unknown_keys(true)).unwrap()
+            .iter()
+            .any(|d| d.key == "GENRE" && d.violation == SchemaViolation::UnknownKey));
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let schema: Schema = serde_json::from_str(r#"{ "keys": { "OFFSET": { "required": true, "pattern": "(" } } }"#).unwrap();
+        assert_eq!(Some("(".to_string()), schema.keys["OFFSET"].pattern);
+        assert!(matches!(validate(&document("#OFFSET:0;"), &schema), Err(SchemaError::InvalidPattern { .. })));
+
+        let unknown_field = serde_json::from_str::<Schema>(r#"{ "keys": { "OFFSET": { "requried": true } } }"#);
+        assert!(unknown_field.is_err());
+    }
+
+    #[test]
+    fn test_builtin_schemas() {
+        let springtime = MSDDocument::from_reader(std::fs::File::open("testdata/Springtime.ssc").unwrap(), MSDParserOptions::new()).unwrap();
+        assert_eq!(Vec::<SchemaDiagnostic>::new(), validate(&springtime, &Schema::ssc().deny_unknown_keys(true)).unwrap());
+        let timing = MSDDocument::from_reader(std::fs::File::open("testdata/conformance/timing.sm").unwrap(), MSDParserOptions::new()).unwrap();
+        assert_eq!(Vec::<SchemaDiagnostic>::new(), validate(&timing, &Schema::sm().deny_unknown_keys(true)).unwrap());
+
+        let input = "#BPMS:0=120,\n4=x;#DISPLAYBPM:*;#NOTES:a;#NOTES:b;#METER:9;";
+        let violations: Vec<_> = validate(&document(input), &Schema::sm().deny_unknown_keys(true))
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.key, d.violation))
+            .collect();
+        assert_eq!(vec![
+            ("BPMS".to_string(), SchemaViolation::PatternMismatch { pattern: BEAT_VALUES_PATTERN.to_string(), value: "0=120,\n4=x".to_string() }),
+            ("NOTES".to_string(), SchemaViolation::TooFewComponents { min: 7, found: 2 }),
+            ("NOTES".to_string(), SchemaViolation::TooFewComponents { min: 7, found: 2 }),
+            ("METER".to_string(), SchemaViolation::UnknownKey),
+        ], violations);
+
+        let input = "#BPMS:0=120;#LASTSECONDHINT:95.5;#LASTSECONDHINT:x;";
+        let violations: Vec<_> = validate(&document(input), &Schema::ssc().deny_unknown_keys(true))
+            .unwrap()
+            .into_iter()
+            .map(|d| d.violation)
+            .collect();
+        assert_eq!(vec![
+            SchemaViolation::DuplicateKey,
+            SchemaViolation::PatternMismatch { pattern: NUMBER_PATTERN.to_string(), value: "x".to_string() },
+        ], violations);
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+
+use crate::simfile::Simfile;
+
+/// Writing system of a piece of text, as guessed by [`detect_script`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+#[non_exhaustive]
+pub enum Script {
+    Latin,
+    /// Hiragana or katakana, with or without kanji.
+    Japanese,
+    Korean,
+    /// Chinese characters without any kana, so either Chinese or Japanese written only in kanji.
+    Han,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Thai,
+}
+
+impl Script {
+    /// The script `c` belongs to, if it is a letter of one of the known scripts.
+    fn of(c: char) -> Option<Script> {
+        match c {
+            c if c.is_ascii_alphabetic() => Some(Script::Latin),
+            // The middle dot and the prolonged sound mark are common in Chinese titles too
+            '\u{30fb}' | '\u{30fc}' | '\u{ff70}' => None,
+            '\u{00c0}'..='\u{024f}' | '\u{1e00}'..='\u{1eff}' if c.is_alphabetic() => Some(Script::Latin),
+            '\u{3040}'..='\u{30ff}' | '\u{31f0}'..='\u{31ff}' | '\u{ff66}'..='\u{ff9f}' => Some(Script::Japanese),
+            '\u{1100}'..='\u{11ff}' | '\u{3130}'..='\u{318f}' | '\u{ac00}'..='\u{d7af}' => Some(Script::Korean),
+            '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '\u{f900}'..='\u{faff}' => Some(Script::Han),
+            '\u{0400}'..='\u{04ff}' => Some(Script::Cyrillic),
+            '\u{0370}'..='\u{03ff}' => Some(Script::Greek),
+            '\u{0600}'..='\u{06ff}' => Some(Script::Arabic),
+            '\u{0590}'..='\u{05ff}' => Some(Script::Hebrew),
+            '\u{0e00}'..='\u{0e7f}' => Some(Script::Thai),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Guess the writing system of `text` from the Unicode ranges of its letters.
+///
+/// Latin letters show up in titles of every language, like `feat.` or `DDR`, so any other script wins over Latin.
+/// Kana mark the text as [`Script::Japanese`] even when kanji outnumber them. Otherwise the most frequent
+/// script wins. Returns `None` if `text` has no letters of a known script, e.g. if it is only digits and symbols.
+///
+/// This is a heuristic meant for grouping and filtering songs, not a language detector.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::script::{detect_script, Script};
+/// assert_eq!(Some(Script::Latin), detect_script("Springtime"));
+/// assert_eq!(Some(Script::Japanese), detect_script("恋は渾沌の隷也 (feat. 某)"));
+/// assert_eq!(Some(Script::Korean), detect_script("봄날 (Spring Day)"));
+/// assert_eq!(None, detect_script("1/2 ♪"));
+/// ```
+pub fn detect_script(text: &str) -> Option<Script> {
+    let mut counts: Vec<(Script, usize)> = Vec::new();
+    for script in text.chars().filter_map(Script::of) {
+        match counts.iter_mut().find(|(s, _)| *s == script) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((script, 1)),
+        }
+    }
+
+    if counts.iter().any(|(script, _)| *script == Script::Japanese) {
+        return Some(Script::Japanese);
+    }
+    // Ties go to the script that appears first
+    let most_frequent = |scripts: &mut dyn Iterator<Item = &(Script, usize)>| {
+        scripts.fold(None, |best: Option<(Script, usize)>, &(script, count)| match best {
+            Some((_, best_count)) if best_count >= count => best,
+            _ => Some((script, count)),
+        })
+    };
+    most_frequent(&mut counts.iter().filter(|(script, _)| *script != Script::Latin))
+        .or_else(|| most_frequent(&mut counts.iter()))
+        .map(|(script, _)| script)
+}
+
+impl Simfile {
+    /// The script of the title, see [`detect_script`].
+    pub fn title_script(&self) -> Option<Script> {
+        detect_script(&self.title)
+    }
+
+    /// The script of the artist, see [`detect_script`].
+    pub fn artist_script(&self) -> Option<Script> {
+        detect_script(&self.artist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_script() {
+        assert_eq!(Some(Script::Latin), detect_script("Café Ünïcode"));
+        assert_eq!(Some(Script::Han), detect_script("春 Spring"));
+        assert_eq!(Some(Script::Japanese), detect_script("ｶﾀｶﾅ"));
+        assert_eq!(Some(Script::Cyrillic), detect_script("Катюша"));
+        assert_eq!(Some(Script::Greek), detect_script("Αα Бб"));
+        assert_eq!(Some(Script::Han), detect_script("周杰倫・夜曲ー"));
+        assert_eq!(Some(Script::Japanese), detect_script("ラブ・ソング"));
+        assert_eq!(None, detect_script(""));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        assert_eq!("\"japanese\"", serde_json::to_string(&Script::Japanese).unwrap());
+        assert_eq!(Script::Han, serde_json::from_str("\"han\"").unwrap());
+    }
+
+    #[test]
+    fn test_simfile_scripts() {
+        let simfile = Simfile { title: "桜".to_string(), artist: "Kommisar".to_string(), ..Simfile::default() };
+
+        assert_eq!(Some(Script::Han), simfile.title_script());
+        assert_eq!(Some(Script::Latin), simfile.artist_script());
+    }
+}
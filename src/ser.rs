@@ -0,0 +1,642 @@
+use std::io::Write;
+use std::{error, fmt};
+
+use serde::ser::{self, Impossible, Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeTuple, SerializeTupleStruct};
+
+use crate::parameter::{MSDParameter, MSDParameterError};
+use crate::serialize::{MSDSerializeOptions, MsdSerialize};
+
+/// Custom error type for serializing user types as MSD.
+#[derive(Debug)]
+pub enum SerializeError {
+    /// Writing the parameters failed.
+    Parameter(MSDParameterError),
+    /// The value has a shape MSD cannot express, e.g. a nested struct.
+    Message(String),
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::Parameter(e) => write!(f, "{}", e),
+            SerializeError::Message(message) => write!(f, "SerializeError: {}", message),
+        }
+    }
+}
+
+impl error::Error for SerializeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SerializeError::Parameter(e) => Some(e),
+            SerializeError::Message(_) => None,
+        }
+    }
+}
+
+impl ser::Error for SerializeError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        SerializeError::Message(message.to_string())
+    }
+}
+
+impl From<MSDParameterError> for SerializeError {
+    fn from(e: MSDParameterError) -> Self {
+        SerializeError::Parameter(e)
+    }
+}
+
+fn unsupported(what: &str) -> SerializeError {
+    SerializeError::Message(format!("{} cannot be written as MSD", what))
+}
+
+/// Serialize a struct or map as MSD, one `#KEY:VALUE;` parameter per field, in field order.
+///
+/// The counterpart of [`from_reader`](crate::de::from_reader):
+///
+/// - keys are field names in uppercase without underscores, so `sample_start` is written as `#SAMPLESTART`;
+///   map keys are written as they are
+/// - numbers are written with [`Display`](fmt::Display), `bool`s as `YES`/`NO`, and `None` as a blank value
+/// - sequences become one comma-separated value, like `#BPMS:0=120,4=240;`
+/// - tuples become one component per element, like `#NOTES:dance-single:Hard:9;`
+/// - special characters are escaped
+///
+/// # Errors
+///
+/// Returns an error if writing fails, or if the value is not a struct or map of scalars and flat sequences.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde::Serialize;
+/// #[derive(Serialize)]
+/// struct Header {
+///     title: String,
+///     offset: f64,
+///     sample_start: Option<f64>,
+///     notes: (String, u32),
+/// }
+///
+/// let header = Header { title: "A:B".to_string(), offset: -0.028, sample_start: None, notes: ("Hard".to_string(), 9) };
+///
+/// assert_eq!(
+///     "#TITLE:A\\:B;\n#OFFSET:-0.028;\n#SAMPLESTART:;\n#NOTES:Hard:9;\n",
+///     msdparser::ser::to_string(&header).unwrap()
+/// );
+/// ```
+pub fn to_writer<W: Write, T: Serialize + ?Sized>(mut writer: W, value: &T) -> Result<(), SerializeError> {
+    to_parameters(value)?.serialize_msd(&mut writer, &MSDSerializeOptions::new())?;
+    Ok(())
+}
+
+/// Serialize a struct or map as an MSD string. See [`to_writer`].
+///
+/// # Errors
+///
+/// Returns an error if the value is not a struct or map of scalars and flat sequences.
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, SerializeError> {
+    let mut output = Vec::new();
+    to_writer(&mut output, value)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Serialize a struct or map into parameters. See [`to_writer`].
+///
+/// # Errors
+///
+/// Returns an error if the value is not a struct or map of scalars and flat sequences.
+pub fn to_parameters<T: Serialize + ?Sized>(value: &T) -> Result<Vec<MSDParameter>, SerializeError> {
+    value.serialize(DocumentSerializer)
+}
+
+/// Key for a struct field: uppercase, without underscores.
+fn field_key(field: &str) -> String {
+    field.chars().filter(|&c| c != '_').map(|c| c.to_ascii_uppercase()).collect()
+}
+
+/// Serializes a struct or map into a list of parameters.
+struct DocumentSerializer;
+
+/// Collects the parameters of a struct or map.
+struct Document {
+    parameters: Vec<MSDParameter>,
+    key: Option<String>,
+}
+
+impl Document {
+    fn push<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<(), SerializeError> {
+        let mut components = vec![key];
+        components.extend(value.serialize(ComponentsSerializer)?);
+        self.parameters.push(MSDParameter::new(components));
+        Ok(())
+    }
+}
+
+macro_rules! unsupported_document {
+    ($($method:ident($($arg:ty),*),)*) => {
+        $(
+            fn $method(self, $(_: $arg),*) -> Result<Self::Ok, Self::Error> {
+                Err(unsupported("a value outside of a struct or map"))
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for DocumentSerializer {
+    type Ok = Vec<MSDParameter>;
+    type Error = SerializeError;
+    type SerializeSeq = Impossible<Self::Ok, Self::Error>;
+    type SerializeTuple = Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleStruct = Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleVariant = Impossible<Self::Ok, Self::Error>;
+    type SerializeMap = Document;
+    type SerializeStruct = Document;
+    type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
+
+    unsupported_document! {
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("an enum document"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Err(unsupported("a sequence outside of a struct or map"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Err(unsupported("a tuple outside of a struct or map"))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Err(unsupported("a tuple struct document"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(unsupported("an enum document"))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(Document { parameters: Vec::with_capacity(len.unwrap_or_default()), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(Document { parameters: Vec::with_capacity(len), key: None })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(unsupported("an enum document"))
+    }
+}
+
+impl SerializeStruct for Document {
+    type Ok = Vec<MSDParameter>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+        self.push(field_key(key), value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.parameters)
+    }
+}
+
+impl SerializeMap for Document {
+    type Ok = Vec<MSDParameter>;
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        self.key = Some(key.serialize(ScalarSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let key = self.key.take().ok_or_else(|| SerializeError::Message("map value without a key".to_string()))?;
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.parameters)
+    }
+}
+
+/// Serializes a field value into the components after the key.
+struct ComponentsSerializer;
+
+/// Collects the elements of a sequence or tuple.
+struct Components {
+    components: Vec<String>,
+    /// Whether the elements are written as one comma-separated component, as sequences are.
+    joined: bool,
+}
+
+impl Components {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.components.push(value.serialize(ScalarSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Vec<String> {
+        if self.joined || self.components.is_empty() {
+            vec![self.components.join(",")]
+        } else {
+            self.components
+        }
+    }
+}
+
+macro_rules! scalar_components {
+    ($($method:ident($arg:ty),)*) => {
+        $(
+            fn $method(self, v: $arg) -> Result<Self::Ok, Self::Error> {
+                ScalarSerializer.$method(v).map(|value| vec![value])
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for ComponentsSerializer {
+    type Ok = Vec<String>;
+    type Error = SerializeError;
+    type SerializeSeq = Components;
+    type SerializeTuple = Components;
+    type SerializeTupleStruct = Components;
+    type SerializeTupleVariant = Impossible<Self::Ok, Self::Error>;
+    type SerializeMap = Impossible<Self::Ok, Self::Error>;
+    type SerializeStruct = Impossible<Self::Ok, Self::Error>;
+    type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
+
+    scalar_components! {
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_unit_struct(&'static str),
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(vec![String::new()])
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(vec![String::new()])
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(vec![variant.to_string()])
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(Components { components: Vec::with_capacity(len.unwrap_or_default()), joined: true })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(Components { components: Vec::with_capacity(len), joined: false })
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Ok(Components { components: Vec::with_capacity(len), joined: false })
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Err(unsupported("a nested map"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Self::Error> {
+        Err(unsupported("a nested struct"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+}
+
+impl SerializeSeq for Components {
+    type Ok = Vec<String>;
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(Components::end(self))
+    }
+}
+
+impl SerializeTuple for Components {
+    type Ok = Vec<String>;
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(Components::end(self))
+    }
+}
+
+impl SerializeTupleStruct for Components {
+    type Ok = Vec<String>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(Components::end(self))
+    }
+}
+
+/// Serializes a single component.
+struct ScalarSerializer;
+
+macro_rules! display_scalar {
+    ($($method:ident($arg:ty),)*) => {
+        $(
+            fn $method(self, v: $arg) -> Result<Self::Ok, Self::Error> {
+                Ok(v.to_string())
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for ScalarSerializer {
+    type Ok = String;
+    type Error = SerializeError;
+    type SerializeSeq = Impossible<Self::Ok, Self::Error>;
+    type SerializeTuple = Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleStruct = Impossible<Self::Ok, Self::Error>;
+    type SerializeTupleVariant = Impossible<Self::Ok, Self::Error>;
+    type SerializeMap = Impossible<Self::Ok, Self::Error>;
+    type SerializeStruct = Impossible<Self::Ok, Self::Error>;
+    type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
+
+    display_scalar! {
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        Ok(if v { "YES" } else { "NO" }.to_string())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Ok(String::from_utf8_lossy(v).into_owned())
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(String::new())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(String::new())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(String::new())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Err(unsupported("a nested sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Err(unsupported("a nested tuple"))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Err(unsupported("a nested tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Err(unsupported("a nested map"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Self::Error> {
+        Err(unsupported("a nested struct"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(unsupported("an enum variant with data"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Difficulty {
+        Hard,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Header {
+        title: String,
+        sample_start: Option<f64>,
+        selectable: bool,
+        bpms: Vec<String>,
+        notes: (String, Difficulty, u32),
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let header = Header {
+            title: "A;B // C".to_string(),
+            sample_start: Some(12.5),
+            selectable: false,
+            bpms: vec!["0=120".to_string(), "4=240".to_string()],
+            notes: ("dance-single".to_string(), Difficulty::Hard, 9),
+        };
+        let output = to_string(&header).unwrap();
+
+        assert_eq!(
+            "#TITLE:A\\;B \\// C;\n#SAMPLESTART:12.5;\n#SELECTABLE:NO;\n#BPMS:0=120,4=240;\n#NOTES:dance-single:Hard:9;\n",
+            output
+        );
+        assert_eq!(header, crate::de::from_str(&output).unwrap());
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Numbers {
+        stops: Vec<f64>,
+        empty: Vec<u32>,
+        single: Vec<u32>,
+        big: i128,
+        unsigned: u128,
+    }
+
+    #[test]
+    fn test_round_trip_numbers() {
+        let numbers = Numbers {
+            stops: vec![-0.5, 1.25, 2.0],
+            empty: Vec::new(),
+            single: vec![7],
+            big: i128::MIN,
+            unsigned: u128::MAX,
+        };
+        let output = to_string(&numbers).unwrap();
+
+        assert_eq!(
+            format!("#STOPS:-0.5,1.25,2;\n#EMPTY:;\n#SINGLE:7;\n#BIG:{};\n#UNSIGNED:{};\n", i128::MIN, u128::MAX),
+            output
+        );
+        assert_eq!(numbers, crate::de::from_str(&output).unwrap());
+    }
+
+    #[test]
+    fn test_map_and_unsupported() {
+        let map = BTreeMap::from([("B", 2), ("A", 1)]);
+        assert_eq!("#A:1;\n#B:2;\n", to_string(&map).unwrap());
+
+        assert!(matches!(to_string(&vec![1, 2]), Err(SerializeError::Message(_))));
+        assert!(matches!(to_string(&BTreeMap::from([("A", vec![vec![1]])])), Err(SerializeError::Message(_))));
+    }
+}
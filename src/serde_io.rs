@@ -0,0 +1,15 @@
+//! `#[serde(with = "crate::serde_io")]` for [`io::Error`] fields, which serde does not support.
+//!
+//! The error is stored as its message; deserializing gives an error of kind [`io::ErrorKind::Other`].
+
+use std::io;
+
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer>(error: &io::Error, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(error)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<io::Error, D::Error> {
+    String::deserialize(deserializer).map(io::Error::other)
+}
@@ -0,0 +1,518 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use crate::borrowed::MSDParameterRef;
+use crate::key_map::KeyMap;
+use crate::lexer::EscapePolicy;
+use crate::parameter::{Comment, CommentPlacement, MSDParameter, MSDParameterError};
+
+/// Line ending written after each parameter of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// What is written between the parameters of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum RecordSeparator {
+    /// One line ending, so every parameter is on a line of its own.
+    #[default]
+    LineEnding,
+    /// Nothing, so parameters follow each other on the same line.
+    Nothing,
+    /// A line ending followed by this many blank lines.
+    BlankLines(usize),
+}
+
+impl RecordSeparator {
+    /// Write the separator using `line_ending`.
+    pub(crate) fn write(&self, writer: &mut dyn Write, line_ending: LineEnding) -> std::io::Result<()> {
+        let line_breaks = match self {
+            RecordSeparator::LineEnding => 1,
+            RecordSeparator::Nothing => 0,
+            RecordSeparator::BlankLines(blank_lines) => blank_lines + 1,
+        };
+        for _ in 0..line_breaks {
+            writer.write_all(line_ending.as_str().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings shared by every [`MsdSerialize`] implementation.
+///
+/// Not `Copy`, since it holds the per-key escape overrides, but cloning is cheap: they are shared between clones.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct MSDSerializeOptions {
+    /// How special text is escaped. Defaults to [`EscapePolicy::Escaped`].
+    ///
+    /// See [`MSDParameter::serialize_component`].
+    pub escapes: EscapePolicy,
+    /// Escape policies that replace `escapes` for the whole parameter with a given key. Defaults to being empty.
+    /// Set with [`MSDSerializeOptions::escapes_for_key`].
+    ///
+    /// See [`MSDSerializeOptions::step_mania`] for the overrides StepMania's own writer uses.
+    key_escapes: KeyMap<EscapePolicy>,
+    /// Line ending used by the separator and after the last parameter of a document. Defaults to [`LineEnding::Lf`].
+    pub line_ending: LineEnding,
+    /// What is written between the parameters of a document. Defaults to [`RecordSeparator::LineEnding`].
+    pub separator: RecordSeparator,
+    /// Whether a document ends with a line ending after its last parameter. Defaults to `true`.
+    pub trailing_line_ending: bool,
+    /// Whether the [`MSDParameter::comments`] are written, each where it was found according to its
+    /// [`CommentPlacement`]: on a line of its own before or after the parameter, inside a value, or on the same
+    /// line after the `;`. Defaults to `true`.
+    ///
+    /// [`CommentPlacement`]: crate::parameter::CommentPlacement
+    pub comments: bool,
+    /// Whether to write keys as they were spelled in the source instead of normalized. Defaults to `false`.
+    ///
+    /// See [`MSDParameter::original_key`].
+    pub preserve_key_casing: bool,
+    /// How numbers of typed values, e.g. the beats and BPMs of [`Simfile::bpms`], are written.
+    /// Defaults to [`FloatFormat::new`].
+    ///
+    /// [`Simfile::bpms`]: crate::simfile::Simfile::bpms
+    pub float_format: FloatFormat,
+}
+
+impl Default for MSDSerializeOptions {
+    fn default() -> Self {
+        Self {
+            escapes: EscapePolicy::Escaped,
+            key_escapes: KeyMap::new(),
+            line_ending: LineEnding::Lf,
+            separator: RecordSeparator::LineEnding,
+            trailing_line_ending: true,
+            comments: true,
+            preserve_key_casing: false,
+            float_format: FloatFormat::new(),
+        }
+    }
+}
+
+impl MSDSerializeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Settings that write files the way StepMania does: metadata is escaped, `#NOTES` and `#NOTES2` are not.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use msdparser::{EscapePolicy, MSDParameter};
+    /// # use msdparser::serialize::{MSDSerializeOptions, MsdSerialize};
+    /// let title = MSDParameter::new(vec!["TITLE".to_string(), "A:B".to_string()]);
+    /// let notes = MSDParameter::new(vec!["NOTES".to_string(), "dance-single".to_string(), "0000\n0000\n".to_string()]);
+    ///
+    /// let options = MSDSerializeOptions::step_mania();
+    /// assert_eq!(EscapePolicy::Unescaped, options.escapes_for("notes"));
+    /// assert_eq!("#TITLE:A\\:B;\n#NOTES:dance-single:0000\n0000\n;\n", vec![title, notes].to_msd_string(&options).unwrap());
+    /// ```
+    pub fn step_mania() -> Self {
+        Self::new()
+            .escapes_for_key("NOTES", EscapePolicy::Unescaped)
+            .escapes_for_key("NOTES2", EscapePolicy::Unescaped)
+    }
+
+    pub fn escapes(mut self, escapes: impl Into<EscapePolicy>) -> Self {
+        self.escapes = escapes.into();
+        self
+    }
+
+    /// Use `escapes` for parameters with `key`, compared ignoring ASCII case and surrounding whitespace.
+    pub fn escapes_for_key(mut self, key: &str, escapes: impl Into<EscapePolicy>) -> Self {
+        self.key_escapes.insert(key, escapes.into());
+        self
+    }
+
+    /// The escape policies set for single keys.
+    pub fn key_escapes(&self) -> &KeyMap<EscapePolicy> {
+        &self.key_escapes
+    }
+
+    /// The escape policy for a parameter with `key`.
+    pub fn escapes_for(&self, key: &str) -> EscapePolicy {
+        if self.key_escapes.is_empty() {
+            return self.escapes;
+        }
+        self.key_escapes.get(key).copied().unwrap_or(self.escapes)
+    }
+
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    pub fn separator(mut self, separator: RecordSeparator) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    pub fn trailing_line_ending(mut self, trailing_line_ending: bool) -> Self {
+        self.trailing_line_ending = trailing_line_ending;
+        self
+    }
+
+    pub fn comments(mut self, comments: bool) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    pub fn preserve_key_casing(mut self, preserve_key_casing: bool) -> Self {
+        self.preserve_key_casing = preserve_key_casing;
+        self
+    }
+
+    pub fn float_format(mut self, float_format: FloatFormat) -> Self {
+        self.float_format = float_format;
+        self
+    }
+}
+
+/// How numbers are written back to text, e.g. the beats and BPMs of `#BPMS`.
+///
+/// The defaults match StepMania, which writes every number with 6 decimal places.
+/// Numbers are always written in positional notation, never with an exponent like `1e-7`, which engines can't read.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::serialize::FloatFormat;
+/// assert_eq!("120.000000", FloatFormat::new().format(120.0));
+/// assert_eq!("0.5", FloatFormat::new().trim_trailing_zeros(true).format(0.5));
+/// assert_eq!("181.685", FloatFormat::new().decimals(Some(3)).format(181.685));
+/// assert_eq!("0.0000001", FloatFormat::new().decimals(None).format(1e-7));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+#[non_exhaustive]
+pub struct FloatFormat {
+    /// Number of decimal places to round to, or `None` for the shortest text that reads back as the same number.
+    /// Defaults to `Some(6)`.
+    pub decimals: Option<usize>,
+    /// Whether to drop zeros at the end of the fractional part, and the decimal point if nothing is left.
+    /// Defaults to `false`.
+    pub trim_trailing_zeros: bool,
+}
+
+impl Default for FloatFormat {
+    fn default() -> Self {
+        Self {
+            decimals: Some(6),
+            trim_trailing_zeros: false,
+        }
+    }
+}
+
+impl FloatFormat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decimals(mut self, decimals: Option<usize>) -> Self {
+        self.decimals = decimals;
+        self
+    }
+
+    pub fn trim_trailing_zeros(mut self, trim_trailing_zeros: bool) -> Self {
+        self.trim_trailing_zeros = trim_trailing_zeros;
+        self
+    }
+
+    /// Write `value` as text.
+    pub fn format(&self, value: f64) -> String {
+        // `Display` for floats never uses scientific notation, unlike `Debug`
+        let mut text = match self.decimals {
+            Some(decimals) => format!("{:.*}", decimals, value),
+            None => format!("{}", value),
+        };
+        if self.trim_trailing_zeros && text.contains('.') {
+            let trimmed = text.trim_end_matches('0').trim_end_matches('.').len();
+            text.truncate(trimmed);
+        }
+        text
+    }
+}
+
+/// Anything that can be written out as MSD.
+///
+/// Lets writers, formatters and caches handle parameters, documents and higher-level types uniformly.
+/// The writer is a trait object so that implementations stay usable behind `dyn MsdSerialize`.
+///
+/// Besides parameters and documents, it is implemented by the typed values of the `simfile` feature, such as
+/// `simfile::Simfile`, `charts::Chart`, and by [`DisplayBpm`](crate::values::DisplayBpm) and
+/// [`RadarValues`](crate::values::RadarValues), which write their numbers with [`MSDSerializeOptions::float_format`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::MSDParameter;
+/// # use msdparser::serialize::{LineEnding, MsdSerialize, MSDSerializeOptions};
+/// let parameters = vec![
+///     MSDParameter::new(vec!["TITLE".to_string(), "A;B".to_string()]),
+///     MSDParameter::new(vec!["ARTIST".to_string(), "C".to_string()]),
+/// ];
+/// let options = MSDSerializeOptions::new().line_ending(LineEnding::CrLf);
+///
+/// assert_eq!(parameters.to_msd_string(&options).unwrap(), "#TITLE:A\\;B;\r\n#ARTIST:C;\r\n");
+/// ```
+pub trait MsdSerialize {
+    /// Write `self` to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails, or if a component contains a special substring and escapes are disabled.
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError>;
+
+    /// Serialize `self` into a `String`.
+    ///
+    /// # Errors
+    ///
+    /// See [`MsdSerialize::serialize_msd`].
+    fn to_msd_string(&self, options: &MSDSerializeOptions) -> Result<String, MSDParameterError> {
+        let mut output = Vec::new();
+        self.serialize_msd(&mut output, options)?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
+
+/// Whether a comment at byte `offset` of `component` can be written back there: the line break that ended it must
+/// still follow, and no `/` may come right before that would run into its `//`.
+fn fits_inside(component: &str, offset: usize) -> bool {
+    component.is_char_boundary(offset)
+        && component[offset..].starts_with(['\n', '\r'])
+        && !component[..offset].ends_with('/')
+}
+
+/// Whether the last line written for a parameter with `comments` is a comment, so whatever follows it must start on
+/// a new line.
+pub(crate) fn ends_with_comment<S>(comments: &[Comment<S>], options: &MSDSerializeOptions) -> bool {
+    options.comments && comments.iter().any(|comment| matches!(comment.placement, CommentPlacement::SameLine | CommentPlacement::After))
+}
+
+/// Write a parameter and, if [`MSDSerializeOptions::comments`] is set, its comments where they were found.
+fn write_parameter<S: AsRef<str>>(
+    writer: &mut dyn Write,
+    components: &[&str],
+    comments: &[Comment<S>],
+    escapes: EscapePolicy,
+    options: &MSDSerializeOptions,
+) -> Result<(), MSDParameterError> {
+    let comments = if options.comments { comments } else { &[] };
+    let line_ending = options.line_ending.as_str();
+    let inside = |comment: &Comment<S>| match comment.placement {
+        CommentPlacement::Inside { component, offset } => {
+            components.get(component).is_some_and(|text| fits_inside(text, offset)).then_some((component, offset))
+        },
+        _ => None,
+    };
+
+    for comment in comments {
+        if comment.placement == CommentPlacement::Before || matches!(comment.placement, CommentPlacement::Inside { .. }) && inside(comment).is_none() {
+            write!(writer, "//{}{}", comment.text.as_ref(), line_ending)?;
+        }
+    }
+
+    writer.write_all(b"#")?;
+    for (i, component) in components.iter().enumerate() {
+        if i != 0 {
+            writer.write_all(b":")?;
+        }
+        let mut start = 0;
+        for comment in comments {
+            if let Some((_, offset)) = inside(comment).filter(|&(index, offset)| index == i && offset >= start) {
+                writer.write_all(MSDParameter::serialize_component(&component[start..offset], escapes)?.as_bytes())?;
+                write!(writer, "//{}", comment.text.as_ref())?;
+                start = offset;
+            }
+        }
+        writer.write_all(MSDParameter::serialize_component(&component[start..], escapes)?.as_bytes())?;
+    }
+    writer.write_all(b";")?;
+
+    let mut same_line = true;
+    for comment in comments {
+        match comment.placement {
+            CommentPlacement::SameLine if same_line => {
+                write!(writer, " //{}", comment.text.as_ref())?;
+                same_line = false;
+            },
+            CommentPlacement::SameLine | CommentPlacement::After => {
+                write!(writer, "{}//{}", line_ending, comment.text.as_ref())?;
+                same_line = false;
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// A single parameter with its comments, without a line ending.
+impl MsdSerialize for MSDParameter {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        let key = if options.preserve_key_casing { self.original_key() } else { None };
+        let components: Vec<&str> = self.components.iter().enumerate()
+            .map(|(i, component)| match key {
+                Some(key) if i == 0 => key,
+                _ => component.as_str(),
+            })
+            .collect();
+        let escapes = options.escapes_for(self.components.first().map_or("", String::as_str));
+        write_parameter(writer, &components, &self.comments, escapes, options)
+    }
+}
+
+/// A single parameter with its comments, without a line ending.
+impl MsdSerialize for MSDParameterRef<'_> {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        let components: Vec<&str> = self.components.iter().map(Cow::as_ref).collect();
+        let escapes = options.escapes_for(components.first().copied().unwrap_or_default());
+        write_parameter(writer, &components, &self.comments, escapes, options)
+    }
+}
+
+/// A document, with the [`RecordSeparator`] between parameters and optionally a line ending after the last one.
+impl MsdSerialize for [MSDParameter] {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        for (i, parameter) in self.iter().enumerate() {
+            if i != 0 {
+                match options.separator {
+                    RecordSeparator::Nothing if ends_with_comment(&self[i - 1].comments, options) => {
+                        writer.write_all(options.line_ending.as_str().as_bytes())?
+                    },
+                    separator => separator.write(writer, options.line_ending)?,
+                }
+            }
+            parameter.serialize_msd(writer, options)?;
+        }
+        if options.trailing_line_ending && !self.is_empty() {
+            writer.write_all(options.line_ending.as_str().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl MsdSerialize for Vec<MSDParameter> {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        self.as_slice().serialize_msd(writer, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::borrowed::parse_msd_str;
+
+    #[test]
+    fn test_generic_serialization() {
+        fn serialize_all(items: &[&dyn MsdSerialize]) -> Vec<String> {
+            items.iter().map(|item| item.to_msd_string(&MSDSerializeOptions::new()).unwrap()).collect()
+        }
+
+        let input = "#A:B\\:C;";
+        let borrowed = parse_msd_str(input, true, false).next().unwrap().unwrap();
+        let owned = borrowed.clone().into_owned();
+        let document = vec![owned.clone(), owned.clone()];
+
+        assert_eq!(
+            vec!["#A:B\\:C;", "#A:B\\:C;", "#A:B\\:C;\n#A:B\\:C;\n"],
+            serialize_all(&[&borrowed, &owned, &document])
+        );
+    }
+
+    #[test]
+    fn test_preserve_key_casing() {
+        let options = crate::parser::MSDParserOptions::new().normalize_keys(true);
+        let mut parameters: Vec<MSDParameter> = crate::parser::parse_msd_with_options(b"#title:A;#Artist:B;".as_ref(), options)
+            .map(Result::unwrap)
+            .collect();
+        parameters[1].components[0] = "CREDIT".to_string();
+
+        assert_eq!("#TITLE:A;\n#CREDIT:B;\n", parameters.to_msd_string(&MSDSerializeOptions::new()).unwrap());
+        assert_eq!(
+            "#title:A;\n#CREDIT:B;\n",
+            parameters.to_msd_string(&MSDSerializeOptions::new().preserve_key_casing(true)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        let parameter = MSDParameter::new(vec!["A".to_string(), "B:C".to_string()]);
+
+        assert!(parameter.to_msd_string(&MSDSerializeOptions::new().escapes(false)).is_err());
+        assert_eq!(
+            "#A:B\\:C;\r\n",
+            vec![parameter].to_msd_string(&MSDSerializeOptions::new().line_ending(LineEnding::CrLf)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_key_escapes() {
+        let parameters = vec![
+            MSDParameter::new(vec!["TITLE".to_string(), "a\\b".to_string()]),
+            MSDParameter::new(vec!["notes".to_string(), "a\\b".to_string()]),
+            MSDParameter::new(vec!["BPMS".to_string(), "0=1#2".to_string()]),
+        ];
+        let options = MSDSerializeOptions::new()
+            .escapes_for_key(" NOTES ", false)
+            .escapes_for_key("bpms", EscapePolicy::StrictStepMania);
+
+        assert_eq!("#TITLE:a\\\\b;\n#notes:a\\b;\n#BPMS:0=1\\#2;\n", parameters.to_msd_string(&options).unwrap());
+        assert_eq!(Some(&EscapePolicy::Unescaped), options.key_escapes().get("notes"));
+        let bad_notes = MSDParameter::new(vec!["NOTES".to_string(), "a:b".to_string()]);
+        assert!(bad_notes.to_msd_string(&MSDSerializeOptions::step_mania()).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_key_escapes() {
+        let options: MSDSerializeOptions = serde_json::from_str(r#"{"key_escapes": {" Notes": "unescaped"}}"#).unwrap();
+        assert_eq!(MSDSerializeOptions::new().escapes_for_key("NOTES", EscapePolicy::Unescaped), options);
+        assert_eq!(EscapePolicy::Unescaped, options.escapes_for("notes"));
+    }
+
+    #[test]
+    fn test_record_separators() {
+        let parameters = vec![
+            MSDParameter::new(vec!["A".to_string(), "B".to_string()]),
+            MSDParameter::new(vec!["C".to_string(), "D".to_string()]),
+        ];
+        let serialize = |options: MSDSerializeOptions| parameters.to_msd_string(&options).unwrap();
+
+        assert_eq!("#A:B;#C:D;", serialize(MSDSerializeOptions::new().separator(RecordSeparator::Nothing).trailing_line_ending(false)));
+        assert_eq!(
+            "#A:B;\r\n\r\n\r\n#C:D;\r\n",
+            serialize(MSDSerializeOptions::new().separator(RecordSeparator::BlankLines(2)).line_ending(LineEnding::CrLf))
+        );
+        assert_eq!("#A:B;\n#C:D;", serialize(MSDSerializeOptions::new().trailing_line_ending(false)));
+        assert_eq!("", Vec::<MSDParameter>::new().to_msd_string(&MSDSerializeOptions::new()).unwrap());
+    }
+
+    #[test]
+    fn test_float_format() {
+        let trimmed = FloatFormat::new().trim_trailing_zeros(true);
+
+        assert_eq!("-0.090000", FloatFormat::new().format(-0.09));
+        assert_eq!(("120", "0.0005", "-0.09"), (trimmed.format(120.0).as_str(), trimmed.format(0.0005).as_str(), trimmed.format(-0.09).as_str()));
+        assert_eq!("100000000000000000000", FloatFormat::new().decimals(None).format(1e20));
+        assert_eq!("90.843", FloatFormat::new().decimals(Some(3)).trim_trailing_zeros(true).format(90.8430001));
+        assert_eq!("4", FloatFormat::new().decimals(Some(0)).trim_trailing_zeros(true).format(4.0));
+    }
+}
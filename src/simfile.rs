@@ -0,0 +1,461 @@
+use std::io::Write;
+use std::ops::Range;
+use std::{error, fmt};
+
+use crate::charts::{charts, CanonicalOrder, Chart};
+use crate::document::MSDDocument;
+use crate::parameter::{MSDParameter, MSDParameterError};
+use crate::serialize::{FloatFormat, MSDSerializeOptions, MsdSerialize};
+use crate::values::{parse_beat_value_pairs, DisplayBpm};
+
+mod split;
+#[cfg(feature = "io")]
+mod song_dir;
+pub use split::{merge_charts, ConflictPolicy, MergeChartsError};
+#[cfg(feature = "io")]
+pub use song_dir::{load_song_dir, SongDir, SongDirError, SongDirWarning, SONG_DIR_PREFERENCE};
+
+/// The fields of an SM or SSC simfile that most tools need, with their values converted to Rust types.
+///
+/// Built with `Simfile::try_from(parameters)`, or [`Simfile::from_dwi`] for a `.dwi` file. Unknown keys are ignored, blank values leave the default,
+/// and when a key is repeated the last occurrence wins, as in StepMania. In an `.ssc` file only the parameters before
+/// the first `#NOTEDATA` are song fields; the ones after it belong to the charts.
+///
+/// The struct is `#[non_exhaustive]` so that more of the keys StepMania reads, like `#SPEEDS`, can become fields
+/// later: start from [`Simfile::default`] and set the public fields instead of writing a struct literal.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[non_exhaustive]
+pub struct Simfile {
+    pub title: String,
+    pub subtitle: String,
+    pub artist: String,
+    pub title_translit: String,
+    pub subtitle_translit: String,
+    pub artist_translit: String,
+    pub genre: String,
+    pub credit: String,
+    pub music: String,
+    pub banner: String,
+    pub background: String,
+    /// Seconds between the start of the music and beat 0.
+    pub offset: f64,
+    pub sample_start: Option<f64>,
+    pub sample_length: Option<f64>,
+    pub display_bpm: Option<DisplayBpm>,
+    /// `(beat, bpm)` pairs.
+    pub bpms: Vec<(f64, f64)>,
+    /// `(beat, seconds)` pairs.
+    pub stops: Vec<(f64, f64)>,
+    /// `(beat, seconds)` pairs.
+    pub delays: Vec<(f64, f64)>,
+    /// `(beat, length in beats)` pairs.
+    pub warps: Vec<(f64, f64)>,
+    pub charts: Vec<Chart>,
+}
+
+/// Why a field could not be converted.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum FieldErrorReason {
+    /// The value is not a number.
+    InvalidNumber,
+    /// An entry of a `beat=value,...` list is not two numbers separated by `=`.
+    InvalidBeatValuePair,
+    /// A `#DISPLAYBPM` is not a number, two numbers separated by `:`, or `*`.
+    InvalidDisplayBpm,
+}
+
+impl fmt::Display for FieldErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldErrorReason::InvalidNumber => write!(f, "not a number"),
+            FieldErrorReason::InvalidBeatValuePair => write!(f, "not a list of beat=value pairs"),
+            FieldErrorReason::InvalidDisplayBpm => write!(f, "not a BPM, a BPM range or '*'"),
+        }
+    }
+}
+
+/// A field that failed conversion.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct FieldError {
+    /// The key as written in the document.
+    pub key: String,
+    /// The raw value.
+    pub value: String,
+    pub reason: FieldErrorReason,
+    /// Byte range of the parameter in the source, if it was parsed.
+    pub span: Option<Range<usize>>,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}:{}: {}", self.key, self.value, self.reason)?;
+        if let Some(span) = &self.span {
+            write!(f, " (bytes {}..{})", span.start, span.end)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every field of a document that failed conversion to a [`Simfile`], in document order.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct SimfileError {
+    pub errors: Vec<FieldError>,
+}
+
+impl fmt::Display for SimfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SimfileError: {} field(s) failed conversion", self.errors.len())?;
+        for error in &self.errors {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for SimfileError {}
+
+impl Simfile {
+    /// Sort the charts in `order`, e.g. so converted or formatted files list their charts predictably.
+    ///
+    /// The sort is stable, so charts that compare equal keep their relative order.
+    pub fn sort_charts(&mut self, order: &CanonicalOrder) {
+        self.charts.sort_by(|a, b| order.compare(a, b));
+    }
+
+    /// The simfile as the parameters of an `.sm` file, header first and then the charts, with numbers written
+    /// with `format`.
+    ///
+    /// Every text field, `#OFFSET`, `#BPMS` and `#STOPS` are always written; the other fields only when set.
+    /// Converting the result back with `Simfile::try_from` gives an equal simfile, up to the precision of `format`.
+    pub fn to_parameters(&self, format: &FloatFormat) -> Vec<MSDParameter> {
+        let parameter = |key: &str, value: String| MSDParameter::new(vec![key.to_string(), value]);
+        let pairs = |pairs: &[(f64, f64)]| format_beat_value_pairs(pairs, format, ",");
+
+        let mut parameters: Vec<MSDParameter> = TEXT_FIELDS.iter()
+            .map(|&key| {
+                let value = match key {
+                    "TITLE" => &self.title,
+                    "SUBTITLE" => &self.subtitle,
+                    "ARTIST" => &self.artist,
+                    "TITLETRANSLIT" => &self.title_translit,
+                    "SUBTITLETRANSLIT" => &self.subtitle_translit,
+                    "ARTISTTRANSLIT" => &self.artist_translit,
+                    "GENRE" => &self.genre,
+                    "CREDIT" => &self.credit,
+                    "MUSIC" => &self.music,
+                    "BANNER" => &self.banner,
+                    _ => &self.background,
+                };
+                parameter(key, value.clone())
+            })
+            .collect();
+
+        parameters.push(parameter("OFFSET", format.format(self.offset)));
+        if let Some(start) = self.sample_start {
+            parameters.push(parameter("SAMPLESTART", format.format(start)));
+        }
+        if let Some(length) = self.sample_length {
+            parameters.push(parameter("SAMPLELENGTH", format.format(length)));
+        }
+        match self.display_bpm {
+            Some(DisplayBpm::Range(min, max)) => {
+                parameters.push(MSDParameter::new(vec!["DISPLAYBPM".to_string(), format.format(min), format.format(max)]));
+            },
+            Some(DisplayBpm::Fixed(bpm)) => parameters.push(parameter("DISPLAYBPM", format.format(bpm))),
+            Some(DisplayBpm::Random) => parameters.push(parameter("DISPLAYBPM", "*".to_string())),
+            None => {},
+        }
+        parameters.push(parameter("BPMS", pairs(&self.bpms)));
+        parameters.push(parameter("STOPS", pairs(&self.stops)));
+        if !self.delays.is_empty() {
+            parameters.push(parameter("DELAYS", pairs(&self.delays)));
+        }
+        if !self.warps.is_empty() {
+            parameters.push(parameter("WARPS", pairs(&self.warps)));
+        }
+        parameters.extend(self.charts.iter().map(Chart::to_parameter));
+        parameters
+    }
+
+    /// The string field stored under `key`, which must be uppercase.
+    fn text_field(&mut self, key: &str) -> Option<&mut String> {
+        Some(match key {
+            "TITLE" => &mut self.title,
+            "SUBTITLE" => &mut self.subtitle,
+            "ARTIST" => &mut self.artist,
+            "TITLETRANSLIT" => &mut self.title_translit,
+            "SUBTITLETRANSLIT" => &mut self.subtitle_translit,
+            "ARTISTTRANSLIT" => &mut self.artist_translit,
+            "GENRE" => &mut self.genre,
+            "CREDIT" => &mut self.credit,
+            "MUSIC" => &mut self.music,
+            "BANNER" => &mut self.banner,
+            "BACKGROUND" => &mut self.background,
+            _ => return None,
+        })
+    }
+}
+
+/// Keys of the text fields, in the order StepMania writes them.
+const TEXT_FIELDS: [&str; 11] = [
+    "TITLE", "SUBTITLE", "ARTIST", "TITLETRANSLIT", "SUBTITLETRANSLIT", "ARTISTTRANSLIT", "GENRE", "CREDIT", "BANNER",
+    "BACKGROUND", "MUSIC",
+];
+
+/// The parameters [`Simfile::to_parameters`] gives, with numbers written with
+/// [`MSDSerializeOptions::float_format`], as a document.
+impl MsdSerialize for Simfile {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        self.to_parameters(&options.float_format).serialize_msd(writer, options)
+    }
+}
+
+fn parse_number(value: &str) -> Result<f64, FieldErrorReason> {
+    value.trim().parse().map_err(|_| FieldErrorReason::InvalidNumber)
+}
+
+fn parse_pairs(value: &str) -> Result<Vec<(f64, f64)>, FieldErrorReason> {
+    parse_beat_value_pairs(value).map_err(|_| FieldErrorReason::InvalidBeatValuePair)
+}
+
+/// Write `(beat, value)` pairs, like [`Simfile::bpms`], back to the text of a `beat=value,...` list.
+///
+/// Entries are joined by `separator`: `","` for a single line, or `"\n,"` to put every entry on its own line
+/// like StepMania does.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::serialize::FloatFormat;
+/// # use msdparser::simfile::format_beat_value_pairs;
+/// let bpms = [(0.0, 181.685), (304.0, 90.843)];
+///
+/// assert_eq!("0.000000=181.685000,304.000000=90.843000", format_beat_value_pairs(&bpms, &FloatFormat::new(), ","));
+/// assert_eq!("0.000=181.685\n,304.000=90.843", format_beat_value_pairs(&bpms, &FloatFormat::new().decimals(Some(3)), "\n,"));
+/// ```
+pub fn format_beat_value_pairs(pairs: &[(f64, f64)], format: &FloatFormat, separator: &str) -> String {
+    pairs.iter()
+        .map(|&(beat, value)| format!("{}={}", format.format(beat), format.format(value)))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+impl TryFrom<&[MSDParameter]> for Simfile {
+    type Error = SimfileError;
+
+    /// Convert every known field, collecting all failures instead of stopping at the first.
+    fn try_from(parameters: &[MSDParameter]) -> Result<Self, Self::Error> {
+        Simfile::convert(parameters, false)
+    }
+}
+
+impl Simfile {
+    /// Convert the parameters of a `.dwi` file, whose timing StepMania reads from other keys than in `.sm` files.
+    ///
+    /// `#BPM` is the BPM at beat 0 and `#GAP` the milliseconds before it, so it gives an [`offset`](Simfile::offset)
+    /// of `-GAP / 1000`. `#CHANGEBPM` and `#FREEZE` list `index=value` pairs where the index counts sixteenth notes,
+    /// and freezes last milliseconds; they become [`bpms`](Simfile::bpms) and [`stops`](Simfile::stops) in beats
+    /// and seconds. The `.sm` timing keys are ignored, as StepMania does for DWI files, and so is `#DISPLAYBPM`,
+    /// which DWI writes in a syntax of its own.
+    ///
+    /// # Errors
+    ///
+    /// Returns every field that failed to convert, like `Simfile::try_from`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use msdparser::parse_msd;
+    /// # use msdparser::simfile::Simfile;
+    /// let parameters: Vec<_> = parse_msd(&b"#BPM:150;#GAP:250;#CHANGEBPM:64=300;#FREEZE:32=500;"[..], true, false)
+    ///     .collect::<Result<_, _>>()
+    ///     .unwrap();
+    /// let simfile = Simfile::from_dwi(&parameters).unwrap();
+    ///
+    /// assert_eq!(-0.25, simfile.offset);
+    /// assert_eq!(vec![(0.0, 150.0), (16.0, 300.0)], simfile.bpms);
+    /// assert_eq!(vec![(8.0, 0.5)], simfile.stops);
+    /// ```
+    pub fn from_dwi(parameters: &[MSDParameter]) -> Result<Self, SimfileError> {
+        Simfile::convert(parameters, true)
+    }
+
+    fn convert(parameters: &[MSDParameter], dwi: bool) -> Result<Self, SimfileError> {
+        let mut simfile = Simfile { charts: charts(parameters), ..Simfile::default() };
+        let mut errors = Vec::new();
+        let mut dwi_bpm = None;
+        let mut dwi_changes = Vec::new();
+
+        // The parameters of an .ssc chart, from its #NOTEDATA on, belong to the chart rather than the song
+        let header = parameters.iter()
+            .take_while(|parameter| !parameter.components.first().is_some_and(|key| key.trim().eq_ignore_ascii_case("NOTEDATA")));
+        for parameter in header {
+            let (Some(key), Some(value)) = (parameter.components.first(), parameter.components.get(1)) else { continue };
+            if value.trim().is_empty() {
+                continue;
+            }
+
+            let key_upper = key.trim().to_ascii_uppercase();
+            if let Some(field) = simfile.text_field(&key_upper) {
+                *field = value.clone();
+                continue;
+            }
+
+            let result = match (key_upper.as_str(), dwi) {
+                ("SAMPLESTART", _) => parse_number(value).map(|start| simfile.sample_start = Some(start)),
+                ("SAMPLELENGTH", _) => parse_number(value).map(|length| simfile.sample_length = Some(length)),
+                ("DISPLAYBPM", false) => parameter.components[1..].join(":").parse()
+                    .map(|display_bpm| simfile.display_bpm = Some(display_bpm))
+                    .map_err(|_| FieldErrorReason::InvalidDisplayBpm),
+                ("OFFSET", false) => parse_number(value).map(|offset| simfile.offset = offset),
+                ("BPMS", false) => parse_pairs(value).map(|bpms| simfile.bpms = bpms),
+                ("STOPS" | "FREEZES", false) => parse_pairs(value).map(|stops| simfile.stops = stops),
+                ("DELAYS", false) => parse_pairs(value).map(|delays| simfile.delays = delays),
+                ("WARPS", false) => parse_pairs(value).map(|warps| simfile.warps = warps),
+                ("GAP", true) => parse_number(value).map(|gap| simfile.offset = -gap / 1000.0),
+                ("BPM", true) => parse_number(value).map(|bpm| dwi_bpm = Some(bpm)),
+                ("CHANGEBPM", true) => parse_pairs(value)
+                    .map(|changes| dwi_changes = changes.into_iter().map(|(index, bpm)| (index / 4.0, bpm)).collect()),
+                ("FREEZE", true) => parse_pairs(value)
+                    .map(|freezes| simfile.stops = freezes.into_iter().map(|(index, ms)| (index / 4.0, ms / 1000.0)).collect()),
+                _ => Ok(()),
+            };
+
+            if let Err(reason) = result {
+                errors.push(FieldError {
+                    key: key.clone(),
+                    value: value.clone(),
+                    reason,
+                    span: parameter.span.clone(),
+                });
+            }
+        }
+
+        if dwi {
+            simfile.bpms = dwi_bpm.map(|bpm| (0.0, bpm)).into_iter().chain(dwi_changes).collect();
+        }
+
+        if errors.is_empty() {
+            Ok(simfile)
+        } else {
+            Err(SimfileError { errors })
+        }
+    }
+}
+
+impl TryFrom<&Vec<MSDParameter>> for Simfile {
+    type Error = SimfileError;
+
+    fn try_from(parameters: &Vec<MSDParameter>) -> Result<Self, Self::Error> {
+        Simfile::try_from(parameters.as_slice())
+    }
+}
+
+impl TryFrom<&MSDDocument> for Simfile {
+    type Error = SimfileError;
+
+    fn try_from(document: &MSDDocument) -> Result<Self, Self::Error> {
+        Simfile::try_from(document.parameters())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::parser::parse_msd;
+
+    fn parse(input: &[u8]) -> Vec<MSDParameter> {
+        parse_msd(input, true, false).map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn test_springtime() {
+        let parameters = parse(&fs::read("testdata/Springtime.ssc").unwrap());
+        let simfile = Simfile::try_from(&parameters).unwrap();
+        assert_eq!(Ok(&simfile), Simfile::try_from(&MSDDocument::from(parameters)).as_ref());
+
+        assert_eq!(("Springtime", "Kommisar"), (simfile.title.as_str(), simfile.artist.as_str()));
+        assert_eq!(vec![(0.0, 181.685)], simfile.bpms);
+        assert_eq!("", simfile.credit);
+        assert_eq!(9, simfile.charts.len());
+        assert_eq!(("dance-single", "Challenge", "12"), (simfile.charts[0].steps_type.as_str(), simfile.charts[0].difficulty.as_str(), simfile.charts[0].meter.as_str()));
+    }
+
+    #[test]
+    fn test_sort_charts() {
+        let mut simfile = Simfile::try_from(&parse(b"#NOTES:dance-single::Hard:9::;#NOTES:dance-single::Easy:3::;")).unwrap();
+        simfile.sort_charts(&CanonicalOrder::new().difficulties(["Hard", "Easy"]));
+        assert_eq!("Hard", simfile.charts[0].difficulty);
+
+        simfile.sort_charts(&CanonicalOrder::new());
+        assert_eq!("Easy", simfile.charts[0].difficulty);
+    }
+
+    #[test]
+    fn test_aggregated_errors() {
+        let parameters = parse(b"#TITLE:A;\n#OFFSET:abc;\n#SAMPLESTART:;\n#BPMS:0=120,4=x;\n#STOPS:1=0.5,;");
+        let error = Simfile::try_from(&parameters).unwrap_err();
+
+        assert_eq!(vec![
+            FieldError { key: "OFFSET".to_string(), value: "abc".to_string(), reason: FieldErrorReason::InvalidNumber, span: Some(10..22) },
+            FieldError { key: "BPMS".to_string(), value: "0=120,4=x".to_string(), reason: FieldErrorReason::InvalidBeatValuePair, span: Some(38..54) },
+        ], error.errors);
+        assert!(error.to_string().starts_with("SimfileError: 2 field(s) failed conversion\n  #OFFSET:abc: not a number (bytes 10..22)"));
+    }
+
+    #[test]
+    fn test_delays_and_warps() {
+        let simfile = Simfile::try_from(&parse(b"#DELAYS:4=0.25,;\n#WARPS: 8=1.5 ;")).unwrap();
+
+        assert_eq!((vec![(4.0, 0.25)], vec![(8.0, 1.5)]), (simfile.delays, simfile.warps));
+    }
+
+    #[test]
+    fn test_from_dwi() {
+        let parameters = parse(b"#TITLE:A;\n#CHANGEBPM:8=240,16=120;\n#BPM:150.5;\n#GAP:-120;\n#FREEZE:4=250;\n#OFFSET:9;\n#BPMS:0=1;");
+        let simfile = Simfile::from_dwi(&parameters).unwrap();
+
+        assert_eq!("A", simfile.title);
+        assert_eq!(0.12, simfile.offset);
+        assert_eq!(vec![(0.0, 150.5), (2.0, 240.0), (4.0, 120.0)], simfile.bpms);
+        assert_eq!(vec![(1.0, 0.25)], simfile.stops);
+
+        let error = Simfile::from_dwi(&parse(b"#BPM:fast;")).unwrap_err();
+        assert_eq!(FieldErrorReason::InvalidNumber, error.errors[0].reason);
+    }
+
+    #[test]
+    fn test_display_bpm() {
+        let simfile = Simfile::try_from(&parse(b"#DISPLAYBPM:100:200;")).unwrap();
+        assert_eq!(Some(DisplayBpm::Range(100.0, 200.0)), simfile.display_bpm);
+
+        let error = Simfile::try_from(&parse(b"#DISPLAYBPM:fast;")).unwrap_err();
+        assert_eq!(FieldErrorReason::InvalidDisplayBpm, error.errors[0].reason);
+    }
+
+    #[test]
+    fn test_pairs_round_trip() {
+        let parameters = parse(&fs::read("testdata/Springtime.ssc").unwrap());
+        let simfile = Simfile::try_from(&parameters).unwrap();
+        let format = FloatFormat::new().decimals(Some(3));
+
+        assert_eq!("0.000=181.685", format_beat_value_pairs(&simfile.bpms, &format, "\n,"));
+        assert_eq!(Ok(simfile.bpms.clone()), parse_pairs(&format_beat_value_pairs(&simfile.bpms, &FloatFormat::new(), ",")));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let input = b"#TITLE:A\\:B;#OFFSET:-0.5;#DISPLAYBPM:100:200;#BPMS:0=120,4=240;#WARPS:8=1;\
+            #NOTES:dance-single:me:Hard:9:0.5,1:0000\n;#SINGLE:MANIAC:8:0000;";
+        let simfile = Simfile::try_from(&parse(input)).unwrap();
+        let options = MSDSerializeOptions::step_mania().float_format(FloatFormat::new().trim_trailing_zeros(true));
+        let output = simfile.to_msd_string(&options).unwrap();
+
+        assert!(output.starts_with("#TITLE:A\\:B;\n#SUBTITLE:;\n"));
+        assert!(output.contains("#OFFSET:-0.5;\n#DISPLAYBPM:100:200;\n#BPMS:0=120,4=240;\n#STOPS:;\n#WARPS:8=1;\n"));
+        assert!(output.ends_with("#NOTES:dance-single:me:Hard:9:0.5,1:0000;\n#SINGLE:MANIAC:8:0000;\n"));
+        assert_eq!(Ok(simfile), Simfile::try_from(&parse(output.as_bytes())));
+    }
+}
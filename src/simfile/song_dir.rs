@@ -0,0 +1,218 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::{error, fmt};
+
+use super::{Simfile, SimfileError};
+use crate::parser::{parse_msd_with_options, MSDParserError, MSDParserOptions};
+
+/// Simfile extensions in the order StepMania prefers them.
+pub const SONG_DIR_PREFERENCE: [&str; 3] = ["ssc", "sm", "dwi"];
+
+/// Custom error type for [`load_song_dir`], naming the file that failed.
+#[derive(Debug)]
+pub enum SongDirError {
+    /// The directory contains no `.ssc`, `.sm` or `.dwi` file.
+    NoSimfile(PathBuf),
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, MSDParserError),
+    Conversion(PathBuf, SimfileError),
+}
+
+impl fmt::Display for SongDirError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SongDirError::NoSimfile(path) => write!(f, "{}: no simfile found", path.display()),
+            SongDirError::Io(path, e) => write!(f, "{}: IO Error: {}", path.display(), e),
+            SongDirError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            SongDirError::Conversion(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl error::Error for SongDirError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SongDirError::NoSimfile(_) => None,
+            SongDirError::Io(_, e) => Some(e),
+            SongDirError::Parse(_, e) => Some(e),
+            SongDirError::Conversion(_, e) => Some(e),
+        }
+    }
+}
+
+/// Something suspicious about a song directory that did not prevent loading it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SongDirWarning {
+    /// Several files share the preferred extension; the first by name was used.
+    AmbiguousSimfile { ignored: PathBuf },
+    /// A sibling simfile disagrees with the one used on a core metadata field.
+    MetadataMismatch { sibling: PathBuf, key: &'static str, used: String, sibling_value: String },
+    /// A sibling simfile could not be loaded for comparison.
+    UnreadableSibling { sibling: PathBuf, message: String },
+}
+
+impl fmt::Display for SongDirWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SongDirWarning::AmbiguousSimfile { ignored } => write!(f, "{}: ignored, another file has the same extension", ignored.display()),
+            SongDirWarning::MetadataMismatch { sibling, key, used, sibling_value } => {
+                write!(f, "{}: #{} is {:?}, but {:?} in the file used", sibling.display(), key, sibling_value, used)
+            },
+            SongDirWarning::UnreadableSibling { sibling, message } => write!(f, "{}: {}", sibling.display(), message),
+        }
+    }
+}
+
+/// The simfile picked from a song directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SongDir {
+    /// The file that was loaded.
+    pub path: PathBuf,
+    pub simfile: Simfile,
+    /// Backup files such as `song.sm.old`, which StepMania never loads.
+    pub backups: Vec<PathBuf>,
+    pub warnings: Vec<SongDirWarning>,
+}
+
+fn extension_of(path: &Path) -> Option<String> {
+    path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase)
+}
+
+fn is_backup(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let lower = name.to_ascii_lowercase();
+    let stem = lower.trim_end_matches('~').trim_end_matches(".old").trim_end_matches(".bak");
+    stem != lower && SONG_DIR_PREFERENCE.iter().any(|e| stem.ends_with(&format!(".{}", e)))
+}
+
+fn load_simfile(path: &Path) -> Result<Simfile, SongDirError> {
+    let file = File::open(path).map_err(|e| SongDirError::Io(path.to_path_buf(), e))?;
+    let parameters = parse_msd_with_options(file, MSDParserOptions::for_path(path))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| SongDirError::Parse(path.to_path_buf(), e))?;
+    let simfile = if extension_of(path).as_deref() == Some("dwi") {
+        Simfile::from_dwi(&parameters)
+    } else {
+        Simfile::try_from(parameters.as_slice())
+    };
+    simfile.map_err(|e| SongDirError::Conversion(path.to_path_buf(), e))
+}
+
+/// Load the simfile of a song directory the way StepMania picks it: `.ssc` over `.sm` over `.dwi`.
+///
+/// A `.dwi` file is read in [`Dialect::Dwi`](crate::lexer::Dialect::Dwi), so backslashes in it stay as written,
+/// and converted with [`Simfile::from_dwi`], so it gets its timing from `#BPM`, `#GAP` and friends.
+///
+/// Backups like `song.sm.old`, `song.ssc.bak` or `song.sm~` are never loaded but are listed in the result.
+/// The other simfiles are loaded too, and a warning is added for every core field (`TITLE`, `SUBTITLE`, `ARTIST`)
+/// on which they disagree with the file used, since that usually means one of them is stale.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be read, contains no simfile, or the preferred file cannot be loaded.
+/// Siblings that fail to load only produce a warning.
+pub fn load_song_dir<P: AsRef<Path>>(path: P) -> Result<SongDir, SongDirError> {
+    let dir = path.as_ref();
+    let mut candidates = Vec::new();
+    let mut backups = Vec::new();
+
+    for entry in fs::read_dir(dir).map_err(|e| SongDirError::Io(dir.to_path_buf(), e))? {
+        let path = entry.map_err(|e| SongDirError::Io(dir.to_path_buf(), e))?.path();
+        if !path.is_file() {
+            continue;
+        }
+        if is_backup(&path) {
+            backups.push(path);
+        } else if let Some(rank) = extension_of(&path).and_then(|e| SONG_DIR_PREFERENCE.iter().position(|p| *p == e)) {
+            candidates.push((rank, path));
+        }
+    }
+    candidates.sort();
+    backups.sort();
+
+    let mut candidates = candidates.into_iter();
+    let (rank, used) = candidates.next().ok_or_else(|| SongDirError::NoSimfile(dir.to_path_buf()))?;
+    let simfile = load_simfile(&used)?;
+    let mut warnings = Vec::new();
+
+    for (sibling_rank, sibling) in candidates {
+        if sibling_rank == rank {
+            warnings.push(SongDirWarning::AmbiguousSimfile { ignored: sibling });
+            continue;
+        }
+        let other = match load_simfile(&sibling) {
+            Ok(other) => other,
+            Err(e) => {
+                warnings.push(SongDirWarning::UnreadableSibling { sibling, message: e.to_string() });
+                continue;
+            },
+        };
+        for (key, used_value, sibling_value) in [
+            ("TITLE", &simfile.title, &other.title),
+            ("SUBTITLE", &simfile.subtitle, &other.subtitle),
+            ("ARTIST", &simfile.artist, &other.artist),
+        ] {
+            if used_value.trim() != sibling_value.trim() {
+                warnings.push(SongDirWarning::MetadataMismatch {
+                    sibling: sibling.clone(),
+                    key,
+                    used: used_value.clone(),
+                    sibling_value: sibling_value.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(SongDir { path: used, simfile, backups, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_load_song_dir() {
+        let dir = TempDir::new("song-dir");
+        fs::write(dir.join("song.sm"), "#TITLE:Old Title;\n#ARTIST:K;").unwrap();
+        fs::write(dir.join("song.SSC"), "#TITLE:New Title;\n#ARTIST:K;").unwrap();
+        fs::write(dir.join("song.ssc.old"), "#TITLE:Older;").unwrap();
+        fs::write(dir.join("song.dwi"), "#TITLE:New Title;\n#ARTIST:K;\n#GAP:x;").unwrap();
+
+        let song = load_song_dir(dir.path()).unwrap();
+        assert_eq!(dir.join("song.SSC"), song.path);
+        assert_eq!("New Title", song.simfile.title);
+        assert_eq!(vec![dir.join("song.ssc.old")], song.backups);
+        assert_eq!(2, song.warnings.len());
+        assert_eq!(SongDirWarning::MetadataMismatch {
+            sibling: dir.join("song.sm"),
+            key: "TITLE",
+            used: "New Title".to_string(),
+            sibling_value: "Old Title".to_string(),
+        }, song.warnings[0]);
+        assert!(matches!(&song.warnings[1], SongDirWarning::UnreadableSibling { sibling, .. } if *sibling == dir.join("song.dwi")));
+
+        let missing = dir.join("missing");
+        assert!(matches!(load_song_dir(&missing), Err(ref e @ SongDirError::Io(..)) if e.source().is_some()));
+    }
+
+    #[test]
+    fn test_dwi_timing() {
+        let dir = TempDir::new("song-dir-dwi");
+        fs::write(dir.join("song.dwi"), "#TITLE:A\\B;\n#BPM:150;\n#GAP:500;\n#CHANGEBPM:16=300;\n#SINGLE:BASIC:3:0000;").unwrap();
+
+        let song = load_song_dir(dir.path()).unwrap();
+        assert_eq!("A\\B", song.simfile.title);
+        assert_eq!(-0.5, song.simfile.offset);
+        assert_eq!(vec![(0.0, 150.0), (4.0, 300.0)], song.simfile.bpms);
+        assert_eq!(1, song.simfile.charts.len());
+
+        fs::write(dir.join("song.dwi"), "#BPM:x;").unwrap();
+        let error = load_song_dir(dir.path()).unwrap_err();
+        assert!(matches!(error, SongDirError::Conversion(..)));
+        assert!(error.source().unwrap().to_string().contains("not a number"));
+    }
+}
@@ -0,0 +1,239 @@
+use std::{error, fmt};
+
+use super::Simfile;
+use crate::charts::Chart;
+
+/// How [`merge_charts`] resolves a chart that takes the same slot as a chart already in the target.
+///
+/// Two charts take the same slot when their steps type and difficulty match, ignoring ASCII case, and for
+/// `Edit` charts also their description, since a simfile can hold several edits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum ConflictPolicy {
+    /// Keep the chart already in the target.
+    KeepOurs,
+    /// Replace it in place with the incoming chart.
+    TakeTheirs,
+    /// Keep it and append the incoming chart.
+    KeepBoth,
+    /// Reject the merge.
+    #[default]
+    Error,
+}
+
+/// Custom error type for [`merge_charts`]. The target is left unchanged.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MergeChartsError {
+    /// The simfiles play differently because of their `field`, one of `offset`, `bpms`, `stops`, `delays` or
+    /// `warps`, so the incoming charts would play out of sync.
+    TimingMismatch { field: &'static str },
+    /// Under [`ConflictPolicy::Error`], the incoming chart at `index` takes the slot of an existing chart.
+    Conflict { index: usize, steps_type: String, difficulty: String },
+}
+
+impl fmt::Display for MergeChartsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeChartsError::TimingMismatch { field } => write!(f, "the simfiles have different {}", field),
+            MergeChartsError::Conflict { index, steps_type, difficulty } => {
+                write!(f, "chart {} ({} {}) is already present", index, steps_type, difficulty)
+            },
+        }
+    }
+}
+
+impl error::Error for MergeChartsError {}
+
+fn same_slot(a: &Chart, b: &Chart) -> bool {
+    a.steps_type.eq_ignore_ascii_case(&b.steps_type)
+        && a.difficulty.eq_ignore_ascii_case(&b.difficulty)
+        && (!a.difficulty.eq_ignore_ascii_case("Edit") || a.description == b.description)
+}
+
+/// How far apart two timing values may be and still count as equal, to allow for the precision they were written with.
+const TIMING_TOLERANCE: f64 = 1e-6;
+
+/// The segments of a timing field that affect playback, in order of their beat.
+///
+/// Zero-length stops, delays and warps do nothing, and neither does a BPM change to the BPM already in effect.
+fn effective_segments(pairs: &[(f64, f64)], bpms: bool) -> Vec<(f64, f64)> {
+    let mut sorted = pairs.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut segments: Vec<(f64, f64)> = Vec::with_capacity(sorted.len());
+    for (beat, value) in sorted {
+        let redundant = match segments.last() {
+            Some(&(_, previous)) if bpms => (value - previous).abs() <= TIMING_TOLERANCE,
+            _ => !bpms && value.abs() <= TIMING_TOLERANCE,
+        };
+        if !redundant {
+            segments.push((beat, value));
+        }
+    }
+    segments
+}
+
+fn same_segments(a: &[(f64, f64)], b: &[(f64, f64)], bpms: bool) -> bool {
+    let (a, b) = (effective_segments(a, bpms), effective_segments(b, bpms));
+    a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| {
+        (a.0 - b.0).abs() <= TIMING_TOLERANCE && (a.1 - b.1).abs() <= TIMING_TOLERANCE
+    })
+}
+
+/// The first timing field in which the simfiles play differently, if any.
+fn timing_difference(a: &Simfile, b: &Simfile) -> Option<&'static str> {
+    if (a.offset - b.offset).abs() > TIMING_TOLERANCE {
+        Some("offset")
+    } else if !same_segments(&a.bpms, &b.bpms, true) {
+        Some("bpms")
+    } else if !same_segments(&a.stops, &b.stops, false) {
+        Some("stops")
+    } else if !same_segments(&a.delays, &b.delays, false) {
+        Some("delays")
+    } else if !same_segments(&a.warps, &b.warps, false) {
+        Some("warps")
+    } else {
+        None
+    }
+}
+
+impl Simfile {
+    /// A copy of this simfile holding only the chart at `index`, e.g. to hand a single chart to a stepartist,
+    /// or `None` if there is no such chart.
+    ///
+    /// The song metadata and timing are kept, so the chart plays the same on its own.
+    pub fn extract_chart(&self, index: usize) -> Option<Simfile> {
+        let chart = self.charts.get(index)?.clone();
+        Some(Simfile { charts: vec![chart], ..self.clone() })
+    }
+}
+
+/// Add the charts of `from` to `into`, e.g. to consolidate the work of several stepartists into one simfile.
+///
+/// Charts are only meaningful against the timing they were written for, and a [`Simfile`] has one timing for
+/// all its charts, so both simfiles must play the same. Timing is compared by its effect rather than as written:
+/// the order of the segments, zero-length stops, delays and warps, BPM changes to the BPM already in effect and
+/// rounding in the last decimals make no difference. If `into` has no charts yet, it takes the timing of `from`
+/// instead, since there is nothing to keep in sync with. Charts taking the slot of an existing chart are resolved
+/// with `policy`; the others are appended in order.
+///
+/// # Errors
+///
+/// Returns an error, leaving `into` unchanged, if `into` has charts and the timing plays differently, or if
+/// `policy` is [`ConflictPolicy::Error`] and a chart conflicts.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::parse_msd;
+/// # use msdparser::simfile::{merge_charts, ConflictPolicy, Simfile};
+/// let parse = |input: &str| {
+///     let parameters: Vec<_> = parse_msd(input.as_bytes(), true, false).map(Result::unwrap).collect();
+///     Simfile::try_from(&parameters).unwrap()
+/// };
+/// let mut song = parse("#BPMS:0=120;#NOTES:dance-single:A:Easy:3::0000;");
+/// let theirs = parse("#BPMS:0=120;#NOTES:dance-single:B:Easy:4::1000;#NOTES:dance-single:B:Hard:9::1111;");
+///
+/// merge_charts(&theirs, &mut song, ConflictPolicy::KeepOurs).unwrap();
+///
+/// let charts: Vec<_> = song.charts.iter().map(|chart| (chart.description.as_str(), chart.difficulty.as_str())).collect();
+/// assert_eq!(vec![("A", "Easy"), ("B", "Hard")], charts);
+/// ```
+pub fn merge_charts(from: &Simfile, into: &mut Simfile, policy: ConflictPolicy) -> Result<(), MergeChartsError> {
+    if into.charts.is_empty() {
+        into.offset = from.offset;
+        into.bpms = from.bpms.clone();
+        into.stops = from.stops.clone();
+        into.delays = from.delays.clone();
+        into.warps = from.warps.clone();
+    } else if let Some(field) = timing_difference(from, into) {
+        return Err(MergeChartsError::TimingMismatch { field });
+    }
+
+    let slots: Vec<Option<usize>> = from.charts.iter()
+        .map(|chart| into.charts.iter().position(|existing| same_slot(existing, chart)))
+        .collect();
+    if policy == ConflictPolicy::Error {
+        if let Some(index) = slots.iter().position(Option::is_some) {
+            let chart = &from.charts[index];
+            return Err(MergeChartsError::Conflict {
+                index,
+                steps_type: chart.steps_type.clone(),
+                difficulty: chart.difficulty.clone(),
+            });
+        }
+    }
+
+    for (chart, slot) in from.charts.iter().zip(slots) {
+        match (slot, policy) {
+            (Some(_), ConflictPolicy::KeepOurs) => {},
+            (Some(existing), ConflictPolicy::TakeTheirs) => into.charts[existing] = chart.clone(),
+            _ => into.charts.push(chart.clone()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_msd;
+
+    fn parse(input: &str) -> Simfile {
+        let parameters: Vec<_> = parse_msd(input.as_bytes(), true, false).map(Result::unwrap).collect();
+        Simfile::try_from(&parameters).unwrap()
+    }
+
+    #[test]
+    fn test_extract_chart() {
+        let simfile = parse("#TITLE:A;#BPMS:0=120;#NOTES:dance-single::Easy:3::0000;#NOTES:dance-single::Hard:9::1111;");
+        let extracted = simfile.extract_chart(1).unwrap();
+
+        assert_eq!(vec![simfile.charts[1].clone()], extracted.charts);
+        assert_eq!(Simfile { charts: Vec::new(), ..simfile.clone() }, Simfile { charts: Vec::new(), ..extracted });
+        assert_eq!(None, simfile.extract_chart(2));
+    }
+
+    #[test]
+    fn test_merge_charts() {
+        let song = parse("#BPMS:0=120;#NOTES:dance-single:A:Hard:9::0000;#NOTES:dance-single:A:Edit:10::0000;");
+        let theirs = parse("#BPMS:0=120;#NOTES:dance-single:B:hard:8::1000;#NOTES:dance-single:B:Edit:11::1000;");
+
+        let mut merged = song.clone();
+        merge_charts(&theirs, &mut merged, ConflictPolicy::TakeTheirs).unwrap();
+        let descriptions: Vec<_> = merged.charts.iter().map(|chart| chart.description.as_str()).collect();
+        assert_eq!(vec!["B", "A", "B"], descriptions);
+
+        let mut merged = song.clone();
+        assert_eq!(
+            Err(MergeChartsError::Conflict { index: 0, steps_type: "dance-single".to_string(), difficulty: "hard".to_string() }),
+            merge_charts(&theirs, &mut merged, ConflictPolicy::default())
+        );
+        assert_eq!(song, merged);
+
+        assert_eq!(
+            Err(MergeChartsError::TimingMismatch { field: "bpms" }),
+            merge_charts(&parse("#BPMS:0=121;"), &mut merged, ConflictPolicy::KeepBoth)
+        );
+    }
+
+    #[test]
+    fn test_merge_charts_timing() {
+        let song = parse("#OFFSET:-0.1;#BPMS:0=120,16=180;#STOPS:8=0.5;#NOTES:dance-single:A:Easy:3::0000;");
+        let theirs = parse("#OFFSET:-0.1000000001;#BPMS:16=180.000,0=120,4=120;#STOPS:8=0.5,12=0;#NOTES:dance-single:B:Hard:9::1000;");
+
+        let mut merged = song.clone();
+        merge_charts(&theirs, &mut merged, ConflictPolicy::Error).unwrap();
+        assert_eq!((song.bpms.clone(), 2), (merged.bpms, merged.charts.len()));
+
+        let mut merged = song.clone();
+        assert_eq!(
+            Err(MergeChartsError::TimingMismatch { field: "stops" }),
+            merge_charts(&parse("#OFFSET:-0.1;#BPMS:0=120,16=180;#STOPS:8=0.25;"), &mut merged, ConflictPolicy::KeepBoth)
+        );
+        assert_eq!(song, merged);
+
+        let mut empty = parse("#TITLE:Springtime;#BPMS:0=60;");
+        merge_charts(&theirs, &mut empty, ConflictPolicy::Error).unwrap();
+        assert_eq!((theirs.bpms.clone(), theirs.stops.clone(), 1), (empty.bpms, empty.stops, empty.charts.len()));
+    }
+}
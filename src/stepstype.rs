@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::{error, fmt};
+
+/// Column counts of the steps types known to StepMania.
+pub const BUILTIN_STEPS_TYPES: [(&str, usize); 36] = [
+    ("dance-single", 4),
+    ("dance-double", 8),
+    ("dance-couple", 8),
+    ("dance-solo", 6),
+    ("dance-threepanel", 3),
+    ("dance-routine", 8),
+    ("pump-single", 5),
+    ("pump-halfdouble", 6),
+    ("pump-double", 10),
+    ("pump-couple", 10),
+    ("pump-routine", 10),
+    ("kb7-single", 7),
+    ("ez2-single", 5),
+    ("ez2-double", 10),
+    ("ez2-real", 7),
+    ("para-single", 5),
+    ("ds3ddx-single", 8),
+    ("bm-single5", 6),
+    ("bm-double5", 12),
+    ("bm-single7", 8),
+    ("bm-double7", 16),
+    ("maniax-single", 4),
+    ("maniax-double", 8),
+    ("techno-single4", 4),
+    ("techno-single5", 5),
+    ("techno-single8", 8),
+    ("techno-double4", 8),
+    ("techno-double5", 10),
+    ("techno-double8", 16),
+    ("pnm-five", 5),
+    ("pnm-nine", 9),
+    ("lights-cabinet", 6),
+    ("kickbox-human", 4),
+    ("kickbox-quadarm", 4),
+    ("kickbox-insect", 6),
+    ("kickbox-arachnid", 8),
+];
+
+/// Number of columns of a built-in steps type, ignoring ASCII case and surrounding whitespace.
+pub fn columns_for(steps_type: &str) -> Option<usize> {
+    let steps_type = steps_type.trim();
+    BUILTIN_STEPS_TYPES.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(steps_type))
+        .map(|&(_, columns)| columns)
+}
+
+/// A note row that does not have the number of columns its steps type requires.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct RowWidthError {
+    pub row: String,
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for RowWidthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "row '{}' has {} columns, expected {}", self.row, self.found, self.expected)
+    }
+}
+
+impl error::Error for RowWidthError {}
+
+/// Table of steps types and their column counts, extensible with custom game modes.
+///
+/// Starts out with [`BUILTIN_STEPS_TYPES`]; registered entries take precedence over built-in ones.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StepsTypeRegistry {
+    custom: HashMap<String, usize>,
+}
+
+impl StepsTypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a custom steps type, or override the column count of a built-in one.
+    pub fn register(&mut self, steps_type: &str, columns: usize) -> &mut Self {
+        self.custom.insert(steps_type.trim().to_ascii_lowercase(), columns);
+        self
+    }
+
+    /// Number of columns of a steps type, ignoring ASCII case and surrounding whitespace.
+    pub fn columns(&self, steps_type: &str) -> Option<usize> {
+        self.custom.get(&steps_type.trim().to_ascii_lowercase())
+            .copied()
+            .or_else(|| columns_for(steps_type))
+    }
+
+    /// Check that a note row has exactly as many columns as the steps type requires.
+    ///
+    /// Returns `None` if the steps type is unknown.
+    pub fn validate_row(&self, steps_type: &str, row: &str) -> Option<Result<(), RowWidthError>> {
+        self.columns(steps_type).map(|expected| validate_row(row, expected))
+    }
+
+    /// A row without any notes for the steps type, or `None` if it is unknown.
+    pub fn blank_row(&self, steps_type: &str) -> Option<String> {
+        self.columns(steps_type).map(blank_row)
+    }
+}
+
+/// Check that a note row (without its line terminator) has exactly `columns` columns.
+///
+/// Columns are counted in characters, so multi-byte garbage is reported rather than miscounted.
+pub fn validate_row(row: &str, columns: usize) -> Result<(), RowWidthError> {
+    let found = row.chars().count();
+    if found == columns {
+        Ok(())
+    } else {
+        Err(RowWidthError { row: row.to_string(), expected: columns, found })
+    }
+}
+
+/// A row of `columns` empty cells, e.g. `0000` for four columns.
+pub fn blank_row(columns: usize) -> String {
+    "0".repeat(columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        assert_eq!(Some(4), columns_for("dance-single"));
+        assert_eq!(Some(8), columns_for(" Dance-Double\n"));
+        assert_eq!(Some(5), columns_for("pump-single"));
+        assert_eq!(None, columns_for("dance-quintuple"));
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = StepsTypeRegistry::new();
+        registry.register("custom-twelve", 12).register("dance-single", 5);
+
+        assert_eq!(Some(12), registry.columns("CUSTOM-twelve"));
+        assert_eq!(Some(5), registry.columns("dance-single"));
+        assert_eq!(Some(10), registry.columns("pump-double"));
+        assert_eq!(Some("000000000000".to_string()), registry.blank_row("custom-twelve"));
+        assert_eq!(None, registry.blank_row("unknown"));
+    }
+
+    #[test]
+    fn test_validate_row() {
+        let registry = StepsTypeRegistry::new();
+
+        assert_eq!(Some(Ok(())), registry.validate_row("dance-single", "1001"));
+        assert_eq!(
+            Some(Err(RowWidthError { row: "10010".to_string(), expected: 4, found: 5 })),
+            registry.validate_row("dance-single", "10010")
+        );
+        assert_eq!(None, registry.validate_row("unknown", "1"));
+    }
+}
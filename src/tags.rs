@@ -0,0 +1,148 @@
+use std::str::FromStr;
+use std::{error, fmt};
+
+macro_rules! known_tags {
+    ($($(#[$doc:meta])* $tag:ident => $key:literal,)*) => {
+        /// A key StepMania knows in `.sm` or `.ssc` files, so code can match on keys instead of string literals.
+        ///
+        /// Parsed with [`str::parse`], ignoring ASCII case and surrounding whitespace, and written back in
+        /// uppercase with [`KnownTag::as_str`].
+        ///
+        /// # Examples
+        ///
+        /// ```rust
+        /// # use msdparser::parse_msd;
+        /// # use msdparser::tags::KnownTag;
+        /// let parameter = parse_msd(b"#bpms:0=120;".as_ref(), true, false).next().unwrap().unwrap();
+        ///
+        /// assert_eq!(Some(KnownTag::Bpms), parameter.known_key());
+        /// assert_eq!("BPMS", KnownTag::Bpms.as_str());
+        /// ```
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "UPPERCASE"))]
+        pub enum KnownTag {
+            $($(#[$doc])* $tag,)*
+        }
+
+        impl KnownTag {
+            /// Every known tag, song tags first and chart tags last.
+            pub const ALL: &'static [KnownTag] = &[$(KnownTag::$tag,)*];
+
+            /// The key as StepMania writes it, e.g. `"BPMS"`.
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(KnownTag::$tag => $key,)*
+                }
+            }
+        }
+    };
+}
+
+known_tags! {
+    /// Format version of an `.ssc` file.
+    Version => "VERSION",
+    Title => "TITLE",
+    Subtitle => "SUBTITLE",
+    Artist => "ARTIST",
+    TitleTranslit => "TITLETRANSLIT",
+    SubtitleTranslit => "SUBTITLETRANSLIT",
+    ArtistTranslit => "ARTISTTRANSLIT",
+    Genre => "GENRE",
+    Origin => "ORIGIN",
+    Credit => "CREDIT",
+    Banner => "BANNER",
+    Background => "BACKGROUND",
+    PreviewVid => "PREVIEWVID",
+    Jacket => "JACKET",
+    CdImage => "CDIMAGE",
+    DiscImage => "DISCIMAGE",
+    LyricsPath => "LYRICSPATH",
+    CdTitle => "CDTITLE",
+    Music => "MUSIC",
+    Preview => "PREVIEW",
+    Offset => "OFFSET",
+    SampleStart => "SAMPLESTART",
+    SampleLength => "SAMPLELENGTH",
+    Selectable => "SELECTABLE",
+    DisplayBpm => "DISPLAYBPM",
+    Bpms => "BPMS",
+    Stops => "STOPS",
+    /// Old name of `#STOPS`.
+    Freezes => "FREEZES",
+    Delays => "DELAYS",
+    Warps => "WARPS",
+    TimeSignatures => "TIMESIGNATURES",
+    TickCounts => "TICKCOUNTS",
+    Combos => "COMBOS",
+    Speeds => "SPEEDS",
+    Scrolls => "SCROLLS",
+    Fakes => "FAKES",
+    Labels => "LABELS",
+    BgChanges => "BGCHANGES",
+    FgChanges => "FGCHANGES",
+    KeySounds => "KEYSOUNDS",
+    Attacks => "ATTACKS",
+    InstrumentTrack => "INSTRUMENTTRACK",
+    MusicLength => "MUSICLENGTH",
+    /// Second at which an `.ssc` song's last note is reached, for the end of the song.
+    LastSecondHint => "LASTSECONDHINT",
+    /// Start of a chart in an `.ssc` file.
+    NoteData => "NOTEDATA",
+    ChartName => "CHARTNAME",
+    StepsType => "STEPSTYPE",
+    Description => "DESCRIPTION",
+    ChartStyle => "CHARTSTYLE",
+    Difficulty => "DIFFICULTY",
+    Meter => "METER",
+    RadarValues => "RADARVALUES",
+    /// A whole chart in an `.sm` file, or the note data of a chart in an `.ssc` file.
+    Notes => "NOTES",
+    Notes2 => "NOTES2",
+}
+
+impl fmt::Display for KnownTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Error for a key that is not a [`KnownTag`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnknownTagError {
+    /// The key as given.
+    pub key: String,
+}
+
+impl fmt::Display for UnknownTagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown tag '{}'", self.key)
+    }
+}
+
+impl error::Error for UnknownTagError {}
+
+impl FromStr for KnownTag {
+    type Err = UnknownTagError;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        let trimmed = key.trim();
+        KnownTag::ALL.iter()
+            .copied()
+            .find(|tag| tag.as_str().eq_ignore_ascii_case(trimmed))
+            .ok_or_else(|| UnknownTagError { key: key.to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for &tag in KnownTag::ALL {
+            assert_eq!(Ok(tag), tag.as_str().parse());
+        }
+        assert_eq!(Ok(KnownTag::NoteData), " notedata ".parse());
+        assert_eq!(Err(UnknownTagError { key: "NOTE".to_string() }), "NOTE".parse::<KnownTag>());
+    }
+}
@@ -0,0 +1,62 @@
+//! Helpers shared by the unit tests.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "fs")]
+use crate::progress::ProgressSink;
+
+/// A fresh directory under the system temporary directory, removed again when dropped.
+///
+/// Each directory gets its own name, so tests running in parallel, in this process or another, never share one.
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("msdparser-{}-{}-{}", name, process::id(), count));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    #[cfg(any(feature = "fs", feature = "simfile"))]
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A [`ProgressSink`] that records every file it is told about and adds up the bytes.
+#[cfg(feature = "fs")]
+#[derive(Default)]
+pub(crate) struct Totals {
+    pub(crate) files: Vec<PathBuf>,
+    pub(crate) bytes: u64,
+}
+
+#[cfg(feature = "fs")]
+impl ProgressSink for Totals {
+    fn on_file(&mut self, path: &Path) {
+        self.files.push(path.to_path_buf());
+    }
+
+    fn on_bytes(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+}
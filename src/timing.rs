@@ -0,0 +1,397 @@
+use std::{error, fmt};
+
+use crate::simfile::Simfile;
+use crate::tags::KnownTag;
+
+/// Largest beat accepted in timing data or passed to [`TimingData::seconds_at_beat`], about 650 hours at 100 BPM.
+pub const MAX_BEAT: f64 = 1e6;
+
+/// Largest offset, stop or delay accepted in timing data, in seconds (one day).
+pub const MAX_SECONDS: f64 = 86_400.0;
+
+/// What to do with timing values that are finite but out of range, like a 10^9 beat stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum OverflowPolicy {
+    /// Reject the timing data with [`TimingErrorKind::OutOfRange`].
+    #[default]
+    Error,
+    /// Clamp the value to [`MAX_BEAT`] or [`MAX_SECONDS`] and carry on.
+    ///
+    /// Values that are not finite, and BPMs of zero, are still rejected.
+    Saturate,
+}
+
+/// Why timing data was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimingErrorKind {
+    /// A beat or value is NaN or infinite.
+    NotFinite,
+    /// A BPM is zero.
+    ZeroBpm,
+    /// A delay or warp has a negative length.
+    NegativeLength,
+    /// A beat or value is beyond [`MAX_BEAT`] or [`MAX_SECONDS`].
+    OutOfRange,
+    /// There is no positive BPM, so beats can't be converted to seconds.
+    NoBpm,
+    /// A computed time is too large to represent.
+    Overflow,
+}
+
+impl fmt::Display for TimingErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimingErrorKind::NotFinite => write!(f, "not a finite number"),
+            TimingErrorKind::ZeroBpm => write!(f, "BPM is zero"),
+            TimingErrorKind::NegativeLength => write!(f, "length is negative"),
+            TimingErrorKind::OutOfRange => write!(f, "out of range"),
+            TimingErrorKind::NoBpm => write!(f, "no positive BPM"),
+            TimingErrorKind::Overflow => write!(f, "time overflows"),
+        }
+    }
+}
+
+/// Custom error type for timing data, naming the entry that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimingError {
+    /// The key holding the entry, or `None` for the beat passed to [`TimingData::seconds_at_beat`].
+    pub key: Option<KnownTag>,
+    /// Index of the entry within its list; always 0 for `#OFFSET` and the queried beat.
+    pub index: usize,
+    /// Why the entry was rejected, or for the queried beat, why it or the time computed for it was.
+    pub kind: TimingErrorKind,
+}
+
+impl TimingError {
+    fn new(key: Option<KnownTag>, index: usize, kind: TimingErrorKind) -> Self {
+        Self { key, index, kind }
+    }
+}
+
+impl fmt::Display for TimingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.key {
+            Some(key) => write!(f, "TimingError: #{} entry {}: {}", key, self.index, self.kind),
+            None => write!(f, "TimingError: beat: {}", self.kind),
+        }
+    }
+}
+
+impl error::Error for TimingError {}
+
+/// Check that `value` is finite and at most `max` in magnitude, clamping it under [`OverflowPolicy::Saturate`].
+fn bounded(value: f64, max: f64, policy: OverflowPolicy) -> Result<f64, TimingErrorKind> {
+    if !value.is_finite() {
+        return Err(TimingErrorKind::NotFinite);
+    }
+    match policy {
+        OverflowPolicy::Error if value.abs() > max => Err(TimingErrorKind::OutOfRange),
+        OverflowPolicy::Error => Ok(value),
+        OverflowPolicy::Saturate => Ok(value.clamp(-max, max)),
+    }
+}
+
+/// Turn a computed time into an error if it is not finite.
+fn checked(value: f64) -> Result<f64, TimingError> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(TimingError::new(None, 0, TimingErrorKind::Overflow))
+    }
+}
+
+/// Validate the `(beat, value)` pairs of `key`, returning them sorted by beat, or every invalid entry.
+fn validate_pairs(
+    key: KnownTag,
+    pairs: &[(f64, f64)],
+    value_max: f64,
+    policy: OverflowPolicy,
+    errors: &mut Vec<TimingError>,
+) -> Vec<(f64, f64)> {
+    let mut valid = Vec::with_capacity(pairs.len());
+    for (index, &(beat, value)) in pairs.iter().enumerate() {
+        let result = bounded(beat, MAX_BEAT, policy).and_then(|beat| {
+            let value = bounded(value, value_max, policy)?;
+            match key {
+                KnownTag::Bpms if value == 0.0 => Err(TimingErrorKind::ZeroBpm),
+                KnownTag::Delays | KnownTag::Warps if value < 0.0 => Err(TimingErrorKind::NegativeLength),
+                _ => Ok((beat, value)),
+            }
+        });
+        match result {
+            Ok(pair) => valid.push(pair),
+            Err(kind) => errors.push(TimingError::new(Some(key), index, kind)),
+        }
+    }
+    valid.sort_by(|a, b| a.0.total_cmp(&b.0));
+    valid
+}
+
+/// The beat reached `seconds` after `from`, following the positive BPMs of `bpms`, which must not be empty.
+fn beat_after_seconds(bpms: &[(f64, f64)], from: f64, mut seconds: f64) -> f64 {
+    let mut beat = from;
+    loop {
+        let i = bpms.iter().rposition(|&(start, _)| start <= beat).unwrap_or(0);
+        let (_, bpm) = bpms[i];
+        let next = bpms.get(i + 1).map_or(MAX_BEAT, |&(start, _)| start);
+        let end = beat + seconds * bpm / 60.0;
+        if end <= next || next >= MAX_BEAT {
+            return end.min(MAX_BEAT);
+        }
+        seconds -= (next - beat) * 60.0 / bpm;
+        beat = next;
+    }
+}
+
+/// The timing of a song, validated so that converting beats to seconds never yields NaN or infinity.
+///
+/// Built from a [`Simfile`] with [`TimingData::new`]. Entries are sorted by beat. The fields are read with
+/// getters so that nothing can break what validation ensured, such as there always being a BPM.
+///
+/// Negative BPMs and stops, a trick of older `.sm` files, are converted to warps like StepMania does: the
+/// time they rewind is skipped instead, so every BPM and stop is positive. Writing the lists back with
+/// [`format_beat_value_pairs`] converts such a chart to warps for good.
+///
+/// [`format_beat_value_pairs`]: crate::simfile::format_beat_value_pairs
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingData {
+    offset: f64,
+    bpms: Vec<(f64, f64)>,
+    stops: Vec<(f64, f64)>,
+    delays: Vec<(f64, f64)>,
+    warps: Vec<(f64, f64)>,
+}
+
+impl TimingData {
+    /// Validate the timing of `simfile`, handling out-of-range values according to `policy`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first invalid entry; see [`validate_timing`] for all of them.
+    pub fn new(simfile: &Simfile, policy: OverflowPolicy) -> Result<Self, TimingError> {
+        Self::validate(simfile, policy).map_err(|errors| errors[0])
+    }
+
+    fn validate(simfile: &Simfile, policy: OverflowPolicy) -> Result<Self, Vec<TimingError>> {
+        let mut errors = Vec::new();
+        let offset = bounded(simfile.offset, MAX_SECONDS, policy).unwrap_or_else(|kind| {
+            errors.push(TimingError::new(Some(KnownTag::Offset), 0, kind));
+            0.0
+        });
+        let timing = Self {
+            offset,
+            bpms: validate_pairs(KnownTag::Bpms, &simfile.bpms, f64::MAX, policy, &mut errors),
+            stops: validate_pairs(KnownTag::Stops, &simfile.stops, MAX_SECONDS, policy, &mut errors),
+            delays: validate_pairs(KnownTag::Delays, &simfile.delays, MAX_SECONDS, policy, &mut errors),
+            warps: validate_pairs(KnownTag::Warps, &simfile.warps, MAX_BEAT, policy, &mut errors),
+        };
+        if !simfile.bpms.iter().any(|&(_, bpm)| bpm > 0.0) {
+            errors.push(TimingError::new(Some(KnownTag::Bpms), 0, TimingErrorKind::NoBpm));
+        }
+
+        if errors.is_empty() {
+            Ok(timing.negatives_to_warps())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Seconds between the start of the music and beat 0.
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    /// `(beat, bpm)` pairs; never empty, and every BPM is positive.
+    pub fn bpms(&self) -> &[(f64, f64)] {
+        &self.bpms
+    }
+
+    /// `(beat, seconds)` pairs; every stop is positive.
+    pub fn stops(&self) -> &[(f64, f64)] {
+        &self.stops
+    }
+
+    /// `(beat, seconds)` pairs.
+    pub fn delays(&self) -> &[(f64, f64)] {
+        &self.delays
+    }
+
+    /// `(beat, length in beats)` pairs, including those converted from negative BPMs and stops.
+    pub fn warps(&self) -> &[(f64, f64)] {
+        &self.warps
+    }
+
+    /// Replace negative BPMs and stops by the warps that skip the time they rewind.
+    fn negatives_to_warps(mut self) -> Self {
+        let bpms = std::mem::take(&mut self.bpms);
+        self.bpms = bpms.iter().copied().filter(|&(_, bpm)| bpm > 0.0).collect();
+
+        let mut i = 0;
+        while i < bpms.len() {
+            let (start, bpm) = bpms[i];
+            if bpm > 0.0 {
+                i += 1;
+                continue;
+            }
+            // Consecutive negative segments rewind time together
+            let mut rewound = 0.0;
+            while i < bpms.len() && bpms[i].1 < 0.0 {
+                let end = bpms.get(i + 1).map_or(MAX_BEAT, |&(next, _)| next);
+                rewound += (end - bpms[i].0) * 60.0 / -bpms[i].1;
+                i += 1;
+            }
+            let end = bpms.get(i).map_or(MAX_BEAT, |&(next, _)| next);
+            self.warps.push((start, beat_after_seconds(&self.bpms, end, rewound) - start));
+        }
+
+        for &(beat, seconds) in self.stops.iter().filter(|&&(_, seconds)| seconds < 0.0) {
+            self.warps.push((beat, beat_after_seconds(&self.bpms, beat, -seconds) - beat));
+        }
+        self.stops.retain(|&(_, seconds)| seconds >= 0.0);
+        self.warps.sort_by(|a, b| a.0.total_cmp(&b.0));
+        self
+    }
+
+    /// Beats of `start..end` that are not skipped by a warp. Overlapping warps are only counted once.
+    fn unwarped_beats(&self, start: f64, end: f64) -> f64 {
+        let mut covered = start;
+        let mut warped = 0.0;
+        for &(beat, length) in &self.warps {
+            let (from, to) = (beat.max(covered), (beat + length).min(end));
+            if to > from {
+                warped += to - from;
+                covered = to;
+            }
+        }
+        (end - start - warped).max(0.0)
+    }
+
+    /// Whether `beat` is skipped by a warp, so notes on it are neither shown nor judged.
+    pub fn is_warped(&self, beat: f64) -> bool {
+        self.warps.iter().any(|&(start, length)| (start..start + length).contains(&beat))
+    }
+
+    /// Seconds from the start of the music to `beat`, counting BPM changes, warps, and the stops and delays
+    /// before it. A delay on `beat` itself is counted, a stop is not, since it starts after the beat is hit.
+    ///
+    /// Beats before the first BPM change use the first BPM.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `beat` is not finite or beyond [`MAX_BEAT`], or if the time is too large to represent,
+    /// e.g. after a long stretch at a tiny BPM.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use msdparser::simfile::Simfile;
+    /// # use msdparser::timing::{OverflowPolicy, TimingData};
+    /// let mut simfile = Simfile::default();
+    /// simfile.bpms = vec![(0.0, 120.0), (4.0, 60.0)];
+    /// simfile.stops = vec![(2.0, 1.5)];
+    /// let timing = TimingData::new(&simfile, OverflowPolicy::Error).unwrap();
+    ///
+    /// assert_eq!(Ok(1.0), timing.seconds_at_beat(2.0));
+    /// assert_eq!(Ok(4.5), timing.seconds_at_beat(5.0));
+    /// ```
+    pub fn seconds_at_beat(&self, beat: f64) -> Result<f64, TimingError> {
+        let beat = bounded(beat, MAX_BEAT, OverflowPolicy::Error).map_err(|kind| TimingError::new(None, 0, kind))?;
+
+        // Validation guarantees a positive BPM, and the fields can't be changed afterwards
+        let (_, first_bpm) = self.bpms[0];
+        let mut seconds = -self.offset + checked(beat.min(0.0) * 60.0 / first_bpm)?;
+        for (i, &(start, bpm)) in self.bpms.iter().enumerate() {
+            let start = if i == 0 { 0.0 } else { start.max(0.0) };
+            let end = self.bpms.get(i + 1).map_or(beat, |&(next, _)| next.min(beat));
+            if end > start {
+                seconds = checked(seconds + checked(self.unwarped_beats(start, end) * 60.0 / bpm)?)?;
+            }
+        }
+
+        let stops = self.stops.iter().filter(|&&(at, _)| at < beat);
+        let delays = self.delays.iter().filter(|&&(at, _)| at <= beat);
+        for &(_, length) in stops.chain(delays) {
+            seconds = checked(seconds + length)?;
+        }
+        Ok(seconds)
+    }
+}
+
+/// Check the timing of `simfile` for values that would make beat-to-seconds conversion meaningless, like a
+/// zero BPM, NaN or a 10^9 beat stop, returning every problem in the order offset, BPMs, stops, delays, warps.
+///
+/// Validation uses [`OverflowPolicy::Error`], so out-of-range values are reported too.
+pub fn validate_timing(simfile: &Simfile) -> Vec<TimingError> {
+    TimingData::validate(simfile, OverflowPolicy::Error).err().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(bpms: Vec<(f64, f64)>, stops: Vec<(f64, f64)>) -> Simfile {
+        Simfile { bpms, stops, ..Simfile::default() }
+    }
+
+    #[test]
+    fn test_seconds_at_beat() {
+        let simfile = Simfile {
+            offset: 0.5,
+            delays: vec![(8.0, 0.25)],
+            warps: vec![(9.0, 1.0)],
+            ..song(vec![(4.0, 60.0), (0.0, 120.0)], vec![(2.0, 1.5)])
+        };
+        let timing = TimingData::new(&simfile, OverflowPolicy::Error).unwrap();
+
+        assert_eq!(Ok(-1.0), timing.seconds_at_beat(-1.0));
+        assert_eq!(Ok(0.5), timing.seconds_at_beat(2.0));
+        assert_eq!(Ok(3.0), timing.seconds_at_beat(4.0));
+        assert_eq!(Ok(7.25), timing.seconds_at_beat(8.0));
+        assert_eq!(timing.seconds_at_beat(9.0), timing.seconds_at_beat(10.0));
+        assert_eq!(Err(TimingError::new(None, 0, TimingErrorKind::NotFinite)), timing.seconds_at_beat(f64::NAN));
+    }
+
+    #[test]
+    fn test_absurd_timing() {
+        let simfile = Simfile {
+            warps: vec![(1.0, -2.0)],
+            ..song(vec![(0.0, 0.0), (f64::NAN, 120.0)], vec![(1e9, 1.0), (4.0, 1e9)])
+        };
+        let kinds: Vec<_> = validate_timing(&simfile).iter().map(|e| (e.key.unwrap(), e.index, e.kind)).collect();
+
+        assert_eq!(vec![
+            (KnownTag::Bpms, 0, TimingErrorKind::ZeroBpm),
+            (KnownTag::Bpms, 1, TimingErrorKind::NotFinite),
+            (KnownTag::Stops, 0, TimingErrorKind::OutOfRange),
+            (KnownTag::Stops, 1, TimingErrorKind::OutOfRange),
+            (KnownTag::Warps, 0, TimingErrorKind::NegativeLength),
+        ], kinds);
+        assert_eq!("TimingError: #BPMS entry 0: BPM is zero", validate_timing(&simfile)[0].to_string());
+        assert!(validate_timing(&song(vec![(0.0, 120.0)], Vec::new())).is_empty());
+        assert_eq!(TimingErrorKind::NoBpm, validate_timing(&Simfile::default())[0].kind);
+        assert_eq!(TimingErrorKind::NoBpm, validate_timing(&song(vec![(0.0, -120.0)], Vec::new()))[0].kind);
+    }
+
+    #[test]
+    fn test_saturate_and_overflow() {
+        let saturated = TimingData::new(&song(vec![(0.0, 120.0)], vec![(1e9, 1e9)]), OverflowPolicy::Saturate).unwrap();
+        assert_eq!(&[(MAX_BEAT, MAX_SECONDS)], saturated.stops());
+
+        let timing = TimingData::new(&song(vec![(0.0, 1e-320)], Vec::new()), OverflowPolicy::Error).unwrap();
+        assert_eq!(TimingErrorKind::Overflow, timing.seconds_at_beat(MAX_BEAT).unwrap_err().kind);
+    }
+
+    #[test]
+    fn test_negatives_to_warps() {
+        // 1 beat at -60 BPM rewinds 1 second, which takes 2 beats at 120 BPM to make up
+        let timing = TimingData::new(&song(vec![(0.0, 120.0), (4.0, -60.0), (5.0, 120.0)], vec![(8.0, -0.25)]), OverflowPolicy::Error).unwrap();
+
+        assert_eq!(&[(0.0, 120.0), (5.0, 120.0)], timing.bpms());
+        assert_eq!(&[(4.0, 3.0), (8.0, 0.5)], timing.warps());
+        assert!(timing.stops().is_empty());
+        assert!(timing.is_warped(6.5) && !timing.is_warped(7.0));
+        assert_eq!(timing.seconds_at_beat(4.0), timing.seconds_at_beat(7.0));
+        assert_eq!(Ok(2.75), timing.seconds_at_beat(9.0));
+    }
+}
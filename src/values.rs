@@ -0,0 +1,406 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::{error, fmt};
+
+use crate::parameter::{MSDParameter, MSDParameterError};
+use crate::serialize::{MSDSerializeOptions, MsdSerialize};
+
+/// Error for an entry of a `beat=value,...` list that is not two numbers separated by `=`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BeatValueError {
+    /// Index of the entry within the list, counting skipped empty entries.
+    pub index: usize,
+    /// The entry, trimmed.
+    pub entry: String,
+}
+
+impl fmt::Display for BeatValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {} '{}' is not a beat=value pair", self.index, self.entry)
+    }
+}
+
+impl error::Error for BeatValueError {}
+
+/// Parse a `beat=value,beat=value` list, as found in `#BPMS`, `#STOPS`, `#DELAYS` and `#WARPS`, into
+/// `(beat, value)` pairs.
+///
+/// Whitespace around entries and numbers is ignored, and so are empty entries, e.g. from a trailing comma.
+/// Comments must already be stripped, as the parser does. `#SPEEDS` entries carry two more fields after the
+/// value, so they are rejected rather than silently truncated; read them with [`parse_speeds`].
+///
+/// # Errors
+///
+/// Returns the first entry that is not exactly two numbers separated by `=`.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::values::parse_beat_value_pairs;
+/// assert_eq!(Ok(vec![(0.0, 120.0), (64.0, 240.5)]), parse_beat_value_pairs("0.000=120.000\n,64=240.5,"));
+/// assert_eq!(Ok(vec![]), parse_beat_value_pairs(" "));
+/// assert!(parse_beat_value_pairs("0=120=1=0").is_err());
+/// ```
+pub fn parse_beat_value_pairs(value: &str) -> Result<Vec<(f64, f64)>, BeatValueError> {
+    value.split(',')
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, entry)| !entry.is_empty())
+        .map(|(index, entry)| {
+            let error = || BeatValueError { index, entry: entry.to_string() };
+            let (beat, value) = entry.split_once('=').ok_or_else(error)?;
+            match (beat.trim().parse(), value.trim().parse()) {
+                (Ok(beat), Ok(value)) => Ok((beat, value)),
+                _ => Err(error()),
+            }
+        })
+        .collect()
+}
+
+/// What the duration of a [`Speed`] is measured in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum SpeedUnit {
+    /// Written as `0`.
+    #[default]
+    Beats,
+    /// Written as `1`.
+    Seconds,
+}
+
+/// A scroll speed change from `#SPEEDS`, parsed with [`parse_speeds`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Speed {
+    /// Beat the change starts at.
+    pub beat: f64,
+    /// Multiplier of the scroll speed once the change is done.
+    pub ratio: f64,
+    /// How long the change takes, in [`Speed::unit`]s. `0` changes the speed at once.
+    pub duration: f64,
+    pub unit: SpeedUnit,
+}
+
+/// Error for an entry of a `#SPEEDS` list that is not three numbers and an optional unit separated by `=`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpeedError {
+    /// Index of the entry within the list, counting skipped empty entries.
+    pub index: usize,
+    /// The entry, trimmed.
+    pub entry: String,
+}
+
+impl fmt::Display for SpeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {} '{}' is not a beat=ratio=duration=unit speed", self.index, self.entry)
+    }
+}
+
+impl error::Error for SpeedError {}
+
+/// Parse a `#SPEEDS` list of `beat=ratio=duration=unit` entries into [`Speed`]s.
+///
+/// Whitespace and empty entries are ignored as in [`parse_beat_value_pairs`]. Like StepMania, an entry without a
+/// unit is measured in beats, and so is any unit other than `1`.
+///
+/// # Errors
+///
+/// Returns the first entry that does not have three or four numbers separated by `=`.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::values::{parse_speeds, Speed, SpeedUnit};
+/// assert_eq!(Ok(vec![
+///     Speed { beat: 0.0, ratio: 1.0, duration: 0.0, unit: SpeedUnit::Beats },
+///     Speed { beat: 32.0, ratio: 0.5, duration: 1.5, unit: SpeedUnit::Seconds },
+/// ]), parse_speeds("0.000=1.000=0.000=0,\n32=0.5=1.5=1"));
+/// assert!(parse_speeds("0=1").is_err());
+/// ```
+pub fn parse_speeds(value: &str) -> Result<Vec<Speed>, SpeedError> {
+    value.split(',')
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, entry)| !entry.is_empty())
+        .map(|(index, entry)| {
+            let error = || SpeedError { index, entry: entry.to_string() };
+            let fields = entry.split('=')
+                .map(|field| field.trim().parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|_| error())?;
+            match fields[..] {
+                [beat, ratio, duration] => Ok(Speed { beat, ratio, duration, unit: SpeedUnit::Beats }),
+                [beat, ratio, duration, unit] => {
+                    let unit = if unit == 1.0 { SpeedUnit::Seconds } else { SpeedUnit::Beats };
+                    Ok(Speed { beat, ratio, duration, unit })
+                },
+                _ => Err(error()),
+            }
+        })
+        .collect()
+}
+
+/// The BPM a song select screen shows for a song, parsed from `#DISPLAYBPM`.
+///
+/// A range is written with the bounds in two components, `#DISPLAYBPM:100:200;`, so join the components after
+/// the key with `:` before parsing, as [`MSDParameter::value`] does not.
+///
+/// [`MSDParameter::value`]: crate::parameter::MSDParameter::value
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::values::DisplayBpm;
+/// assert_eq!(Ok(DisplayBpm::Fixed(150.0)), " 150 ".parse());
+/// assert_eq!(Ok(DisplayBpm::Range(100.0, 200.0)), "100:200".parse());
+/// assert_eq!(Ok(DisplayBpm::Random), "*".parse());
+/// assert_eq!("100:200", DisplayBpm::Range(100.0, 200.0).to_string());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum DisplayBpm {
+    /// A single BPM.
+    Fixed(f64),
+    /// The lowest and highest BPM, in the order written.
+    Range(f64, f64),
+    /// `*`: a number that changes randomly, hiding the real BPM.
+    Random,
+}
+
+/// Error for a `#DISPLAYBPM` value that is not a number, two numbers separated by `:`, or `*`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DisplayBpmError {
+    /// The value as given.
+    pub value: String,
+}
+
+impl fmt::Display for DisplayBpmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' is not a BPM, a BPM range or '*'", self.value)
+    }
+}
+
+impl error::Error for DisplayBpmError {}
+
+impl FromStr for DisplayBpm {
+    type Err = DisplayBpmError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let error = || DisplayBpmError { value: value.to_string() };
+        let bpm = |s: &str| s.trim().parse::<f64>().ok().filter(|bpm| bpm.is_finite()).ok_or_else(error);
+
+        if value.trim() == "*" {
+            return Ok(DisplayBpm::Random);
+        }
+        match value.split_once(':') {
+            Some((min, max)) => Ok(DisplayBpm::Range(bpm(min)?, bpm(max)?)),
+            None => Ok(DisplayBpm::Fixed(bpm(value)?)),
+        }
+    }
+}
+
+impl fmt::Display for DisplayBpm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplayBpm::Fixed(bpm) => write!(f, "{}", bpm),
+            DisplayBpm::Range(min, max) => write!(f, "{}:{}", min, max),
+            DisplayBpm::Random => write!(f, "*"),
+        }
+    }
+}
+
+/// A `#DISPLAYBPM` parameter, without a line ending. Numbers are written with [`MSDSerializeOptions::float_format`].
+impl MsdSerialize for DisplayBpm {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        let format = |bpm: f64| options.float_format.format(bpm);
+        let values = match *self {
+            DisplayBpm::Fixed(bpm) => vec![format(bpm)],
+            DisplayBpm::Range(min, max) => vec![format(min), format(max)],
+            DisplayBpm::Random => vec!["*".to_string()],
+        };
+        let mut components = vec!["DISPLAYBPM".to_string()];
+        components.extend(values);
+        MSDParameter::new(components).serialize_msd(writer, options)
+    }
+}
+
+/// A category of [`RadarValues`], in the order StepMania 5.1 writes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "kebab-case"))]
+pub enum RadarCategory {
+    Stream,
+    Voltage,
+    Air,
+    Freeze,
+    Chaos,
+    /// Missing from the 13 categories of StepMania 5.0.
+    Notes,
+    TapsAndHolds,
+    Jumps,
+    Holds,
+    Mines,
+    Hands,
+    Rolls,
+    Lifts,
+    Fakes,
+}
+
+/// Number of values per player StepMania 5.1 writes.
+const RADAR_CATEGORIES: usize = 14;
+/// Number of values per player StepMania 5.0 writes, without [`RadarCategory::Notes`].
+const RADAR_CATEGORIES_5_0: usize = 13;
+
+/// The radar values of a chart, parsed from `#RADARVALUES` with [`parse_radar_values`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RadarValues {
+    /// The values of each player, as written. `.ssc` files list both players, `.sm` files only one.
+    pub players: Vec<Vec<f64>>,
+}
+
+impl RadarValues {
+    /// The value of `category` for `player`, counting from 0, or `None` if the list is too short to hold it.
+    ///
+    /// Lists of 13 values per player are read as StepMania 5.0 wrote them, which have no
+    /// [`RadarCategory::Notes`].
+    pub fn get(&self, player: usize, category: RadarCategory) -> Option<f64> {
+        let values = self.players.get(player)?;
+        let index = if values.len() == RADAR_CATEGORIES_5_0 {
+            match category {
+                RadarCategory::Notes => return None,
+                category if category > RadarCategory::Notes => category as usize - 1,
+                category => category as usize,
+            }
+        } else {
+            category as usize
+        };
+        values.get(index).copied()
+    }
+}
+
+/// A `#RADARVALUES` parameter with the values of every player in one list, without a line ending.
+/// Numbers are written with [`MSDSerializeOptions::float_format`].
+impl MsdSerialize for RadarValues {
+    fn serialize_msd(&self, writer: &mut dyn Write, options: &MSDSerializeOptions) -> Result<(), MSDParameterError> {
+        let values: Vec<String> = self.players.iter().flatten().map(|&value| options.float_format.format(value)).collect();
+        MSDParameter::new(vec!["RADARVALUES".to_string(), values.join(",")]).serialize_msd(writer, options)
+    }
+}
+
+/// Error for an entry of a `#RADARVALUES` list that is not a number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RadarValuesError {
+    /// Index of the entry within the list.
+    pub index: usize,
+    /// The entry, trimmed.
+    pub entry: String,
+}
+
+impl fmt::Display for RadarValuesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "radar value {} '{}' is not a number", self.index, self.entry)
+    }
+}
+
+impl error::Error for RadarValuesError {}
+
+/// Parse a comma-separated `#RADARVALUES` list, splitting it per player.
+///
+/// Lists of two full sets of 14 or 13 values, as `.ssc` files have, are split into two players; any other
+/// length, short or long, is kept as the values of a single player, so old and hand-edited files still parse.
+/// Whitespace around entries is ignored and so is a trailing comma.
+///
+/// # Errors
+///
+/// Returns the first entry that is not a number.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::values::{parse_radar_values, RadarCategory};
+/// let radar = parse_radar_values("0.993,1.229,0.256,0.845,0.980").unwrap();
+///
+/// assert_eq!(Some(1.229), radar.get(0, RadarCategory::Voltage));
+/// assert_eq!(None, radar.get(0, RadarCategory::Jumps));
+/// ```
+pub fn parse_radar_values(value: &str) -> Result<RadarValues, RadarValuesError> {
+    let mut entries: Vec<&str> = value.split(',').map(str::trim).collect();
+    if entries.last() == Some(&"") {
+        entries.pop();
+    }
+    let values = entries.iter()
+        .enumerate()
+        .map(|(index, entry)| entry.parse().map_err(|_| RadarValuesError { index, entry: entry.to_string() }))
+        .collect::<Result<Vec<f64>, _>>()?;
+
+    let players = match values.len() {
+        n if n == 2 * RADAR_CATEGORIES => values.chunks(RADAR_CATEGORIES).map(<[f64]>::to_vec).collect(),
+        n if n == 2 * RADAR_CATEGORIES_5_0 => values.chunks(RADAR_CATEGORIES_5_0).map(<[f64]>::to_vec).collect(),
+        0 => Vec::new(),
+        _ => vec![values],
+    };
+    Ok(RadarValues { players })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::FloatFormat;
+
+    #[test]
+    fn test_parse_beat_value_pairs() {
+        assert_eq!(Ok(vec![(0.0, 120.0), (4.5, -0.25)]), parse_beat_value_pairs(" 0 = 120 ,\r\n4.5=-0.25 , ,"));
+        assert_eq!(Ok(vec![]), parse_beat_value_pairs(""));
+        assert_eq!(Err(BeatValueError { index: 2, entry: "4=x".to_string() }), parse_beat_value_pairs("0=120,,4=x"));
+        assert_eq!(Err(BeatValueError { index: 0, entry: "120".to_string() }), parse_beat_value_pairs("120"));
+        assert_eq!("entry 0 '120' is not a beat=value pair", parse_beat_value_pairs("120").unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_parse_speeds() {
+        assert_eq!(Ok(vec![
+            Speed { beat: 0.0, ratio: 1.0, duration: 0.0, unit: SpeedUnit::Beats },
+            Speed { beat: 16.0, ratio: 2.0, duration: 4.0, unit: SpeedUnit::Beats },
+            Speed { beat: 32.0, ratio: 0.5, duration: 1.0, unit: SpeedUnit::Seconds },
+        ]), parse_speeds(" 0=1=0 ,\r\n16 = 2 = 4 = 0,32=0.5=1=1, ,"));
+        assert_eq!(Ok(vec![]), parse_speeds(""));
+        assert_eq!(Err(SpeedError { index: 1, entry: "4=2".to_string() }), parse_speeds("0=1=0=0,4=2"));
+        assert_eq!(Err(SpeedError { index: 0, entry: "0=1=x=0".to_string() }), parse_speeds("0=1=x=0"));
+        assert_eq!(Err(SpeedError { index: 0, entry: "0=1=0=0=0".to_string() }), parse_speeds("0=1=0=0=0"));
+        assert!(parse_beat_value_pairs("0=1=0=0").is_err());
+    }
+
+    #[test]
+    fn test_display_bpm() {
+        assert_eq!(Ok(DisplayBpm::Range(200.0, 100.5)), " 200 : 100.5 ".parse());
+        assert_eq!(Ok(DisplayBpm::Random), " * ".parse());
+        for invalid in ["", "*:200", "100:", "100:200:300", "NaN", "fast"] {
+            assert_eq!(Err(DisplayBpmError { value: invalid.to_string() }), invalid.parse::<DisplayBpm>());
+        }
+        assert_eq!("150.5", DisplayBpm::Fixed(150.5).to_string());
+    }
+
+    #[test]
+    fn test_radar_values() {
+        let ssc = "0.993,1.229,0.256,0.845,0.980,897,864,33,109,42,0,0,0,0,1,2,3,4,5,6,7,8,9,10,11,12,13,14";
+        let radar = parse_radar_values(ssc).unwrap();
+        assert_eq!(2, radar.players.len());
+        assert_eq!((Some(897.0), Some(6.0)), (radar.get(0, RadarCategory::Notes), radar.get(1, RadarCategory::Notes)));
+        assert_eq!(Some(14.0), radar.get(1, RadarCategory::Fakes));
+
+        let sm5_0 = parse_radar_values("1,2,3,4,5,6,7,8,9,10,11,12,13, ").unwrap();
+        assert_eq!((None, Some(6.0), Some(13.0)), (sm5_0.get(0, RadarCategory::Notes), sm5_0.get(0, RadarCategory::TapsAndHolds), sm5_0.get(0, RadarCategory::Fakes)));
+        assert_eq!(RadarValues::default(), parse_radar_values("").unwrap());
+        assert_eq!(Err(RadarValuesError { index: 1, entry: "x".to_string() }), parse_radar_values("1, x"));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let options = MSDSerializeOptions::new().float_format(FloatFormat::new().trim_trailing_zeros(true));
+        assert_eq!("#DISPLAYBPM:100:200.5;", DisplayBpm::Range(100.0, 200.5).to_msd_string(&options).unwrap());
+        assert_eq!("#DISPLAYBPM:*;", DisplayBpm::Random.to_msd_string(&options).unwrap());
+
+        let radar = parse_radar_values("0.5,1,0.25").unwrap();
+        assert_eq!("#RADARVALUES:0.5,1,0.25;", radar.to_msd_string(&options).unwrap());
+        assert_eq!("#RADARVALUES:;", RadarValues::default().to_msd_string(&options).unwrap());
+    }
+}
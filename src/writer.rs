@@ -0,0 +1,213 @@
+use std::io::Write;
+
+use crate::parameter::{MSDParameter, MSDParameterError};
+use crate::serialize::{ends_with_comment, MSDSerializeOptions, MsdSerialize, RecordSeparator};
+
+/// Writer for MSD data, the output-side counterpart to [`MSDParser`](crate::parser::MSDParser).
+///
+/// Parameters are escaped and separated like a document, by the [`RecordSeparator`] of the
+/// [`MSDSerializeOptions`]. The separator after a parameter is only written once something follows it, so call
+/// [`MSDWriter::finish`] at the end to write the trailing line ending. Dropping the writer or taking the underlying
+/// writer back with [`MSDWriter::into_inner`] leaves it out. Comments are always on lines of their own.
+///
+/// # Examples
+///
+/// ```rust
+/// # use msdparser::MSDParameter;
+/// # use msdparser::writer::MSDWriter;
+/// let mut writer = MSDWriter::new(Vec::new());
+/// writer.write_comment("Generated").unwrap();
+/// writer.write_parameter(&MSDParameter::new(vec!["TITLE".to_string(), "A;B".to_string()])).unwrap();
+/// writer.write_raw("#OFFSET:0;").unwrap();
+///
+/// assert_eq!("// Generated\n#TITLE:A\\;B;\n#OFFSET:0;", String::from_utf8(writer.finish().unwrap()).unwrap());
+/// ```
+#[derive(Debug)]
+pub struct MSDWriter<W> {
+    writer: W,
+    options: MSDSerializeOptions,
+    parameters_written: usize,
+    /// Whether the last thing written was a parameter, whose separator is still owed.
+    separator_pending: bool,
+    /// Whether that parameter ended with a comment, so the separator must at least break the line.
+    line_break_pending: bool,
+}
+
+impl<W: Write> MSDWriter<W> {
+    /// Create a writer with the default [`MSDSerializeOptions`].
+    pub fn new(writer: W) -> Self {
+        Self::with_options(writer, MSDSerializeOptions::new())
+    }
+
+    pub fn with_options(writer: W, options: MSDSerializeOptions) -> Self {
+        Self { writer, options, parameters_written: 0, separator_pending: false, line_break_pending: false }
+    }
+
+    /// The options this writer was created with.
+    pub fn options(&self) -> &MSDSerializeOptions {
+        &self.options
+    }
+
+    /// Number of parameters written so far.
+    pub fn parameters_written(&self) -> usize {
+        self.parameters_written
+    }
+
+    /// Write a parameter, after the separator if a parameter came right before it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails, or if a component contains a special substring and escapes are disabled.
+    /// Nothing is written in the latter case.
+    pub fn write_parameter(&mut self, parameter: &MSDParameter) -> Result<(), MSDParameterError> {
+        // Serialize first so that a rejected parameter leaves no partial output behind
+        let mut buffer = Vec::new();
+        parameter.serialize_msd(&mut buffer, &self.options)?;
+        self.write_pending_separator(false)?;
+        self.writer.write_all(&buffer)?;
+        self.parameters_written += 1;
+        self.separator_pending = true;
+        self.line_break_pending = ends_with_comment(&parameter.comments, &self.options);
+        Ok(())
+    }
+
+    /// Write the separator owed to the last parameter, but at least a line ending when `line_break` is set or the
+    /// parameter ended with a comment.
+    fn write_pending_separator(&mut self, line_break: bool) -> Result<(), MSDParameterError> {
+        if std::mem::take(&mut self.separator_pending) {
+            let line_break = line_break || std::mem::take(&mut self.line_break_pending);
+            match self.options.separator {
+                RecordSeparator::Nothing if line_break => self.writer.write_all(self.options.line_ending.as_str().as_bytes())?,
+                separator => separator.write(&mut self.writer, self.options.line_ending)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `text` as a comment. Every line of `text` becomes a `// ` comment line of its own.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn write_comment(&mut self, text: &str) -> Result<(), MSDParameterError> {
+        self.write_pending_separator(true)?;
+        let line_ending = self.options.line_ending.as_str();
+        for line in text.lines() {
+            write!(self.writer, "// {}{}", line, line_ending)?;
+        }
+        if text.is_empty() {
+            write!(self.writer, "//{}", line_ending)?;
+        }
+        Ok(())
+    }
+
+    /// Write `text` as it is, without escaping or a line ending, after the separator owed to the last parameter.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn write_raw(&mut self, text: &str) -> Result<(), MSDParameterError> {
+        self.write_pending_separator(false)?;
+        self.writer.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Write the trailing line ending if the last thing written was a parameter and
+    /// [`MSDSerializeOptions::trailing_line_ending`] is set, flush and return the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing or flushing fails.
+    pub fn finish(mut self) -> Result<W, MSDParameterError> {
+        if self.separator_pending && self.options.trailing_line_ending {
+            self.writer.write_all(self.options.line_ending.as_str().as_bytes())?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Flush the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing fails.
+    pub fn flush(&mut self) -> Result<(), MSDParameterError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Mutable access to the underlying writer.
+    ///
+    /// Writing to it directly bypasses escaping and line endings.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// The underlying writer, as it is.
+    ///
+    /// **This does not finish the output**: the trailing line ending [`MSDWriter::finish`] writes is left out, and
+    /// nothing is flushed. Use it to keep writing to the underlying writer without MSD framing, e.g. to append
+    /// something after the last parameter on the same line; call [`MSDWriter::finish`] to end a document.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use msdparser::MSDParameter;
+    /// # use msdparser::writer::MSDWriter;
+    /// let mut writer = MSDWriter::new(Vec::new());
+    /// writer.write_parameter(&MSDParameter::new(vec!["TITLE".to_string(), "A".to_string()])).unwrap();
+    ///
+    /// assert_eq!(b"#TITLE:A;", writer.into_inner().as_slice());
+    /// ```
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize::LineEnding;
+
+    #[test]
+    fn test_writer() {
+        let options = MSDSerializeOptions::new().line_ending(LineEnding::CrLf);
+        let mut writer = MSDWriter::with_options(Vec::new(), options);
+        writer.write_comment("two\nlines").unwrap();
+        writer.write_comment("").unwrap();
+        writer.write_parameter(&MSDParameter::new(vec!["NOTES".to_string(), "a//b".to_string(), "c\\".to_string()])).unwrap();
+
+        assert_eq!(1, writer.parameters_written());
+        assert_eq!("// two\r\n// lines\r\n//\r\n#NOTES:a\\//b:c\\\\;\r\n", String::from_utf8(writer.finish().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_separators() {
+        let parameter = MSDParameter::new(vec!["A".to_string(), "B".to_string()]);
+        let options = MSDSerializeOptions::new().separator(RecordSeparator::Nothing).trailing_line_ending(false);
+        let mut writer = MSDWriter::with_options(Vec::new(), options);
+        writer.write_parameter(&parameter).unwrap();
+        writer.write_parameter(&parameter).unwrap();
+        writer.write_comment("c").unwrap();
+        writer.write_parameter(&parameter).unwrap();
+        assert_eq!("#A:B;#A:B;\n// c\n#A:B;", String::from_utf8(writer.finish().unwrap()).unwrap());
+
+        let mut writer = MSDWriter::with_options(Vec::new(), MSDSerializeOptions::new().separator(RecordSeparator::BlankLines(1)));
+        writer.write_parameter(&parameter).unwrap();
+        writer.write_parameter(&parameter).unwrap();
+        let document = vec![parameter.clone(), parameter].to_msd_string(writer.options()).unwrap();
+        assert_eq!(document, String::from_utf8(writer.finish().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_rejected_parameter() {
+        let mut writer = MSDWriter::with_options(Vec::new(), MSDSerializeOptions::new().escapes(false));
+        assert!(writer.write_parameter(&MSDParameter::new(vec!["A".to_string(), "B:C".to_string()])).is_err());
+        assert_eq!(0, writer.parameters_written());
+        assert!(writer.get_ref().is_empty());
+    }
+}
@@ -0,0 +1,41 @@
+//! Driver shared by the conformance suites.
+//!
+//! Every input in `testdata/conformance` is rendered to text by the suite and compared against the expectation
+//! stored next to it as `<input>.<level>`. Run with `MSDPARSER_BLESS=1` to write the current output as the
+//! expectation instead, then review the diff before committing it.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Compare `render(input)` with `<input>.<expectation>` for every corpus input with one of `input_extensions`.
+pub fn check_corpus(input_extensions: &[&str], expectation: &str, render: impl Fn(&[u8]) -> String) {
+    let corpus = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/conformance");
+    let bless = env::var_os("MSDPARSER_BLESS").is_some();
+
+    let mut inputs: Vec<PathBuf> = fs::read_dir(&corpus)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| input_extensions.iter().any(|e| ext == *e)))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no conformance inputs in {}", corpus.display());
+
+    let mut failures = Vec::new();
+    for input in inputs {
+        let actual = render(&fs::read(&input).unwrap());
+        let expected_path = PathBuf::from(format!("{}.{}", input.display(), expectation));
+
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => {},
+            Ok(expected) => failures.push(format!("{}\n--- expected\n{}--- actual\n{}", input.display(), expected, actual)),
+            Err(e) => failures.push(format!("{}: cannot read {}: {}", input.display(), expected_path.display(), e)),
+        }
+    }
+
+    assert!(failures.is_empty(), "{} conformance case(s) failed:\n\n{}", failures.len(), failures.join("\n"));
+}
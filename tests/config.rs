@@ -0,0 +1,55 @@
+//! Loading parsing, formatting and linting policy from a repo-local `.msdfmt.toml`.
+
+use msdparser::format::FormatOptions;
+use msdparser::lint::{LintCode, LintOptions, PortabilityOptions};
+use msdparser::serialize::LineEnding;
+use msdparser::{EscapePolicy, MSDParserOptions};
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct Config {
+    parser: MSDParserOptions,
+    format: FormatOptions,
+    lint: LintOptions,
+}
+
+#[test]
+fn msdfmt_toml() {
+    let config: Config = toml::from_str(r#"
+        [parser]
+        escapes = "unescaped"
+        keep_comments = true
+
+        [format]
+        line_ending = "crlf"
+        blank_lines = 2
+
+        [lint]
+        allow = ["mixed-escapes"]
+        portability = { install_prefix = "", max_path = 100 }
+    "#).unwrap();
+
+    let parser = MSDParserOptions::new().escapes(EscapePolicy::Unescaped).keep_comments(true);
+    assert_eq!(parser, config.parser);
+    let format = FormatOptions::new().line_ending(LineEnding::CrLf).blank_lines(2);
+    assert_eq!(format, config.format);
+    let lint = LintOptions::new()
+        .allow(LintCode::MixedEscapes)
+        .portability(PortabilityOptions::new().install_prefix("").max_path(100));
+    assert_eq!(lint, config.lint);
+}
+
+#[test]
+fn empty_config_is_default() {
+    let config: Config = toml::from_str("").unwrap();
+
+    assert_eq!(MSDParserOptions::default(), config.parser);
+    assert_eq!(FormatOptions::default(), config.format);
+    assert_eq!(LintOptions::default(), config.lint);
+}
+
+#[test]
+fn unknown_values_are_rejected() {
+    assert!(toml::from_str::<Config>("[lint]\nallow = [\"no-such-lint\"]").is_err());
+    assert!(toml::from_str::<Config>("[format]\nline_ending = \"cr\"").is_err());
+}
@@ -0,0 +1,19 @@
+//! Level 2 conformance: the parameters and errors of every corpus input, with escapes on and stray text reported.
+
+mod common;
+
+use msdparser::parse_msd;
+
+#[test]
+fn level_2_parameters() {
+    common::check_corpus(&["msd", "sm"], "parameters", |input| {
+        let mut output = String::new();
+        for result in parse_msd(input, true, false) {
+            match result {
+                Ok(parameter) => output += &format!("{:?} {:?}\n", parameter.span.unwrap(), parameter.components),
+                Err(e) => output += &format!("error: {}\n", e),
+            }
+        }
+        output
+    });
+}
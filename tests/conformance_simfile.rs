@@ -0,0 +1,24 @@
+//! Level 3 conformance: the typed simfile of every corpus simfile.
+
+mod common;
+
+use msdparser::parse_msd;
+use msdparser::simfile::Simfile;
+use msdparser::MSDParameter;
+
+#[test]
+fn level_3_simfile() {
+    common::check_corpus(&["sm"], "simfile", |input| {
+        let parameters: Vec<MSDParameter> = parse_msd(input, true, true).map(Result::unwrap).collect();
+        let simfile = Simfile::try_from(&parameters).unwrap();
+
+        let mut output = format!(
+            "title: {:?}\nartist: {:?}\noffset: {:?}\nsample_start: {:?}\nbpms: {:?}\nstops: {:?}\n",
+            simfile.title, simfile.artist, simfile.offset, simfile.sample_start, simfile.bpms, simfile.stops,
+        );
+        for chart in &simfile.charts {
+            output += &format!("chart: {} {} {} {} rows\n", chart.steps_type, chart.difficulty, chart.meter, chart.notes.concat().lines().count());
+        }
+        output
+    });
+}
@@ -0,0 +1,17 @@
+//! Level 1 conformance: the tokens of every corpus input, with their spans.
+
+mod common;
+
+use msdparser::lexer::lex_msd;
+
+#[test]
+fn level_1_tokens() {
+    common::check_corpus(&["msd", "sm"], "tokens", |input| {
+        let mut output = String::new();
+        for token_match in lex_msd(input, true) {
+            let token_match = token_match.unwrap();
+            output += &format!("{:?} {:?} {:?}\n", token_match.span, token_match.token, token_match.text);
+        }
+        output
+    });
+}
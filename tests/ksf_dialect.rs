@@ -0,0 +1,22 @@
+//! The parameters of every `.ksf` corpus input under `Dialect::Ksf`. Where `#STEP` ends is not `MsdFile.cpp`'s
+//! doing, which reads it to the end of the input, but where StepMania's KSF loader stops reading step rows, so
+//! these expectations are not part of the parity corpus in `tests/stepmania_parity.rs`. Both parsers must agree.
+
+mod common;
+
+use msdparser::borrowed::MSDStrParser;
+use msdparser::{parse_msd_with_options, Dialect, MSDParserOptions};
+
+#[test]
+fn ksf_parameters() {
+    common::check_corpus(&["ksf"], "dialect", |input| {
+        let options = MSDParserOptions::new().dialect(Dialect::Ksf);
+        let parameters: Vec<_> = parse_msd_with_options(input, options).map(Result::unwrap).collect();
+
+        let text = String::from_utf8(input.to_vec()).unwrap();
+        let borrowed: Vec<_> = MSDStrParser::with_options(&text, options).map(|parameter| parameter.unwrap().into_owned()).collect();
+        assert_eq!(parameters, borrowed);
+
+        parameters.iter().map(|parameter| format!("{:?}\n", parameter.components)).collect()
+    });
+}
@@ -0,0 +1,31 @@
+//! Parity with StepMania's `MsdFile.cpp`: the parameters of every corpus input under `Dialect::StepMania`, and of
+//! every `.dwi` input under `Dialect::Dwi`, which must match what the game reads for these inputs.
+//! Both parsers must agree. `.ksf` inputs are checked in `tests/ksf_dialect.rs` instead.
+
+mod common;
+
+use msdparser::borrowed::MSDStrParser;
+use msdparser::{parse_msd_with_options, Dialect, MSDParserOptions};
+
+fn check_dialect(input_extensions: &[&str], dialect: Dialect) {
+    common::check_corpus(input_extensions, "stepmania", |input| {
+        let options = MSDParserOptions::new().dialect(dialect);
+        let parameters: Vec<_> = parse_msd_with_options(input, options).map(Result::unwrap).collect();
+
+        let text = String::from_utf8(input.to_vec()).unwrap();
+        let borrowed: Vec<_> = MSDStrParser::with_options(&text, options).map(|parameter| parameter.unwrap().into_owned()).collect();
+        assert_eq!(parameters, borrowed);
+
+        parameters.iter().map(|parameter| format!("{:?}\n", parameter.components)).collect()
+    });
+}
+
+#[test]
+fn stepmania_parameters() {
+    check_dialect(&["msd", "sm"], Dialect::StepMania);
+}
+
+#[test]
+fn dwi_parameters() {
+    check_dialect(&["dwi"], Dialect::Dwi);
+}
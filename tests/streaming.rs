@@ -0,0 +1,65 @@
+//! The memory guarantees documented on `MSDParser` and `MSDLexer`: peak memory depends on the largest parameter
+//! or line, never on the size of the input.
+
+mod support;
+
+use std::sync::OnceLock;
+
+use msdparser::lexer::lex_msd;
+use msdparser::parse_msd;
+use support::{peak_memory, GeneratedInput, PeakAllocator, ThrottledReader};
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+const HEADER: &[u8] = b"#TITLE:Streaming;\n#ARTIST:Test;\n#BPMS:0=120;\n";
+
+const MEASURE: &str = "0000\n1001\n0110\n0000\n,\n";
+
+/// A chart of 256 measures, about 6 KiB.
+fn chart() -> &'static [u8] {
+    static CHART: OnceLock<String> = OnceLock::new();
+    CHART.get_or_init(|| {
+        format!("#NOTES:\n     dance-single:\n     :\n     Hard:\n     9:\n     0,0,0,0,0:\n{};\n", MEASURE.repeat(256))
+    }).as_bytes()
+}
+
+/// Room for the lexer's buffer, a read chunk and the parameter being built, however large the input.
+const BOUND: usize = 64 * 1024;
+
+fn parse_charts(charts: usize) -> usize {
+    let reader = ThrottledReader::new(GeneratedInput::new(HEADER, chart(), charts, b""), 1000);
+    peak_memory(|| {
+        let mut parameters = 0;
+        for parameter in parse_msd(reader, true, false) {
+            assert!(parameter.unwrap().value().is_some());
+            parameters += 1;
+        }
+        assert_eq!(3 + charts, parameters);
+    })
+}
+
+#[test]
+fn test_parser_memory_is_bounded_by_largest_parameter() {
+    let small = parse_charts(4);
+    // About 6 MiB of input
+    let large = parse_charts(1000);
+
+    assert!(large < BOUND, "parsing 1000 charts peaked at {} bytes", large);
+    assert!(large < small + chart().len(), "peak grew from {} to {} bytes with the input", small, large);
+}
+
+#[test]
+fn test_lexer_memory_is_bounded_by_longest_line() {
+    // A single parameter of about 1.5 MiB, which only the lexer can stream
+    let reader = ThrottledReader::new(GeneratedInput::new(b"#NOTES:\n", MEASURE.as_bytes(), 64 * 1024, b";\n"), 1000);
+    let peak = peak_memory(|| {
+        let mut length = 0;
+        for token in lex_msd(reader, true) {
+            length += token.unwrap().text.len();
+        }
+        assert_eq!(8 + MEASURE.len() * 64 * 1024 + 2, length);
+    });
+
+    assert!(peak < BOUND, "lexing a 1.5 MiB value peaked at {} bytes", peak);
+}
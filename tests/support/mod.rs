@@ -0,0 +1,100 @@
+//! Readers and an allocator for tests that check how much memory parsing takes.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{self, Read};
+
+/// Reader that returns at most `max_read` bytes per call, like a slow socket or pipe.
+pub struct ThrottledReader<R> {
+    inner: R,
+    max_read: usize,
+}
+
+impl<R> ThrottledReader<R> {
+    pub fn new(inner: R, max_read: usize) -> Self {
+        assert!(max_read > 0, "a throttled reader must make progress");
+        Self { inner, max_read }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = buf.len().min(self.max_read);
+        self.inner.read(&mut buf[..length])
+    }
+}
+
+/// Reader that yields `head`, then `body` `count` times, then `tail`, without ever holding the whole input.
+pub struct GeneratedInput {
+    parts: Vec<&'static [u8]>,
+    part: usize,
+    offset: usize,
+}
+
+impl GeneratedInput {
+    pub fn new(head: &'static [u8], body: &'static [u8], count: usize, tail: &'static [u8]) -> Self {
+        let mut parts = vec![head];
+        parts.extend(std::iter::repeat_n(body, count));
+        parts.push(tail);
+        Self { parts, part: 0, offset: 0 }
+    }
+}
+
+impl Read for GeneratedInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(part) = self.parts.get(self.part) {
+            let rest = &part[self.offset..];
+            if rest.is_empty() {
+                self.part += 1;
+                self.offset = 0;
+                continue;
+            }
+            let length = rest.len().min(buf.len());
+            buf[..length].copy_from_slice(&rest[..length]);
+            self.offset += length;
+            return Ok(length);
+        }
+        Ok(0)
+    }
+}
+
+thread_local! {
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+    static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+/// Allocator that tracks the bytes allocated by each thread, so tests running in parallel don't see each other.
+pub struct PeakAllocator;
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        track(layout.size() as isize);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        track(-(layout.size() as isize));
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        track(new_size as isize - layout.size() as isize);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+fn track(change: isize) {
+    // `try_with` keeps allocations during thread teardown from panicking
+    let _ = LIVE.try_with(|live| {
+        live.set(live.get() + change);
+        let _ = PEAK.try_with(|peak| peak.set(peak.get().max(live.get())));
+    });
+}
+
+/// Run `f` and return the most bytes it had allocated on this thread at once.
+pub fn peak_memory(f: impl FnOnce()) -> usize {
+    let baseline = LIVE.with(Cell::get);
+    PEAK.with(|peak| peak.set(baseline));
+    f();
+    (PEAK.with(Cell::get) - baseline).max(0) as usize
+}